@@ -24,6 +24,7 @@ COLOR_ACCENT = "#4a9eff"
 # Message limits
 MAX_MESSAGE_LENGTH = 32000
 DEFAULT_MAX_TOKENS = 4096
+DEFAULT_CONTEXT_TOKENS = 16384  # Assumed context size of the model
 
 # API Defaults
 DEFAULT_TEMPERATURE = 0.7
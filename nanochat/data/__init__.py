@@ -10,184 +10,33 @@ from contextlib import contextmanager
 from typing import Optional, List
 from datetime import datetime
 
-from sqlalchemy import create_engine, Column, Integer, String, Text, Boolean, DateTime, ForeignKey, func
+from sqlalchemy import create_engine, event, Column, Integer, String, Text, Boolean, DateTime, ForeignKey, func
+from sqlalchemy.types import TypeDecorator
 from sqlalchemy.ext.declarative import declarative_base
 from sqlalchemy.orm import sessionmaker, Session, relationship
-from sqlalchemy.exc import SQLAlchemyError
 
 logger = logging.getLogger(__name__)
 
 # SQLAlchemy Base
 Base = declarative_base()
 
-# Database version
-DB_VERSION = 1
 
+# ==================== ORM Models ====================
 
-# ==================== Migration System ====================
-
-class Migration:
-    """Base migration class"""
-
-    def __init__(self, version: int, description: str):
-        self.version = version
-        self.description = description
-
-    def up(self, engine):
-        """Apply migration"""
-        raise NotImplementedError
-
-    def down(self, engine):
-        """Rollback migration"""
-        raise NotImplementedError
-
-
-class Migration_v1(Migration):
-    """Initial schema migration"""
-
-    def __init__(self):
-        super().__init__(1, "Create conversations and messages tables")
-
-    def up(self, engine):
-        """Create initial schema"""
-        # Create all tables
-        Base.metadata.create_all(bind=engine)
-
-    def down(self, engine):
-        """Drop all tables"""
-        Base.metadata.drop_all(bind=engine)
-
-
-class Migration_v2(Migration):
-    """Add projects table and project_id to conversations"""
-
-    def __init__(self):
-        super().__init__(2, "Add projects table for conversation organization")
-
-    def up(self, engine):
-        """Create projects table and add project_id to conversations"""
-        from sqlalchemy import text
-
-        with engine.connect() as conn:
-            # Create projects table
-            conn.execute(text("""
-                CREATE TABLE IF NOT EXISTS projects (
-                    id INTEGER PRIMARY KEY AUTOINCREMENT,
-                    name VARCHAR(100) NOT NULL UNIQUE,
-                    color VARCHAR(7) DEFAULT '#4a9eff',
-                    description TEXT,
-                    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
-                    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
-                    order_index INTEGER NOT NULL DEFAULT 0
-                )
-            """))
-
-            # Add project_id column to conversations if it doesn't exist
-            # SQLite doesn't have IF NOT EXISTS for ADD COLUMN, so we check first
-            result = conn.execute(text("PRAGMA table_info(conversations)"))
-            columns = [row[1] for row in result]
-            if 'project_id' not in columns:
-                conn.execute(text("""
-                    ALTER TABLE conversations ADD COLUMN project_id INTEGER REFERENCES projects(id)
-                """))
-
-            conn.commit()
-
-    def down(self, engine):
-        """Drop projects table"""
-        from sqlalchemy import text
-
-        with engine.connect() as conn:
-            # Note: SQLite doesn't support DROP COLUMN, so we can't remove project_id
-            conn.execute(text("DROP TABLE IF EXISTS projects"))
-            conn.commit()
-
-
-class MigrationManager:
-    """Database migration manager"""
-
-    def __init__(self, db_manager: 'DatabaseManager'):
-        """
-        Initialize migration manager
-
-        Args:
-            db_manager: DatabaseManager instance
-        """
-        self.db_manager = db_manager
-        self.migrations = {
-            1: Migration_v1(),
-            2: Migration_v2()
-        }
-
-    def get_current_version(self) -> int:
-        """Get current database version from metadata table"""
-        from sqlalchemy import inspect, text
-
-        inspector = inspect(self.db_manager.engine)
-        if 'alembic_version' in inspector.get_table_names():
-            # Using alembic-style version table
-            with self.db_manager.get_session() as session:
-                result = session.execute(text("SELECT version_num FROM alembic_version LIMIT 1"))
-                row = result.fetchone()
-                return row[0] if row else 0
-        return 0
-
-    def set_version(self, version: int):
-        """Set database version in metadata table"""
-        from sqlalchemy import text
-
-        # Create version table if it doesn't exist
-        with self.db_manager.engine.connect() as conn:
-            conn.execute(text("""
-                CREATE TABLE IF NOT EXISTS alembic_version (
-                    version_num INTEGER PRIMARY KEY
-                )
-            """))
-            conn.execute(text("""
-                INSERT OR REPLACE INTO alembic_version (version_num)
-                VALUES (:version)
-            """), {"version": version})
-            conn.commit()
-
-    def migrate(self, target_version: int = None):
-        """
-        Run migrations to bring database to target version
+class MessageContent(TypeDecorator):
+    """
+    Message text column
 
-        Args:
-            target_version: Target version (defaults to latest)
-        """
-        current_version = self.get_current_version()
-
-        if target_version is None:
-            target_version = max(self.migrations.keys())
-
-        if current_version == target_version:
-            logger.info(f"Database already at version {target_version}")
-            return
-
-        if current_version < target_version:
-            # Upgrade
-            for version in range(current_version + 1, target_version + 1):
-                if version in self.migrations:
-                    migration = self.migrations[version]
-                    logger.info(f"Applying migration {version}: {migration.description}")
-                    migration.up(self.db_manager.engine)
-                    self.set_version(version)
-                    logger.info(f"Migration {version} completed")
-        else:
-            # Downgrade
-            for version in range(current_version, target_version, -1):
-                if version in self.migrations:
-                    migration = self.migrations[version]
-                    logger.info(f"Rolling back migration {version}: {migration.description}")
-                    migration.down(self.db_manager.engine)
-                    self.set_version(version - 1)
-                    logger.info(f"Rollback {version} completed")
-
-        logger.info(f"Database migrated to version {target_version}")
+    The Rust store keeps long bodies as zstd frames; they are read through
+    the nanochat_content() SQL function DatabaseManager registers, so
+    callers always get text.
+    """
+    impl = Text
+    cache_ok = True
 
+    def column_expression(self, column):
+        return func.nanochat_content(column)
 
-# ==================== ORM Models ====================
 
 class Project(Base):
     """Project model for organizing conversations into folders"""
@@ -238,7 +87,7 @@ class Message(Base):
     id = Column(Integer, primary_key=True, autoincrement=True)
     conversation_id = Column(Integer, ForeignKey('conversations.id'), nullable=False, index=True)
     role = Column(String(20), nullable=False)  # 'user' or 'assistant'
-    content = Column(Text, nullable=False)
+    content = Column(MessageContent, nullable=False)
     created_at = Column(DateTime, default=datetime.utcnow, nullable=False)
     used_web_search = Column(Boolean, default=False, nullable=False)
     web_sources = Column(Text, nullable=True)  # JSON string of web sources
@@ -278,6 +127,9 @@ class DatabaseManager:
             connect_args={"check_same_thread": False}  # Needed for SQLite
         )
 
+        # Decode compressed message bodies in SQL, see MessageContent
+        event.listen(self.engine, "connect", self._register_functions)
+
         # Create session factory
         self.SessionLocal = sessionmaker(
             autocommit=False,
@@ -287,16 +139,48 @@ class DatabaseManager:
 
         logger.info(f"Database manager initialized: {self.db_path}")
 
+    def _register_functions(self, dbapi_connection, connection_record):
+        """Register nanochat_content() on a new SQLite connection"""
+        try:
+            import nanochat_rust
+        except ImportError:
+            nanochat_rust = None
+
+        db_path = str(self.db_path)
+
+        def nanochat_content(value):
+            if isinstance(value, bytes) and nanochat_rust is not None:
+                return nanochat_rust.decompress_content(db_path, value)
+            return value
+
+        dbapi_connection.create_function("nanochat_content", 1, nanochat_content, deterministic=True)
+
     def init_db(self):
-        """Initialize database schema with migrations"""
+        """
+        Migrate the database to the current schema
+
+        Checksummed, transactional migrations from nanochat_rust; a
+        database with tables is backed up before it is upgraded.
+
+        Raises:
+            RuntimeError: If nanochat_rust isn't installed
+            nanochat_rust.MigrationError: If the database can't be migrated
+        """
+        try:
+            import nanochat_rust
+        except ImportError:
+            raise RuntimeError(
+                "nanochat_rust is required to set up the database; build it from rust/"
+            ) from None
+
         try:
-            # Run migrations
-            migration_manager = MigrationManager(self)
-            migration_manager.migrate()
-            logger.info("Database initialization complete")
-        except SQLAlchemyError as e:
-            logger.error(f"Failed to initialize database: {e}")
+            report = nanochat_rust.migrate_database(str(self.db_path))
+        except nanochat_rust.MigrationError as e:
+            logger.error(f"Failed to migrate database: {e}")
             raise
+        if report.backup_path:
+            logger.info(f"Database backup created: {report.backup_path}")
+        logger.info(f"Database initialization complete: {report!r}")
 
     @contextmanager
     def get_session(self):
@@ -57,11 +57,11 @@ def upgrade(database_manager: DatabaseManager):
             ("What is cognitive behavioral therapy?", "learn", 4),
         ]
 
-        for text, category, display_order in default_prompts:
+        for prompt_text, category, display_order in default_prompts:
             session.execute(text("""
                 INSERT INTO suggested_prompts (text, category, display_order, created_at)
-                VALUES (?, ?, ?, datetime('now'))
-            """), (text, category, display_order))
+                VALUES (:text, :category, :display_order, datetime('now'))
+            """), {"text": prompt_text, "category": category, "display_order": display_order})
 
         session.commit()
         print("Migration v3: Created suggested_prompts table with default prompts")
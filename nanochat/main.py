@@ -120,6 +120,8 @@ class NanoChatApplication(Gtk.Application):
         """Handle stop generation request"""
         logger.info("Stop generation signal received")
         self.stop_generation_flag = True
+        # Drop the connection now rather than at the next chunk
+        self.app_state.cancel_generation()
 
     def send_message_async(self, message: str, use_web_search: bool = False):
         """Send message asynchronously (run from UI thread)"""
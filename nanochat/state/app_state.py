@@ -5,6 +5,7 @@ from nanochat.data import DatabaseManager, ConversationRepository, MessageReposi
 from nanochat.api import NanoGPTClient
 from nanochat.api.model_cache import ModelCache
 from nanochat.config import config
+from nanochat.constants import DEFAULT_CONTEXT_TOKENS, DEFAULT_MAX_TOKENS
 from nanochat.state.conversation_mode import ConversationMode, get_mode_config
 
 logger = logging.getLogger(__name__)
@@ -22,11 +23,21 @@ class ApplicationState:
         self.db = DatabaseManager(config.db_path)
         self.db.init_db()
 
-        # API client (will be initialized when API key is available)
-        self.api_client: NanoGPTClient = None
+        # Rust storage engine, when the extension is built; regenerated and
+        # edited messages become branches only through it
+        self.store = self._open_store()
 
-        # Model cache
-        self.model_cache = ModelCache()
+        # Token usage and cost of each response, recorded through the store
+        self.usage_ledger = self._open_usage_ledger()
+
+        # API client (will be initialized when API key is available):
+        # nanochat_rust.NanoGptClient, or NanoGPTClient without the extension
+        self.api_client = None
+
+        # Available models: nanochat_rust.ModelRegistry, with context sizes
+        # and prices, or the plain ModelCache of ids without the extension
+        self.model_registry = self._open_model_registry()
+        self.model_cache = ModelCache() if self.model_registry is None else None
 
         # Current conversation
         self.current_conversation_id = None
@@ -34,8 +45,90 @@ class ApplicationState:
         # Current conversation mode (default to STANDARD)
         self.current_conversation_mode = ConversationMode.STANDARD
 
+        # Cancellation handle of the in-flight request (Rust client only)
+        self.current_request = None
+
         logger.info("Application state initialized")
 
+    def _open_store(self):
+        """Open the nanochat_rust Store on the database, or None without it"""
+        try:
+            import nanochat_rust
+        except ImportError:
+            return None
+        return nanochat_rust.Store(str(self.db.db_path))
+
+    def _open_model_registry(self):
+        """Open the nanochat_rust ModelRegistry, or None without it"""
+        try:
+            import nanochat_rust
+        except ImportError:
+            return None
+        return nanochat_rust.ModelRegistry()
+
+    def _open_usage_ledger(self):
+        """Open the UsageLedger on the store's database, or None without a store"""
+        if self.store is None:
+            return None
+        import nanochat_rust
+        return nanochat_rust.UsageLedger(str(self.db.db_path))
+
+    def _save_message(self, role: str, content: str, used_web_search: bool = False,
+                      web_sources=None, parent_id: int = None) -> int:
+        """
+        Save a message to the current conversation
+
+        Args:
+            role: Message role
+            content: Message content
+            used_web_search: Whether web search was used
+            web_sources: Web sources, stored as JSON
+            parent_id: Message this replies to (defaults to the active branch tip)
+
+        Returns:
+            ID of the new message
+        """
+        import json
+
+        web_sources = json.dumps(web_sources) if web_sources else None
+        if self.store is not None:
+            return self.store.add_message(
+                self.current_conversation_id,
+                role,
+                content,
+                used_web_search=used_web_search,
+                web_sources=web_sources,
+                parent_id=parent_id
+            )
+
+        with self.db.get_session() as session:
+            msg_repo = MessageRepository(session)
+            msg = msg_repo.create_message(
+                self.current_conversation_id,
+                role,
+                content,
+                used_web_search=used_web_search,
+                web_sources=web_sources
+            )
+            return msg.id
+
+    def _record_usage(self, message_id: int, usage) -> None:
+        """
+        Record what a response cost, if the client reported its usage
+
+        Args:
+            message_id: The saved assistant message
+            usage: Usage from the response stream, or None
+        """
+        if self.usage_ledger is None or usage is None:
+            return
+        try:
+            self.usage_ledger.record(
+                message_id, self.api_client.model, usage, registry=self.model_registry
+            )
+        except Exception as e:
+            logger.error(f"Failed to record usage: {e}")
+
     def init_api_client(self, api_key: str = None, base_url: str = None, model: str = None):
         """Initialize API client with configuration"""
         if not api_key:
@@ -45,13 +138,23 @@ class ApplicationState:
         if not model:
             model = config.model
 
-        self.api_client = NanoGPTClient(
-            api_key=api_key,
-            base_url=base_url,
-            model=model
-        )
+        try:
+            from nanochat_rust import NanoGptClient
+        except ImportError:
+            self.api_client = NanoGPTClient(
+                api_key=api_key,
+                base_url=base_url,
+                model=model
+            )
+        else:
+            # Its requests can be aborted mid-chunk; see cancel_generation
+            self.api_client = NanoGptClient(
+                api_key,
+                base_url=base_url,
+                model=model
+            )
 
-        logger.info("API client initialized")
+        logger.info(f"API client initialized ({type(self.api_client).__name__})")
 
     def create_conversation(self) -> int:
         """Create a new conversation"""
@@ -68,9 +171,12 @@ class ApplicationState:
         logger.info(f"Loaded conversation {conversation_id}")
 
     def get_conversation_messages(self, conversation_id: int) -> list:
-        """Get all messages for a conversation"""
+        """Get the messages on the conversation's active branch"""
         import json
 
+        if self.store is not None:
+            return self.store.get_conversation_messages(conversation_id)
+
         with self.db.get_session() as session:
             msg_repo = MessageRepository(session)
             messages = msg_repo.get_messages(conversation_id)
@@ -87,6 +193,15 @@ class ApplicationState:
                 for msg in messages
             ]
 
+    def get_message_window(self, conversation_id: int):
+        """
+        Get a MessageWindow paging back through a conversation's active
+        branch, or None without the Rust store
+        """
+        if self.store is None:
+            return None
+        return self.store.message_window(conversation_id)
+
     def get_all_conversations(self) -> list:
         """Get all conversations"""
         with self.db.get_session() as session:
@@ -123,16 +238,19 @@ class ApplicationState:
             return None
 
     def delete_conversation(self, conversation_id: int) -> bool:
-        """Delete a conversation"""
-        with self.db.get_session() as session:
-            conv_repo = ConversationRepository(session)
-            success = conv_repo.delete_conversation(conversation_id)
+        """Delete a conversation with its messages and their usage"""
+        if self.store is not None:
+            success = self.store.delete_conversation(conversation_id)
+        else:
+            with self.db.get_session() as session:
+                conv_repo = ConversationRepository(session)
+                success = conv_repo.delete_conversation(conversation_id)
 
-            # Clear current conversation if it was deleted
-            if success and self.current_conversation_id == conversation_id:
-                self.current_conversation_id = None
+        # Clear current conversation if it was deleted
+        if success and self.current_conversation_id == conversation_id:
+            self.current_conversation_id = None
 
-            return success
+        return success
 
     def rename_conversation(self, conversation_id: int, new_title: str) -> bool:
         """Rename a conversation"""
@@ -275,8 +393,6 @@ Assistant: {assistant_msg}"""
         Yields:
             Tuples of (role, content, web_sources) as they arrive
         """
-        import json
-
         if not self.api_client:
             raise ValueError("API client not initialized")
 
@@ -285,21 +401,41 @@ Assistant: {assistant_msg}"""
             self.create_conversation()
 
         # Save user message
-        with self.db.get_session() as session:
-            msg_repo = MessageRepository(session)
-            msg_repo.create_message(
-                self.current_conversation_id,
-                'user',
-                message
-            )
+        self._save_message('user', message)
 
         # Yield user message
         yield ('user', message, None)
 
-        # Get conversation history
-        history = self.get_conversation_messages(self.current_conversation_id)
+        # Get conversation history, trimmed to what the model can take
+        history = self._fit_history(self.get_conversation_messages(self.current_conversation_id))
+
+        # Close it ourselves so a stopped response is saved right away
+        gen = self._stream_response(message, history, use_web_search)
+        try:
+            async for update in gen:
+                yield update
+        finally:
+            await gen.aclose()
+
+    async def _stream_response(self, message: str, history: list,
+                               use_web_search: bool = False, parent_id: int = None):
+        """
+        Stream the answer to a user message and save it
+
+        Reasoning is wrapped in <think> tags. If the stream is cut short
+        (stop button, cancel_generation, an error) whatever arrived is
+        still saved.
+
+        Args:
+            message: User message to answer
+            history: Messages to send before it
+            use_web_search: Whether to use web search
+            parent_id: Message the answer replies to (defaults to the
+                active branch tip)
 
-        # Send to API and stream response
+        Yields:
+            Tuples of ('assistant', content, web_sources) as they arrive
+        """
         response_content = ""
         web_sources = None
         used_web_search = False
@@ -307,15 +443,21 @@ Assistant: {assistant_msg}"""
         # Create generator and ensure it's properly closed
         gen = self.api_client.send_message(
             message=message,
-            conversation_history=history[:-1],  # Exclude the message we just added
+            conversation_history=history,
             use_web_search=use_web_search,
             stream=True
         )
+        # Only Rust client streams can be aborted mid-chunk
+        self.current_request = gen.cancel_handle() if hasattr(gen, 'cancel_handle') else None
 
-        message_saved = False
+        message_id = None
         in_thinking_block = False
         try:
             async for chunk in gen:
+                if message_id is not None:
+                    # Only the usage event follows the one marked done
+                    continue
+
                 to_yield = ""
                 
                 # Handle reasoning content (normalize to <think> tags)
@@ -352,60 +494,108 @@ Assistant: {assistant_msg}"""
 
                 if chunk.done:
                     # Save assistant message WITH web_sources
-                    with self.db.get_session() as session:
-                        msg_repo = MessageRepository(session)
-                        msg_repo.create_message(
-                            self.current_conversation_id,
-                            'assistant',
-                            response_content,
-                            used_web_search=used_web_search,
-                            web_sources=json.dumps(web_sources) if web_sources else None
-                        )
-                    message_saved = True
+                    message_id = self._save_message(
+                        'assistant', response_content, used_web_search, web_sources, parent_id
+                    )
 
                     # Final yield with sources
                     yield ('assistant', None, web_sources)
-                    break
+
+            # Read once the stream has ended (Rust client only)
+            if message_id is not None:
+                self._record_usage(message_id, getattr(gen, 'usage', None))
         finally:
             # Ensure message is saved if generation was interrupted (e.g. stop button)
-            if not message_saved and response_content:
+            if message_id is None and response_content:
                 logger.info("Saving interrupted/partial assistant message")
                 try:
-                    with self.db.get_session() as session:
-                        msg_repo = MessageRepository(session)
-                        msg_repo.create_message(
-                            self.current_conversation_id,
-                            'assistant',
-                            response_content,
-                            used_web_search=used_web_search,
-                            web_sources=json.dumps(web_sources) if web_sources else None
-                        )
+                    self._save_message('assistant', response_content, used_web_search, web_sources, parent_id)
                 except Exception as e:
                     logger.error(f"Failed to save partial message: {e}")
 
+            self.current_request = None
             # Ensure generator is closed to clean up resources
             await gen.aclose()
 
+    def _fit_history(self, messages: list) -> list:
+        """
+        Fit the history into the model's context, leaving room for the reply
+
+        The oldest turns are dropped first, and the current mode's system
+        prompt is put at the start. Without the Rust extension the history
+        is sent whole.
+
+        Args:
+            messages: Messages on the active branch, ending with the user
+                message being sent
+
+        Returns:
+            The messages to send before that user message
+        """
+        try:
+            from nanochat_rust import fit_context
+        except ImportError:
+            return messages[:-1]
+
+        context_tokens = None
+        if self.model_registry is not None:
+            context_tokens = self.model_registry.context_length(self.api_client.model)
+        context_tokens = context_tokens or DEFAULT_CONTEXT_TOKENS
+        # Small contexts keep at least half for the history
+        reply_tokens = min(DEFAULT_MAX_TOKENS, context_tokens // 2)
+        fitted = fit_context(
+            messages,
+            self.api_client.model,
+            max(context_tokens - reply_tokens, 1),
+            system_prompt=self.get_mode_config().system_prompt or None
+        )
+        # The latest user message is always kept last; the client adds it
+        return fitted[:-1]
+
+    def cancel_generation(self) -> bool:
+        """
+        Abort the in-flight API request immediately
+
+        Safe to call from the GTK thread. The response stream then ends and
+        send_message saves the partial assistant message as usual. The
+        Python client has no handle; it stops at the next chunk instead.
+
+        Returns:
+            True if a request was aborted
+        """
+        handle = self.current_request
+        if handle is None:
+            return False
+        return handle.cancel()
+
     async def regenerate_last_response(self):
         """
         Regenerate the last assistant response
 
         This method:
         1. Gets the last user message
-        2. Deletes the last assistant message from database
+        2. Keeps the last assistant message as an alternative branch (with
+           the Rust store) or deletes it from the database
         3. Re-sends the user message to get a new response
 
         Yields:
             Tuples of (role, content, web_sources) as they arrive
         """
-        import json
-
         if not self.api_client:
             raise ValueError("API client not initialized")
 
         if not self.current_conversation_id:
             raise ValueError("No active conversation")
 
+        if self.store is not None:
+            gen = self._regenerate_as_branch()
+            try:
+                async for update in gen:
+                    yield update
+            finally:
+                await gen.aclose()
+            return
+
         # Get conversation history
         messages = self.get_conversation_messages(self.current_conversation_id)
 
@@ -444,48 +634,81 @@ Assistant: {assistant_msg}"""
         # Get updated conversation history (without the deleted assistant message)
         history = self.get_conversation_messages(self.current_conversation_id)
 
-        # Send to API and stream response
-        response_content = ""
-        web_sources = None
-        used_web_search = False
+        gen = self._stream_response(last_user_msg, self._fit_history(history))
+        try:
+            async for update in gen:
+                yield update
+        finally:
+            await gen.aclose()
 
-        # Create generator and ensure it's properly closed
-        gen = self.api_client.send_message(
-            message=last_user_msg,
-            conversation_history=history,
-            use_web_search=False,  # Use same web search setting as before
-            stream=True
+    async def _regenerate_as_branch(self):
+        """
+        Answer the last user message on the active branch again, adding the
+        response as a sibling of the previous one
+
+        Yields:
+            Tuples of (role, content, web_sources) as they arrive
+        """
+        path = self.get_conversation_messages(self.current_conversation_id)
+        user_index = next(
+            (i for i in reversed(range(len(path))) if path[i]['role'] == 'user'),
+            None
         )
+        if user_index is None:
+            raise ValueError("No user message found to regenerate")
+        user_message = path[user_index]
 
+        gen = self._stream_response(
+            user_message['content'],
+            self._fit_history(path[:user_index + 1]),
+            parent_id=user_message['id']
+        )
         try:
-            async for chunk in gen:
-                if chunk.content:
-                    response_content += chunk.content
-                    yield ('assistant', chunk.content, None)
+            async for update in gen:
+                yield update
+        finally:
+            await gen.aclose()
 
-                # Capture web_sources when available
-                if chunk.web_sources:
-                    web_sources = chunk.web_sources
-                    used_web_search = True
+    def edit_message(self, message_id: int, content: str):
+        """
+        Add an edited version of a message as a new branch and show it
 
-                if chunk.done:
-                    # Save new assistant message
-                    with self.db.get_session() as session:
-                        msg_repo = MessageRepository(session)
-                        msg_repo.create_message(
-                            self.current_conversation_id,
-                            'assistant',
-                            response_content,
-                            used_web_search=used_web_search,
-                            web_sources=json.dumps(web_sources) if web_sources else None
-                        )
+        Follow up with regenerate_last_response to answer an edited user
+        message.
 
-                    # Final yield with sources
-                    yield ('assistant', None, web_sources)
-                    break
-        finally:
-            # Ensure generator is closed to clean up resources
-            await gen.aclose()
+        Args:
+            message_id: Message to edit
+            content: New content
+
+        Returns:
+            ID of the new message, or None if editing isn't available
+        """
+        if self.store is None:
+            return None
+        return self.store.edit_message(message_id, content)
+
+    def get_message_alternatives(self, message_id: int) -> list:
+        """
+        Get a message and its alternatives (regenerations and edits)
+
+        Returns:
+            List of message dicts, oldest first
+        """
+        if self.store is None:
+            return []
+        return self.store.get_siblings(message_id)
+
+    def switch_branch(self, message_id: int) -> bool:
+        """
+        Show the branch through a message
+
+        Returns:
+            True if switched, False if the message doesn't exist or
+            branching isn't available
+        """
+        if self.store is None:
+            return False
+        return self.store.switch_branch(message_id)
 
     def get_cached_models(self):
         """
@@ -494,16 +717,23 @@ Assistant: {assistant_msg}"""
         Returns:
             List of model IDs if cache is valid, None otherwise
         """
-        return self.model_cache.get_cached_models()
+        if self.model_registry is None:
+            return self.model_cache.get_cached_models()
+        if not self.model_registry.is_fresh():
+            return None
+        return self.model_registry.ids()
 
-    def cache_models(self, models: list):
+    def get_model_summary(self, model_id: str):
         """
-        Save models to cache
+        Get a one-line description of a model for the model picker
 
-        Args:
-            models: List of model IDs to cache
+        Returns:
+            Context size, prices and capabilities, or None if unknown
         """
-        self.model_cache.save_models(models)
+        if self.model_registry is None:
+            return None
+        info = self.model_registry.get(model_id)
+        return info.summary if info is not None else None
 
     async def fetch_models(self):
         """
@@ -519,10 +749,13 @@ Assistant: {assistant_msg}"""
         if not self.api_client:
             raise ValueError("API client not initialized")
 
-        models = await self.api_client.fetch_models()
-
-        # Cache the models
-        self.model_cache.save_models(models)
+        if self.model_registry is not None:
+            # Full entries, so the registry learns context sizes and prices
+            self.model_registry.update(await self.api_client.fetch_models(detailed=True))
+            models = self.model_registry.ids()
+        else:
+            models = await self.api_client.fetch_models()
+            self.model_cache.save_models(models)
 
         logger.info(f"Fetched and cached {len(models)} models")
         return models
@@ -34,6 +34,9 @@ from nanochat.ui.suggested_prompts import SuggestedPrompts
 from nanochat.ui.thinking_widget import ThinkingWidget
 from nanochat.state.conversation_mode import ConversationMode
 
+# Older messages are requested when the view is scrolled this close to the top
+LOAD_OLDER_THRESHOLD = 200
+
 logger = logging.getLogger(__name__)
 
 
@@ -463,7 +466,8 @@ class ChatView(Gtk.Box):
         'regenerate-requested': (GObject.SIGNAL_RUN_FIRST, None, ()),
         'message-deleted': (GObject.SIGNAL_RUN_FIRST, None, ()),
         'stop-generation': (GObject.SIGNAL_RUN_FIRST, None, ()),
-        'continue-requested': (GObject.SIGNAL_RUN_FIRST, None, ())
+        'continue-requested': (GObject.SIGNAL_RUN_FIRST, None, ()),
+        'load-older-requested': (GObject.SIGNAL_RUN_FIRST, None, ())
     }
 
     def __init__(self):
@@ -493,6 +497,14 @@ class ChatView(Gtk.Box):
         self.messages_box.set_margin_bottom(24)
         self.scrolled.set_child(self.messages_box)
 
+        # Older messages are loaded page by page as the view reaches the top
+        self.has_older_messages = False
+        self._loading_older = False
+        self._bottom_offset = None  # Distance from the bottom to keep while prepending
+        adj = self.scrolled.get_vadjustment()
+        adj.connect("value-changed", self._on_scroll_changed)
+        adj.connect("changed", self._on_scroll_range_changed)
+
         # Add scrolled window to overlay
         self.main_overlay.set_child(self.scrolled)
 
@@ -1059,18 +1071,70 @@ class ChatView(Gtk.Box):
                 last_child = last_child.get_prev_sibling()
         else:
             # Create new message row
-            message_row = MessageRow(role, content, timestamp, web_sources)
-
-            # Connect to message action signals
-            message_row.connect("copy-requested", self._on_copy_requested)
-            message_row.connect("regenerate-requested", self._on_regenerate_requested)
-            message_row.connect("delete-requested", self._on_delete_requested)
-
-            self.messages_box.append(message_row)
+            self.messages_box.append(self._create_message_row(role, content, timestamp, web_sources))
 
         # Scroll to bottom
         GLib.timeout_add(100, self.scroll_to_bottom)
 
+    def _create_message_row(self, role: str, content: str, timestamp: str = None,
+                            web_sources: list = None) -> 'MessageRow':
+        """Create a message row connected to the message action handlers"""
+        message_row = MessageRow(role, content, timestamp, web_sources)
+
+        # Connect to message action signals
+        message_row.connect("copy-requested", self._on_copy_requested)
+        message_row.connect("regenerate-requested", self._on_regenerate_requested)
+        message_row.connect("delete-requested", self._on_delete_requested)
+        return message_row
+
+    def prepend_messages(self, messages: list):
+        """
+        Add older messages above the ones shown, keeping the view in place
+
+        Args:
+            messages: Message dicts, oldest first
+        """
+        self._loading_older = False
+        if not messages:
+            return
+
+        adj = self.scrolled.get_vadjustment()
+        self._bottom_offset = adj.get_upper() - adj.get_value()
+
+        previous = None
+        for msg in messages:
+            message_row = self._create_message_row(
+                msg['role'],
+                msg['content'],
+                msg.get('timestamp'),
+                msg.get('web_sources')
+            )
+            self.messages_box.insert_child_after(message_row, previous)
+            previous = message_row
+
+        # Navigation indexes count rows from the top
+        self.current_message_index = -1
+
+    def set_has_older_messages(self, has_older: bool):
+        """Set whether scrolling to the top should load older messages"""
+        self.has_older_messages = has_older
+        self._loading_older = False
+
+    def _on_scroll_changed(self, adj):
+        """Ask for older messages when scrolled near the top"""
+        if not self.has_older_messages or self._loading_older:
+            return
+        if adj.get_value() <= adj.get_lower() + LOAD_OLDER_THRESHOLD:
+            self._loading_older = True
+            self.emit("load-older-requested")
+
+    def _on_scroll_range_changed(self, adj):
+        """Keep the view still once prepended messages are laid out"""
+        if self._bottom_offset is None:
+            return
+        adj.set_value(adj.get_upper() - self._bottom_offset)
+        self._bottom_offset = None
+
     def _on_copy_requested(self, message_row, content):
         """Handle copy request - show toast notification"""
         self.show_toast("Copied to clipboard")
@@ -1133,6 +1197,8 @@ class ChatView(Gtk.Box):
 
     def show_welcome(self):
         """Show welcome screen (clear messages)"""
+        self.set_has_older_messages(False)
+
         # Remove all messages
         child = self.messages_box.get_first_child()
         while child:
@@ -1144,6 +1210,7 @@ class ChatView(Gtk.Box):
 
     def clear(self):
         """Clear all messages"""
+        self.set_has_older_messages(False)
         child = self.messages_box.get_first_child()
         while child:
             next_child = child.get_next_sibling()
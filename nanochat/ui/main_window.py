@@ -42,6 +42,7 @@ class MainWindow(_BaseWindow):
 
         # State
         self.current_conversation_id = None
+        self.message_window = None  # Pages of the shown conversation (Rust store only)
         self.web_search_enabled = False
         self.app = None  # NanoChatApplication
         self.app_state = None  # ApplicationState
@@ -92,6 +93,7 @@ class MainWindow(_BaseWindow):
         self.chat_view.connect('conversation-mode-changed', self.on_conversation_mode_changed)
         self.chat_view.connect('regenerate-requested', self.on_regenerate_requested)
         self.chat_view.connect('message-deleted', self.on_message_deleted)
+        self.chat_view.connect('load-older-requested', self.on_load_older_requested)
 
         if ADW_AVAILABLE:
             # Use Adw.ToolbarView to wrap content with header bar
@@ -343,7 +345,12 @@ class MainWindow(_BaseWindow):
         self.current_conversation_id = conversation_id
         # Load messages if controller exists
         if self.app_state:
-            messages = self.app_state.get_conversation_messages(conversation_id)
+            # Newest page first; older ones load as the view scrolls up
+            self.message_window = self.app_state.get_message_window(conversation_id)
+            if self.message_window is not None:
+                messages = self.message_window.load_older()
+            else:
+                messages = self.app_state.get_conversation_messages(conversation_id)
             self.chat_view.clear()
             for msg in messages:
                 self.chat_view.add_message(
@@ -352,12 +359,23 @@ class MainWindow(_BaseWindow):
                     msg.get('timestamp'),
                     msg.get('web_sources')  # Include web_sources
                 )
+            self.chat_view.set_has_older_messages(
+                self.message_window is not None and self.message_window.has_more
+            )
 
             # Load web search preference for this conversation
             web_search_enabled = self.app_state.get_web_search_enabled(conversation_id)
             self.chat_view.set_web_search_enabled(web_search_enabled)
             self.web_search_enabled = web_search_enabled
 
+    def on_load_older_requested(self, chat_view):
+        """Show the page of messages before the oldest one on screen"""
+        if self.message_window is None:
+            chat_view.set_has_older_messages(False)
+            return
+        chat_view.prepend_messages(self.message_window.load_older())
+        chat_view.set_has_older_messages(self.message_window.has_more)
+
     def on_conversation_deleted(self, sidebar, conversation_id):
         """Handle conversation deletion"""
         print(f"Deleting conversation: {conversation_id}")
@@ -324,12 +324,12 @@ class SettingsDialog(Gtk.Dialog):
 
     def get_values(self):
         """Get form values"""
-        selected_model = self.model_dropdown.get_active_text()
+        selected_model = self.model_dropdown.get_active_id()
         # If dropdown is empty, fall back to current model
         if not selected_model:
             selected_model = self.selected_model
 
-        selected_title_model = self.title_model_dropdown.get_active_text()
+        selected_title_model = self.title_model_dropdown.get_active_id()
         if not selected_title_model:
             selected_title_model = self.selected_title_model
 
@@ -376,14 +376,14 @@ class SettingsDialog(Gtk.Dialog):
 
     def on_model_changed(self, dropdown):
         """Handle model dropdown change"""
-        model_id = dropdown.get_active_text()
+        model_id = dropdown.get_active_id()
         if model_id:
             self.selected_model = model_id
             logger.debug(f"Model selected: {model_id}")
 
     def on_title_model_changed(self, dropdown):
         """Handle title model dropdown change"""
-        model_id = dropdown.get_active_text()
+        model_id = dropdown.get_active_id()
         if model_id:
             self.selected_title_model = model_id
             logger.debug(f"Title model selected: {model_id}")
@@ -437,21 +437,23 @@ class SettingsDialog(Gtk.Dialog):
             self.available_models = models
             self._populate_model_dropdown()
             self.model_status_label.set_text(f"Loaded {len(models)} models from API")
-
-            # Cache the models
-            if self.app_state:
-                self.app_state.cache_models(models)
         else:
             self.model_status_label.set_text("No models found")
 
+    def _model_label(self, model_id: str) -> str:
+        """Dropdown text for a model: its ID and, when known, its summary"""
+        summary = self.app_state.get_model_summary(model_id) if self.app_state else None
+        return f"{model_id} — {summary}" if summary else model_id
+
     def _populate_model_dropdown(self):
         """Populate the model dropdown with available models"""
         self.model_dropdown.remove_all()
         self.title_model_dropdown.remove_all()
 
         for model in self.available_models:
-            self.model_dropdown.append_text(model)
-            self.title_model_dropdown.append_text(model)
+            label = self._model_label(model)
+            self.model_dropdown.append(model, label)
+            self.title_model_dropdown.append(model, label)
 
         # Select the current model
         if self.selected_model in self.available_models:
@@ -0,0 +1,525 @@
+// Client for the NanoGPT API
+//
+// Mirrors nanochat.api.NanoGPTClient, but all instances share one
+// connection pool and the requests run on the module's tokio runtime.
+// Streamed responses are exposed to asyncio as an async iterator.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use pyo3::exceptions::{PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::AbortHandle;
+
+use super::delta::{StreamDelta, Usage};
+use super::error::{parse_retry_after, ApiError};
+use super::retry::{with_retries, AttemptError, Reporter, RetryPolicy};
+use super::runtime::{awaitable, http, runtime, IntoPyErr};
+use super::sse::SseDecoder;
+use super::{json_to_py, py_bool};
+
+const DEFAULT_BASE_URL: &str = "https://nano-gpt.com/api";
+/// Matches nanochat.constants.DEFAULT_MAX_TOKENS
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+type Item = Result<StreamDelta, ApiError>;
+
+struct Config {
+    api_key: String,
+    base_url: String,
+    /// Limit for getting response headers, and for each gap between chunks
+    timeout: Duration,
+}
+
+impl Config {
+    fn post(&self, path: &str, body: &Value) -> reqwest::RequestBuilder {
+        http()
+            .post(format!("{}{}", self.base_url, path))
+            .bearer_auth(&self.api_key)
+            .json(body)
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        http()
+            .get(format!("{}{}", self.base_url, path))
+            .bearer_auth(&self.api_key)
+    }
+
+    /// Send a request, turning error statuses into errors
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let exchange = async {
+            let response = request.send().await.map_err(ApiError::from_reqwest)?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response);
+            }
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            let body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| format!("HTTP {}", status.as_u16()));
+            Err(ApiError::Status {
+                status: status.as_u16(),
+                body,
+                retry_after,
+            })
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| ApiError::Timeout)?
+    }
+
+    async fn json(&self, response: reqwest::Response) -> Result<Value, ApiError> {
+        let bytes = tokio::time::timeout(self.timeout, response.bytes())
+            .await
+            .map_err(|_| ApiError::Timeout)?
+            .map_err(ApiError::from_reqwest)?;
+        serde_json::from_slice(&bytes).map_err(|e| ApiError::Parse(e.to_string()))
+    }
+}
+
+/// Async client for the NanoGPT chat, web search and models endpoints
+#[pyclass]
+pub struct NanoGptClient {
+    config: Arc<Config>,
+    /// Model used when send_message isn't given one
+    #[pyo3(get, set)]
+    model: String,
+    #[pyo3(get, set)]
+    retry_policy: RetryPolicy,
+    /// Called with a RetryAttempt before each retry, on the loop that made
+    /// the request
+    #[pyo3(get, set)]
+    on_retry: Option<PyObject>,
+}
+
+#[pymethods]
+impl NanoGptClient {
+    #[new]
+    #[pyo3(signature = (
+        api_key,
+        base_url=DEFAULT_BASE_URL,
+        timeout=60.0,
+        model="gpt-4",
+        retry_policy=None,
+        on_retry=None,
+    ))]
+    fn new(
+        api_key: String,
+        base_url: &str,
+        timeout: f64,
+        model: &str,
+        retry_policy: Option<RetryPolicy>,
+        on_retry: Option<PyObject>,
+    ) -> PyResult<Self> {
+        let timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|_| PyValueError::new_err("timeout must be a positive number of seconds"))?;
+        Ok(Self {
+            config: Arc::new(Config {
+                api_key,
+                base_url: base_url.trim_end_matches('/').to_string(),
+                timeout,
+            }),
+            model: model.to_string(),
+            retry_policy: retry_policy.unwrap_or_default(),
+            on_retry,
+        })
+    }
+
+    #[getter]
+    fn base_url(&self) -> String {
+        self.config.base_url.clone()
+    }
+
+    /// Send a message and return an async iterator of StreamDelta
+    ///
+    /// `conversation_history` is a list of dicts with "role" and "content".
+    /// With `use_web_search` the message is sent to /web as a query and the
+    /// answer arrives as one final delta carrying `web_sources`.
+    #[pyo3(signature = (
+        message,
+        conversation_history,
+        use_web_search=false,
+        stream=true,
+        temperature=0.7,
+        max_tokens=DEFAULT_MAX_TOKENS,
+        model=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn send_message(
+        &self,
+        py: Python,
+        message: &str,
+        conversation_history: Vec<&PyDict>,
+        use_web_search: bool,
+        stream: bool,
+        temperature: f64,
+        max_tokens: u32,
+        model: Option<&str>,
+    ) -> PyResult<ChatStream> {
+        let config = Arc::clone(&self.config);
+        let policy = self.retry_policy.clone();
+        let reporter = Reporter::new(py, self.on_retry.as_ref())?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let task = if use_web_search {
+            let query = message.to_string();
+            runtime().spawn(async move {
+                let result = with_retries(&policy, reporter.as_ref(), false, || {
+                    web_search(&config, &query, &sender)
+                })
+                .await;
+                if let Err(e) = result {
+                    let _ = sender.send(Err(e));
+                }
+            })
+        } else {
+            let mut messages = Vec::with_capacity(conversation_history.len() + 1);
+            for entry in conversation_history {
+                messages.push(json!({
+                    "role": required(entry, "role")?,
+                    "content": required(entry, "content")?,
+                }));
+            }
+            messages.push(json!({"role": "user", "content": message}));
+            let mut body = json!({
+                "model": model.unwrap_or(&self.model),
+                "messages": messages,
+                "stream": stream,
+                "temperature": temperature,
+                "max_tokens": max_tokens,
+            });
+            if stream {
+                // Without this, streamed responses carry no token counts
+                body["stream_options"] = json!({"include_usage": true});
+            }
+            runtime().spawn(async move {
+                let result = with_retries(&policy, reporter.as_ref(), false, || {
+                    chat_completions(&config, &body, stream, &sender)
+                })
+                .await;
+                if let Err(e) = result {
+                    let _ = sender.send(Err(e));
+                }
+            })
+        };
+
+        Ok(ChatStream {
+            receiver: Arc::new(Mutex::new(receiver)),
+            handle: CancelHandle::new(task.abort_handle()),
+            usage: Arc::default(),
+        })
+    }
+
+    /// Awaitable list of model IDs from /v1/models
+    ///
+    /// With `detailed`, the model entries themselves, with context sizes,
+    /// pricing and capabilities where the API has them; ModelRegistry.update
+    /// takes them as they are.
+    #[pyo3(signature = (detailed=false))]
+    fn fetch_models(&self, py: Python, detailed: bool) -> PyResult<PyObject> {
+        let config = Arc::clone(&self.config);
+        let policy = self.retry_policy.clone();
+        let reporter = Reporter::new(py, self.on_retry.as_ref())?;
+        let path = if detailed {
+            "/v1/models?detailed=true"
+        } else {
+            "/v1/models"
+        };
+        awaitable(py, async move {
+            let data = with_retries(&policy, reporter.as_ref(), true, || async {
+                let response = config.send(config.get(path)).await?;
+                Ok(config.json(response).await?)
+            })
+            .await?;
+            let models = data
+                .get("data")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            Ok::<_, ApiError>(Models { models, detailed })
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "NanoGptClient(base_url={:?}, model={:?})",
+            self.config.base_url, self.model
+        )
+    }
+}
+
+/// Aborts one request from any thread
+///
+/// The connection is dropped at once, even mid-chunk. The stream then ends
+/// normally after the deltas already received, so callers save partial
+/// content exactly as for a stop between chunks.
+#[pyclass]
+#[derive(Clone)]
+pub struct CancelHandle {
+    task: AbortHandle,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl CancelHandle {
+    /// Abort the request; false if it had already finished or been cancelled
+    fn cancel(&self) -> bool {
+        if self.task.is_finished() || self.cancelled.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        self.task.abort();
+        true
+    }
+
+    fn __call__(&self) -> bool {
+        self.cancel()
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CancelHandle(cancelled={}, finished={})",
+            py_bool(self.cancelled()),
+            py_bool(self.task.is_finished())
+        )
+    }
+}
+
+impl CancelHandle {
+    fn new(task: AbortHandle) -> Self {
+        Self {
+            task,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// Deltas of one in-flight response, consumed with `async for`
+///
+/// Dropping the stream aborts the request.
+#[pyclass]
+pub struct ChatStream {
+    receiver: Arc<Mutex<mpsc::UnboundedReceiver<Item>>>,
+    handle: CancelHandle,
+    /// The latest token counts the server reported
+    usage: Arc<std::sync::Mutex<Option<Usage>>>,
+}
+
+#[pymethods]
+impl ChatStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// A handle that can abort this request from another thread
+    fn cancel_handle(&self) -> CancelHandle {
+        self.handle.clone()
+    }
+
+    fn cancel(&self) -> bool {
+        self.handle.cancel()
+    }
+
+    /// True if the response was cut short by cancel()
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.handle.cancelled()
+    }
+
+    /// Awaitable that aborts the request, like an async generator's aclose
+    fn aclose(&self, py: Python) -> PyResult<PyObject> {
+        self.handle.cancel();
+        awaitable(py, async { Ok::<_, ApiError>(()) })
+    }
+
+    /// Token counts for the response, once the server has sent them
+    ///
+    /// They arrive in a final event after the one marked done, so read
+    /// this after iterating to the end.
+    #[getter]
+    fn usage(&self) -> Option<Usage> {
+        self.usage.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn __anext__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        let receiver = Arc::clone(&self.receiver);
+        let usage = Arc::clone(&self.usage);
+        awaitable(py, async move {
+            match receiver.lock().await.recv().await {
+                Some(Ok(delta)) => {
+                    if let Some(reported) = &delta.usage {
+                        *usage.lock().unwrap_or_else(|e| e.into_inner()) = Some(reported.clone());
+                    }
+                    Ok(delta)
+                }
+                Some(Err(e)) => Err(Next::Failed(e)),
+                None => Err(Next::Exhausted),
+            }
+        })
+        .map(Some)
+    }
+}
+
+impl Drop for ChatStream {
+    fn drop(&mut self) {
+        self.handle.task.abort();
+    }
+}
+
+/// Why no further delta is coming
+enum Next {
+    Failed(ApiError),
+    Exhausted,
+}
+
+impl IntoPyErr for Next {
+    fn into_pyerr(self, py: Python) -> PyErr {
+        match self {
+            Next::Failed(e) => e.into_pyerr(py),
+            Next::Exhausted => PyStopAsyncIteration::new_err(()),
+        }
+    }
+}
+
+fn required(entry: &PyDict, key: &str) -> PyResult<String> {
+    entry
+        .get_item(key)?
+        .ok_or_else(|| PyValueError::new_err(format!("history message is missing {:?}", key)))?
+        .extract()
+}
+
+/// One attempt at a chat completion
+async fn chat_completions(
+    config: &Config,
+    body: &Value,
+    stream: bool,
+    sender: &mpsc::UnboundedSender<Item>,
+) -> Result<(), AttemptError> {
+    let response = config
+        .send(config.post("/v1/chat/completions", body))
+        .await?;
+
+    if !stream {
+        let data = config.json(response).await?;
+        if data.pointer("/choices/0/message").is_none() {
+            return Err(ApiError::Parse("no message in response".to_string()).into());
+        }
+        let mut delta = StreamDelta::from_json(&data).unwrap_or_else(StreamDelta::end);
+        if let Some(error) = delta.error {
+            return Err(ApiError::Server(error).into());
+        }
+        delta.done = true;
+        let _ = sender.send(Ok(delta));
+        return Ok(());
+    }
+
+    let mut committed = false;
+    stream_deltas(config, response, sender, &mut committed)
+        .await
+        .map_err(|error| AttemptError { error, committed })
+}
+
+/// Decode a streamed response, noting once any delta has been sent
+async fn stream_deltas(
+    config: &Config,
+    response: reqwest::Response,
+    sender: &mpsc::UnboundedSender<Item>,
+    committed: &mut bool,
+) -> Result<(), ApiError> {
+    let mut decoder = SseDecoder::new();
+    let mut body = response.bytes_stream();
+    loop {
+        let chunk = tokio::time::timeout(config.timeout, body.next())
+            .await
+            .map_err(|_| ApiError::Timeout)?;
+        let Some(chunk) = chunk else {
+            break;
+        };
+        let chunk = chunk.map_err(ApiError::from_reqwest)?;
+        forward(decoder.push(&chunk), sender, committed)?;
+        if decoder.is_done() {
+            return Ok(());
+        }
+    }
+    forward(decoder.finish(), sender, committed)
+}
+
+/// Pass deltas on; errors the server reports end the stream
+fn forward(
+    deltas: Vec<StreamDelta>,
+    sender: &mpsc::UnboundedSender<Item>,
+    committed: &mut bool,
+) -> Result<(), ApiError> {
+    for delta in deltas {
+        // Undecodable events are skipped, as the Python client did
+        if delta.malformed {
+            continue;
+        }
+        if let Some(error) = delta.error {
+            return Err(ApiError::Server(error));
+        }
+        let _ = sender.send(Ok(delta));
+        *committed = true;
+    }
+    Ok(())
+}
+
+/// One attempt at a web search; the answer is sent only once complete
+async fn web_search(
+    config: &Config,
+    query: &str,
+    sender: &mpsc::UnboundedSender<Item>,
+) -> Result<(), AttemptError> {
+    let body = json!({
+        "query": query,
+        "depth": "standard",
+        "outputType": "sourcedAnswer",
+    });
+    let response = config.send(config.post("/web", &body)).await?;
+    let data = config.json(response).await?;
+    let Some(answer) = data.pointer("/data/answer") else {
+        return Err(ApiError::Server("Unexpected web search response format".to_string()).into());
+    };
+    let delta = StreamDelta {
+        content: answer.as_str().unwrap_or_default().to_string(),
+        web_sources: Some(data.pointer("/data/sources").cloned().unwrap_or(json!([]))),
+        ..StreamDelta::end()
+    };
+    let _ = sender.send(Ok(delta));
+    Ok(())
+}
+
+/// A /v1/models listing, handed to Python as ids or as entries
+struct Models {
+    models: Vec<Value>,
+    detailed: bool,
+}
+
+impl IntoPy<PyObject> for Models {
+    fn into_py(self, py: Python) -> PyObject {
+        if self.detailed {
+            let entries = Value::Array(self.models);
+            // Only fails on objects Python can't build, which JSON never has
+            return json_to_py(py, &entries).unwrap_or_else(|_| py.None());
+        }
+        self.models
+            .iter()
+            .filter_map(|m| m.get("id").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .into_py(py)
+    }
+}
@@ -0,0 +1,415 @@
+// Typed deltas decoded from chat completion chunks
+//
+// Field meanings follow the Python StreamChunk: `done` is set once a
+// finish_reason arrives or the stream ends, and `reasoning` takes
+// `reasoning_content` (OpenAI) before `reasoning` (NanoGPT).
+
+use pyo3::prelude::*;
+use serde_json::Value;
+
+use super::{json_to_py, py_bool, py_optional};
+
+/// One fragment of a streamed tool call; arguments arrive piecewise
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct ToolCallDelta {
+    /// Which tool call the fragment belongs to
+    #[pyo3(get)]
+    pub index: usize,
+    #[pyo3(get)]
+    pub id: Option<String>,
+    #[pyo3(get)]
+    pub name: Option<String>,
+    /// JSON text to append to the call's arguments
+    #[pyo3(get)]
+    pub arguments: String,
+}
+
+#[pymethods]
+impl ToolCallDelta {
+    fn __repr__(&self) -> String {
+        format!(
+            "ToolCallDelta(index={}, id={}, name={}, arguments={:?})",
+            self.index,
+            py_optional(&self.id),
+            py_optional(&self.name),
+            self.arguments
+        )
+    }
+}
+
+/// Token counts reported by the server, usually with the last chunk
+#[pyclass]
+#[derive(Clone, Debug, Default)]
+pub struct Usage {
+    #[pyo3(get)]
+    pub prompt_tokens: u64,
+    #[pyo3(get)]
+    pub completion_tokens: u64,
+    #[pyo3(get)]
+    pub total_tokens: u64,
+    /// Part of completion_tokens spent on reasoning
+    #[pyo3(get)]
+    pub reasoning_tokens: u64,
+}
+
+#[pymethods]
+impl Usage {
+    fn __repr__(&self) -> String {
+        format!(
+            "Usage(prompt_tokens={}, completion_tokens={}, total_tokens={}, reasoning_tokens={})",
+            self.prompt_tokens, self.completion_tokens, self.total_tokens, self.reasoning_tokens
+        )
+    }
+}
+
+impl Usage {
+    pub(crate) fn from_json(usage: &Value) -> Option<Self> {
+        let count = |v: Option<&Value>| v.and_then(Value::as_u64).unwrap_or(0);
+        usage.as_object()?;
+        let prompt_tokens = count(usage.get("prompt_tokens"));
+        let completion_tokens = count(usage.get("completion_tokens"));
+        Some(Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: usage
+                .get("total_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(prompt_tokens + completion_tokens),
+            reasoning_tokens: count(
+                usage
+                    .pointer("/completion_tokens_details/reasoning_tokens")
+                    .or_else(|| usage.get("reasoning_tokens")),
+            ),
+        })
+    }
+}
+
+/// Everything one server event contributed to the response
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct StreamDelta {
+    #[pyo3(get)]
+    pub content: String,
+    #[pyo3(get)]
+    pub reasoning: Option<String>,
+    #[pyo3(get)]
+    pub done: bool,
+    #[pyo3(get)]
+    pub finish_reason: Option<String>,
+    #[pyo3(get)]
+    pub tool_calls: Vec<ToolCallDelta>,
+    #[pyo3(get)]
+    pub usage: Option<Usage>,
+    pub web_sources: Option<Value>,
+    /// Set for server error events and data that isn't valid JSON
+    #[pyo3(get)]
+    pub error: Option<String>,
+    /// SSE event type, "message" unless the server named one
+    #[pyo3(get)]
+    pub event: String,
+    /// Last event ID seen, for resuming the stream
+    #[pyo3(get)]
+    pub id: Option<String>,
+    /// The error is undecodable data rather than one the server reported
+    pub malformed: bool,
+}
+
+impl Default for StreamDelta {
+    fn default() -> Self {
+        Self {
+            content: String::new(),
+            reasoning: None,
+            done: false,
+            finish_reason: None,
+            tool_calls: Vec::new(),
+            usage: None,
+            web_sources: None,
+            error: None,
+            event: "message".to_string(),
+            id: None,
+            malformed: false,
+        }
+    }
+}
+
+#[pymethods]
+impl StreamDelta {
+    #[getter]
+    fn web_sources(&self, py: Python) -> PyResult<Option<PyObject>> {
+        self.web_sources
+            .as_ref()
+            .map(|sources| json_to_py(py, sources))
+            .transpose()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "StreamDelta(content={:?}, reasoning={}, done={}, finish_reason={}, error={})",
+            self.content,
+            py_optional(&self.reasoning),
+            py_bool(self.done),
+            py_optional(&self.finish_reason),
+            py_optional(&self.error)
+        )
+    }
+}
+
+impl StreamDelta {
+    /// Interpret a chunk of either format the server sends: OpenAI-style
+    /// `choices`, or the plain `{content, done}` object. Non-streaming
+    /// responses (`choices[0].message`) are accepted too. Returns None when
+    /// the chunk carries nothing of interest, such as a role-only delta.
+    pub fn from_json(data: &Value) -> Option<Self> {
+        let mut delta = Self::default();
+        if let Some(error) = data.get("error").filter(|e| !e.is_null()) {
+            delta.error = Some(error_message(error));
+            return Some(delta);
+        }
+
+        delta.usage = data.get("usage").and_then(Usage::from_json);
+        delta.web_sources = data.get("web_sources").filter(|s| !s.is_null()).cloned();
+
+        if let Some(choice) = data.get("choices").and_then(|c| c.get(0)) {
+            if let Some(body) = choice.get("delta").or_else(|| choice.get("message")) {
+                delta.content = string_field(body, "content").unwrap_or_default();
+                delta.reasoning = string_field(body, "reasoning_content")
+                    .filter(|r| !r.is_empty())
+                    .or_else(|| string_field(body, "reasoning"))
+                    .filter(|r| !r.is_empty());
+                delta.tool_calls = tool_calls(body);
+            }
+            delta.finish_reason = string_field(choice, "finish_reason");
+            delta.done = delta.finish_reason.is_some();
+        } else if data.get("content").is_some() {
+            delta.content = string_field(data, "content").unwrap_or_default();
+            delta.done = data.get("done").and_then(Value::as_bool).unwrap_or(false);
+        }
+
+        let useful = !delta.content.is_empty()
+            || delta.reasoning.is_some()
+            || delta.done
+            || !delta.tool_calls.is_empty()
+            || delta.usage.is_some()
+            || delta.web_sources.is_some();
+        useful.then_some(delta)
+    }
+
+    /// The terminating `[DONE]` event
+    pub fn end() -> Self {
+        Self {
+            done: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn failure(message: String) -> Self {
+        Self {
+            error: Some(message),
+            ..Self::default()
+        }
+    }
+
+    pub fn malformed(message: String) -> Self {
+        Self {
+            malformed: true,
+            ..Self::failure(message)
+        }
+    }
+}
+
+fn string_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+fn tool_calls(body: &Value) -> Vec<ToolCallDelta> {
+    let Some(calls) = body.get("tool_calls").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    calls
+        .iter()
+        .enumerate()
+        .map(|(position, call)| ToolCallDelta {
+            // Complete messages omit the index; their order is the index
+            index: call
+                .get("index")
+                .and_then(Value::as_u64)
+                .map_or(position, |i| i as usize),
+            id: string_field(call, "id"),
+            name: call.get("function").and_then(|f| string_field(f, "name")),
+            arguments: call
+                .get("function")
+                .and_then(|f| string_field(f, "arguments"))
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Human-readable text of an `error` value, which may be a string or an
+/// object with a message
+pub(crate) fn error_message(error: &Value) -> String {
+    match error {
+        Value::String(s) => s.clone(),
+        _ => error
+            .get("message")
+            .and_then(Value::as_str)
+            .map_or_else(|| error.to_string(), str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn delta(data: Value) -> StreamDelta {
+        StreamDelta::from_json(&data).expect("a useful delta")
+    }
+
+    #[test]
+    fn openai_chunks() {
+        let d = delta(json!({"choices": [{"delta": {"content": "Hel"}, "finish_reason": null}]}));
+        assert_eq!(d.content, "Hel");
+        assert!(!d.done);
+        assert_eq!(d.reasoning, None);
+
+        let d = delta(json!({"choices": [{"delta": {}, "finish_reason": "stop"}]}));
+        assert!(d.done);
+        assert_eq!(d.finish_reason.as_deref(), Some("stop"));
+
+        // Role-only deltas carry nothing
+        assert!(
+            StreamDelta::from_json(&json!({"choices": [{"delta": {"role": "assistant"}}]}))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn reasoning_content_comes_before_reasoning() {
+        let d = delta(json!({"choices": [{"delta": {
+            "reasoning_content": "openai", "reasoning": "nanogpt"
+        }}]}));
+        assert_eq!(d.reasoning.as_deref(), Some("openai"));
+
+        let d = delta(json!({"choices": [{"delta": {"reasoning": "nanogpt"}}]}));
+        assert_eq!(d.reasoning.as_deref(), Some("nanogpt"));
+
+        // Empty strings don't count as reasoning
+        let d = delta(json!({"choices": [{"delta": {
+            "reasoning_content": "", "reasoning": "fallback"
+        }}]}));
+        assert_eq!(d.reasoning.as_deref(), Some("fallback"));
+        assert!(
+            StreamDelta::from_json(&json!({"choices": [{"delta": {"reasoning": ""}}]})).is_none()
+        );
+    }
+
+    #[test]
+    fn plain_and_complete_messages() {
+        let d = delta(json!({"content": "hi", "done": true}));
+        assert_eq!((d.content.as_str(), d.done), ("hi", true));
+        assert!(StreamDelta::from_json(&json!({"content": "", "done": false})).is_none());
+
+        let d = delta(json!({"choices": [{
+            "message": {"content": "whole", "tool_calls": [
+                {"id": "a", "function": {"name": "f", "arguments": "{}"}},
+                {"id": "b", "function": {"name": "g"}}
+            ]},
+            "finish_reason": "tool_calls"
+        }]}));
+        assert_eq!(d.content, "whole");
+        let calls: Vec<(usize, Option<&str>, &str)> = d
+            .tool_calls
+            .iter()
+            .map(|c| (c.index, c.name.as_deref(), c.arguments.as_str()))
+            .collect();
+        assert_eq!(calls, [(0, Some("f"), "{}"), (1, Some("g"), "")]);
+    }
+
+    #[test]
+    fn streamed_tool_call_fragments_keep_their_index() {
+        let d = delta(json!({"choices": [{"delta": {"tool_calls": [
+            {"index": 2, "function": {"arguments": "{\"q\":"}}
+        ]}}]}));
+        assert_eq!(d.tool_calls[0].index, 2);
+        assert_eq!(d.tool_calls[0].id, None);
+        assert_eq!(d.tool_calls[0].arguments, "{\"q\":");
+    }
+
+    #[test]
+    fn usage_only_chunks() {
+        let d = delta(json!({"choices": [], "usage": {
+            "prompt_tokens": 10,
+            "completion_tokens": 7,
+            "completion_tokens_details": {"reasoning_tokens": 3}
+        }}));
+        assert_eq!(d.content, "");
+        assert!(!d.done);
+        let usage = d.usage.unwrap();
+        assert_eq!(
+            (
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ),
+            (10, 7, 17)
+        );
+        assert_eq!(usage.reasoning_tokens, 3);
+
+        let usage = Usage::from_json(&json!({
+            "prompt_tokens": 1, "total_tokens": 5, "reasoning_tokens": 2
+        }))
+        .unwrap();
+        assert_eq!((usage.completion_tokens, usage.total_tokens), (0, 5));
+        assert_eq!(usage.reasoning_tokens, 2);
+        assert!(Usage::from_json(&json!(null)).is_none());
+        assert!(StreamDelta::from_json(&json!({"usage": null})).is_none());
+    }
+
+    #[test]
+    fn web_sources_are_kept() {
+        let d = delta(json!({"web_sources": [{"url": "https://example.com"}]}));
+        assert_eq!(d.web_sources, Some(json!([{"url": "https://example.com"}])));
+        assert!(StreamDelta::from_json(&json!({"web_sources": null})).is_none());
+    }
+
+    #[test]
+    fn error_objects_end_the_delta() {
+        let d = delta(json!({
+            "error": {"message": "rate limited", "code": 429},
+            "choices": [{"delta": {"content": "ignored"}}]
+        }));
+        assert_eq!(d.error.as_deref(), Some("rate limited"));
+        assert_eq!(d.content, "");
+        assert!(!d.malformed);
+
+        assert_eq!(
+            delta(json!({"error": "plain"})).error.as_deref(),
+            Some("plain")
+        );
+        // A null error is no error
+        assert_eq!(
+            delta(json!({"error": null, "content": "fine"})).content,
+            "fine"
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(error_message(&json!("text")), "text");
+        assert_eq!(error_message(&json!({"message": "boom"})), "boom");
+        assert_eq!(error_message(&json!({"code": 500})), "{\"code\":500}");
+        assert_eq!(error_message(&json!(42)), "42");
+    }
+
+    #[test]
+    fn constructors() {
+        assert!(StreamDelta::end().done);
+        let failure = StreamDelta::failure("oops".into());
+        assert_eq!(failure.error.as_deref(), Some("oops"));
+        assert!(!failure.malformed);
+        let malformed = StreamDelta::malformed("bad json".into());
+        assert!(malformed.malformed);
+        assert_eq!(malformed.event, "message");
+    }
+}
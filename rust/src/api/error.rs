@@ -0,0 +1,121 @@
+// API failures and their Python exceptions
+//
+// Errors are raised as the classes in nanochat.api.exceptions so existing
+// `except AuthenticationError` handlers keep working unchanged.
+
+use std::time::{Duration, SystemTime};
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+
+const EXCEPTIONS_MODULE: &str = "nanochat.api.exceptions";
+
+#[derive(Clone, Debug)]
+pub enum ApiError {
+    /// The server answered with a non-success status
+    Status {
+        status: u16,
+        body: String,
+        /// How long the server asked us to wait, from Retry-After
+        retry_after: Option<Duration>,
+    },
+    Timeout,
+    Connection(String),
+    /// The response arrived but couldn't be understood
+    Parse(String),
+    /// An error reported inside an otherwise successful response
+    Server(String),
+}
+
+impl ApiError {
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ApiError::Timeout
+        } else {
+            ApiError::Connection(e.to_string())
+        }
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            ApiError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether trying again could succeed: rate limits, server-side
+    /// failures and network trouble
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Status { status, .. } => {
+                matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+            }
+            ApiError::Timeout | ApiError::Connection(_) => true,
+            ApiError::Parse(_) | ApiError::Server(_) => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.describe().1
+    }
+
+    /// Exception class name and message, as the Python client raised them
+    fn describe(&self) -> (&'static str, String) {
+        match self {
+            ApiError::Status { status: 401, .. } => {
+                ("AuthenticationError", "Invalid API key".to_string())
+            }
+            ApiError::Status { status: 429, .. } => {
+                ("RateLimitError", "Rate limit exceeded".to_string())
+            }
+            ApiError::Status {
+                status: 400, body, ..
+            } => {
+                let message = if body.is_empty() {
+                    "Invalid request".to_string()
+                } else {
+                    body.clone()
+                };
+                ("InvalidRequestError", message)
+            }
+            ApiError::Status { status, body, .. } => {
+                ("APIError", format!("API returned {}: {}", status, body))
+            }
+            ApiError::Timeout => ("TimeoutError", "Request timed out".to_string()),
+            ApiError::Connection(e) => ("ConnectionError", format!("Connection error: {}", e)),
+            ApiError::Parse(e) => ("APIError", format!("Failed to parse response: {}", e)),
+            ApiError::Server(e) => ("APIError", e.clone()),
+        }
+    }
+
+    pub fn into_pyerr(self, py: Python) -> PyErr {
+        let (class, message) = self.describe();
+        let raised = py
+            .import(EXCEPTIONS_MODULE)
+            .and_then(|module| module.getattr(class))
+            .and_then(|class| class.call1((message.clone(), self.status_code())));
+        match raised {
+            Ok(exception) => PyErr::from_value(exception),
+            // Outside the application, e.g. when the module is used alone
+            Err(_) => PyRuntimeError::new_err(message),
+        }
+    }
+}
+
+/// Parse a Retry-After value: delay seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means "now"
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
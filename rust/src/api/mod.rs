@@ -0,0 +1,103 @@
+// NanoGPT API support: HTTP client, retries and stream decoding
+
+mod client;
+mod delta;
+mod error;
+mod retry;
+mod runtime;
+mod sse;
+
+use std::fmt::Display;
+
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+use serde_json::Value;
+
+pub use client::{CancelHandle, ChatStream, NanoGptClient};
+pub use delta::{StreamDelta, ToolCallDelta, Usage};
+pub use retry::{RetryAttempt, RetryPolicy};
+pub use sse::SseDecoder;
+
+/// `True`/`False`, for reprs
+pub(crate) fn py_bool(value: bool) -> &'static str {
+    if value {
+        "True"
+    } else {
+        "False"
+    }
+}
+
+/// A quoted string or `None`, for reprs
+pub(crate) fn py_optional(value: &Option<String>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "None".to_string(), |v| format!("{:?}", v))
+}
+
+/// A number or `None`, for reprs
+pub(crate) fn py_number(value: Option<impl Display>) -> String {
+    value.map_or_else(|| "None".to_string(), |v| v.to_string())
+}
+
+/// Convert parsed JSON into the equivalent Python object
+pub(crate) fn json_to_py(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                i.into_py(py)
+            } else if let Some(u) = n.as_u64() {
+                u.into_py(py)
+            } else {
+                n.as_f64().unwrap_or_default().into_py(py)
+            }
+        }
+        Value::String(s) => s.into_py(py),
+        Value::Array(items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(json_to_py(py, item)?)?;
+            }
+            list.into_py(py)
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, json_to_py(py, item)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+/// Convert a Python object built from JSON (dicts, lists, scalars) back
+/// into JSON; anything else becomes its string form
+pub(crate) fn py_to_json(value: &PyAny) -> PyResult<Value> {
+    Ok(if value.is_none() {
+        Value::Null
+    } else if let Ok(b) = value.downcast::<PyBool>() {
+        Value::Bool(b.is_true())
+    } else if let Ok(i) = value.downcast::<PyLong>() {
+        match i.extract::<i64>() {
+            Ok(i) => Value::from(i),
+            Err(_) => Value::from(i.extract::<f64>()?),
+        }
+    } else if let Ok(f) = value.downcast::<PyFloat>() {
+        serde_json::Number::from_f64(f.value()).map_or(Value::Null, Value::Number)
+    } else if let Ok(s) = value.downcast::<PyString>() {
+        Value::String(s.to_str()?.to_string())
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        let mut map = serde_json::Map::with_capacity(dict.len());
+        for (key, item) in dict {
+            map.insert(key.str()?.to_str()?.to_string(), py_to_json(item)?);
+        }
+        Value::Object(map)
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        Value::Array(list.iter().map(py_to_json).collect::<PyResult<_>>()?)
+    } else if let Ok(tuple) = value.downcast::<PyTuple>() {
+        Value::Array(tuple.iter().map(py_to_json).collect::<PyResult<_>>()?)
+    } else {
+        Value::String(value.str()?.to_str()?.to_string())
+    })
+}
@@ -0,0 +1,332 @@
+// Retrying failed API calls
+//
+// Delays grow exponentially with random jitter so clients that failed
+// together don't retry together. A Retry-After from the server is a lower
+// bound on the delay. Requests that aren't idempotent are only retried
+// while nothing from the response has reached the caller yet.
+
+use std::future::Future;
+use std::time::Duration;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use rand::Rng;
+
+use super::error::ApiError;
+use super::runtime::LoopHandle;
+
+/// The longest max_delay a policy accepts, in seconds
+const MAX_DELAY_LIMIT: f64 = 3600.0;
+
+/// When and how often to retry
+///
+/// Read-only once made, so every policy has passed the checks in `new()`.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retrying
+    #[pyo3(get)]
+    pub max_attempts: u32,
+    /// Delay before the first retry, in seconds
+    #[pyo3(get)]
+    pub base_delay: f64,
+    /// Upper bound on the delay; a longer Retry-After is not waited for
+    #[pyo3(get)]
+    pub max_delay: f64,
+    /// Fraction of each delay that is randomised, from 0 to 1
+    #[pyo3(get)]
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: 1.0,
+            max_delay: 30.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+#[pymethods]
+impl RetryPolicy {
+    #[new]
+    #[pyo3(signature = (max_attempts=4, base_delay=1.0, max_delay=30.0, jitter=0.5))]
+    fn new(max_attempts: u32, base_delay: f64, max_delay: f64, jitter: f64) -> PyResult<Self> {
+        Self::checked(max_attempts, base_delay, max_delay, jitter).map_err(PyValueError::new_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RetryPolicy(max_attempts={}, base_delay={}, max_delay={}, jitter={})",
+            self.max_attempts, self.base_delay, self.max_delay, self.jitter
+        )
+    }
+}
+
+impl RetryPolicy {
+    /// A policy with these settings, or why they are invalid
+    pub fn checked(
+        max_attempts: u32,
+        base_delay: f64,
+        max_delay: f64,
+        jitter: f64,
+    ) -> Result<Self, &'static str> {
+        if max_attempts == 0 {
+            return Err("max_attempts must be at least 1");
+        }
+        if !(base_delay.is_finite() && max_delay.is_finite() && jitter.is_finite()) {
+            return Err("delays and jitter must be finite numbers");
+        }
+        if base_delay < 0.0 || max_delay < 0.0 {
+            return Err("delays must not be negative");
+        }
+        if max_delay > MAX_DELAY_LIMIT {
+            return Err("max_delay must be at most 3600 seconds");
+        }
+        Ok(Self {
+            max_attempts,
+            base_delay,
+            max_delay,
+            jitter: jitter.clamp(0.0, 1.0),
+        })
+    }
+
+    /// How long to wait before attempt `next` (2 for the first retry), or
+    /// None to give up
+    pub fn delay(&self, next: u32, error: &ApiError) -> Option<Duration> {
+        if next > self.max_attempts || !error.is_transient() {
+            return None;
+        }
+        let max = self.max_delay.max(0.0);
+        let backoff = (self.base_delay.max(0.0) * 2f64.powi(next as i32 - 2)).min(max);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let mut delay = backoff * (1.0 - jitter * rand::thread_rng().gen::<f64>());
+
+        if let Some(after) = error.retry_after() {
+            let after = after.as_secs_f64();
+            if after > max {
+                return None;
+            }
+            delay = delay.max(after);
+        }
+        Duration::try_from_secs_f64(delay).ok()
+    }
+}
+
+/// Sent to the client's on_retry callback before each retry
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct RetryAttempt {
+    /// The attempt about to be made, 2 for the first retry
+    #[pyo3(get)]
+    pub attempt: u32,
+    #[pyo3(get)]
+    pub max_attempts: u32,
+    /// Seconds until the attempt
+    #[pyo3(get)]
+    pub delay: f64,
+    /// Why the previous attempt failed
+    #[pyo3(get)]
+    pub error: String,
+    #[pyo3(get)]
+    pub status_code: Option<u16>,
+}
+
+#[pymethods]
+impl RetryAttempt {
+    /// Short text for the UI, e.g. "retrying in 4s (attempt 2 of 4)"
+    fn __str__(&self) -> String {
+        format!(
+            "retrying in {}s (attempt {} of {})",
+            self.delay.ceil(),
+            self.attempt,
+            self.max_attempts
+        )
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "RetryAttempt(attempt={}, max_attempts={}, delay={:.2}, error={:?})",
+            self.attempt, self.max_attempts, self.delay, self.error
+        )
+    }
+}
+
+/// A failed attempt
+pub struct AttemptError {
+    pub error: ApiError,
+    /// Part of the response was already delivered, so a retry would
+    /// duplicate it
+    pub committed: bool,
+}
+
+impl From<ApiError> for AttemptError {
+    fn from(error: ApiError) -> Self {
+        Self {
+            error,
+            committed: false,
+        }
+    }
+}
+
+/// Where retry attempts are reported: a Python callable run on the loop
+/// that made the request
+#[derive(Clone)]
+pub struct Reporter {
+    callback: PyObject,
+    event_loop: LoopHandle,
+}
+
+impl Reporter {
+    /// A reporter for `callback`, if one is set
+    pub fn new(py: Python, callback: Option<&PyObject>) -> PyResult<Option<Self>> {
+        let Some(callback) = callback.filter(|c| !c.is_none(py)) else {
+            return Ok(None);
+        };
+        let (_, event_loop) = LoopHandle::current(py)?;
+        Ok(Some(Self {
+            callback: callback.clone_ref(py),
+            event_loop,
+        }))
+    }
+
+    fn report(&self, attempt: RetryAttempt) {
+        let callback = self.callback.clone();
+        self.event_loop.call_soon(move |py| {
+            callback.call1(py, (attempt,))?;
+            Ok(())
+        });
+    }
+}
+
+/// Run `attempt` until it succeeds or the policy gives up
+///
+/// With `idempotent` false, failures after part of the response was
+/// delivered are returned as they are.
+pub async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    reporter: Option<&Reporter>,
+    idempotent: bool,
+    mut attempt: F,
+) -> Result<T, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, AttemptError>>,
+{
+    let mut number = 1;
+    loop {
+        let failure = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(failure) => failure,
+        };
+        if failure.committed && !idempotent {
+            return Err(failure.error);
+        }
+        number += 1;
+        let Some(delay) = policy.delay(number, &failure.error) else {
+            return Err(failure.error);
+        };
+        if let Some(reporter) = reporter {
+            reporter.report(RetryAttempt {
+                attempt: number,
+                max_attempts: policy.max_attempts,
+                delay: delay.as_secs_f64(),
+                error: failure.error.message(),
+                status_code: failure.error.status_code(),
+            });
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16, retry_after: Option<u64>) -> ApiError {
+        ApiError::Status {
+            status,
+            body: String::new(),
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+    }
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy::checked(4, 1.0, 5.0, jitter).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = policy(0.0);
+        let delays: Vec<f64> = (2..=4)
+            .map(|n| policy.delay(n, &ApiError::Timeout).unwrap().as_secs_f64())
+            .collect();
+        assert_eq!(delays, [1.0, 2.0, 4.0]);
+        assert!(policy.delay(5, &ApiError::Timeout).is_none());
+
+        let capped = RetryPolicy::checked(10, 1.0, 5.0, 0.0).unwrap();
+        assert_eq!(
+            capped.delay(8, &ApiError::Timeout),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let policy = policy(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(3, &ApiError::Timeout).unwrap().as_secs_f64();
+            assert!((1.0..=2.0).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let policy = policy(0.0);
+        for code in [408, 429, 500, 502, 503, 504] {
+            assert!(policy.delay(2, &status(code, None)).is_some(), "{}", code);
+        }
+        for error in [
+            status(400, None),
+            status(401, None),
+            status(404, None),
+            ApiError::Parse("bad".into()),
+            ApiError::Server("no".into()),
+        ] {
+            assert!(policy.delay(2, &error).is_none(), "{:?}", error);
+        }
+        assert!(policy
+            .delay(2, &ApiError::Connection("reset".into()))
+            .is_some());
+    }
+
+    #[test]
+    fn retry_after_is_a_lower_bound_within_the_cap() {
+        let policy = policy(0.0);
+        assert_eq!(
+            policy.delay(2, &status(429, Some(3))),
+            Some(Duration::from_secs(3))
+        );
+        // Shorter than the backoff: the backoff wins
+        assert_eq!(
+            policy.delay(4, &status(503, Some(1))),
+            Some(Duration::from_secs(4))
+        );
+        // Longer than we are willing to wait: give up
+        assert!(policy.delay(2, &status(429, Some(60))).is_none());
+    }
+
+    #[test]
+    fn new_validates() {
+        assert!(RetryPolicy::checked(0, 1.0, 5.0, 0.5).is_err());
+        assert!(RetryPolicy::checked(3, -1.0, 5.0, 0.5).is_err());
+        assert!(RetryPolicy::checked(3, 1.0, f64::NAN, 0.5).is_err());
+        assert!(RetryPolicy::checked(3, 1.0, f64::INFINITY, 0.5).is_err());
+        assert!(RetryPolicy::checked(3, f64::NAN, 5.0, 0.5).is_err());
+        assert!(RetryPolicy::checked(3, 1.0, 5.0, f64::NAN).is_err());
+        assert!(RetryPolicy::checked(3, 1.0, MAX_DELAY_LIMIT + 1.0, 0.5).is_err());
+        assert_eq!(RetryPolicy::checked(3, 1.0, 5.0, 7.0).unwrap().jitter, 1.0);
+    }
+}
@@ -0,0 +1,201 @@
+// Running Rust futures for asyncio callers
+//
+// Requests run on a small tokio runtime owned by the module. Python gets a
+// plain asyncio future from its own running loop. Worker threads never
+// touch the interpreter: results are queued as jobs and the loop is woken
+// through a socket it watches with add_reader, so futures are resolved and
+// callbacks run on the loop's own thread. This keeps shutdown safe and needs no asyncio
+// binding crate.
+
+use std::future::Future;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use pyo3::exceptions::PyIOError;
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use tokio::runtime::Runtime;
+
+use super::error::ApiError;
+
+/// Idle connections are kept this long for reuse
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("nanochat-http")
+            .enable_all()
+            .build()
+            .expect("failed to start the HTTP runtime")
+    })
+}
+
+/// The connection pool shared by every client
+pub fn http() -> &'static reqwest::Client {
+    static HTTP: OnceLock<reqwest::Client> = OnceLock::new();
+    HTTP.get_or_init(|| {
+        reqwest::Client::builder()
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .build()
+            .expect("failed to build the HTTP client")
+    })
+}
+
+/// Failures that surface in Python as an exception on the future
+pub trait IntoPyErr {
+    fn into_pyerr(self, py: Python) -> PyErr;
+}
+
+impl IntoPyErr for ApiError {
+    fn into_pyerr(self, py: Python) -> PyErr {
+        ApiError::into_pyerr(self, py)
+    }
+}
+
+/// Work to run on an event loop's thread
+type Job = Box<dyn FnOnce(Python) -> PyResult<()> + Send>;
+
+/// The part of a Notifier that worker threads hold
+struct Shared {
+    jobs: Mutex<Vec<Job>>,
+    wake: UnixStream,
+}
+
+/// Lets worker threads run code on one event loop's thread
+#[derive(Clone)]
+pub struct LoopHandle {
+    shared: Arc<Shared>,
+}
+
+impl LoopHandle {
+    /// The handle of the running loop, or outside a coroutine of the
+    /// thread's current loop, along with the loop itself
+    pub fn current(py: Python<'_>) -> PyResult<(&PyAny, Self)> {
+        let asyncio = py.import("asyncio")?;
+        let event_loop = asyncio
+            .call_method0("get_running_loop")
+            .or_else(|_| asyncio.call_method0("get_event_loop"))?;
+        let notifier = notifier_for(py, event_loop)?;
+        let shared = Arc::clone(&notifier.borrow().shared);
+        Ok((event_loop, Self { shared }))
+    }
+
+    /// Queue `job` for the loop; safe to call from any thread
+    pub fn call_soon(&self, job: impl FnOnce(Python) -> PyResult<()> + Send + 'static) {
+        self.shared
+            .jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Box::new(job));
+        // A full socket already has a wake-up pending
+        let _ = (&self.shared.wake).write(&[1]);
+    }
+}
+
+/// Owns the socket an event loop watches for queued jobs
+#[pyclass]
+struct Notifier {
+    shared: Arc<Shared>,
+    reader: UnixStream,
+}
+
+#[pymethods]
+impl Notifier {
+    /// Reader callback: run every queued job
+    ///
+    /// A failing job doesn't stop the rest; the first error is raised to
+    /// the loop's exception handler afterwards.
+    fn drain(&self, py: Python) -> PyResult<()> {
+        let mut buf = [0u8; 256];
+        while matches!((&self.reader).read(&mut buf), Ok(n) if n > 0) {}
+
+        let jobs = std::mem::take(&mut *self.shared.jobs.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut first_error = None;
+        for job in jobs {
+            if let Err(e) = job(py) {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+impl Notifier {
+    fn new() -> std::io::Result<Self> {
+        let (reader, wake) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        wake.set_nonblocking(true)?;
+        Ok(Self {
+            shared: Arc::new(Shared {
+                jobs: Mutex::new(Vec::new()),
+                wake,
+            }),
+            reader,
+        })
+    }
+}
+
+/// The notifier of `event_loop`, created and registered on first use
+fn notifier_for<'py>(py: Python<'py>, event_loop: &'py PyAny) -> PyResult<&'py PyCell<Notifier>> {
+    static NOTIFIERS: GILOnceCell<PyObject> = GILOnceCell::new();
+    let notifiers = NOTIFIERS
+        .get_or_try_init(py, || {
+            PyResult::Ok(
+                py.import("weakref")?
+                    .call_method0("WeakKeyDictionary")?
+                    .into(),
+            )
+        })?
+        .as_ref(py);
+
+    let existing = notifiers.call_method1("get", (event_loop,))?;
+    if !existing.is_none() {
+        return Ok(existing.downcast()?);
+    }
+    let notifier = Notifier::new().map_err(|e| PyIOError::new_err(e.to_string()))?;
+    let fd = notifier.reader.as_raw_fd();
+    let notifier = PyCell::new(py, notifier)?;
+    event_loop.call_method1("add_reader", (fd, notifier.getattr("drain")?))?;
+    notifiers.set_item(event_loop, notifier)?;
+    Ok(notifier)
+}
+
+/// Spawn `task` and return an asyncio future for its result
+///
+/// The future belongs to the loop LoopHandle::current picks, so
+/// `loop.run_until_complete(...)` works outside a coroutine too.
+pub fn awaitable<F, T, E>(py: Python, task: F) -> PyResult<PyObject>
+where
+    F: Future<Output = Result<T, E>> + Send + 'static,
+    T: IntoPy<PyObject> + Send + 'static,
+    E: IntoPyErr + Send + 'static,
+{
+    let (event_loop, handle) = LoopHandle::current(py)?;
+    let future: PyObject = event_loop.call_method0("create_future")?.into();
+
+    let target = future.clone_ref(py);
+    runtime().spawn(async move {
+        let result = task.await;
+        handle.call_soon(move |py| {
+            let target = target.as_ref(py);
+            // Cancelled from Python in the meantime
+            if target.call_method0("done")?.is_true()? {
+                return Ok(());
+            }
+            match result {
+                Ok(value) => target.call_method1("set_result", (value.into_py(py),))?,
+                Err(e) => {
+                    target.call_method1("set_exception", (e.into_pyerr(py).into_value(py),))?
+                }
+            };
+            Ok(())
+        });
+    });
+    Ok(future)
+}
@@ -0,0 +1,294 @@
+// Server-sent event decoding for streamed chat completions
+//
+// Follows the WHATWG event-stream rules: lines end in CR, LF or CRLF,
+// consecutive `data:` lines are joined with newlines, and an event is
+// dispatched on a blank line. Lines are buffered as bytes and only decoded
+// once complete, so a UTF-8 sequence split across chunks comes out whole.
+
+use pyo3::prelude::*;
+use serde_json::Value;
+
+use super::delta::{error_message, StreamDelta};
+
+/// How much of unparseable data to quote in the error
+const ERROR_PREVIEW_CHARS: usize = 200;
+
+/// Incremental decoder turning raw response bytes into typed deltas
+#[pyclass]
+#[derive(Default)]
+pub struct SseDecoder {
+    /// Bytes of the current, unterminated line
+    line: Vec<u8>,
+    /// The last byte was CR, so a following LF belongs to the same break
+    after_cr: bool,
+    /// A byte-order mark is only skipped at the very start
+    started: bool,
+    data: String,
+    event: String,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+    done: bool,
+}
+
+#[pymethods]
+impl SseDecoder {
+    #[new]
+    fn py_new() -> Self {
+        Self::default()
+    }
+
+    /// Add response bytes and return the deltas of completed events
+    fn feed(&mut self, data: &[u8]) -> Vec<StreamDelta> {
+        self.push(data)
+    }
+
+    /// End of body: dispatch a final event the server didn't terminate
+    #[pyo3(name = "finish")]
+    fn py_finish(&mut self) -> Vec<StreamDelta> {
+        self.finish()
+    }
+
+    /// The `id:` of the last event, to send as Last-Event-ID on reconnect
+    #[getter]
+    fn last_event_id(&self) -> Option<String> {
+        self.last_event_id.clone()
+    }
+
+    /// Reconnection delay in milliseconds requested by the server
+    #[getter]
+    fn retry(&self) -> Option<u64> {
+        self.retry
+    }
+
+    /// True once `[DONE]` has been received
+    #[getter]
+    fn done(&self) -> bool {
+        self.done
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<StreamDelta> {
+        let mut out = Vec::new();
+        for &byte in bytes {
+            let after_cr = std::mem::take(&mut self.after_cr);
+            match byte {
+                b'\n' if after_cr => {}
+                b'\n' => self.end_line(&mut out),
+                b'\r' => {
+                    self.after_cr = true;
+                    self.end_line(&mut out);
+                }
+                _ => self.line.push(byte),
+            }
+        }
+        out
+    }
+
+    pub fn finish(&mut self) -> Vec<StreamDelta> {
+        let mut out = Vec::new();
+        if !self.line.is_empty() {
+            self.end_line(&mut out);
+        }
+        self.dispatch(&mut out);
+        self.after_cr = false;
+        out
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn retry_ms(&self) -> Option<u64> {
+        self.retry
+    }
+
+    fn end_line(&mut self, out: &mut Vec<StreamDelta>) {
+        let bytes = std::mem::take(&mut self.line);
+        let mut line = String::from_utf8_lossy(&bytes);
+        if !self.started {
+            self.started = true;
+            if let Some(rest) = line.strip_prefix('\u{feff}') {
+                line = rest.to_string().into();
+            }
+        }
+
+        if line.is_empty() {
+            self.dispatch(out);
+            return;
+        }
+        if line.starts_with(':') {
+            // Comment, typically a keep-alive
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_ref(), ""),
+        };
+        match field {
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "event" => self.event = value.to_string(),
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self, out: &mut Vec<StreamDelta>) {
+        let event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() || self.done {
+            return;
+        }
+        data.pop();
+        let event = if event.is_empty() {
+            "message".to_string()
+        } else {
+            event
+        };
+
+        let start = out.len();
+        if data.trim() == "[DONE]" {
+            self.done = true;
+            out.push(StreamDelta::end());
+        } else if event == "error" {
+            let message = serde_json::from_str::<Value>(&data)
+                .map(|v| error_message(v.get("error").unwrap_or(&v)))
+                .unwrap_or(data);
+            out.push(StreamDelta::failure(message));
+        } else {
+            match serde_json::from_str::<Value>(&data) {
+                Ok(value) => out.extend(StreamDelta::from_json(&value)),
+                // Some servers omit the blank line between events, which
+                // glues several JSON objects into one data field
+                Err(_) if data.contains('\n') && lines_are_json(&data) => {
+                    for line in data.lines().filter(|l| !l.trim().is_empty()) {
+                        if let Ok(value) = serde_json::from_str::<Value>(line) {
+                            out.extend(StreamDelta::from_json(&value));
+                        }
+                    }
+                }
+                Err(e) => {
+                    let preview: String = data.chars().take(ERROR_PREVIEW_CHARS).collect();
+                    out.push(StreamDelta::malformed(format!(
+                        "Malformed event data ({}): {}",
+                        e, preview
+                    )));
+                }
+            }
+        }
+
+        for delta in &mut out[start..] {
+            delta.event = event.clone();
+            delta.id = self.last_event_id.clone();
+        }
+    }
+}
+
+fn lines_are_json(data: &str) -> bool {
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .all(|l| serde_json::from_str::<Value>(l).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = "data: {\"choices\":[{\"delta\":{\"content\":\"Hé\"}}]}\r\n\r\n\
+        : keep-alive\n\n\
+        id: 7\nevent: message\n\
+        data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"hmm\",\"content\":\"llo\"}}]}\n\n\
+        data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}],\
+        \"usage\":{\"prompt_tokens\":3,\"completion_tokens\":2}}\r\r\
+        data: [DONE]\n\n";
+
+    fn decode_in_chunks(bytes: &[u8], size: usize) -> Vec<StreamDelta> {
+        let mut decoder = SseDecoder::new();
+        let mut deltas: Vec<StreamDelta> = bytes
+            .chunks(size)
+            .flat_map(|chunk| decoder.push(chunk))
+            .collect();
+        deltas.extend(decoder.finish());
+        deltas
+    }
+
+    #[test]
+    fn any_chunking_gives_the_same_deltas() {
+        let bytes = STREAM.as_bytes();
+        for size in 1..=bytes.len() {
+            let deltas = decode_in_chunks(bytes, size);
+            let content: String = deltas.iter().map(|d| d.content.as_str()).collect();
+            assert_eq!(content, "Héllo", "chunk size {}", size);
+            assert_eq!(deltas.len(), 4);
+            assert_eq!(deltas[1].reasoning.as_deref(), Some("hmm"));
+            assert_eq!(deltas[1].id.as_deref(), Some("7"));
+            assert_eq!(deltas[2].finish_reason.as_deref(), Some("stop"));
+            assert_eq!(deltas[2].usage.as_ref().unwrap().total_tokens, 5);
+            assert!(deltas[3].done);
+        }
+    }
+
+    #[test]
+    fn multi_line_data_is_joined() {
+        let deltas = decode_in_chunks(b"data: {\"content\":\ndata: \"a\", \"done\": true}\n\n", 4);
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].content, "a");
+        assert!(deltas[0].done);
+    }
+
+    #[test]
+    fn events_missing_blank_lines_are_split() {
+        let deltas = decode_in_chunks(
+            b"data: {\"content\":\"a\"}\ndata: {\"content\":\"b\"}\n\n",
+            3,
+        );
+        let content: Vec<&str> = deltas.iter().map(|d| d.content.as_str()).collect();
+        assert_eq!(content, ["a", "b"]);
+    }
+
+    #[test]
+    fn unterminated_final_event_is_flushed_by_finish() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"data: {\"content\":\"tail\"}").is_empty());
+        let deltas = decoder.finish();
+        assert_eq!(deltas[0].content, "tail");
+    }
+
+    #[test]
+    fn errors_and_malformed_data() {
+        let deltas = decode_in_chunks(
+            b"event: error\ndata: {\"error\":{\"message\":\"rate limited\"}}\n\ndata: {oops\n\n",
+            5,
+        );
+        assert_eq!(deltas[0].error.as_deref(), Some("rate limited"));
+        assert!(!deltas[0].malformed);
+        assert!(deltas[1].malformed);
+        assert!(deltas[1].error.as_ref().unwrap().contains("{oops"));
+    }
+
+    #[test]
+    fn bom_retry_and_events_after_done() {
+        let mut decoder = SseDecoder::new();
+        let deltas = decoder.push(
+            "\u{feff}retry: 1500\ndata: [DONE]\n\ndata: {\"content\":\"late\"}\n\n".as_bytes(),
+        );
+        assert_eq!(decoder.retry_ms(), Some(1500));
+        assert!(decoder.is_done());
+        assert_eq!(deltas.len(), 1);
+        assert!(deltas[0].done);
+    }
+}
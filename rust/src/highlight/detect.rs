@@ -0,0 +1,288 @@
+// Language detection for code blocks without an info string
+//
+// Cheap signal counting over a few dozen tell-tale tokens. It only has to
+// tell the languages people usually paste into a chat apart; anything
+// unconvincing is left as plain text rather than coloured wrongly.
+
+/// Minimum score before a guess is trusted
+const MIN_SCORE: u32 = 3;
+
+/// A pattern starting with '^' must begin a (trimmed) line
+const SIGNALS: &[(&str, &[(&str, u32)])] = &[
+    (
+        "rs",
+        &[
+            ("fn ", 2),
+            ("let mut ", 3),
+            ("^use std", 3),
+            ("^impl ", 3),
+            ("pub fn ", 3),
+            ("&self", 2),
+            ("&mut ", 2),
+            ("println!", 3),
+            ("::new(", 1),
+            ("-> ", 1),
+            ("Option<", 2),
+            ("Result<", 2),
+            ("#[derive", 3),
+            ("^match ", 1),
+        ],
+    ),
+    (
+        "py",
+        &[
+            ("^def ", 3),
+            ("^import ", 1),
+            ("^from ", 1),
+            (" import ", 1),
+            ("self.", 1),
+            ("^elif ", 3),
+            ("print(", 1),
+            ("None", 1),
+            ("__init__", 3),
+            ("^class ", 1),
+            ("):\n", 2),
+            ("^@", 1),
+            (" is not ", 2),
+            ("^if __name__", 3),
+        ],
+    ),
+    (
+        "js",
+        &[
+            ("^const ", 2),
+            ("^let ", 1),
+            ("function ", 2),
+            ("=> ", 2),
+            ("console.log", 3),
+            ("require(", 3),
+            ("^export ", 2),
+            ("===", 3),
+            ("^import ", 1),
+            ("document.", 2),
+            ("async ", 1),
+            ("await ", 1),
+            ("undefined", 2),
+        ],
+    ),
+    (
+        "go",
+        &[
+            ("^package ", 3),
+            ("^func ", 3),
+            (":= ", 2),
+            ("fmt.", 3),
+            ("^import (", 3),
+            ("err != nil", 3),
+            ("chan ", 1),
+        ],
+    ),
+    (
+        "cpp",
+        &[
+            ("std::", 3),
+            ("cout", 2),
+            ("^#include <iostream>", 3),
+            ("template<", 3),
+            ("template <", 3),
+            ("^namespace ", 2),
+            ("nullptr", 3),
+            ("::", 1),
+        ],
+    ),
+    (
+        "c",
+        &[
+            ("^#include", 2),
+            ("int main(", 2),
+            ("printf(", 2),
+            ("malloc(", 3),
+            ("NULL", 2),
+            ("->", 1),
+            ("^#define ", 2),
+            ("sizeof(", 1),
+        ],
+    ),
+    (
+        "java",
+        &[
+            ("public class ", 3),
+            ("public static void ", 3),
+            ("System.out.", 3),
+            ("^import java", 3),
+            ("private ", 1),
+            ("@Override", 3),
+            ("new ", 1),
+        ],
+    ),
+    (
+        "sh",
+        &[
+            ("^$ ", 3),
+            ("^sudo ", 3),
+            ("^apt ", 2),
+            ("^apt-get ", 3),
+            ("^npm ", 2),
+            ("^pip ", 2),
+            ("^cd ", 2),
+            ("^echo ", 2),
+            ("^export ", 1),
+            ("^git ", 2),
+            ("^cargo ", 2),
+            ("^fi", 2),
+            ("; then", 3),
+            ("^done", 1),
+            ("$(", 2),
+            (" | grep", 2),
+            (" && ", 1),
+        ],
+    ),
+    (
+        "html",
+        &[
+            ("<!DOCTYPE", 3),
+            ("<html", 3),
+            ("<div", 3),
+            ("<body", 3),
+            ("<span", 2),
+            ("<p>", 2),
+            ("</", 1),
+        ],
+    ),
+    ("xml", &[("<?xml", 3), ("</", 1), ("/>", 1), ("xmlns", 2)]),
+    (
+        "css",
+        &[
+            ("color:", 2),
+            ("px;", 2),
+            ("margin", 1),
+            ("padding", 1),
+            ("^.", 1),
+            ("^@media", 3),
+            ("display:", 2),
+        ],
+    ),
+    (
+        "sql",
+        &[
+            ("SELECT ", 3),
+            ("INSERT INTO", 3),
+            ("CREATE TABLE", 3),
+            ("UPDATE ", 1),
+            ("FROM ", 1),
+            ("WHERE ", 2),
+            ("JOIN ", 2),
+            ("ALTER TABLE", 3),
+        ],
+    ),
+    (
+        "yaml",
+        &[
+            ("^---", 2),
+            ("^- name:", 3),
+            (": |", 2),
+            ("^version:", 2),
+            ("^services:", 3),
+        ],
+    ),
+    (
+        "rb",
+        &[
+            ("^end", 2),
+            ("puts ", 3),
+            (" do |", 3),
+            ("^require '", 3),
+            ("attr_accessor", 3),
+            (".each ", 2),
+        ],
+    ),
+    (
+        "diff",
+        &[("^+++ ", 3), ("^--- ", 2), ("^@@ ", 3), ("^diff --git", 3)],
+    ),
+];
+
+/// Guess a syntect token for `code`, or None if nothing is convincing
+pub fn guess(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return Some("json");
+    }
+
+    let lines: Vec<&str> = code.lines().map(str::trim_start).collect();
+    let mut best = None;
+    let mut best_score = MIN_SCORE - 1;
+    for &(token, signals) in SIGNALS {
+        let score: u32 = signals
+            .iter()
+            .filter(|(pattern, _)| match pattern.strip_prefix('^') {
+                Some(prefix) => lines.iter().any(|line| line.starts_with(prefix)),
+                None => code.contains(pattern),
+            })
+            .map(|&(_, weight)| weight)
+            .sum();
+        // Ties go to the earlier, more common language
+        if score > best_score {
+            best = Some(token);
+            best_score = score;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_languages_are_recognised() {
+        let cases = [
+            (
+                "rs",
+                "fn main() {\n    let mut v = Vec::new();\n    println!(\"{:?}\", v);\n}",
+            ),
+            (
+                "py",
+                "def greet(name):\n    if name is not None:\n        print(name)\n",
+            ),
+            (
+                "js",
+                "const add = (a, b) => a + b;\nconsole.log(add(1, 2));",
+            ),
+            (
+                "go",
+                "package main\n\nfunc main() {\n\tx := 1\n\tfmt.Println(x)\n}",
+            ),
+            ("sh", "$ sudo apt-get install curl\n$ cd /tmp && ls"),
+            ("sql", "SELECT id, name FROM users WHERE active = 1"),
+            ("diff", "diff --git a/x b/x\n--- a/x\n+++ b/x\n@@ -1 +1 @@"),
+            ("json", "{\"name\": \"nanochat\", \"tags\": [1, 2]}"),
+        ];
+        for (expected, code) in cases {
+            assert_eq!(guess(code), Some(expected), "{}", code);
+        }
+    }
+
+    #[test]
+    fn unconvincing_code_is_left_alone() {
+        assert_eq!(guess(""), None);
+        assert_eq!(guess("   \n  "), None);
+        assert_eq!(guess("Thanks, that worked!"), None);
+        // One weak signal is not enough
+        assert_eq!(guess("x -> y"), None);
+        // Looks like JSON but isn't
+        assert_ne!(guess("{not json"), Some("json"));
+    }
+
+    #[test]
+    fn line_anchored_signals_need_a_line_start() {
+        // "^def " after indentation still counts, mid-line it doesn't
+        assert_eq!(guess("    def f(x):\n        return x"), Some("py"));
+        assert_eq!(guess("we def initely agree"), None);
+    }
+}
@@ -0,0 +1,225 @@
+// Syntax highlighting of code blocks as Pango markup
+
+mod detect;
+mod theme;
+
+use std::sync::OnceLock;
+
+use pyo3::prelude::*;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{FontStyle, Style, Theme};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::pango::escape;
+
+/// Info strings people write that aren't syntect names or extensions
+const ALIASES: &[(&str, &str)] = &[
+    ("shell", "sh"),
+    ("console", "sh"),
+    ("shellsession", "sh"),
+    ("terminal", "sh"),
+    ("ts", "js"),
+    ("typescript", "js"),
+    ("tsx", "js"),
+    ("jsx", "js"),
+    ("node", "js"),
+    ("mjs", "js"),
+    ("python3", "py"),
+    ("golang", "go"),
+    ("csharp", "cs"),
+    ("c#", "cs"),
+    ("ini", "properties"),
+    ("toml", "properties"),
+    ("dockerfile", "sh"),
+    ("plaintext", "txt"),
+    ("text", "txt"),
+];
+
+/// A run of text and the span that colours it, None for the default colour
+pub type Segment = (Option<String>, String);
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(theme::build)
+}
+
+/// Resolve an info string, detecting the language when it's empty
+fn find_syntax(code: &str, lang: &str) -> &'static SyntaxReference {
+    let syntaxes = syntax_set();
+    let lang = lang.trim().to_lowercase();
+
+    let found = if lang.is_empty() {
+        code.lines()
+            .next()
+            .and_then(|line| syntaxes.find_syntax_by_first_line(line))
+            .or_else(|| detect::guess(code).and_then(|t| syntaxes.find_syntax_by_token(t)))
+    } else {
+        let token = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == lang)
+            .map_or(lang.as_str(), |(_, token)| token);
+        syntaxes.find_syntax_by_token(token)
+    };
+    found.unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+}
+
+/// Highlight `code` line by line; spans never cross lines
+pub fn highlight_lines(code: &str, lang: &str) -> Vec<Vec<Segment>> {
+    let syntax = find_syntax(code, lang);
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    let code = code.trim_end_matches('\n');
+
+    LinesWithEndings::from(code)
+        .map(|line| {
+            let regions = highlighter
+                .highlight_line(line, syntax_set())
+                .unwrap_or_else(|_| vec![(Style::default(), line)]);
+            let mut segments: Vec<Segment> = Vec::new();
+            for (style, text) in regions {
+                let text = text.trim_end_matches(['\n', '\r']);
+                if text.is_empty() {
+                    continue;
+                }
+                let span = span_for(style);
+                match segments.last_mut() {
+                    Some((last, buf)) if *last == span => buf.push_str(text),
+                    _ => segments.push((span, text.to_string())),
+                }
+            }
+            segments
+        })
+        .collect()
+}
+
+fn span_for(style: Style) -> Option<String> {
+    let color = theme::to_hex(style.foreground);
+    let mut attrs = String::new();
+    if color != theme::FOREGROUND && style.foreground.a != 0 {
+        attrs.push_str(&format!(" foreground=\"{}\"", color));
+    }
+    if style.font_style.contains(FontStyle::BOLD) {
+        attrs.push_str(" weight=\"bold\"");
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        attrs.push_str(" style=\"italic\"");
+    }
+    (!attrs.is_empty()).then(|| format!("<span{}>", attrs))
+}
+
+/// Highlighted lines joined into one markup string
+pub fn to_markup(code: &str, lang: &str) -> String {
+    highlight_lines(code, lang)
+        .iter()
+        .map(|segments| {
+            segments
+                .iter()
+                .map(|(span, text)| match span {
+                    Some(open) => format!("{}{}</span>", open, escape(text)),
+                    None => escape(text),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Highlight code as Pango markup
+///
+/// Without `lang` the language is detected from the code. Text in the
+/// default colour is left unstyled, so wrap the result in
+/// `<tt><span foreground="#a9b7c6">` (or similar) for display.
+#[pyfunction]
+#[pyo3(signature = (code, lang = None))]
+pub fn highlight(py: Python<'_>, code: &str, lang: Option<&str>) -> String {
+    py.allow_threads(|| to_markup(code, lang.unwrap_or("")))
+}
+
+/// Name of the detected language (e.g. "Python"), or None if unsure
+#[pyfunction]
+pub fn detect_language(code: &str) -> Option<String> {
+    let syntax = find_syntax(code, "");
+    (syntax.name != "Plain Text").then(|| syntax.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syntax_name(code: &str, lang: &str) -> &'static str {
+        &find_syntax(code, lang).name
+    }
+
+    #[test]
+    fn info_strings_resolve_through_aliases() {
+        assert_eq!(syntax_name("", "python"), "Python");
+        assert_eq!(syntax_name("", "py"), "Python");
+        assert_eq!(syntax_name("", "python3"), "Python");
+        assert_eq!(syntax_name("", "typescript"), "JavaScript");
+        assert_eq!(syntax_name("", "golang"), "Go");
+        assert_eq!(syntax_name("", "c#"), "C#");
+        assert_eq!(syntax_name("", "shell"), syntax_name("", "sh"));
+        // Case and surrounding spaces don't matter
+        assert_eq!(syntax_name("", "  Rust "), "Rust");
+    }
+
+    #[test]
+    fn unknown_languages_fall_back_to_plain_text() {
+        assert_eq!(syntax_name("x = 1", "no-such-language"), "Plain Text");
+        assert_eq!(syntax_name("just some words", ""), "Plain Text");
+        assert_eq!(
+            to_markup("<b> & 'q'", "no-such-language"),
+            "&lt;b&gt; &amp; &#39;q&#39;"
+        );
+    }
+
+    #[test]
+    fn missing_info_strings_are_detected() {
+        assert_eq!(
+            syntax_name("#!/usr/bin/env python3\nprint(1)", ""),
+            "Python"
+        );
+        let rust = "use std::io;\n\nfn main() {\n    let mut x = 1;\n    println!(\"{}\", x);\n}";
+        assert_eq!(syntax_name(rust, ""), "Rust");
+        assert_eq!(detect_language(rust).as_deref(), Some("Rust"));
+        assert_eq!(detect_language("hello there"), None);
+    }
+
+    #[test]
+    fn markup_is_escaped_and_coloured_per_line() {
+        let markup = to_markup("if a < b and c > d:\n    s = \"<tag>&\"\n", "python");
+        assert!(markup.contains("&lt;"));
+        assert!(markup.contains("&gt;"));
+        assert!(markup.contains("&lt;tag&gt;&amp;"));
+        assert!(!markup.contains("<tag>"));
+        // The keyword gets the accent colour, and the trailing newline is dropped
+        assert!(markup.starts_with("<span foreground=\"#4a9eff\">if</span>"));
+        assert_eq!(markup.lines().count(), 2);
+
+        for line in highlight_lines("/* a\n   b */ int x;", "c") {
+            for (span, text) in line {
+                assert!(!text.contains('\n'));
+                if let Some(span) = span {
+                    assert!(span.starts_with("<span ") && span.ends_with('>'));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plain_text_has_no_spans() {
+        let lines = highlight_lines("one\ntwo", "text");
+        assert_eq!(
+            lines,
+            vec![
+                vec![(None, "one".to_string())],
+                vec![(None, "two".to_string())]
+            ]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// Colour scheme for code blocks, tuned for the app's dark background
+
+use std::str::FromStr;
+
+use syntect::highlighting::{
+    Color, FontStyle, ScopeSelectors, StyleModifier, Theme, ThemeItem, ThemeSettings,
+};
+
+/// COLOR_BG in constants.py
+const BACKGROUND: &str = "#1a1b1e";
+/// Default code colour, same as RenderOptions.code_color
+pub const FOREGROUND: &str = "#a9b7c6";
+
+/// (scope selectors, colour, font style)
+const RULES: &[(&str, &str, FontStyle)] = &[
+    (
+        "comment, punctuation.definition.comment",
+        "#6a737d",
+        FontStyle::ITALIC,
+    ),
+    (
+        "string, punctuation.definition.string",
+        "#98c379",
+        FontStyle::empty(),
+    ),
+    ("string.regexp", "#d16969", FontStyle::empty()),
+    ("constant.character.escape", "#d7ba7d", FontStyle::empty()),
+    (
+        "constant.numeric, constant.language, constant.other, support.constant",
+        "#d19a66",
+        FontStyle::empty(),
+    ),
+    // COLOR_ACCENT
+    (
+        "keyword, storage, keyword.operator.word, meta.preprocessor",
+        "#4a9eff",
+        FontStyle::empty(),
+    ),
+    ("keyword.operator", "#a9b7c6", FontStyle::empty()),
+    (
+        "entity.name.function, support.function, variable.function",
+        "#dcdcaa",
+        FontStyle::empty(),
+    ),
+    (
+        "entity.name.type, entity.name.class, entity.name.struct, entity.name.enum, \
+         entity.other.inherited-class, support.type, support.class, storage.type.primitive",
+        "#4ec9b0",
+        FontStyle::empty(),
+    ),
+    ("variable.parameter", "#e0e0e0", FontStyle::empty()),
+    ("entity.name.tag", "#4a9eff", FontStyle::empty()),
+    (
+        "entity.other.attribute-name, support.type.property-name",
+        "#9cdcfe",
+        FontStyle::empty(),
+    ),
+    ("markup.heading", "#4a9eff", FontStyle::BOLD),
+    ("markup.bold", "#e0e0e0", FontStyle::BOLD),
+    ("markup.italic", "#e0e0e0", FontStyle::ITALIC),
+    ("markup.inserted", "#98c379", FontStyle::empty()),
+    ("markup.deleted", "#e06c75", FontStyle::empty()),
+    (
+        "meta.diff.range, meta.diff.header",
+        "#a0a0a0",
+        FontStyle::empty(),
+    ),
+    ("invalid", "#f44747", FontStyle::empty()),
+];
+
+pub fn build() -> Theme {
+    let scopes = RULES
+        .iter()
+        .map(|&(selectors, color, font_style)| ThemeItem {
+            scope: ScopeSelectors::from_str(selectors).expect("valid scope selector"),
+            style: StyleModifier {
+                foreground: Some(parse_color(color)),
+                background: None,
+                font_style: Some(font_style),
+            },
+        })
+        .collect();
+
+    Theme {
+        name: Some("nanochat-dark".to_string()),
+        author: None,
+        settings: ThemeSettings {
+            foreground: Some(parse_color(FOREGROUND)),
+            background: Some(parse_color(BACKGROUND)),
+            ..ThemeSettings::default()
+        },
+        scopes,
+    }
+}
+
+/// "#rrggbb" to a colour; only used on the constants above
+fn parse_color(hex: &str) -> Color {
+    let value = u32::from_str_radix(hex.trim_start_matches('#'), 16).expect("hex colour");
+    Color {
+        r: (value >> 16) as u8,
+        g: (value >> 8) as u8,
+        b: value as u8,
+        a: 0xff,
+    }
+}
+
+pub fn to_hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_round_trip() {
+        for hex in [BACKGROUND, FOREGROUND, "#000000", "#ffffff", "#4a9eff"] {
+            assert_eq!(to_hex(parse_color(hex)), hex);
+        }
+        assert_eq!(parse_color("#102030").a, 0xff);
+    }
+
+    #[test]
+    fn theme_has_every_rule() {
+        let theme = build();
+        assert_eq!(theme.scopes.len(), RULES.len());
+        assert_eq!(
+            theme.settings.foreground.map(to_hex).as_deref(),
+            Some(FOREGROUND)
+        );
+        assert_eq!(
+            theme.settings.background.map(to_hex).as_deref(),
+            Some(BACKGROUND)
+        );
+        let comment = &theme.scopes[0].style;
+        assert_eq!(comment.font_style, Some(FontStyle::ITALIC));
+    }
+}
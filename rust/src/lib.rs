@@ -1,11 +1,86 @@
+// pyo3 0.20's #[pymethods] expansion trips this lint on newer compilers
+#![allow(non_local_definitions)]
+
 use pyo3::prelude::*;
 
-// Minimal PyO3 module for Phase 1
-// Will be expanded in later phases for search, markdown parsing, etc.
+mod api;
+mod highlight;
+mod math;
+mod models;
+mod pango;
+mod parser;
+mod search;
+mod store;
+mod thinking;
+mod tokens;
+mod usage;
+
+// Native extension for NanoChat: search, markdown and math rendering,
+// streaming API access, token budgeting and the conversations.db store
 
 #[pymodule]
-fn nanochat_rust(_py: Python, m: &PyModule) -> PyResult<()> {
+fn nanochat_rust(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(greet, m)?)?;
+
+    // Search
+    m.add_class::<search::SearchIndex>()?;
+    m.add_class::<search::SearchHit>()?;
+    m.add_class::<search::IndexSync>()?;
+    m.add_class::<search::SyncStats>()?;
+    m.add_class::<search::FuzzyMatch>()?;
+    m.add_function(wrap_pyfunction!(search::fuzzy_filter, m)?)?;
+    m.add(
+        "QuerySyntaxError",
+        py.get_type::<search::QuerySyntaxError>(),
+    )?;
+
+    // Markdown
+    m.add_class::<parser::RenderOptions>()?;
+    m.add_function(wrap_pyfunction!(parser::render_pango, m)?)?;
+    m.add_class::<parser::StreamingRenderer>()?;
+    m.add_class::<parser::RenderedBlock>()?;
+    m.add_function(wrap_pyfunction!(parser::parse_blocks, m)?)?;
+    m.add_class::<parser::Block>()?;
+    m.add_class::<parser::ListItem>()?;
+    m.add_function(wrap_pyfunction!(highlight::highlight, m)?)?;
+    m.add_function(wrap_pyfunction!(highlight::detect_language, m)?)?;
+    m.add_function(wrap_pyfunction!(math::render_math, m)?)?;
+
+    // Streaming
+    m.add_class::<thinking::ThinkingParser>()?;
+    m.add_class::<thinking::Segment>()?;
+
+    // Context
+    m.add_function(wrap_pyfunction!(tokens::count_tokens, m)?)?;
+    m.add_function(wrap_pyfunction!(tokens::fit_context, m)?)?;
+
+    // Models
+    m.add_class::<models::ModelRegistry>()?;
+    m.add_class::<models::ModelInfo>()?;
+
+    // Usage
+    m.add_class::<usage::UsageLedger>()?;
+    m.add_class::<usage::UsageTotals>()?;
+
+    // Storage
+    m.add_class::<store::Store>()?;
+    m.add_class::<store::MessageWindow>()?;
+    m.add_class::<store::CompactionStats>()?;
+    m.add_class::<store::MigrationReport>()?;
+    m.add_function(wrap_pyfunction!(store::migrate_database, m)?)?;
+    m.add_function(wrap_pyfunction!(store::decompress_content, m)?)?;
+    m.add("MigrationError", py.get_type::<store::MigrationError>())?;
+
+    // API
+    m.add_class::<api::NanoGptClient>()?;
+    m.add_class::<api::ChatStream>()?;
+    m.add_class::<api::CancelHandle>()?;
+    m.add_class::<api::RetryPolicy>()?;
+    m.add_class::<api::RetryAttempt>()?;
+    m.add_class::<api::SseDecoder>()?;
+    m.add_class::<api::StreamDelta>()?;
+    m.add_class::<api::ToolCallDelta>()?;
+    m.add_class::<api::Usage>()?;
     Ok(())
 }
 
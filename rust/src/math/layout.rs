@@ -0,0 +1,540 @@
+// Laying out parsed math as text
+//
+// Inline math becomes a single line using Unicode scripts where possible
+// (x², aᵢ, ½). Display math is drawn on a character grid: stacked
+// fractions, limits above and below big operators and bracketed matrices,
+// meant to be shown in a monospace font.
+
+use unicode_width::UnicodeWidthStr;
+
+use super::parse::Node;
+use super::symbols::{subscript, superscript, vulgar_fraction, Class};
+
+/// Single-line rendering
+pub fn linear(node: &Node) -> String {
+    match node {
+        Node::Sym(text, _) | Node::BigOp(text) => text.clone(),
+        Node::Row(nodes) => spaced(nodes)
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Gap(gap) => gap.to_string(),
+                Piece::Node(node) => linear(node),
+            })
+            .collect(),
+        Node::Frac(num, den) => {
+            let (num, den) = (linear(num), linear(den));
+            match vulgar_fraction(&num, &den) {
+                Some(glyph) => glyph.to_string(),
+                None => format!("{}/{}", parenthesize(&num), parenthesize(&den)),
+            }
+        }
+        Node::Binom(n, k) => format!("C({}, {})", linear(n), linear(k)),
+        Node::Scripts { base, sub, sup } => {
+            let mut out = match base.as_ref() {
+                Node::Frac(..) => format!("({})", linear(base)),
+                _ => linear(base),
+            };
+            if let Some(sub) = sub {
+                out.push_str(&script(&linear(sub), subscript, '_'));
+            }
+            if let Some(sup) = sup {
+                out.push_str(&script(&linear(sup), superscript, '^'));
+            }
+            out
+        }
+        Node::Sqrt { index, body } => {
+            let root = match index.as_deref().map(linear).as_deref() {
+                None | Some("") | Some("2") => "√".to_string(),
+                Some("3") => "∛".to_string(),
+                Some("4") => "∜".to_string(),
+                Some(n) => format!("{}√", script(n, superscript, '^')),
+            };
+            format!("{}{}", root, parenthesize(&linear(body)))
+        }
+        Node::Accent(mark, body) => accented(&linear(body), *mark),
+        Node::Fenced { left, body, right } => format!("{}{}{}", left, linear(body), right),
+        Node::Matrix {
+            rows,
+            left,
+            right,
+            aligned,
+        } => {
+            let cell_gap = if *aligned { " " } else { "  " };
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| {
+                    let cells: Vec<String> = row.iter().map(linear).collect();
+                    cells.join(cell_gap).trim().to_string()
+                })
+                .collect();
+            format!("{}{}{}", left, rows.join("; "), right)
+        }
+        Node::Lines(lines) => lines.iter().map(linear).collect::<Vec<_>>().join("; "),
+    }
+}
+
+/// Multi-line rendering for display math
+pub fn display(node: &Node) -> Vec<String> {
+    layout(node)
+        .lines
+        .into_iter()
+        .map(|line| line.trim_end().to_string())
+        .collect()
+}
+
+/// A block of text lines of equal width, with the line the surrounding
+/// text sits on
+struct TextBox {
+    lines: Vec<String>,
+    baseline: usize,
+    width: usize,
+}
+
+impl TextBox {
+    fn text(text: &str) -> Self {
+        TextBox {
+            width: width(text),
+            lines: vec![text.to_string()],
+            baseline: 0,
+        }
+    }
+
+    fn height(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Pad every line to `width`, centring the content
+    fn centered(mut self, width: usize) -> Self {
+        let left = (width.saturating_sub(self.width)) / 2;
+        for line in &mut self.lines {
+            let right = width.saturating_sub(left + self::width(line));
+            *line = format!("{}{}{}", " ".repeat(left), line, " ".repeat(right));
+        }
+        self.width = self.width.max(width);
+        self
+    }
+
+    fn aligned(mut self, width: usize, right: bool) -> Self {
+        for line in &mut self.lines {
+            let pad = " ".repeat(width.saturating_sub(self::width(line)));
+            *line = if right {
+                format!("{}{}", pad, line)
+            } else {
+                format!("{}{}", line, pad)
+            };
+        }
+        self.width = self.width.max(width);
+        self
+    }
+}
+
+/// Place boxes side by side on a common baseline
+fn hconcat(boxes: Vec<TextBox>) -> TextBox {
+    let above = boxes.iter().map(|b| b.baseline).max().unwrap_or(0);
+    let below = boxes
+        .iter()
+        .map(|b| b.height() - b.baseline - 1)
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec![String::new(); above + below + 1];
+    let mut total = 0;
+
+    for b in &boxes {
+        let offset = above - b.baseline;
+        for (i, line) in lines.iter_mut().enumerate() {
+            match i.checked_sub(offset).and_then(|j| b.lines.get(j)) {
+                Some(text) => {
+                    line.push_str(text);
+                    line.push_str(&" ".repeat(b.width.saturating_sub(width(text))));
+                }
+                None => line.push_str(&" ".repeat(b.width)),
+            }
+        }
+        total += b.width;
+    }
+
+    TextBox {
+        lines,
+        baseline: above,
+        width: total,
+    }
+}
+
+/// Stack boxes vertically, centred; the baseline is that of box `base`
+fn vstack(boxes: Vec<TextBox>, base: usize) -> TextBox {
+    let width = boxes.iter().map(|b| b.width).max().unwrap_or(0);
+    let baseline = boxes[..base].iter().map(TextBox::height).sum::<usize>() + boxes[base].baseline;
+    let lines = boxes
+        .into_iter()
+        .flat_map(|b| b.centered(width).lines)
+        .collect();
+    TextBox {
+        lines,
+        baseline,
+        width,
+    }
+}
+
+fn layout(node: &Node) -> TextBox {
+    match node {
+        Node::Sym(text, _) | Node::BigOp(text) => TextBox::text(text),
+        Node::Row(nodes) => {
+            let boxes: Vec<TextBox> = spaced(nodes)
+                .into_iter()
+                .map(|piece| match piece {
+                    Piece::Gap(gap) => TextBox::text(gap),
+                    Piece::Node(node) => layout(node),
+                })
+                .collect();
+            if boxes.is_empty() {
+                TextBox::text("")
+            } else {
+                hconcat(boxes)
+            }
+        }
+        Node::Frac(num, den) => {
+            let (num, den) = (layout(num), layout(den));
+            let width = num.width.max(den.width) + 2;
+            let bar = TextBox::text(&"─".repeat(width));
+            vstack(vec![num, bar, den], 1)
+        }
+        Node::Binom(n, k) => {
+            let stacked = vstack(vec![layout(n), layout(k)], 0);
+            let mut boxed = delimited(stacked, "(", ")");
+            // Centre the pair on the surrounding baseline
+            boxed.baseline = boxed.height() / 2;
+            boxed
+        }
+        Node::Scripts { base, sub, sup } => {
+            if let Some(text) = inline_scripts(node) {
+                return TextBox::text(&text);
+            }
+            let base_box = layout(base);
+            let sub = sub.as_deref().map(layout);
+            let sup = sup.as_deref().map(layout);
+
+            if matches!(base.as_ref(), Node::BigOp(_)) {
+                let mut boxes = Vec::new();
+                let has_sup = sup.is_some();
+                boxes.extend(sup);
+                boxes.push(base_box);
+                boxes.extend(sub);
+                return vstack(boxes, usize::from(has_sup));
+            }
+
+            // Scripts in a column to the right of the base
+            let gap = TextBox {
+                lines: vec![String::new(); base_box.height()],
+                baseline: base_box.baseline,
+                width: 0,
+            };
+            let sup_height = sup.as_ref().map_or(0, TextBox::height);
+            let mut column = Vec::new();
+            column.extend(sup);
+            column.push(gap);
+            column.extend(sub);
+            let width = column.iter().map(|b| b.width).max().unwrap_or(0);
+            let lines = column
+                .into_iter()
+                .flat_map(|b| b.aligned(width, false).lines)
+                .collect();
+            let column = TextBox {
+                lines,
+                baseline: sup_height + base_box.baseline,
+                width,
+            };
+            hconcat(vec![base_box, column])
+        }
+        Node::Sqrt { index, body } => {
+            let body_box = layout(body);
+            if body_box.height() == 1 {
+                return TextBox::text(&linear(node));
+            }
+            let prefix = match index.as_deref().map(linear) {
+                Some(n) if !n.is_empty() && n != "2" => script(&n, superscript, '^'),
+                _ => String::new(),
+            };
+            let indent = " ".repeat(width(&prefix) + 1);
+            let mut lines = vec![format!("{}{}", indent, "_".repeat(body_box.width))];
+            let last = body_box.height() - 1;
+            for (i, line) in body_box.lines.iter().enumerate() {
+                let mark = if i == last {
+                    format!("{}√", prefix)
+                } else {
+                    format!("{}│", " ".repeat(width(&prefix)))
+                };
+                lines.push(format!("{}{}", mark, line));
+            }
+            TextBox {
+                lines,
+                baseline: body_box.baseline + 1,
+                width: body_box.width + width(&indent),
+            }
+        }
+        Node::Accent(..) => TextBox::text(&linear(node)),
+        Node::Fenced { left, body, right } => delimited(layout(body), left, right),
+        Node::Matrix {
+            rows,
+            left,
+            right,
+            aligned,
+        } => delimited(
+            grid(rows, *aligned, !left.is_empty() && right.is_empty()),
+            left,
+            right,
+        ),
+        Node::Lines(lines) => {
+            let boxes: Vec<TextBox> = lines.iter().map(layout).collect();
+            vstack(boxes, 0)
+        }
+    }
+}
+
+/// Cells laid out in columns; `left_aligned` for cases
+fn grid(rows: &[Vec<Node>], aligned: bool, left_aligned: bool) -> TextBox {
+    let cells: Vec<Vec<TextBox>> = rows
+        .iter()
+        .map(|row| row.iter().map(layout).collect())
+        .collect();
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in &cells {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.width);
+        }
+    }
+
+    let gap = if aligned { " " } else { "  " };
+    let mut lines_out = Vec::new();
+    for row in cells {
+        let mut boxes = Vec::new();
+        let count = row.len();
+        for (i, cell) in row.into_iter().enumerate() {
+            let cell = if aligned {
+                // align: right-aligned left sides, left-aligned right sides
+                cell.aligned(widths[i], i % 2 == 0)
+            } else if left_aligned {
+                cell.aligned(widths[i], false)
+            } else {
+                cell.centered(widths[i])
+            };
+            boxes.push(cell);
+            if i + 1 < count {
+                boxes.push(TextBox::text(gap));
+            }
+        }
+        for width in widths.iter().skip(count) {
+            boxes.push(TextBox::text(&" ".repeat(gap.len() + width)));
+        }
+        lines_out.extend(hconcat(boxes).lines);
+    }
+
+    let width = lines_out.iter().map(|l| width(l)).max().unwrap_or(0);
+    let baseline = lines_out.len().saturating_sub(1) / 2;
+    TextBox {
+        lines: lines_out,
+        baseline,
+        width,
+    }
+    .aligned(width, false)
+}
+
+/// Surround a box with delimiters stretched to its height
+fn delimited(inner: TextBox, left: &str, right: &str) -> TextBox {
+    let height = inner.height();
+    let baseline = inner.baseline;
+    let mut boxes = Vec::new();
+    if !left.is_empty() {
+        boxes.push(delimiter_column(left, height, baseline, true));
+    }
+    boxes.push(inner);
+    if !right.is_empty() {
+        boxes.push(delimiter_column(right, height, baseline, false));
+    }
+    hconcat(boxes)
+}
+
+fn delimiter_column(delim: &str, height: usize, baseline: usize, left: bool) -> TextBox {
+    if height == 1 {
+        let mut b = TextBox::text(delim);
+        b.baseline = 0;
+        return b;
+    }
+
+    // (top, middle, bottom, extension)
+    let pieces = match delim {
+        "(" => ("⎛", "⎜", "⎝", "⎜"),
+        ")" => ("⎞", "⎟", "⎠", "⎟"),
+        "[" => ("⎡", "⎢", "⎣", "⎢"),
+        "]" => ("⎤", "⎥", "⎦", "⎥"),
+        "{" => ("⎧", "⎨", "⎩", "⎪"),
+        "}" => ("⎫", "⎬", "⎭", "⎪"),
+        "⌈" => ("⌈", "│", "│", "│"),
+        "⌉" => ("⌉", "│", "│", "│"),
+        "⌊" => ("│", "│", "⌊", "│"),
+        "⌋" => ("│", "│", "⌋", "│"),
+        "‖" => ("‖", "‖", "‖", "‖"),
+        "|" => ("│", "│", "│", "│"),
+        other => (other, other, other, other),
+    };
+    let (top, middle, bottom, extension) = pieces;
+    let center = height / 2;
+    let lines = (0..height)
+        .map(|i| {
+            let piece = if i == 0 {
+                top
+            } else if i == height - 1 {
+                bottom
+            } else if i == center && matches!(delim, "{" | "}") {
+                middle
+            } else {
+                extension
+            };
+            if left {
+                piece.to_string()
+            } else {
+                format!(" {}", piece)
+            }
+        })
+        .collect::<Vec<_>>();
+    let width = lines.iter().map(|l| width(l)).max().unwrap_or(1);
+    let lines = if left {
+        lines.into_iter().map(|l| format!("{} ", l)).collect()
+    } else {
+        lines
+    };
+    TextBox {
+        lines,
+        baseline,
+        width: width + usize::from(left),
+    }
+}
+
+enum Piece<'a> {
+    Node(&'a Node),
+    Gap(&'static str),
+}
+
+/// Interleave atoms with the spaces TeX would put between them
+fn spaced(nodes: &[Node]) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut prev: Option<Class> = None;
+
+    for (i, node) in nodes.iter().enumerate() {
+        let class = node.class();
+        let next = nodes.get(i + 1).map(Node::class);
+        let operand_before = matches!(prev, Some(Class::Ord | Class::Close));
+        match class {
+            Class::Bin if operand_before && next.is_some() => {
+                pieces.extend([Piece::Gap(" "), Piece::Node(node), Piece::Gap(" ")]);
+            }
+            Class::Rel => {
+                if prev.is_some() {
+                    pieces.push(Piece::Gap(" "));
+                }
+                pieces.push(Piece::Node(node));
+                if next.is_some() {
+                    pieces.push(Piece::Gap(" "));
+                }
+            }
+            Class::Punct => {
+                pieces.push(Piece::Node(node));
+                if next.is_some() {
+                    pieces.push(Piece::Gap(" "));
+                }
+            }
+            Class::Op => {
+                if operand_before {
+                    pieces.push(Piece::Gap(" "));
+                }
+                pieces.push(Piece::Node(node));
+                if matches!(next, Some(Class::Ord | Class::Op)) {
+                    pieces.push(Piece::Gap(" "));
+                }
+            }
+            _ => pieces.push(Piece::Node(node)),
+        }
+        prev = Some(class);
+    }
+    pieces
+}
+
+/// Scripts as Unicode sub/superscript characters, if they all exist
+fn inline_scripts(node: &Node) -> Option<String> {
+    let Node::Scripts { base, sub, sup } = node else {
+        return None;
+    };
+    if matches!(base.as_ref(), Node::BigOp(_)) {
+        return None;
+    }
+    let base = layout(base);
+    if base.height() != 1 {
+        return None;
+    }
+    let mut out = base.lines.into_iter().next().unwrap_or_default();
+    for (script, map) in [
+        (sub, subscript as fn(char) -> Option<char>),
+        (sup, superscript),
+    ] {
+        if let Some(script) = script {
+            if !is_flat(script) {
+                return None;
+            }
+            let text = linear(script);
+            out.push_str(&text.chars().map(map).collect::<Option<String>>()?);
+        }
+    }
+    Some(out)
+}
+
+/// Whether a node has no two-dimensional parts
+fn is_flat(node: &Node) -> bool {
+    match node {
+        Node::Sym(..) | Node::BigOp(_) | Node::Accent(..) => true,
+        Node::Row(nodes) => nodes.iter().all(is_flat),
+        Node::Scripts { .. } => false,
+        _ => false,
+    }
+}
+
+/// `x` as a script, using Unicode characters when possible
+fn script(text: &str, map: fn(char) -> Option<char>, marker: char) -> String {
+    // Scripts are set tight
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let text = text.as_str();
+    if let Some(mapped) = text.chars().map(map).collect::<Option<String>>() {
+        return mapped;
+    }
+    if text.chars().count() == 1 {
+        format!("{}{}", marker, text)
+    } else {
+        format!("{}({})", marker, text)
+    }
+}
+
+/// Parenthesize anything that isn't a single term
+fn parenthesize(text: &str) -> String {
+    let atomic = !text.is_empty()
+        && !text
+            .chars()
+            .any(|c| c.is_whitespace() || "+−-=/,<>±×⋅".contains(c));
+    if atomic {
+        text.to_string()
+    } else {
+        format!("({})", text)
+    }
+}
+
+fn accented(text: &str, mark: char) -> String {
+    let per_char = matches!(mark, '\u{0305}' | '\u{0332}') || text.chars().count() == 1;
+    if per_char {
+        text.chars().flat_map(|c| [c, mark]).collect()
+    } else {
+        format!("{}{}", text, mark)
+    }
+}
+
+/// Display width in monospace cells; combining marks take none
+fn width(text: &str) -> usize {
+    text.width()
+}
@@ -0,0 +1,76 @@
+// TeX math to readable Unicode text
+
+mod layout;
+mod parse;
+mod symbols;
+
+use pyo3::prelude::*;
+
+/// Convert TeX to plain Unicode text
+///
+/// Display math may span several lines and is laid out for a monospace
+/// font; inline math is always a single line.
+pub fn render(tex: &str, display: bool) -> String {
+    let node = parse::parse(tex);
+    if display {
+        layout::display(&node).join("\n")
+    } else {
+        layout::linear(&node).trim().to_string()
+    }
+}
+
+/// Convert TeX to readable Unicode, e.g. `\frac{a}{b}` or `x^2 + \alpha`
+///
+/// The result is plain text: escape it before putting it in markup, and use
+/// `<tt>` for display math so stacked fractions and matrices line up.
+#[pyfunction]
+#[pyo3(signature = (tex, display = false))]
+pub fn render_math(py: Python<'_>, tex: &str, display: bool) -> String {
+    py.allow_threads(|| render(tex, display))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn inline() {
+        assert_eq!(render("x^2 + \\alpha", false), "x² + α");
+        assert_eq!(render("x_i^2", false), "xᵢ²");
+        assert_eq!(render("\\frac{a}{b}", false), "a/b");
+        assert_eq!(render("x''", false), "x′′");
+        assert_eq!(render("^2", false), "²");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(render("\\frac{a}{b}", true), " a\n───\n b");
+        assert_eq!(
+            render("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}", true),
+            "⎛ a  b ⎞\n⎝ c  d ⎠"
+        );
+    }
+
+    #[test]
+    fn long_script_chains_stay_shallow() {
+        // Used to nest one level per script and overflow the stack in layout
+        for chain in ["x^".repeat(3000), "x_".repeat(3000), "x^x_".repeat(2000)] {
+            let tex = chain + "x";
+            assert!(!render(&tex, true).is_empty());
+            assert!(!render(&tex, false).is_empty());
+        }
+        assert!(!render(&format!("x{}", "'".repeat(5000)), true).is_empty());
+        render(&"^".repeat(5000), true);
+        assert!(!render(
+            &format!("{}x{}", "{x^".repeat(3000), "}".repeat(3000)),
+            true
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn short_chains_are_unchanged() {
+        assert_eq!(render("x^y^z", false), "xʸᶻ");
+        assert_eq!(render("a^{b^{c}}", false), "a^(bᶜ)");
+    }
+}
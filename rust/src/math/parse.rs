@@ -0,0 +1,553 @@
+// TeX math to a small expression tree
+//
+// Only the subset that shows up in chat answers is understood. Anything
+// else is kept as literal text, so a formula never fails to render.
+
+use super::symbols::{self, Class};
+
+/// Deeper nesting is flattened to text instead of recursing further
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug)]
+pub enum Node {
+    Sym(String, Class),
+    /// A braced group or other sequence of atoms
+    Row(Vec<Node>),
+    Frac(Box<Node>, Box<Node>),
+    Binom(Box<Node>, Box<Node>),
+    Scripts {
+        base: Box<Node>,
+        sub: Option<Box<Node>>,
+        sup: Option<Box<Node>>,
+    },
+    Sqrt {
+        index: Option<Box<Node>>,
+        body: Box<Node>,
+    },
+    /// Operator taking limits above and below in display mode
+    BigOp(String),
+    Accent(char, Box<Node>),
+    Fenced {
+        left: String,
+        body: Box<Node>,
+        right: String,
+    },
+    Matrix {
+        rows: Vec<Vec<Node>>,
+        left: &'static str,
+        right: &'static str,
+        /// align-like environments: columns alternate right/left
+        aligned: bool,
+    },
+    /// Top-level lines separated by `\\`
+    Lines(Vec<Node>),
+}
+
+impl Node {
+    pub fn class(&self) -> Class {
+        match self {
+            Node::Sym(_, class) => *class,
+            Node::BigOp(_) => Class::Op,
+            Node::Scripts { base, .. } => base.class(),
+            _ => Class::Ord,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Command(String),
+    Char(char),
+    Space,
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Amp,
+}
+
+fn tokenize(tex: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = tex.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphabetic() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    match chars.next() {
+                        Some(c) => name.push(c),
+                        None => continue,
+                    }
+                }
+                Token::Command(name)
+            }
+            '%' => {
+                // Comment to end of line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Amp,
+            c if c.is_whitespace() => Token::Space,
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+pub fn parse(tex: &str) -> Node {
+    let mut parser = Parser {
+        tokens: tokenize(tex),
+        pos: 0,
+        depth: 0,
+    };
+
+    let mut lines = vec![Vec::new()];
+    loop {
+        let mut row = parser.row();
+        lines.last_mut().unwrap().append(&mut row);
+        match parser.next() {
+            Some(Token::Command(c)) if c == "\\" => lines.push(Vec::new()),
+            // Stray &, } or \end at top level: skip it and carry on
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    lines.retain(|line| !line.is_empty());
+    match lines.len() {
+        0 => Node::Row(Vec::new()),
+        1 => Node::Row(lines.pop().unwrap()),
+        _ => Node::Lines(lines.into_iter().map(Node::Row).collect()),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(&Token::Space) {
+            self.pos += 1;
+        }
+    }
+
+    /// Atoms up to a closing brace, `&`, `\\`, `\end` or `\right` (not consumed)
+    fn row(&mut self) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Amp) => break,
+                Some(Token::Command(c)) if matches!(c.as_str(), "\\" | "end" | "right") => break,
+                _ => {}
+            }
+            if let Some(node) = self.scripted() {
+                nodes.push(node);
+            }
+        }
+        nodes
+    }
+
+    /// An atom with any sub/superscripts
+    fn scripted(&mut self) -> Option<Node> {
+        let start = self.pos;
+        let mut base = match self.peek() {
+            // Script without a base, e.g. a leading ^2
+            Some(Token::Sup) | Some(Token::Sub) => Node::Row(Vec::new()),
+            _ => self.atom()?,
+        };
+
+        // Each script that can't fill an empty slot of `base` nests it one
+        // level deeper, so chains like x^x^x^... count towards MAX_DEPTH;
+        // past it the rest of the chain starts a new atom instead.
+        let outer_depth = self.depth;
+        loop {
+            self.skip_spaces();
+            let is_sup = match self.peek() {
+                Some(Token::Sup) => true,
+                Some(Token::Sub) => false,
+                Some(Token::Char('\'')) if self.depth < MAX_DEPTH => {
+                    self.pos += 1;
+                    self.depth += 1;
+                    base = Node::Row(vec![base, Node::Sym("′".to_string(), Class::Ord)]);
+                    continue;
+                }
+                _ => break,
+            };
+            let fills_slot = matches!(
+                (&base, is_sup),
+                (Node::Scripts { sup: None, .. }, true) | (Node::Scripts { sub: None, .. }, false)
+            );
+            if !fills_slot {
+                if self.depth >= MAX_DEPTH {
+                    if self.pos == start {
+                        // A bare script at the limit: drop the marker so
+                        // the caller still makes progress
+                        self.pos += 1;
+                        continue;
+                    }
+                    break;
+                }
+                self.depth += 1;
+            }
+            self.pos += 1;
+            let arg = Box::new(self.argument());
+            base = match base {
+                Node::Scripts { base, sub, sup } if is_sup && sup.is_none() => Node::Scripts {
+                    base,
+                    sub,
+                    sup: Some(arg),
+                },
+                Node::Scripts { base, sub, sup } if !is_sup && sub.is_none() => Node::Scripts {
+                    base,
+                    sub: Some(arg),
+                    sup,
+                },
+                base if is_sup => Node::Scripts {
+                    base: Box::new(base),
+                    sub: None,
+                    sup: Some(arg),
+                },
+                base => Node::Scripts {
+                    base: Box::new(base),
+                    sub: Some(arg),
+                    sup: None,
+                },
+            };
+        }
+        self.depth = outer_depth;
+        Some(base)
+    }
+
+    /// A single argument: a braced group, one character or one command
+    fn argument(&mut self) -> Node {
+        self.skip_spaces();
+        match self.peek() {
+            Some(Token::Char(c)) => {
+                let c = *c;
+                self.pos += 1;
+                char_node(c)
+            }
+            Some(Token::Open) | Some(Token::Command(_)) => {
+                self.atom().unwrap_or(Node::Row(Vec::new()))
+            }
+            _ => Node::Row(Vec::new()),
+        }
+    }
+
+    fn group(&mut self) -> Node {
+        let nodes = self.row();
+        // Skip to the closing brace, ignoring stray & and \\
+        while let Some(token) = self.next() {
+            if token == Token::Close {
+                break;
+            }
+        }
+        Node::Row(nodes)
+    }
+
+    fn atom(&mut self) -> Option<Node> {
+        if self.depth >= MAX_DEPTH {
+            let token = self.next()?;
+            return Some(Node::Sym(token_text(&token), Class::Ord));
+        }
+        self.depth += 1;
+        let node = self.atom_inner();
+        self.depth -= 1;
+        node
+    }
+
+    fn atom_inner(&mut self) -> Option<Node> {
+        match self.next()? {
+            Token::Open => Some(self.group()),
+            Token::Char(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(Token::Char(c)) = self.peek() {
+                    if !c.is_ascii_digit() && *c != '.' {
+                        break;
+                    }
+                    number.push(*c);
+                    self.pos += 1;
+                }
+                Some(Node::Sym(number, Class::Ord))
+            }
+            Token::Char(c) => Some(char_node(c)),
+            Token::Command(name) => self.command(&name),
+            Token::Sup | Token::Sub | Token::Space | Token::Close | Token::Amp => None,
+        }
+    }
+
+    fn command(&mut self, name: &str) -> Option<Node> {
+        if let Some((text, class)) = symbols::symbol(name) {
+            return Some(Node::Sym(text.to_string(), class));
+        }
+        if let Some(op) = symbols::big_operator(name) {
+            return Some(Node::BigOp(op.to_string()));
+        }
+        if let Some(op) = symbols::integral(name) {
+            return Some(Node::Sym(op.to_string(), Class::Op));
+        }
+        if let Some(f) = symbols::function(name) {
+            return Some(Node::Sym(f.to_string(), Class::Op));
+        }
+        if let Some(mark) = symbols::accent(name) {
+            return Some(Node::Accent(mark, Box::new(self.argument())));
+        }
+        if symbols::IGNORED.contains(&name) {
+            return Some(Node::Row(Vec::new()));
+        }
+
+        let node = match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.argument();
+                let den = self.argument();
+                Node::Frac(Box::new(num), Box::new(den))
+            }
+            "binom" | "dbinom" | "tbinom" | "choose" => {
+                let n = self.argument();
+                let k = self.argument();
+                Node::Binom(Box::new(n), Box::new(k))
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                let index = if self.peek() == Some(&Token::Char('[')) {
+                    self.pos += 1;
+                    Some(Box::new(self.until_char(']')))
+                } else {
+                    None
+                };
+                Node::Sqrt {
+                    index,
+                    body: Box::new(self.argument()),
+                }
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" | "mathrm" | "operatorname" => {
+                self.skip_spaces();
+                let text = if self.peek() == Some(&Token::Open) {
+                    self.pos += 1;
+                    self.raw_group()
+                } else {
+                    String::new()
+                };
+                let class = if name == "operatorname" {
+                    Class::Op
+                } else {
+                    Class::Ord
+                };
+                Node::Sym(text, class)
+            }
+            "mathbb" | "mathcal" | "mathscr" | "mathfrak" | "mathbf" | "boldsymbol" => {
+                let mut arg = self.argument();
+                apply_font(&mut arg, name);
+                arg
+            }
+            "mathit" | "mathsf" | "mathtt" | "textsf" | "texttt" => self.argument(),
+            "left" => {
+                let left = self.delimiter();
+                let body = Node::Row(self.row());
+                let right = match self.peek() {
+                    Some(Token::Command(c)) if c == "right" => {
+                        self.pos += 1;
+                        self.delimiter()
+                    }
+                    _ => String::new(),
+                };
+                Node::Fenced {
+                    left,
+                    body: Box::new(body),
+                    right,
+                }
+            }
+            "begin" => self.environment(),
+            // Unknown command: show it as typed
+            _ => Node::Sym(format!("\\{}", name), Class::Ord),
+        };
+        Some(node)
+    }
+
+    fn delimiter(&mut self) -> String {
+        self.skip_spaces();
+        match self.next() {
+            Some(Token::Char('.')) | None => String::new(),
+            Some(Token::Char(c)) => c.to_string(),
+            Some(Token::Command(name)) => symbols::symbol(&name)
+                .map(|(s, _)| s.to_string())
+                .unwrap_or_default(),
+            Some(_) => String::new(),
+        }
+    }
+
+    /// Atoms up to a closing character such as the `]` of \sqrt[n]
+    fn until_char(&mut self, end: char) -> Node {
+        let mut nodes = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None => break,
+                Some(Token::Char(c)) if *c == end => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => {}
+            }
+            match self.scripted() {
+                Some(node) => nodes.push(node),
+                None => break,
+            }
+        }
+        Node::Row(nodes)
+    }
+
+    /// Source text of a group whose opening brace was consumed
+    fn raw_group(&mut self) -> String {
+        let mut text = String::new();
+        let mut depth = 0usize;
+        while let Some(token) = self.next() {
+            match token {
+                Token::Open => depth += 1,
+                Token::Close if depth == 0 => break,
+                Token::Close => depth -= 1,
+                token => text.push_str(&token_text(&token)),
+            }
+        }
+        text
+    }
+
+    fn environment(&mut self) -> Node {
+        self.skip_spaces();
+        let name = if self.peek() == Some(&Token::Open) {
+            self.pos += 1;
+            self.raw_group()
+        } else {
+            String::new()
+        };
+        // Column spec of array/alignat
+        if name == "array" || name.starts_with("alignat") {
+            self.skip_spaces();
+            if self.peek() == Some(&Token::Open) {
+                self.pos += 1;
+                self.raw_group();
+            }
+        }
+
+        let mut rows = vec![Vec::new()];
+        loop {
+            let cell = Node::Row(self.row());
+            rows.last_mut().unwrap().push(cell);
+            match self.next() {
+                Some(Token::Amp) => {}
+                Some(Token::Command(c)) if c == "\\" => rows.push(Vec::new()),
+                Some(Token::Command(c)) if c == "end" => {
+                    self.skip_spaces();
+                    if self.peek() == Some(&Token::Open) {
+                        self.pos += 1;
+                        self.raw_group();
+                    }
+                    break;
+                }
+                // \right or } closes an unterminated environment
+                Some(_) => {
+                    self.pos -= 1;
+                    break;
+                }
+                None => break,
+            }
+        }
+        // A trailing \\ leaves an empty last row
+        if rows.len() > 1 && rows.last().is_some_and(|r| r.iter().all(is_empty)) {
+            rows.pop();
+        }
+
+        let name = name.trim_end_matches('*');
+        let (left, right, aligned) = match name {
+            "pmatrix" => ("(", ")", false),
+            "bmatrix" => ("[", "]", false),
+            "Bmatrix" => ("{", "}", false),
+            "vmatrix" => ("|", "|", false),
+            "Vmatrix" => ("‖", "‖", false),
+            "cases" => ("{", "", false),
+            "align" | "aligned" | "alignat" | "alignedat" | "split" | "eqnarray" => ("", "", true),
+            _ => ("", "", false),
+        };
+        Node::Matrix {
+            rows,
+            left,
+            right,
+            aligned,
+        }
+    }
+}
+
+fn char_node(c: char) -> Node {
+    let (text, class) = match c {
+        '-' => ("−".to_string(), Class::Bin),
+        '+' | '*' | '/' => (c.to_string(), Class::Bin),
+        '=' | '<' | '>' | ':' => (c.to_string(), Class::Rel),
+        ',' | ';' => (c.to_string(), Class::Punct),
+        '(' | '[' => (c.to_string(), Class::Open),
+        ')' | ']' => (c.to_string(), Class::Close),
+        '~' => (" ".to_string(), Class::Ord),
+        _ => (c.to_string(), Class::Ord),
+    };
+    Node::Sym(text, class)
+}
+
+/// Literal text of a token, with symbol commands converted
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Command(name) => match symbols::symbol(name) {
+            Some((s, _)) => s.to_string(),
+            None => name.clone(),
+        },
+        Token::Char(c) => c.to_string(),
+        Token::Space => " ".to_string(),
+        Token::Open => "{".to_string(),
+        Token::Close => "}".to_string(),
+        Token::Sup => "^".to_string(),
+        Token::Sub => "_".to_string(),
+        Token::Amp => "&".to_string(),
+    }
+}
+
+fn apply_font(node: &mut Node, font: &str) {
+    match node {
+        Node::Sym(text, _) => *text = text.chars().map(|c| symbols::font_char(font, c)).collect(),
+        Node::Row(nodes) => nodes.iter_mut().for_each(|n| apply_font(n, font)),
+        Node::Scripts { base, .. } => apply_font(base, font),
+        Node::Accent(_, body) => apply_font(body, font),
+        _ => {}
+    }
+}
+
+fn is_empty(node: &Node) -> bool {
+    matches!(node, Node::Row(nodes) if nodes.is_empty())
+}
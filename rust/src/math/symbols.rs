@@ -0,0 +1,373 @@
+// Symbol tables for TeX commands
+
+/// Spacing class of an atom, as in TeX
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Class {
+    Ord,
+    /// Binary operator: spaced unless unary
+    Bin,
+    /// Relation: always spaced
+    Rel,
+    Open,
+    Close,
+    Punct,
+    /// Named function or big operator, separated from its argument
+    Op,
+}
+
+use Class::*;
+
+const SYMBOLS: &[(&str, &str, Class)] = &[
+    // Greek
+    ("alpha", "α", Ord),
+    ("beta", "β", Ord),
+    ("gamma", "γ", Ord),
+    ("delta", "δ", Ord),
+    ("epsilon", "ϵ", Ord),
+    ("varepsilon", "ε", Ord),
+    ("zeta", "ζ", Ord),
+    ("eta", "η", Ord),
+    ("theta", "θ", Ord),
+    ("vartheta", "ϑ", Ord),
+    ("iota", "ι", Ord),
+    ("kappa", "κ", Ord),
+    ("lambda", "λ", Ord),
+    ("mu", "μ", Ord),
+    ("nu", "ν", Ord),
+    ("xi", "ξ", Ord),
+    ("omicron", "ο", Ord),
+    ("pi", "π", Ord),
+    ("varpi", "ϖ", Ord),
+    ("rho", "ρ", Ord),
+    ("varrho", "ϱ", Ord),
+    ("sigma", "σ", Ord),
+    ("varsigma", "ς", Ord),
+    ("tau", "τ", Ord),
+    ("upsilon", "υ", Ord),
+    ("phi", "ϕ", Ord),
+    ("varphi", "φ", Ord),
+    ("chi", "χ", Ord),
+    ("psi", "ψ", Ord),
+    ("omega", "ω", Ord),
+    ("Gamma", "Γ", Ord),
+    ("Delta", "Δ", Ord),
+    ("Theta", "Θ", Ord),
+    ("Lambda", "Λ", Ord),
+    ("Xi", "Ξ", Ord),
+    ("Pi", "Π", Ord),
+    ("Sigma", "Σ", Ord),
+    ("Upsilon", "Υ", Ord),
+    ("Phi", "Φ", Ord),
+    ("Psi", "Ψ", Ord),
+    ("Omega", "Ω", Ord),
+    // Letter-like
+    ("infty", "∞", Ord),
+    ("partial", "∂", Ord),
+    ("nabla", "∇", Ord),
+    ("hbar", "ℏ", Ord),
+    ("ell", "ℓ", Ord),
+    ("Re", "ℜ", Ord),
+    ("Im", "ℑ", Ord),
+    ("aleph", "ℵ", Ord),
+    ("emptyset", "∅", Ord),
+    ("varnothing", "∅", Ord),
+    ("forall", "∀", Ord),
+    ("exists", "∃", Ord),
+    ("nexists", "∄", Ord),
+    ("neg", "¬", Ord),
+    ("lnot", "¬", Ord),
+    ("angle", "∠", Ord),
+    ("triangle", "△", Ord),
+    ("prime", "′", Ord),
+    ("degree", "°", Ord),
+    ("circ", "∘", Bin),
+    ("cdots", "⋯", Ord),
+    ("ldots", "…", Ord),
+    ("dots", "…", Ord),
+    ("vdots", "⋮", Ord),
+    ("ddots", "⋱", Ord),
+    ("checkmark", "✓", Ord),
+    // Binary operators
+    ("times", "×", Bin),
+    ("cdot", "⋅", Bin),
+    ("div", "÷", Bin),
+    ("pm", "±", Bin),
+    ("mp", "∓", Bin),
+    ("ast", "∗", Bin),
+    ("star", "⋆", Bin),
+    ("bullet", "∙", Bin),
+    ("oplus", "⊕", Bin),
+    ("ominus", "⊖", Bin),
+    ("otimes", "⊗", Bin),
+    ("odot", "⊙", Bin),
+    ("cup", "∪", Bin),
+    ("cap", "∩", Bin),
+    ("setminus", "∖", Bin),
+    ("wedge", "∧", Bin),
+    ("land", "∧", Bin),
+    ("vee", "∨", Bin),
+    ("lor", "∨", Bin),
+    // Relations
+    ("leq", "≤", Rel),
+    ("le", "≤", Rel),
+    ("geq", "≥", Rel),
+    ("ge", "≥", Rel),
+    ("neq", "≠", Rel),
+    ("ne", "≠", Rel),
+    ("approx", "≈", Rel),
+    ("equiv", "≡", Rel),
+    ("sim", "∼", Rel),
+    ("simeq", "≃", Rel),
+    ("cong", "≅", Rel),
+    ("propto", "∝", Rel),
+    ("ll", "≪", Rel),
+    ("gg", "≫", Rel),
+    ("in", "∈", Rel),
+    ("notin", "∉", Rel),
+    ("ni", "∋", Rel),
+    ("subset", "⊂", Rel),
+    ("subseteq", "⊆", Rel),
+    ("supset", "⊃", Rel),
+    ("supseteq", "⊇", Rel),
+    ("perp", "⊥", Rel),
+    ("parallel", "∥", Rel),
+    ("mid", "∣", Rel),
+    ("to", "→", Rel),
+    ("rightarrow", "→", Rel),
+    ("leftarrow", "←", Rel),
+    ("gets", "←", Rel),
+    ("leftrightarrow", "↔", Rel),
+    ("Rightarrow", "⇒", Rel),
+    ("Leftarrow", "⇐", Rel),
+    ("Leftrightarrow", "⇔", Rel),
+    ("implies", "⟹", Rel),
+    ("impliedby", "⟸", Rel),
+    ("iff", "⟺", Rel),
+    ("mapsto", "↦", Rel),
+    ("longrightarrow", "⟶", Rel),
+    ("longmapsto", "⟼", Rel),
+    ("uparrow", "↑", Rel),
+    ("downarrow", "↓", Rel),
+    // Delimiters
+    ("langle", "⟨", Open),
+    ("rangle", "⟩", Close),
+    ("lfloor", "⌊", Open),
+    ("rfloor", "⌋", Close),
+    ("lceil", "⌈", Open),
+    ("rceil", "⌉", Close),
+    ("lvert", "|", Open),
+    ("rvert", "|", Close),
+    ("lVert", "‖", Open),
+    ("rVert", "‖", Close),
+    ("vert", "|", Ord),
+    ("Vert", "‖", Ord),
+    ("|", "‖", Ord),
+    ("{", "{", Open),
+    ("}", "}", Close),
+    ("lbrace", "{", Open),
+    ("rbrace", "}", Close),
+    // Escapes
+    ("%", "%", Ord),
+    ("$", "$", Ord),
+    ("&", "&", Ord),
+    ("#", "#", Ord),
+    ("_", "_", Ord),
+    // Spacing
+    ("quad", "  ", Ord),
+    ("qquad", "    ", Ord),
+    (",", " ", Ord),
+    (":", " ", Ord),
+    (";", " ", Ord),
+    (" ", " ", Ord),
+    ("!", "", Ord),
+];
+
+/// Operators whose scripts go above and below in display mode
+const BIG_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("bigcup", "⋃"),
+    ("bigcap", "⋂"),
+    ("bigoplus", "⨁"),
+    ("bigotimes", "⨂"),
+    ("bigvee", "⋁"),
+    ("bigwedge", "⋀"),
+    ("lim", "lim"),
+    ("limsup", "lim sup"),
+    ("liminf", "lim inf"),
+    ("max", "max"),
+    ("min", "min"),
+    ("sup", "sup"),
+    ("inf", "inf"),
+    ("det", "det"),
+    ("gcd", "gcd"),
+    ("Pr", "Pr"),
+    ("argmax", "arg max"),
+    ("argmin", "arg min"),
+];
+
+/// Integrals keep their limits as scripts, as TeX does by default
+const INTEGRALS: &[(&str, &str)] = &[("int", "∫"), ("iint", "∬"), ("iiint", "∭"), ("oint", "∮")];
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "dim", "ker", "deg", "arg", "hom",
+];
+
+/// Size and style commands that don't change the text rendering
+pub const IGNORED: &[&str] = &[
+    "displaystyle",
+    "textstyle",
+    "scriptstyle",
+    "limits",
+    "nolimits",
+    "big",
+    "Big",
+    "bigg",
+    "Bigg",
+    "bigl",
+    "bigr",
+    "Bigl",
+    "Bigr",
+    "biggl",
+    "biggr",
+    "Biggl",
+    "Biggr",
+    "middle",
+    "nonumber",
+    "notag",
+];
+
+pub fn symbol(name: &str) -> Option<(&'static str, Class)> {
+    SYMBOLS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|&(_, s, class)| (s, class))
+}
+
+pub fn big_operator(name: &str) -> Option<&'static str> {
+    lookup(BIG_OPERATORS, name)
+}
+
+pub fn integral(name: &str) -> Option<&'static str> {
+    lookup(INTEGRALS, name)
+}
+
+pub fn function(name: &str) -> Option<&'static str> {
+    FUNCTIONS.iter().find(|&&f| f == name).copied()
+}
+
+fn lookup(table: &[(&str, &'static str)], name: &str) -> Option<&'static str> {
+    table.iter().find(|(n, _)| *n == name).map(|&(_, s)| s)
+}
+
+/// Combining character for an accent command
+pub fn accent(name: &str) -> Option<char> {
+    Some(match name {
+        "hat" | "widehat" => '\u{0302}',
+        "tilde" | "widetilde" => '\u{0303}',
+        "bar" => '\u{0304}',
+        "overline" => '\u{0305}',
+        "dot" => '\u{0307}',
+        "ddot" => '\u{0308}',
+        "vec" | "overrightarrow" => '\u{20D7}',
+        "underline" => '\u{0332}',
+        _ => return None,
+    })
+}
+
+/// Map a character through a math alphabet such as \mathbb
+pub fn font_char(font: &str, c: char) -> char {
+    let (upper, lower, digit, exceptions): (u32, u32, u32, &[(char, char)]) = match font {
+        "mathbb" => (
+            0x1D538,
+            0x1D552,
+            0x1D7D8,
+            &[
+                ('C', 'ℂ'),
+                ('H', 'ℍ'),
+                ('N', 'ℕ'),
+                ('P', 'ℙ'),
+                ('Q', 'ℚ'),
+                ('R', 'ℝ'),
+                ('Z', 'ℤ'),
+            ],
+        ),
+        "mathcal" | "mathscr" => (
+            0x1D49C,
+            0x1D4B6,
+            0,
+            &[
+                ('B', 'ℬ'),
+                ('E', 'ℰ'),
+                ('F', 'ℱ'),
+                ('H', 'ℋ'),
+                ('I', 'ℐ'),
+                ('L', 'ℒ'),
+                ('M', 'ℳ'),
+                ('R', 'ℛ'),
+                ('e', 'ℯ'),
+                ('g', 'ℊ'),
+                ('o', 'ℴ'),
+            ],
+        ),
+        "mathfrak" => (
+            0x1D504,
+            0x1D51E,
+            0,
+            &[('C', 'ℭ'), ('H', 'ℌ'), ('I', 'ℑ'), ('R', 'ℜ'), ('Z', 'ℨ')],
+        ),
+        "mathbf" | "boldsymbol" => (0x1D400, 0x1D41A, 0x1D7CE, &[]),
+        _ => return c,
+    };
+
+    if let Some(&(_, mapped)) = exceptions.iter().find(|(from, _)| *from == c) {
+        return mapped;
+    }
+    let code = match c {
+        'A'..='Z' => upper + (c as u32 - 'A' as u32),
+        'a'..='z' => lower + (c as u32 - 'a' as u32),
+        '0'..='9' if digit != 0 => digit + (c as u32 - '0' as u32),
+        _ => return c,
+    };
+    char::from_u32(code).unwrap_or(c)
+}
+
+pub fn superscript(c: char) -> Option<char> {
+    convert(
+        c,
+        "0123456789+-−=()niabcdefghjklmoprstuvwxyzT",
+        "⁰¹²³⁴⁵⁶⁷⁸⁹⁺⁻⁻⁼⁽⁾ⁿⁱᵃᵇᶜᵈᵉᶠᵍʰʲᵏˡᵐᵒᵖʳˢᵗᵘᵛʷˣʸᶻᵀ",
+    )
+    .or_else(|| (c == '′').then_some(c))
+}
+
+pub fn subscript(c: char) -> Option<char> {
+    convert(
+        c,
+        "0123456789+-−=()aehijklmnoprstuvxβγρφχ",
+        "₀₁₂₃₄₅₆₇₈₉₊₋₋₌₍₎ₐₑₕᵢⱼₖₗₘₙₒₚᵣₛₜᵤᵥₓᵦᵧᵨᵩᵪ",
+    )
+}
+
+/// Map `c` from one alphabet to the character at the same position in another
+fn convert(c: char, from: &str, to: &str) -> Option<char> {
+    let i = from.chars().position(|f| f == c)?;
+    to.chars().nth(i)
+}
+
+/// Unicode vulgar fraction for simple numeric fractions
+pub fn vulgar_fraction(num: &str, den: &str) -> Option<&'static str> {
+    Some(match (num, den) {
+        ("1", "2") => "½",
+        ("1", "3") => "⅓",
+        ("2", "3") => "⅔",
+        ("1", "4") => "¼",
+        ("3", "4") => "¾",
+        ("1", "5") => "⅕",
+        ("1", "6") => "⅙",
+        ("1", "8") => "⅛",
+        _ => return None,
+    })
+}
@@ -0,0 +1,361 @@
+// Fallback details for well-known models
+//
+// /v1/models often leaves out context sizes, prices or capabilities, and
+// isn't reachable offline. These list values fill the gaps; anything the
+// API reports wins. Prices are USD per million tokens.
+
+/// Capability flags
+pub const REASONING: u8 = 1;
+pub const VISION: u8 = 2;
+pub const TOOLS: u8 = 4;
+
+const R: u8 = REASONING;
+const V: u8 = VISION;
+const T: u8 = TOOLS;
+
+/// id, name, context length, max output tokens, input and output price,
+/// capabilities
+type Row = (&'static str, &'static str, u32, u32, f64, f64, u8);
+
+const KNOWN_MODELS: &[(&str, &[Row])] = &[
+    (
+        "openai",
+        &[
+            (
+                "gpt-3.5-turbo",
+                "GPT-3.5 Turbo",
+                16_385,
+                4_096,
+                0.50,
+                1.50,
+                T,
+            ),
+            ("gpt-4", "GPT-4", 8_192, 8_192, 30.0, 60.0, T),
+            (
+                "gpt-4-turbo",
+                "GPT-4 Turbo",
+                128_000,
+                4_096,
+                10.0,
+                30.0,
+                V | T,
+            ),
+            ("gpt-4o", "GPT-4o", 128_000, 16_384, 2.50, 10.0, V | T),
+            (
+                "gpt-4o-mini",
+                "GPT-4o mini",
+                128_000,
+                16_384,
+                0.15,
+                0.60,
+                V | T,
+            ),
+            (
+                "chatgpt-4o-latest",
+                "ChatGPT-4o",
+                128_000,
+                16_384,
+                5.0,
+                15.0,
+                V,
+            ),
+            ("gpt-4.1", "GPT-4.1", 1_047_576, 32_768, 2.0, 8.0, V | T),
+            (
+                "gpt-4.1-mini",
+                "GPT-4.1 mini",
+                1_047_576,
+                32_768,
+                0.40,
+                1.60,
+                V | T,
+            ),
+            (
+                "gpt-4.1-nano",
+                "GPT-4.1 nano",
+                1_047_576,
+                32_768,
+                0.10,
+                0.40,
+                V | T,
+            ),
+            ("gpt-5", "GPT-5", 400_000, 128_000, 1.25, 10.0, R | V | T),
+            (
+                "gpt-5-mini",
+                "GPT-5 mini",
+                400_000,
+                128_000,
+                0.25,
+                2.0,
+                R | V | T,
+            ),
+            (
+                "gpt-5-nano",
+                "GPT-5 nano",
+                400_000,
+                128_000,
+                0.05,
+                0.40,
+                R | V | T,
+            ),
+            ("o1", "o1", 200_000, 100_000, 15.0, 60.0, R | V | T),
+            ("o1-mini", "o1-mini", 128_000, 65_536, 1.10, 4.40, R),
+            ("o3", "o3", 200_000, 100_000, 2.0, 8.0, R | V | T),
+            ("o3-mini", "o3-mini", 200_000, 100_000, 1.10, 4.40, R | T),
+            (
+                "o4-mini",
+                "o4-mini",
+                200_000,
+                100_000,
+                1.10,
+                4.40,
+                R | V | T,
+            ),
+        ],
+    ),
+    (
+        "anthropic",
+        &[
+            (
+                "claude-3-opus-20240229",
+                "Claude 3 Opus",
+                200_000,
+                4_096,
+                15.0,
+                75.0,
+                V | T,
+            ),
+            (
+                "claude-3-5-haiku-20241022",
+                "Claude 3.5 Haiku",
+                200_000,
+                8_192,
+                0.80,
+                4.0,
+                T,
+            ),
+            (
+                "claude-3-5-sonnet-20241022",
+                "Claude 3.5 Sonnet",
+                200_000,
+                8_192,
+                3.0,
+                15.0,
+                V | T,
+            ),
+            (
+                "claude-3-7-sonnet-20250219",
+                "Claude 3.7 Sonnet",
+                200_000,
+                64_000,
+                3.0,
+                15.0,
+                V | T,
+            ),
+            (
+                "claude-sonnet-4-20250514",
+                "Claude Sonnet 4",
+                200_000,
+                64_000,
+                3.0,
+                15.0,
+                V | T,
+            ),
+            (
+                "claude-opus-4-20250514",
+                "Claude Opus 4",
+                200_000,
+                32_000,
+                15.0,
+                75.0,
+                V | T,
+            ),
+        ],
+    ),
+    (
+        "google",
+        &[
+            (
+                "gemini-2.0-flash",
+                "Gemini 2.0 Flash",
+                1_048_576,
+                8_192,
+                0.10,
+                0.40,
+                V | T,
+            ),
+            (
+                "gemini-2.5-flash",
+                "Gemini 2.5 Flash",
+                1_048_576,
+                65_536,
+                0.30,
+                2.50,
+                R | V | T,
+            ),
+            (
+                "gemini-2.5-pro",
+                "Gemini 2.5 Pro",
+                1_048_576,
+                65_536,
+                1.25,
+                10.0,
+                R | V | T,
+            ),
+        ],
+    ),
+    (
+        "deepseek",
+        &[
+            (
+                "deepseek-chat",
+                "DeepSeek V3",
+                128_000,
+                8_192,
+                0.27,
+                1.10,
+                T,
+            ),
+            (
+                "deepseek-reasoner",
+                "DeepSeek R1",
+                128_000,
+                32_768,
+                0.55,
+                2.19,
+                R,
+            ),
+            ("deepseek-r1", "DeepSeek R1", 128_000, 32_768, 0.55, 2.19, R),
+        ],
+    ),
+    (
+        "x-ai",
+        &[
+            ("grok-3", "Grok 3", 131_072, 131_072, 3.0, 15.0, T),
+            (
+                "grok-3-mini",
+                "Grok 3 Mini",
+                131_072,
+                131_072,
+                0.30,
+                0.50,
+                R | T,
+            ),
+        ],
+    ),
+    (
+        "mistralai",
+        &[(
+            "mistral-large-latest",
+            "Mistral Large",
+            131_072,
+            131_072,
+            2.0,
+            6.0,
+            T,
+        )],
+    ),
+];
+
+/// A bundled model entry
+pub struct Known {
+    pub owned_by: &'static str,
+    pub row: &'static Row,
+}
+
+impl Known {
+    pub fn id(&self) -> &'static str {
+        self.row.0
+    }
+
+    pub fn has(&self, capability: u8) -> bool {
+        self.row.6 & capability != 0
+    }
+}
+
+/// Every bundled model, in table order
+pub fn all() -> impl Iterator<Item = Known> {
+    KNOWN_MODELS
+        .iter()
+        .flat_map(|(owned_by, rows)| rows.iter().map(move |row| Known { owned_by, row }))
+}
+
+/// The bundled entry for `model_id`
+///
+/// Ids may carry a provider prefix ("openai/gpt-4o") or a dated or tagged
+/// suffix ("gpt-4o-2024-08-06", "deepseek-r1:free"); the longest bundled
+/// id the name starts with, at a `-` or `:` boundary, is used.
+pub fn lookup(model_id: &str) -> Option<Known> {
+    let name = model_id
+        .rsplit('/')
+        .next()
+        .unwrap_or(model_id)
+        .to_ascii_lowercase();
+    all()
+        .filter(|k| {
+            name.strip_prefix(k.id())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', ':']))
+        })
+        .max_by_key(|k| k.id().len())
+}
+
+/// Guess reasoning support from the id when nothing else says
+pub fn looks_like_reasoning(model_id: &str) -> bool {
+    let name = model_id.to_ascii_lowercase();
+    ["thinking", "reasoner", "-r1", "/r1", "qwq"]
+        .iter()
+        .any(|hint| name.contains(hint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(model_id: &str) -> Option<&'static str> {
+        lookup(model_id).map(|k| k.id())
+    }
+
+    #[test]
+    fn lookup_matches_prefixes_at_boundaries() {
+        assert_eq!(id("gpt-4o"), Some("gpt-4o"));
+        assert_eq!(id("GPT-4o"), Some("gpt-4o"));
+        assert_eq!(id("openai/gpt-4o-2024-08-06"), Some("gpt-4o"));
+        // The longest bundled id wins
+        assert_eq!(id("gpt-4o-mini-2024-07-18"), Some("gpt-4o-mini"));
+        assert_eq!(id("deepseek-r1:free"), Some("deepseek-r1"));
+        // "gpt-4" is a prefix of "gpt-4omega" but not at a boundary
+        assert_eq!(id("gpt-4omega"), None);
+        assert_eq!(id("unknown-model"), None);
+    }
+
+    #[test]
+    fn known_rows_carry_their_provider_and_flags() {
+        let o3 = lookup("o3").unwrap();
+        assert_eq!(o3.owned_by, "openai");
+        assert!(o3.has(REASONING) && o3.has(VISION) && o3.has(TOOLS));
+        let chat = lookup("deepseek-chat").unwrap();
+        assert_eq!(chat.owned_by, "deepseek");
+        assert!(!chat.has(REASONING) && chat.has(TOOLS));
+    }
+
+    #[test]
+    fn ids_are_unique_and_lowercase() {
+        let ids: Vec<&str> = all().map(|k| k.id()).collect();
+        let unique: std::collections::HashSet<&str> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), ids.len());
+        assert!(ids.iter().all(|id| *id == id.to_ascii_lowercase()));
+    }
+
+    #[test]
+    fn reasoning_hints_in_ids() {
+        for id in [
+            "claude-thinking",
+            "deepseek-reasoner",
+            "x/deepseek-r1",
+            "QwQ-32B",
+            "deepseek/r1",
+        ] {
+            assert!(looks_like_reasoning(id), "{}", id);
+        }
+        assert!(!looks_like_reasoning("gpt-4o"));
+    }
+}
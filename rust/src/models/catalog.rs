@@ -0,0 +1,461 @@
+// Model details from /v1/models and the on-disk cache
+//
+// The payload's shape varies between NanoGPT's plain and detailed listings
+// and the OpenRouter-style fields some providers pass through, so every
+// field is looked up under the names seen in the wild and missing ones are
+// left unknown rather than guessed.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::bundled::{self, REASONING, TOOLS, VISION};
+
+/// Version 1 was the plain id list written by nanochat.api.model_cache
+pub const FORMAT_VERSION: u32 = 2;
+
+/// What is known about one model; None means unknown
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Details {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub context_length: Option<u32>,
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+    /// USD per million prompt tokens
+    #[serde(default)]
+    pub input_price: Option<f64>,
+    /// USD per million completion tokens
+    #[serde(default)]
+    pub output_price: Option<f64>,
+    #[serde(default)]
+    pub reasoning: Option<bool>,
+    #[serde(default)]
+    pub vision: Option<bool>,
+    #[serde(default)]
+    pub tools: Option<bool>,
+}
+
+impl Details {
+    pub fn new(id: &str) -> Self {
+        Details {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Parse one entry of the `data` array; a bare string is just an id
+    pub fn from_api(entry: &Value) -> Option<Self> {
+        let id = match entry {
+            Value::String(id) => return Some(Self::new(id)),
+            _ => entry.get("id")?.as_str()?,
+        };
+        let mut details = Self::new(id);
+        details.name = text(entry, &["name", "display_name"]);
+        details.owned_by = text(entry, &["owned_by", "provider"]);
+        details.description = text(entry, &["description"]).filter(|d| !d.trim().is_empty());
+        details.context_length = count(
+            entry,
+            &[
+                "/context_length",
+                "/context_window",
+                "/max_context_length",
+                "/max_input_tokens",
+                "/top_provider/context_length",
+            ],
+        );
+        details.max_output_tokens = count(
+            entry,
+            &[
+                "/max_output_tokens",
+                "/max_completion_tokens",
+                "/top_provider/max_completion_tokens",
+            ],
+        );
+        if let Some(pricing) = entry.get("pricing") {
+            let scale = price_scale(pricing);
+            details.input_price = price(pricing, &["prompt", "input"], scale);
+            details.output_price = price(pricing, &["completion", "output"], scale);
+        }
+        details.reasoning = capability(entry, &["reasoning", "thinking"], &["reasoning"]);
+        details.vision = capability(entry, &["vision", "image_input", "images"], &[])
+            .or_else(|| takes_images(entry));
+        details.tools = capability(
+            entry,
+            &["tools", "tool_calling", "function_calling"],
+            &["tools", "tool_choice"],
+        );
+        Some(details)
+    }
+
+    /// Fill whatever is still unknown from the bundled table
+    pub fn fill_from_bundled(&mut self) {
+        if let Some(known) = bundled::lookup(&self.id) {
+            let (_, name, context, output, input_price, output_price, _) = *known.row;
+            self.name.get_or_insert_with(|| name.to_string());
+            self.owned_by
+                .get_or_insert_with(|| known.owned_by.to_string());
+            self.context_length.get_or_insert(context);
+            self.max_output_tokens.get_or_insert(output);
+            self.input_price.get_or_insert(input_price);
+            self.output_price.get_or_insert(output_price);
+            self.reasoning.get_or_insert(known.has(REASONING));
+            self.vision.get_or_insert(known.has(VISION));
+            self.tools.get_or_insert(known.has(TOOLS));
+        }
+        if bundled::looks_like_reasoning(&self.id) {
+            self.reasoning.get_or_insert(true);
+        }
+    }
+}
+
+/// The `data` entries of a /v1/models response, or the response itself
+/// when it is already a list
+pub fn entries(payload: &Value) -> Option<&Vec<Value>> {
+    match payload {
+        Value::Array(entries) => Some(entries),
+        _ => payload
+            .get("data")
+            .or_else(|| payload.get("models"))?
+            .as_array(),
+    }
+}
+
+fn text(entry: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| entry.get(key)?.as_str())
+        .map(str::to_string)
+}
+
+/// A number that may arrive as a string
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn count(entry: &Value, pointers: &[&str]) -> Option<u32> {
+    pointers
+        .iter()
+        .filter_map(|p| entry.pointer(p).and_then(number))
+        .find(|n| *n > 0.0)
+        .map(|n| n.min(u32::MAX as f64) as u32)
+}
+
+/// Factor taking the listed prices to USD per million tokens
+///
+/// NanoGPT states its unit; OpenRouter-style listings don't and price per
+/// token, which shows as values far below a cent.
+fn price_scale(pricing: &Value) -> Option<f64> {
+    let unit = pricing
+        .get("unit")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if unit.contains("million") || unit.contains("1m") {
+        Some(1.0)
+    } else if unit.contains("thousand") || unit.contains("1k") {
+        Some(1_000.0)
+    } else if unit.contains("token") {
+        Some(1_000_000.0)
+    } else {
+        None
+    }
+}
+
+fn price(pricing: &Value, keys: &[&str], scale: Option<f64>) -> Option<f64> {
+    let value = keys
+        .iter()
+        .find_map(|key| pricing.get(key).and_then(number))
+        .filter(|v| *v >= 0.0)?;
+    let scale = scale.unwrap_or(if value > 0.0 && value < 0.001 {
+        1_000_000.0
+    } else {
+        1.0
+    });
+    // Per-token prices scaled up carry float noise, e.g. 2.1900000000000004
+    Some((value * scale * 1e6).round() / 1e6)
+}
+
+/// A capability stated as `capabilities.<name>`, a `capabilities` list,
+/// a `supports_<name>` flag, or a `supported_parameters` entry
+fn capability(entry: &Value, names: &[&str], parameters: &[&str]) -> Option<bool> {
+    let flag = |value: &Value| -> Option<bool> {
+        match value {
+            Value::Bool(b) => Some(*b),
+            Value::Object(map) => map.get("supported").and_then(Value::as_bool),
+            _ => None,
+        }
+    };
+    match entry.get("capabilities") {
+        Some(Value::Object(map)) => {
+            if let Some(found) = names.iter().find_map(|n| map.get(*n).and_then(flag)) {
+                return Some(found);
+            }
+        }
+        Some(Value::Array(list)) => {
+            let listed = list
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|c| names.contains(&c));
+            return Some(listed);
+        }
+        _ => {}
+    }
+    if let Some(found) = names.iter().find_map(|n| {
+        entry
+            .get(format!("supports_{}", n))
+            .and_then(Value::as_bool)
+    }) {
+        return Some(found);
+    }
+    if parameters.is_empty() {
+        return None;
+    }
+    let supported = entry.get("supported_parameters")?.as_array()?;
+    Some(
+        supported
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|p| parameters.contains(&p)),
+    )
+}
+
+/// Vision from OpenRouter's `architecture.input_modalities`
+fn takes_images(entry: &Value) -> Option<bool> {
+    let modalities = entry
+        .pointer("/architecture/input_modalities")?
+        .as_array()?;
+    Some(modalities.iter().any(|m| m.as_str() == Some("image")))
+}
+
+/// The registry as stored on disk
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CacheFile {
+    pub version: u32,
+    /// Unix time of the last successful fetch, 0 if never fetched
+    #[serde(default)]
+    pub fetched_at: f64,
+    /// Models the API listed, with details as the API gave them
+    #[serde(default)]
+    pub models: Vec<Details>,
+}
+
+/// models_cache.json as nanochat.api.model_cache wrote it
+#[derive(Deserialize)]
+struct LegacyCache {
+    version: u32,
+    #[serde(default)]
+    timestamp: f64,
+    #[serde(default)]
+    models: Vec<String>,
+}
+
+impl CacheFile {
+    /// Load the cache, or None if it is missing, unreadable as a cache or
+    /// from another version; the next fetch rewrites it
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(path)?;
+        Ok(serde_json::from_slice::<CacheFile>(&data)
+            .ok()
+            .filter(|cache| cache.version == FORMAT_VERSION))
+    }
+
+    /// Read the id list left by the Python model cache
+    pub fn load_legacy(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        let legacy: LegacyCache = serde_json::from_slice(&data).ok()?;
+        (legacy.version == 1).then(|| CacheFile {
+            version: FORMAT_VERSION,
+            fetched_at: legacy.timestamp,
+            models: legacy.models.iter().map(|id| Details::new(id)).collect(),
+        })
+    }
+
+    /// Write the cache atomically (temp file + rename)
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn nanogpt_detailed_entries() {
+        let details = Details::from_api(&json!({
+            "id": "gpt-4o",
+            "name": "GPT-4o",
+            "owned_by": "openai",
+            "description": "  ",
+            "context_length": 128000,
+            "max_output_tokens": "16384",
+            "pricing": {"prompt": 2.5, "completion": 10, "unit": "per million tokens"},
+            "capabilities": {"vision": true, "reasoning": false, "tool_calling": {"supported": true}}
+        }))
+        .unwrap();
+        assert_eq!(
+            details,
+            Details {
+                id: "gpt-4o".into(),
+                name: Some("GPT-4o".into()),
+                owned_by: Some("openai".into()),
+                description: None,
+                context_length: Some(128_000),
+                max_output_tokens: Some(16_384),
+                input_price: Some(2.5),
+                output_price: Some(10.0),
+                reasoning: Some(false),
+                vision: Some(true),
+                tools: Some(true),
+            }
+        );
+    }
+
+    #[test]
+    fn openrouter_style_entries() {
+        let details = Details::from_api(&json!({
+            "id": "anthropic/claude-x",
+            "top_provider": {"context_length": 200000, "max_completion_tokens": 8192},
+            "pricing": {"prompt": "0.000003", "completion": "0.0000150"},
+            "architecture": {"input_modalities": ["text", "image"]},
+            "supported_parameters": ["tools", "temperature"]
+        }))
+        .unwrap();
+        assert_eq!(details.context_length, Some(200_000));
+        assert_eq!(details.max_output_tokens, Some(8_192));
+        // Per-token prices come out per million, without float noise
+        assert_eq!(details.input_price, Some(3.0));
+        assert_eq!(details.output_price, Some(15.0));
+        assert_eq!(details.vision, Some(true));
+        assert_eq!(details.tools, Some(true));
+        assert_eq!(details.reasoning, Some(false));
+    }
+
+    #[test]
+    fn sparse_entries_leave_fields_unknown() {
+        assert_eq!(
+            Details::from_api(&json!("bare-id")),
+            Some(Details::new("bare-id"))
+        );
+        assert_eq!(Details::from_api(&json!({"name": "no id"})), None);
+        assert_eq!(Details::from_api(&json!(42)), None);
+
+        let details = Details::from_api(&json!({
+            "id": "m",
+            "context_length": 0,
+            "pricing": {"prompt": -1, "completion": 0.5, "unit": "per 1K tokens"},
+            "capabilities": ["vision"]
+        }))
+        .unwrap();
+        assert_eq!(details.context_length, None);
+        assert_eq!(details.input_price, None);
+        assert_eq!(details.output_price, Some(500.0));
+        // A capability list answers for everything it could name
+        assert_eq!(details.vision, Some(true));
+        assert_eq!(details.tools, Some(false));
+    }
+
+    #[test]
+    fn gaps_are_filled_from_the_bundled_table() {
+        let mut details = Details::new("openai/gpt-4o-2024-08-06");
+        details.context_length = Some(64_000);
+        details.fill_from_bundled();
+        // The API's value wins, the rest comes from the table
+        assert_eq!(details.context_length, Some(64_000));
+        assert_eq!(details.name.as_deref(), Some("GPT-4o"));
+        assert_eq!(details.owned_by.as_deref(), Some("openai"));
+        assert_eq!(
+            (details.input_price, details.output_price),
+            (Some(2.5), Some(10.0))
+        );
+        assert_eq!(
+            (details.reasoning, details.vision, details.tools),
+            (Some(false), Some(true), Some(true))
+        );
+
+        let mut unknown = Details::new("someone/qwq-32b");
+        unknown.fill_from_bundled();
+        assert_eq!(unknown.reasoning, Some(true));
+        assert_eq!(unknown.context_length, None);
+    }
+
+    #[test]
+    fn entries_accept_every_listing_shape() {
+        let list = json!(["a", "b"]);
+        assert_eq!(entries(&list).map(Vec::len), Some(2));
+        assert_eq!(
+            entries(&json!({"data": [{"id": "a"}]})).map(Vec::len),
+            Some(1)
+        );
+        assert_eq!(entries(&json!({"models": []})).map(Vec::len), Some(0));
+        assert_eq!(entries(&json!({"object": "list"})), None);
+    }
+
+    #[test]
+    fn cache_files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("models_registry.json");
+        assert!(CacheFile::load(&path).unwrap().is_none());
+
+        let cache = CacheFile {
+            version: FORMAT_VERSION,
+            fetched_at: 1_700_000_000.5,
+            models: vec![
+                Details::new("a"),
+                Details::from_api(&json!({"id": "b", "context_length": 8})).unwrap(),
+            ],
+        };
+        cache.save(&path).unwrap();
+        let loaded = CacheFile::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.fetched_at, cache.fetched_at);
+        assert_eq!(loaded.models, cache.models);
+
+        fs::write(&path, r#"{"version": 1, "models": []}"#).unwrap();
+        assert!(CacheFile::load(&path).unwrap().is_none());
+        fs::write(&path, "not json").unwrap();
+        assert!(CacheFile::load(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn legacy_caches_are_imported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("models_cache.json");
+        fs::write(
+            &path,
+            r#"{"version": 1, "timestamp": 1700000000.0, "models": ["gpt-4o", "o3"]}"#,
+        )
+        .unwrap();
+        let cache = CacheFile::load_legacy(&path).unwrap();
+        assert_eq!(cache.version, FORMAT_VERSION);
+        assert_eq!(cache.fetched_at, 1_700_000_000.0);
+        assert_eq!(cache.models, [Details::new("gpt-4o"), Details::new("o3")]);
+
+        fs::write(&path, r#"{"version": 2, "models": ["x"]}"#).unwrap();
+        assert!(CacheFile::load_legacy(&path).is_none());
+        assert!(CacheFile::load_legacy(&dir.path().join("missing.json")).is_none());
+    }
+}
@@ -0,0 +1,497 @@
+// Registry of available models and what each one can do
+//
+// Replaces nanochat.api.model_cache: the full /v1/models payload is kept
+// in models_registry.json, and gaps in it are filled from a bundled table
+// when queried, so fixes to the table apply to old caches too.
+
+mod bundled;
+mod catalog;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::api::{py_bool, py_number, py_to_json};
+use catalog::{CacheFile, Details, FORMAT_VERSION};
+
+const REGISTRY_FILE_NAME: &str = "models_registry.json";
+/// Written by nanochat.api.model_cache; its ids seed a new registry
+const LEGACY_FILE_NAME: &str = "models_cache.json";
+/// Matches ModelCache.CACHE_EXPIRY_SECONDS
+const DEFAULT_MAX_AGE: f64 = 24.0 * 60.0 * 60.0;
+
+/// Details of one model, from the API where it said and the bundled table
+/// otherwise
+#[pyclass]
+#[derive(Clone)]
+pub struct ModelInfo {
+    #[pyo3(get)]
+    pub id: String,
+    /// Display name, the id when none is known
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub owned_by: Option<String>,
+    #[pyo3(get)]
+    pub description: Option<String>,
+    #[pyo3(get)]
+    pub context_length: Option<u32>,
+    #[pyo3(get)]
+    pub max_output_tokens: Option<u32>,
+    /// USD per million prompt tokens
+    #[pyo3(get)]
+    pub input_price: Option<f64>,
+    /// USD per million completion tokens
+    #[pyo3(get)]
+    pub output_price: Option<f64>,
+    #[pyo3(get)]
+    pub supports_reasoning: bool,
+    #[pyo3(get)]
+    pub supports_vision: bool,
+    #[pyo3(get)]
+    pub supports_tools: bool,
+    /// Listed by the last /v1/models fetch, rather than only bundled
+    #[pyo3(get)]
+    pub available: bool,
+}
+
+impl ModelInfo {
+    fn new(details: Details, available: bool) -> Self {
+        ModelInfo {
+            name: details.name.unwrap_or_else(|| details.id.clone()),
+            id: details.id,
+            owned_by: details.owned_by,
+            description: details.description,
+            context_length: details.context_length,
+            max_output_tokens: details.max_output_tokens,
+            input_price: details.input_price,
+            output_price: details.output_price,
+            supports_reasoning: details.reasoning.unwrap_or(false),
+            supports_vision: details.vision.unwrap_or(false),
+            supports_tools: details.tools.unwrap_or(false),
+            available,
+        }
+    }
+}
+
+#[pymethods]
+impl ModelInfo {
+    /// One line for the model picker, e.g.
+    /// "128K context · $2.50 / $10.00 per 1M tokens · vision, tools"
+    #[getter]
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(context) = self.context_length {
+            parts.push(format!("{} context", short_count(context)));
+        }
+        if let (Some(input), Some(output)) = (self.input_price, self.output_price) {
+            parts.push(format!("${:.2} / ${:.2} per 1M tokens", input, output));
+        }
+        let capabilities: Vec<&str> = [
+            (self.supports_reasoning, "reasoning"),
+            (self.supports_vision, "vision"),
+            (self.supports_tools, "tools"),
+        ]
+        .iter()
+        .filter(|(supported, _)| *supported)
+        .map(|(_, name)| *name)
+        .collect();
+        if !capabilities.is_empty() {
+            parts.push(capabilities.join(", "));
+        }
+        parts.join(" · ")
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ModelInfo(id={:?}, context_length={}, supports_reasoning={}, supports_vision={}, available={})",
+            self.id,
+            py_number(self.context_length),
+            py_bool(self.supports_reasoning),
+            py_bool(self.supports_vision),
+            py_bool(self.available)
+        )
+    }
+}
+
+/// 131072 -> "131K", 1048576 -> "1M"
+fn short_count(n: u32) -> String {
+    if n >= 1_000_000 {
+        let millions = format!("{:.1}", n as f64 / 1e6);
+        format!("{}M", millions.trim_end_matches(".0"))
+    } else if n >= 1_000 {
+        format!("{}K", (n as f64 / 1e3).round())
+    } else {
+        n.to_string()
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64())
+}
+
+/// The models the API offers, with context sizes, prices and capabilities
+///
+/// Before the first fetch the bundled models are listed instead, marked
+/// unavailable, so the model picker has something to show offline.
+#[pyclass]
+pub struct ModelRegistry {
+    path: PathBuf,
+    cache: CacheFile,
+    /// Position of each listed id in cache.models
+    positions: HashMap<String, usize>,
+}
+
+#[pymethods]
+impl ModelRegistry {
+    /// Open the registry in `cache_dir`, by default ~/.config/nanochat
+    #[new]
+    #[pyo3(signature = (cache_dir=None))]
+    pub(crate) fn new(cache_dir: Option<&str>) -> PyResult<Self> {
+        let dir = match cache_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => default_dir(),
+        };
+        let path = dir.join(REGISTRY_FILE_NAME);
+        let loaded = CacheFile::load(&path)
+            .map_err(|e| PyIOError::new_err(format!("Failed to load model registry: {}", e)))?;
+        let cache = match loaded {
+            Some(cache) => cache,
+            None => import_legacy(&dir, &path).unwrap_or_else(|| CacheFile {
+                version: FORMAT_VERSION,
+                ..Default::default()
+            }),
+        };
+        let mut registry = ModelRegistry {
+            path,
+            cache,
+            positions: HashMap::new(),
+        };
+        registry.reindex();
+        Ok(registry)
+    }
+
+    #[getter]
+    fn path(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    /// Unix time of the last fetch, None if the API was never asked
+    #[getter]
+    fn fetched_at(&self) -> Option<f64> {
+        (self.cache.fetched_at > 0.0).then_some(self.cache.fetched_at)
+    }
+
+    /// Whether the last fetch is recent enough to skip fetching again
+    #[pyo3(signature = (max_age=DEFAULT_MAX_AGE))]
+    fn is_fresh(&self, max_age: f64) -> bool {
+        self.fetched_at()
+            .is_some_and(|at| now() - at <= max_age && !self.cache.models.is_empty())
+    }
+
+    /// Replace the listed models with a /v1/models response and save it
+    ///
+    /// Takes the response dict, its `data` list, or a list of ids. Returns
+    /// the number of models listed.
+    pub(crate) fn update(&mut self, payload: &PyAny) -> PyResult<usize> {
+        let payload = py_to_json(payload)?;
+        let entries = catalog::entries(&payload).ok_or_else(|| {
+            PyValueError::new_err("expected a /v1/models response or a list of models")
+        })?;
+        let mut models: Vec<Details> = Vec::with_capacity(entries.len());
+        for details in entries.iter().filter_map(Details::from_api) {
+            if !models.iter().any(|m| m.id == details.id) {
+                models.push(details);
+            }
+        }
+
+        self.cache = CacheFile {
+            version: FORMAT_VERSION,
+            fetched_at: now(),
+            models,
+        };
+        self.reindex();
+        self.cache
+            .save(&self.path)
+            .map_err(|e| PyIOError::new_err(format!("Failed to save model registry: {}", e)))?;
+        Ok(self.cache.models.len())
+    }
+
+    /// Every listed model, in the API's order
+    fn models(&self) -> Vec<ModelInfo> {
+        if self.cache.models.is_empty() {
+            return bundled::all()
+                .map(|known| ModelInfo::new(merged(Details::new(known.id())), false))
+                .collect();
+        }
+        self.cache
+            .models
+            .iter()
+            .map(|details| ModelInfo::new(merged(details.clone()), true))
+            .collect()
+    }
+
+    /// Ids of the listed models, as models() orders them
+    fn ids(&self) -> Vec<String> {
+        if self.cache.models.is_empty() {
+            return bundled::all().map(|k| k.id().to_string()).collect();
+        }
+        self.cache.models.iter().map(|m| m.id.clone()).collect()
+    }
+
+    /// Details of `model_id`, None if it is neither listed nor bundled
+    fn get(&self, model_id: &str) -> Option<ModelInfo> {
+        match self.positions.get(model_id) {
+            Some(&i) => Some(ModelInfo::new(merged(self.cache.models[i].clone()), true)),
+            None => bundled::lookup(model_id)
+                .map(|_| ModelInfo::new(merged(Details::new(model_id)), false)),
+        }
+    }
+
+    fn supports_reasoning(&self, model_id: &str) -> bool {
+        self.details(model_id).reasoning.unwrap_or(false)
+    }
+
+    fn supports_vision(&self, model_id: &str) -> bool {
+        self.details(model_id).vision.unwrap_or(false)
+    }
+
+    fn supports_tools(&self, model_id: &str) -> bool {
+        self.details(model_id).tools.unwrap_or(false)
+    }
+
+    /// Context window in tokens, None if unknown
+    fn context_length(&self, model_id: &str) -> Option<u32> {
+        self.details(model_id).context_length
+    }
+
+    /// Longest completion in tokens, None if unknown
+    fn max_output_tokens(&self, model_id: &str) -> Option<u32> {
+        self.details(model_id).max_output_tokens
+    }
+
+    /// Forget the fetched models and delete the cache files, including a
+    /// leftover models_cache.json
+    fn clear(&mut self) -> PyResult<()> {
+        self.cache = CacheFile {
+            version: FORMAT_VERSION,
+            ..Default::default()
+        };
+        self.positions.clear();
+        let legacy = self.path.with_file_name(LEGACY_FILE_NAME);
+        for path in [&self.path, &legacy] {
+            if let Err(e) = fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(PyIOError::new_err(format!(
+                        "Failed to clear model registry: {}",
+                        e
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.cache.models.len()
+    }
+
+    /// Whether the last fetch listed `model_id`
+    fn __contains__(&self, model_id: &str) -> bool {
+        self.positions.contains_key(model_id)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ModelRegistry(path={:?}, models={}, fetched_at={})",
+            self.path.to_string_lossy(),
+            self.cache.models.len(),
+            py_number(self.fetched_at().map(f64::round))
+        )
+    }
+}
+
+impl ModelRegistry {
+    fn reindex(&mut self) {
+        self.positions = self
+            .cache
+            .models
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (m.id.clone(), i))
+            .collect();
+    }
+
+    /// Input and output price in USD per million tokens, if both are known
+    pub(crate) fn prices(&self, model_id: &str) -> Option<(f64, f64)> {
+        let details = self.details(model_id);
+        Some((details.input_price?, details.output_price?))
+    }
+
+    /// Everything known about `model_id`, listed or not
+    fn details(&self, model_id: &str) -> Details {
+        let details = match self.positions.get(model_id) {
+            Some(&i) => self.cache.models[i].clone(),
+            None => Details::new(model_id),
+        };
+        merged(details)
+    }
+}
+
+fn merged(mut details: Details) -> Details {
+    details.fill_from_bundled();
+    details
+}
+
+fn default_dir() -> PathBuf {
+    let home = std::env::var_os("HOME").map_or_else(|| PathBuf::from("."), PathBuf::from);
+    home.join(".config").join("nanochat")
+}
+
+/// Carry over the id list from models_cache.json
+///
+/// The old file is left alone while nanochat.api.model_cache may still
+/// read it.
+fn import_legacy(dir: &Path, path: &Path) -> Option<CacheFile> {
+    let cache = CacheFile::load_legacy(&dir.join(LEGACY_FILE_NAME))?;
+    // Worth keeping even if it can't be written yet
+    let _ = cache.save(path);
+    Some(cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(dir: &tempfile::TempDir) -> ModelRegistry {
+        ModelRegistry::new(dir.path().to_str()).unwrap()
+    }
+
+    #[test]
+    fn counts_are_shortened() {
+        assert_eq!(short_count(131_072), "131K");
+        assert_eq!(short_count(128_000), "128K");
+        assert_eq!(short_count(8_192), "8K");
+        assert_eq!(short_count(1_048_576), "1M");
+        assert_eq!(short_count(1_500_000), "1.5M");
+        assert_eq!(short_count(999), "999");
+    }
+
+    #[test]
+    fn summaries_list_what_is_known() {
+        let mut details = Details::new("gpt-4o");
+        details.fill_from_bundled();
+        assert_eq!(
+            ModelInfo::new(details, true).summary(),
+            "128K context · $2.50 / $10.00 per 1M tokens · vision, tools"
+        );
+        assert_eq!(ModelInfo::new(Details::new("mystery"), true).summary(), "");
+    }
+
+    #[test]
+    fn freshness_follows_the_last_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = registry(&dir);
+        assert!(!registry.is_fresh(DEFAULT_MAX_AGE));
+        assert_eq!(registry.fetched_at(), None);
+
+        registry.cache.fetched_at = now() - 100.0;
+        // Nothing listed yet, so a fetch is still due
+        assert!(!registry.is_fresh(DEFAULT_MAX_AGE));
+        registry.cache.models.push(Details::new("gpt-4o"));
+        assert!(registry.is_fresh(200.0));
+        assert!(!registry.is_fresh(50.0));
+    }
+
+    #[test]
+    fn bundled_models_stand_in_before_the_first_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = registry(&dir);
+        assert_eq!(registry.__len__(), 0);
+        let models = registry.models();
+        assert_eq!(models.len(), bundled::all().count());
+        assert!(models.iter().all(|m| !m.available));
+        assert_eq!(registry.ids()[0], models[0].id);
+
+        let gpt = registry.get("gpt-4o").unwrap();
+        assert!(!gpt.available);
+        assert_eq!(gpt.context_length, Some(128_000));
+        assert!(registry.get("mystery").is_none());
+        assert_eq!(registry.prices("gpt-4o"), Some((2.5, 10.0)));
+        assert_eq!(registry.prices("mystery"), None);
+    }
+
+    #[test]
+    fn listed_models_merge_with_the_bundled_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = registry(&dir);
+        let mut listed = Details::new("gpt-4o");
+        listed.context_length = Some(64_000);
+        registry.cache.models = vec![listed, Details::new("mystery")];
+        registry.reindex();
+
+        assert!(registry.__contains__("gpt-4o"));
+        assert!(!registry.__contains__("o3"));
+        assert_eq!(registry.context_length("gpt-4o"), Some(64_000));
+        assert_eq!(registry.max_output_tokens("gpt-4o"), Some(16_384));
+        assert!(registry.supports_vision("gpt-4o"));
+        assert!(registry.get("mystery").unwrap().available);
+        assert_eq!(registry.ids(), ["gpt-4o", "mystery"]);
+        // Unlisted but bundled models are still described
+        assert!(registry.supports_reasoning("o3"));
+        assert!(!registry.get("o3").unwrap().available);
+    }
+
+    #[test]
+    fn updates_are_saved_and_reloaded() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let mut registry = registry(&dir);
+            let payload = py
+                .eval(
+                    "{'data': [{'id': 'a', 'context_length': 1000}, 'b', {'id': 'a'}]}",
+                    None,
+                    None,
+                )
+                .unwrap();
+            assert_eq!(registry.update(payload).unwrap(), 2);
+            assert!(registry.is_fresh(DEFAULT_MAX_AGE));
+
+            let reloaded = ModelRegistry::new(dir.path().to_str()).unwrap();
+            assert_eq!(reloaded.ids(), ["a", "b"]);
+            assert_eq!(reloaded.context_length("a"), Some(1000));
+
+            let bad = py.eval("{'object': 'list'}", None, None).unwrap();
+            assert!(registry.update(bad).is_err());
+        });
+    }
+
+    #[test]
+    fn legacy_caches_seed_a_new_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(LEGACY_FILE_NAME),
+            r#"{"version": 1, "timestamp": 1700000000.0, "models": ["gpt-4o", "custom"]}"#,
+        )
+        .unwrap();
+        let mut registry = registry(&dir);
+        assert_eq!(registry.ids(), ["gpt-4o", "custom"]);
+        assert_eq!(registry.fetched_at(), Some(1_700_000_000.0));
+        // Saved in the new format straight away; the old file is left alone
+        assert!(dir.path().join(REGISTRY_FILE_NAME).exists());
+        assert!(dir.path().join(LEGACY_FILE_NAME).exists());
+
+        registry.clear().unwrap();
+        assert_eq!(registry.__len__(), 0);
+        assert!(!dir.path().join(REGISTRY_FILE_NAME).exists());
+        assert!(!dir.path().join(LEGACY_FILE_NAME).exists());
+        assert_eq!(registry.models().len(), bundled::all().count());
+    }
+}
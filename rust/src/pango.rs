@@ -0,0 +1,50 @@
+// Helpers for building Pango markup
+
+/// Escape text for use inside Pango markup
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape `text` and wrap the given character ranges in `open`/`close` tags
+///
+/// Ranges are (start, end) character offsets, sorted and non-overlapping.
+pub fn highlight(text: &str, ranges: &[(usize, usize)], open: &str, close: &str) -> String {
+    let mut out = String::with_capacity(text.len() + ranges.len() * (open.len() + close.len()));
+    let mut ranges = ranges.iter().peekable();
+    let mut current = String::new();
+    let mut inside = false;
+
+    for (i, c) in text.chars().enumerate() {
+        if !inside && ranges.peek().is_some_and(|r| r.0 == i) {
+            out.push_str(&escape(&current));
+            current.clear();
+            out.push_str(open);
+            inside = true;
+        }
+        current.push(c);
+        if inside && ranges.peek().is_some_and(|r| r.1 == i + 1) {
+            out.push_str(&escape(&current));
+            current.clear();
+            out.push_str(close);
+            inside = false;
+            ranges.next();
+        }
+    }
+
+    out.push_str(&escape(&current));
+    if inside {
+        out.push_str(close);
+    }
+    out
+}
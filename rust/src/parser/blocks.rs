@@ -0,0 +1,418 @@
+// Markdown as a typed block tree, for building native widgets per block
+//
+// Inline content (paragraph text, headings, table cells) is rendered to
+// Pango markup with the same renderer as render_pango; block structure is
+// left to the caller.
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Parser, Tag, TagEnd};
+use pyo3::prelude::*;
+
+use super::options::RenderOptions;
+use super::render::{markdown_options, render_events};
+use crate::api::py_bool;
+use crate::highlight;
+
+/// One block of a message
+///
+/// `kind` is one of "paragraph", "heading", "code", "math", "table",
+/// "quote", "list" or "rule"; fields not used by a kind are empty.
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct Block {
+    #[pyo3(get)]
+    pub kind: &'static str,
+    /// Pango markup: inline text, highlighted code, or a box-drawn table
+    #[pyo3(get)]
+    pub markup: String,
+    /// Raw text: code source, TeX for math, plain text otherwise
+    #[pyo3(get)]
+    pub text: String,
+    /// Heading level 1-6
+    #[pyo3(get)]
+    pub level: u8,
+    /// Info string of a fenced code block
+    #[pyo3(get)]
+    pub language: Option<String>,
+    /// Table header cells as markup
+    #[pyo3(get)]
+    pub header: Vec<String>,
+    /// Table body rows as markup
+    #[pyo3(get)]
+    pub rows: Vec<Vec<String>>,
+    /// Per column: "left", "center", "right" or "none"
+    #[pyo3(get)]
+    pub alignments: Vec<&'static str>,
+    /// Contents of a quote
+    #[pyo3(get)]
+    pub children: Vec<Block>,
+    #[pyo3(get)]
+    pub items: Vec<ListItem>,
+    /// First number of an ordered list, None for bullet lists
+    #[pyo3(get)]
+    pub start: Option<u64>,
+}
+
+#[pymethods]
+impl Block {
+    fn __repr__(&self) -> String {
+        format!("Block(kind={:?}, text={:?})", self.kind, self.text)
+    }
+}
+
+/// A list item and its blocks
+#[pyclass]
+#[derive(Clone)]
+pub struct ListItem {
+    #[pyo3(get)]
+    pub blocks: Vec<Block>,
+    /// Task list state, None for ordinary items
+    #[pyo3(get)]
+    pub checked: Option<bool>,
+}
+
+#[pymethods]
+impl ListItem {
+    fn __repr__(&self) -> String {
+        format!(
+            "ListItem(checked={}, blocks={})",
+            self.checked.map_or("None", py_bool),
+            self.blocks.len()
+        )
+    }
+}
+
+/// Parse markdown into top-level blocks
+pub fn parse(markdown: &str, options: &RenderOptions) -> Vec<Block> {
+    let events: Vec<Event> = Parser::new_ext(markdown, markdown_options()).collect();
+    let mut parser = BlockParser {
+        events,
+        pos: 0,
+        opts: options,
+        task: None,
+    };
+    parser.blocks()
+}
+
+struct BlockParser<'a, 'o> {
+    events: Vec<Event<'a>>,
+    pos: usize,
+    opts: &'o RenderOptions,
+    /// Task marker seen in the current list item
+    task: Option<bool>,
+}
+
+impl<'a> BlockParser<'a, '_> {
+    /// Blocks up to the end of the enclosing container, consuming its end
+    fn blocks(&mut self) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        while let Some(event) = self.events.get(self.pos).cloned() {
+            if is_inline(&event) {
+                // Tight list items hold inline content without a paragraph
+                let inline = self.take_inline();
+                blocks.push(self.paragraph(inline));
+                continue;
+            }
+
+            self.pos += 1;
+            match event {
+                Event::End(_) => break,
+                Event::Start(Tag::Paragraph) => {
+                    let inline = self.take_until_end();
+                    blocks.push(self.paragraph(inline));
+                }
+                Event::Start(Tag::Heading { level, .. }) => {
+                    let inline = self.take_until_end();
+                    blocks.push(self.heading(level, inline));
+                }
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let language = match kind {
+                        CodeBlockKind::Fenced(info) => {
+                            info.split_whitespace().next().map(str::to_string)
+                        }
+                        CodeBlockKind::Indented => None,
+                    };
+                    let text = plain_text(&self.take_until_end());
+                    blocks.push(Block {
+                        kind: "code",
+                        markup: highlight::to_markup(&text, language.as_deref().unwrap_or("")),
+                        text,
+                        language,
+                        ..Block::default()
+                    });
+                }
+                Event::Start(Tag::HtmlBlock) => {
+                    // Shown literally, like render_pango does
+                    let inline = self.take_until_end();
+                    blocks.push(self.paragraph(inline));
+                }
+                Event::Start(Tag::BlockQuote(_)) => blocks.push(Block {
+                    kind: "quote",
+                    children: self.blocks(),
+                    ..Block::default()
+                }),
+                Event::Start(Tag::List(start)) => blocks.push(Block {
+                    kind: "list",
+                    items: self.list_items(),
+                    start,
+                    ..Block::default()
+                }),
+                Event::Start(Tag::Table(alignments)) => blocks.push(self.table(&alignments)),
+                Event::Rule => blocks.push(Block {
+                    kind: "rule",
+                    ..Block::default()
+                }),
+                // Footnotes, definition lists and metadata are flattened
+                Event::Start(_) => blocks.extend(self.blocks()),
+                _ => {}
+            }
+        }
+        blocks
+    }
+
+    fn list_items(&mut self) -> Vec<ListItem> {
+        let mut items = Vec::new();
+        while let Some(event) = self.events.get(self.pos) {
+            self.pos += 1;
+            match event {
+                Event::Start(Tag::Item) => {
+                    let saved = self.task.take();
+                    let blocks = self.blocks();
+                    items.push(ListItem {
+                        blocks,
+                        checked: self.task.take(),
+                    });
+                    self.task = saved;
+                }
+                Event::End(_) => break,
+                _ => {}
+            }
+        }
+        items
+    }
+
+    fn table(&mut self, alignments: &[Alignment]) -> Block {
+        // Start(Table) was just consumed
+        let start = self.pos - 1;
+        let mut header = Vec::new();
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut in_head = false;
+
+        while let Some(event) = self.events.get(self.pos).cloned() {
+            self.pos += 1;
+            match event {
+                Event::Start(Tag::TableHead) => in_head = true,
+                Event::End(TagEnd::TableHead) => in_head = false,
+                Event::Start(Tag::TableRow) => rows.push(Vec::new()),
+                Event::Start(Tag::TableCell) => {
+                    let inline = self.take_until_end();
+                    let cell = render_events(inline, self.opts);
+                    match rows.last_mut() {
+                        Some(row) if !in_head => row.push(cell),
+                        _ => header.push(cell),
+                    }
+                }
+                Event::End(TagEnd::Table) => break,
+                _ => {}
+            }
+        }
+
+        let events = self.events[start..self.pos.min(self.events.len())].to_vec();
+        Block {
+            kind: "table",
+            markup: render_events(events, self.opts),
+            header,
+            rows,
+            alignments: alignments.iter().map(alignment_name).collect(),
+            ..Block::default()
+        }
+    }
+
+    fn paragraph(&mut self, mut inline: Vec<Event<'a>>) -> Block {
+        if let Some(i) = inline
+            .iter()
+            .position(|e| matches!(e, Event::TaskListMarker(_)))
+        {
+            if let Event::TaskListMarker(checked) = inline.remove(i) {
+                self.task = Some(checked);
+            }
+        }
+
+        // A paragraph holding nothing but $$...$$ is a math block
+        let mut content = inline
+            .iter()
+            .filter(|e| !matches!(e, Event::SoftBreak | Event::HardBreak));
+        if let (Some(Event::DisplayMath(tex)), None) = (content.next(), content.next()) {
+            return Block {
+                kind: "math",
+                text: tex.trim().to_string(),
+                markup: render_events(inline.iter().cloned(), self.opts),
+                ..Block::default()
+            };
+        }
+
+        Block {
+            kind: "paragraph",
+            text: plain_text(&inline).trim_end().to_string(),
+            markup: render_events(inline, self.opts),
+            ..Block::default()
+        }
+    }
+
+    fn heading(&mut self, level: HeadingLevel, inline: Vec<Event<'a>>) -> Block {
+        let text = plain_text(&inline);
+        let events = std::iter::once(Event::Start(Tag::Heading {
+            level,
+            id: None,
+            classes: Vec::new(),
+            attrs: Vec::new(),
+        }))
+        .chain(inline)
+        .chain(std::iter::once(Event::End(TagEnd::Heading(level))));
+        Block {
+            kind: "heading",
+            markup: render_events(events, self.opts),
+            text,
+            level: level as u8,
+            ..Block::default()
+        }
+    }
+
+    /// Events up to the end of the tag just opened, consuming the end
+    fn take_until_end(&mut self) -> Vec<Event<'a>> {
+        let mut depth = 0usize;
+        let mut taken = Vec::new();
+        while let Some(event) = self.events.get(self.pos).cloned() {
+            self.pos += 1;
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => break,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+            taken.push(event);
+        }
+        taken
+    }
+
+    /// A run of inline events at block level
+    fn take_inline(&mut self) -> Vec<Event<'a>> {
+        let mut depth = 0usize;
+        let mut taken = Vec::new();
+        while let Some(event) = self.events.get(self.pos) {
+            match event {
+                Event::Start(_) if is_inline(event) => depth += 1,
+                Event::End(_) if depth > 0 => depth -= 1,
+                _ if depth == 0 && !is_inline(event) => break,
+                _ => {}
+            }
+            taken.push(event.clone());
+            self.pos += 1;
+        }
+        taken
+    }
+}
+
+fn is_inline(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => matches!(
+            tag,
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. } | Tag::Image { .. }
+        ),
+        Event::End(tag) => matches!(
+            tag,
+            TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough
+                | TagEnd::Link
+                | TagEnd::Image
+        ),
+        Event::Rule => false,
+        _ => true,
+    }
+}
+
+fn plain_text(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(t)
+            | Event::Code(t)
+            | Event::InlineMath(t)
+            | Event::DisplayMath(t)
+            | Event::Html(t)
+            | Event::InlineHtml(t) => text.push_str(t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn alignment_name(alignment: &Alignment) -> &'static str {
+    match alignment {
+        Alignment::None => "none",
+        Alignment::Left => "left",
+        Alignment::Center => "center",
+        Alignment::Right => "right",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(markdown: &str) -> Vec<Block> {
+        parse(markdown, &RenderOptions::default())
+    }
+
+    #[test]
+    fn block_kinds() {
+        let kinds: Vec<&str> = blocks(
+            "# Title\n\ntext\n\n```py\nx = 1\n```\n\n$$a+b$$\n\n| a |\n|---|\n| 1 |\n\n> q\n\n- i\n\n---",
+        )
+        .iter()
+        .map(|b| b.kind)
+        .collect();
+        assert_eq!(
+            kinds,
+            [
+                "heading",
+                "paragraph",
+                "code",
+                "math",
+                "table",
+                "quote",
+                "list",
+                "rule"
+            ]
+        );
+    }
+
+    #[test]
+    fn task_items() {
+        let list = blocks("- [x] done\n- [ ] todo\n- plain").remove(0);
+        let checked: Vec<Option<bool>> = list.items.iter().map(|i| i.checked).collect();
+        assert_eq!(checked, [Some(true), Some(false), None]);
+        assert_eq!(
+            list.items[1].__repr__(),
+            "ListItem(checked=False, blocks=1)"
+        );
+        assert_eq!(list.items[2].__repr__(), "ListItem(checked=None, blocks=1)");
+    }
+
+    #[test]
+    fn code_and_tables_keep_their_details() {
+        let parsed = blocks("```rust\nfn f() {}\n```\n\n| a | b |\n|:-|-:|\n| 1 | 2 |");
+        assert_eq!(parsed[0].language.as_deref(), Some("rust"));
+        assert_eq!(parsed[0].text, "fn f() {}\n");
+        assert_eq!(parsed[1].header.len(), 2);
+        assert_eq!(parsed[1].alignments, ["left", "right"]);
+        assert_eq!(parsed[1].rows, [["1", "2"]]);
+    }
+
+    #[test]
+    fn ordered_lists_keep_their_start() {
+        assert_eq!(blocks("3. three\n4. four")[0].start, Some(3));
+        assert_eq!(blocks("- bullet")[0].start, None);
+    }
+}
@@ -0,0 +1,37 @@
+// Markdown parsing and rendering for chat messages
+
+mod blocks;
+mod options;
+mod plain;
+mod render;
+mod streaming;
+mod table;
+
+use pyo3::prelude::*;
+
+pub use blocks::{Block, ListItem};
+pub use options::RenderOptions;
+pub use plain::to_plain_text;
+pub use streaming::{RenderedBlock, StreamingRenderer};
+
+/// Render markdown to Pango markup for a Gtk.Label
+///
+/// The result is always well-formed: source text is escaped and tags are
+/// balanced, so `set_markup` never rejects it.
+#[pyfunction]
+#[pyo3(signature = (markdown, options = None))]
+pub fn render_pango(py: Python<'_>, markdown: &str, options: Option<RenderOptions>) -> String {
+    let options = options.unwrap_or_default();
+    py.allow_threads(|| render::render(markdown, &options))
+}
+
+/// Parse markdown into a list of typed blocks
+///
+/// Lets the UI build real widgets (a Gtk.Grid per table, a copy button per
+/// code block) instead of one big label.
+#[pyfunction]
+#[pyo3(signature = (markdown, options = None))]
+pub fn parse_blocks(py: Python<'_>, markdown: &str, options: Option<RenderOptions>) -> Vec<Block> {
+    let options = options.unwrap_or_default();
+    py.allow_threads(|| blocks::parse(markdown, &options))
+}
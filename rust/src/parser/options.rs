@@ -0,0 +1,75 @@
+// Rendering options shared by the markdown renderers
+
+use pyo3::prelude::*;
+
+/// Colours and switches for render_pango
+///
+/// Defaults match the dark theme in constants.py.
+#[pyclass]
+#[derive(Clone)]
+pub struct RenderOptions {
+    #[pyo3(get, set)]
+    pub link_color: String,
+    #[pyo3(get, set)]
+    pub code_color: String,
+    #[pyo3(get, set)]
+    pub quote_color: String,
+    #[pyo3(get, set)]
+    pub math_color: String,
+    #[pyo3(get, set)]
+    pub dim_color: String,
+    /// Emit `<a href>` (clickable in Gtk.Label) instead of a styled span
+    #[pyo3(get, set)]
+    pub clickable_links: bool,
+    /// Maximum table width in monospace cells; long cells wrap to fit
+    #[pyo3(get, set)]
+    pub table_width: usize,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            link_color: "#4a9eff".to_string(),
+            code_color: "#a9b7c6".to_string(),
+            quote_color: "#4a9eff".to_string(),
+            math_color: "#9cdcfe".to_string(),
+            dim_color: "#a0a0a0".to_string(),
+            clickable_links: true,
+            table_width: 72,
+        }
+    }
+}
+
+#[pymethods]
+impl RenderOptions {
+    #[new]
+    #[pyo3(signature = (
+        link_color = None,
+        code_color = None,
+        quote_color = None,
+        math_color = None,
+        dim_color = None,
+        clickable_links = true,
+        table_width = None
+    ))]
+    fn new(
+        link_color: Option<String>,
+        code_color: Option<String>,
+        quote_color: Option<String>,
+        math_color: Option<String>,
+        dim_color: Option<String>,
+        clickable_links: bool,
+        table_width: Option<usize>,
+    ) -> Self {
+        let defaults = RenderOptions::default();
+        RenderOptions {
+            link_color: link_color.unwrap_or(defaults.link_color),
+            code_color: code_color.unwrap_or(defaults.code_color),
+            quote_color: quote_color.unwrap_or(defaults.quote_color),
+            math_color: math_color.unwrap_or(defaults.math_color),
+            dim_color: dim_color.unwrap_or(defaults.dim_color),
+            clickable_links,
+            table_width: table_width.unwrap_or(defaults.table_width),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Markdown to plain text, for previews and search snippets
+
+use pulldown_cmark::{Event, Options, Parser, TagEnd};
+
+/// Strip markdown syntax, keeping the readable text on a single line
+pub fn to_plain_text(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut out = String::with_capacity(markdown.len());
+
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            // Raw HTML is kept as text: in chat messages it is usually literal
+            Event::Text(text) | Event::Code(text) | Event::InlineHtml(text) | Event::Html(text) => {
+                out.push_str(&text)
+            }
+            Event::SoftBreak | Event::HardBreak | Event::Rule => out.push(' '),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::Item
+                | TagEnd::TableCell
+                | TagEnd::TableRow
+                | TagEnd::TableHead
+                | TagEnd::BlockQuote(_),
+            ) => out.push(' '),
+            _ => {}
+        }
+    }
+
+    // Collapse whitespace runs, including newlines inside code blocks
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,669 @@
+// CommonMark to Pango markup
+//
+// Output is built from a tag stack, and every piece of source text goes
+// through pango::escape, so the result is always well-formed whatever the
+// input looks like.
+
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+use super::options::RenderOptions;
+use super::table::{self, Cell, Run, Table};
+use crate::highlight::highlight_lines;
+use crate::math;
+use crate::pango::escape;
+
+const RULE_WIDTH: usize = 40;
+const BULLETS: &[&str] = &["•", "◦", "▪"];
+
+/// Parser options used everywhere markdown is read for display
+pub fn markdown_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_MATH
+        | Options::ENABLE_GFM
+}
+
+/// Render a markdown document to Pango markup
+pub fn render(markdown: &str, options: &RenderOptions) -> String {
+    render_events(Parser::new_ext(markdown, markdown_options()), options)
+}
+
+/// Render already parsed events, e.g. a single block or table cell
+pub fn render_events<'a>(
+    events: impl IntoIterator<Item = Event<'a>>,
+    options: &RenderOptions,
+) -> String {
+    let mut renderer = Renderer::new(options);
+    for event in events {
+        renderer.event(event);
+    }
+    renderer.finish()
+}
+
+/// A block container that prefixes each of its lines (quote bar, list marker)
+struct Container {
+    /// Prefix for the first line, consumed once written
+    first: Option<String>,
+    rest: String,
+}
+
+/// A table cell being captured as styled runs
+struct CellCapture {
+    runs: Cell,
+    /// Output and line-start state to restore after the cell
+    saved_out: String,
+    saved_at_line_start: bool,
+    /// Tags open before the cell, which its runs don't repeat
+    outer_tags: usize,
+}
+
+struct Renderer<'o> {
+    opts: &'o RenderOptions,
+    out: String,
+    containers: Vec<Container>,
+    /// Opening markup and closing tag of the currently open spans
+    open_tags: Vec<(String, &'static str)>,
+    /// Next number for each open list, None for bullet lists
+    lists: Vec<Option<u64>>,
+    at_line_start: bool,
+    pending_gap: bool,
+    link_depth: usize,
+    code: Option<(String, String)>,
+    table: Option<Table>,
+    cell: Option<CellCapture>,
+}
+
+impl<'o> Renderer<'o> {
+    fn new(opts: &'o RenderOptions) -> Self {
+        Renderer {
+            opts,
+            out: String::new(),
+            containers: Vec::new(),
+            open_tags: Vec::new(),
+            lists: Vec::new(),
+            at_line_start: true,
+            pending_gap: false,
+            link_depth: 0,
+            code: None,
+            table: None,
+            cell: None,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        while !self.open_tags.is_empty() {
+            self.close();
+        }
+        let trimmed = self.out.trim_end_matches('\n').len();
+        self.out.truncate(trimmed);
+        self.out
+    }
+
+    fn event(&mut self, event: Event<'_>) {
+        if let Some((_, code)) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.end_code_block(),
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text_with_links(&text),
+            Event::Code(code) => {
+                let open = format!(
+                    "<tt><span foreground=\"{}\">",
+                    escape(&self.opts.code_color)
+                );
+                self.open_raw(&open, "</span></tt>");
+                self.text(&code);
+                self.close();
+            }
+            Event::InlineMath(tex) => {
+                let open = format!("<i><span foreground=\"{}\">", escape(&self.opts.math_color));
+                self.open_raw(&open, "</span></i>");
+                self.text(&math::render(&tex, false));
+                self.close();
+            }
+            Event::DisplayMath(tex) => {
+                self.ensure_newline();
+                let open = format!(
+                    "<tt><span foreground=\"{}\">",
+                    escape(&self.opts.math_color)
+                );
+                for line in math::render(&tex, true).lines() {
+                    self.open_raw(&open, "</span></tt>");
+                    self.text(&format!("    {}", line));
+                    self.close();
+                    self.newline();
+                }
+            }
+            // Raw HTML is shown as typed, never interpreted
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            Event::SoftBreak | Event::HardBreak => self.newline(),
+            Event::Rule => {
+                self.start_block();
+                let rule = "─".repeat(RULE_WIDTH);
+                self.dim(&rule);
+                self.end_block();
+            }
+            Event::TaskListMarker(checked) => {
+                if let Some(container) = self.containers.last_mut() {
+                    if container.first.is_some() {
+                        let box_char = if checked { "☑" } else { "☐" };
+                        container.first = Some(format!("{} ", box_char));
+                    }
+                }
+            }
+            Event::FootnoteReference(name) => self.text(&format!("[{}]", name)),
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph => self.start_block(),
+            Tag::Heading { level, .. } => {
+                self.start_block();
+                let size = match level {
+                    HeadingLevel::H1 => "x-large",
+                    HeadingLevel::H2 => "large",
+                    HeadingLevel::H3 => "medium",
+                    _ => "",
+                };
+                if size.is_empty() {
+                    self.open_raw("<b>", "</b>");
+                } else {
+                    self.open_raw(
+                        &format!("<span weight=\"bold\" size=\"{}\">", size),
+                        "</span>",
+                    );
+                }
+            }
+            Tag::BlockQuote(_) => {
+                self.start_block();
+                let bar = format!(
+                    "<span foreground=\"{}\">▎</span> ",
+                    escape(&self.opts.quote_color)
+                );
+                self.containers.push(Container {
+                    first: None,
+                    rest: bar,
+                });
+                let dim = format!("<span foreground=\"{}\">", escape(&self.opts.dim_color));
+                self.push_tag(&dim, "</span>");
+            }
+            Tag::CodeBlock(kind) => {
+                self.start_block();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((lang, String::new()));
+            }
+            Tag::HtmlBlock => self.start_block(),
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.ensure_newline();
+                self.pending_gap = false;
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        let marker = format!("{}.", n);
+                        *n += 1;
+                        marker
+                    }
+                    _ => BULLETS[depth % BULLETS.len()].to_string(),
+                };
+                let indent = " ".repeat(marker.chars().count() + 1);
+                self.containers.push(Container {
+                    first: Some(format!("{} ", marker)),
+                    rest: indent,
+                });
+            }
+            Tag::Table(alignments) => {
+                self.start_block();
+                self.table = Some(Table {
+                    rows: Vec::new(),
+                    header_rows: 0,
+                    alignments,
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            Tag::TableCell => {
+                self.cell = Some(CellCapture {
+                    runs: Vec::new(),
+                    saved_out: std::mem::take(&mut self.out),
+                    saved_at_line_start: self.at_line_start,
+                    outer_tags: self.open_tags.len(),
+                });
+                self.at_line_start = false;
+            }
+            Tag::Emphasis => self.open_raw("<i>", "</i>"),
+            Tag::Strong => self.open_raw("<b>", "</b>"),
+            Tag::Strikethrough => self.open_raw("<s>", "</s>"),
+            Tag::Link { dest_url, .. } => self.open_link(&dest_url),
+            Tag::Image { dest_url, .. } => {
+                self.open_link(&dest_url);
+                self.text("🖼 ");
+            }
+            Tag::FootnoteDefinition(_)
+            | Tag::DefinitionList
+            | Tag::DefinitionListTitle
+            | Tag::DefinitionListDefinition
+            | Tag::MetadataBlock(_) => self.start_block(),
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => self.end_block(),
+            TagEnd::Heading(_) => {
+                self.close();
+                self.end_block();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.close();
+                self.containers.pop();
+                self.end_block();
+            }
+            TagEnd::CodeBlock => {}
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.end_block();
+            }
+            TagEnd::Item => {
+                self.ensure_newline();
+                self.containers.pop();
+            }
+            TagEnd::Table => self.end_table(),
+            TagEnd::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.header_rows = table.rows.len();
+                }
+            }
+            TagEnd::TableRow => {}
+            TagEnd::TableCell => {
+                if let Some(capture) = self.cell.take() {
+                    self.out = capture.saved_out;
+                    self.at_line_start = capture.saved_at_line_start;
+                    if let Some(row) = self.table.as_mut().and_then(|t| t.rows.last_mut()) {
+                        row.push(capture.runs);
+                    }
+                }
+            }
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => self.close(),
+            TagEnd::Link | TagEnd::Image => {
+                self.link_depth -= 1;
+                self.close();
+            }
+            TagEnd::FootnoteDefinition
+            | TagEnd::DefinitionList
+            | TagEnd::DefinitionListTitle
+            | TagEnd::DefinitionListDefinition
+            | TagEnd::MetadataBlock(_) => self.end_block(),
+        }
+    }
+
+    fn open_link(&mut self, url: &str) {
+        if self.opts.clickable_links && self.link_depth == 0 {
+            self.open_raw(&format!("<a href=\"{}\">", escape(url)), "</a>");
+        } else {
+            let open = format!(
+                "<span foreground=\"{}\" underline=\"single\">",
+                escape(&self.opts.link_color)
+            );
+            self.open_raw(&open, "</span>");
+        }
+        self.link_depth += 1;
+    }
+
+    fn end_code_block(&mut self) {
+        let Some((lang, code)) = self.code.take() else {
+            return;
+        };
+
+        let label = if lang.is_empty() {
+            "─".repeat(RULE_WIDTH)
+        } else {
+            let head = format!("── {} ", lang);
+            let fill = RULE_WIDTH.saturating_sub(head.chars().count());
+            format!("{}{}", head, "─".repeat(fill))
+        };
+        self.dim(&label);
+        self.newline();
+
+        let open = format!(
+            "<tt><span foreground=\"{}\">",
+            escape(&self.opts.code_color)
+        );
+        for segments in highlight_lines(&code, &lang) {
+            self.open_raw(&open, "</span></tt>");
+            for (span, text) in segments {
+                match span {
+                    Some(span) => {
+                        self.open_raw(&span, "</span>");
+                        self.text(&text);
+                        self.close();
+                    }
+                    None => self.text(&text),
+                }
+            }
+            self.close();
+            self.newline();
+        }
+
+        self.dim(&"─".repeat(RULE_WIDTH));
+        self.end_block();
+    }
+
+    fn end_table(&mut self) {
+        let Some(table) = self.table.take() else {
+            return;
+        };
+        for line in table::format(&table, self.opts.table_width) {
+            self.mono_line(&line);
+        }
+        self.end_block();
+    }
+
+    /// Write one line of pre-built markup in monospace
+    fn mono_line(&mut self, markup: &str) {
+        self.line_prefix();
+        self.out.push_str("<tt>");
+        self.out.push_str(markup);
+        self.out.push_str("</tt>");
+        self.newline();
+    }
+
+    fn dim(&mut self, text: &str) {
+        let open = format!("<span foreground=\"{}\">", escape(&self.opts.dim_color));
+        self.open_raw(&open, "</span>");
+        self.text(text);
+        self.close();
+    }
+
+    /// Text outside links gets bare URLs turned into links
+    fn text_with_links(&mut self, text: &str) {
+        if self.link_depth > 0 {
+            self.text(text);
+            return;
+        }
+
+        let mut rest = text;
+        while let Some((start, end)) = find_url(rest) {
+            self.text(&rest[..start]);
+            let url = &rest[start..end];
+            let href = if url.starts_with("www.") {
+                format!("http://{}", url)
+            } else {
+                url.to_string()
+            };
+            self.open_link(&href);
+            self.text(url);
+            self.link_depth -= 1;
+            self.close();
+            rest = &rest[end..];
+        }
+        self.text(rest);
+    }
+
+    /// Escape and write text, handling line starts
+    fn text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(capture) = &mut self.cell {
+                let tags = &self.open_tags[capture.outer_tags..];
+                capture.runs.push(Run {
+                    text: line.to_string(),
+                    open: tags.iter().map(|(open, _)| open.as_str()).collect(),
+                    close: tags.iter().rev().map(|(_, close)| *close).collect(),
+                });
+                continue;
+            }
+            self.line_prefix();
+            self.out.push_str(&escape(line));
+        }
+    }
+
+    fn open_raw(&mut self, open: &str, close: &'static str) {
+        self.line_prefix();
+        self.push_tag(open, close);
+    }
+
+    /// Open a span without starting a line; at a line start it is written
+    /// together with the prefixes of the next line
+    fn push_tag(&mut self, open: &str, close: &'static str) {
+        if !self.at_line_start {
+            self.out.push_str(open);
+        }
+        self.open_tags.push((open.to_string(), close));
+    }
+
+    fn close(&mut self) {
+        if let Some((_, close)) = self.open_tags.pop() {
+            if !self.at_line_start {
+                self.out.push_str(close);
+            }
+        }
+    }
+
+    /// End the line. Open spans are closed first and reopened after the next
+    /// line's prefixes, so every line is self-contained and prefixes stay unstyled.
+    fn newline(&mut self) {
+        if let Some(capture) = &mut self.cell {
+            // Breaks inside a cell are rewrapped with the rest of the text
+            capture.runs.push(Run {
+                text: " ".to_string(),
+                ..Run::default()
+            });
+            return;
+        }
+        if !self.at_line_start {
+            for (_, close) in self.open_tags.iter().rev() {
+                self.out.push_str(close);
+            }
+        }
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn ensure_newline(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+    }
+
+    /// Write container prefixes and reopen spans if this line is still empty
+    fn line_prefix(&mut self) {
+        if !self.at_line_start {
+            return;
+        }
+        self.at_line_start = false;
+
+        for container in &mut self.containers {
+            let prefix = container
+                .first
+                .take()
+                .unwrap_or_else(|| container.rest.clone());
+            self.out.push_str(&prefix);
+        }
+        for (open, _) in &self.open_tags {
+            self.out.push_str(open);
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.ensure_newline();
+        if self.pending_gap && !self.out.is_empty() {
+            let blank: String = self.containers.iter().map(|c| c.rest.as_str()).collect();
+            self.out.push_str(blank.trim_end());
+            self.out.push('\n');
+        }
+        self.pending_gap = false;
+    }
+
+    fn end_block(&mut self) {
+        self.ensure_newline();
+        self.pending_gap = true;
+    }
+}
+
+/// Byte range of the first bare URL in `text`
+fn find_url(text: &str) -> Option<(usize, usize)> {
+    let start = ["https://", "http://", "www."]
+        .iter()
+        .filter_map(|prefix| {
+            text.match_indices(prefix)
+                .find(|(i, _)| *i == 0 || !text[..*i].ends_with(|c: char| c.is_alphanumeric()))
+                .map(|(i, _)| i)
+        })
+        .min()?;
+
+    let tail = &text[start..];
+    let mut end = tail
+        .find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"')
+        .unwrap_or(tail.len());
+
+    // Trailing punctuation is almost always sentence punctuation
+    while end > 0 {
+        let last = tail[..end].chars().next_back().unwrap_or(' ');
+        let unbalanced_paren =
+            last == ')' && tail[..end].matches('(').count() < tail[..end].matches(')').count();
+        if ".,;:!?'*_".contains(last) || unbalanced_paren {
+            end -= last.len_utf8();
+        } else {
+            break;
+        }
+    }
+
+    let min_len = if tail.starts_with("www.") { 5 } else { 9 };
+    (end >= min_len).then_some((start, start + end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pango(markdown: &str) -> String {
+        render(markdown, &RenderOptions::default())
+    }
+
+    /// Tags balance and every `<` and `&` outside tags is escaped
+    fn assert_well_formed(markup: &str) {
+        let mut stack: Vec<&str> = Vec::new();
+        let mut rest = markup;
+        while let Some(i) = rest.find(['<', '&']) {
+            rest = &rest[i..];
+            if rest.starts_with('&') {
+                let end = rest.find(';').expect("unterminated entity");
+                let entity = &rest[1..end];
+                let numeric = entity
+                    .strip_prefix('#')
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+                assert!(
+                    numeric || matches!(entity, "amp" | "lt" | "gt" | "quot" | "apos"),
+                    "bad entity in {:?}",
+                    markup
+                );
+                rest = &rest[end + 1..];
+                continue;
+            }
+            let end = rest.find('>').expect("unterminated tag");
+            let tag = &rest[1..end];
+            match tag.strip_prefix('/') {
+                Some(name) => assert_eq!(stack.pop(), Some(name), "in {:?}", markup),
+                None => stack.push(tag.split(' ').next().unwrap()),
+            }
+            rest = &rest[end + 1..];
+        }
+        assert!(stack.is_empty(), "unclosed {:?} in {:?}", stack, markup);
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            pango("**bold** and *it* ~~gone~~"),
+            "<b>bold</b> and <i>it</i> <s>gone</s>"
+        );
+        assert_eq!(
+            pango("`x < y`"),
+            "<tt><span foreground=\"#a9b7c6\">x &lt; y</span></tt>"
+        );
+    }
+
+    #[test]
+    fn source_text_is_escaped() {
+        assert_eq!(pango("<b>&amp; x < y"), "&lt;b&gt;&amp; x &lt; y");
+        assert_eq!(
+            pango("[link](http://e.com?a=1&b=2)"),
+            "<a href=\"http://e.com?a=1&amp;b=2\">link</a>"
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            pango("# Title\n\npara"),
+            "<span weight=\"bold\" size=\"x-large\">Title</span>\n\npara"
+        );
+        assert_eq!(
+            pango("- a\n- [x] b\n\n1. one\n2. two"),
+            "• a\n☑ b\n\n1. one\n2. two"
+        );
+        assert_eq!(
+            pango("| a | b |\n|---|:-:|\n| 1 | 2 |"),
+            "<tt>┌───┬───┐</tt>\n<tt>│ <b>a</b> │ <b>b</b> │</tt>\n<tt>├───┼───┤</tt>\n\
+             <tt>│ 1 │ 2 │</tt>\n<tt>└───┴───┘</tt>"
+        );
+    }
+
+    #[test]
+    fn links_can_be_plain_spans() {
+        let options = RenderOptions {
+            clickable_links: false,
+            ..Default::default()
+        };
+        let markup = render("[site](http://example.com)", &options);
+        assert!(!markup.contains("<a "));
+        assert!(markup.contains("site"));
+        assert_well_formed(&markup);
+    }
+
+    #[test]
+    fn always_well_formed() {
+        for markdown in [
+            "***<i>**",
+            "**unclosed *nested `code",
+            "> quote with **bold\n> over lines**",
+            "- item\n  > quote in list\n  ```\n  <code> & more\n  ```",
+            "| a | *b |\n|---|---|\n| <x> | & |",
+            "$$\\frac{a}{b}$$ and $x^2",
+            "```python\nprint('<tag>' & 1)\n```",
+            "[link with **bold**](<http://e.com/a b>) <http://auto.link?x&y>",
+            "<span foreground='red'>raw html</span>",
+            "&nbsp; &#169; &unknown;",
+        ] {
+            assert_well_formed(&pango(markdown));
+        }
+    }
+}
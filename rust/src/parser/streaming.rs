@@ -0,0 +1,355 @@
+// Incremental markdown rendering for streamed responses
+//
+// The accumulated text is split into top-level blocks. A block is finished
+// once a later block has started after a blank line (or right away for
+// headings, rules and closed fences, which can't grow). Finished blocks are
+// rendered once; only the trailing open block is re-rendered as chunks
+// arrive, so a long answer costs roughly linear time instead of a full
+// re-parse per chunk.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use pyo3::prelude::*;
+
+use super::options::RenderOptions;
+use super::render::{markdown_options, render};
+use crate::api::py_bool;
+
+/// A rendered top-level block
+#[pyclass]
+#[derive(Clone)]
+pub struct RenderedBlock {
+    /// Stable for the lifetime of the block, including when it closes
+    #[pyo3(get)]
+    pub id: u64,
+    #[pyo3(get)]
+    pub markup: String,
+    /// False while the block can still change
+    #[pyo3(get)]
+    pub closed: bool,
+}
+
+#[pymethods]
+impl RenderedBlock {
+    fn __repr__(&self) -> String {
+        format!(
+            "RenderedBlock(id={}, closed={}, markup={:?})",
+            self.id,
+            py_bool(self.closed),
+            self.markup
+        )
+    }
+}
+
+/// Stateful renderer fed with streamed chunks
+///
+/// `push()` returns only the blocks whose markup changed; the UI can keep one
+/// widget per block id and update just those.
+#[pyclass]
+pub struct StreamingRenderer {
+    options: RenderOptions,
+    source: String,
+    /// Byte offset where the open block starts; everything before is closed
+    committed_end: usize,
+    closed: Vec<RenderedBlock>,
+    open: Option<RenderedBlock>,
+    next_id: u64,
+}
+
+#[pymethods]
+impl StreamingRenderer {
+    #[new]
+    #[pyo3(signature = (options = None))]
+    fn new(options: Option<RenderOptions>) -> Self {
+        StreamingRenderer {
+            options: options.unwrap_or_default(),
+            source: String::new(),
+            committed_end: 0,
+            closed: Vec::new(),
+            open: None,
+            next_id: 0,
+        }
+    }
+
+    /// Append a chunk and return the blocks that changed
+    fn push(&mut self, py: Python<'_>, text: &str) -> Vec<RenderedBlock> {
+        self.source.push_str(text);
+        py.allow_threads(|| self.update(false))
+    }
+
+    /// Mark the stream complete, closing the trailing block
+    fn finish(&mut self, py: Python<'_>) -> Vec<RenderedBlock> {
+        py.allow_threads(|| self.update(true))
+    }
+
+    /// All blocks rendered so far, in document order
+    fn blocks(&self) -> Vec<RenderedBlock> {
+        self.closed
+            .iter()
+            .chain(self.open.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// The whole document as one markup string
+    fn markup(&self) -> String {
+        self.closed
+            .iter()
+            .chain(self.open.iter())
+            .map(|b| b.markup.as_str())
+            .filter(|m| !m.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Raw markdown received so far
+    #[getter]
+    fn text(&self) -> String {
+        self.source.clone()
+    }
+
+    /// Forget everything, e.g. when a response is regenerated
+    fn reset(&mut self) {
+        self.source.clear();
+        self.committed_end = 0;
+        self.closed.clear();
+        self.open = None;
+    }
+}
+
+impl StreamingRenderer {
+    fn update(&mut self, finished: bool) -> Vec<RenderedBlock> {
+        let tail = &self.source[self.committed_end..];
+        let blocks = top_level_blocks(tail);
+        if blocks.is_empty() {
+            return Vec::new();
+        }
+
+        let mut changed = Vec::new();
+        let mut open_id = self.open.take().map(|b| b.id);
+        let mut next_id = self.next_id;
+        let mut allocate_id = || {
+            next_id += 1;
+            next_id - 1
+        };
+
+        // Close blocks from the front until one could still grow
+        let mut open_start = 0;
+        for pair in blocks.windows(2) {
+            let (block, next) = (&pair[0], &pair[1]);
+            let source = &tail[block.range.start..next.range.start];
+            if !block.self_contained && !ends_with_blank_line(source) {
+                break;
+            }
+            let block = RenderedBlock {
+                id: open_id.take().unwrap_or_else(&mut allocate_id),
+                markup: render(source, &self.options),
+                closed: true,
+            };
+            changed.push(block.clone());
+            self.closed.push(block);
+            open_start = next.range.start;
+        }
+
+        let source = &tail[open_start..];
+        let markup = if finished {
+            render(source, &self.options)
+        } else {
+            render(&complete_partial_table(source), &self.options)
+        };
+        let block = RenderedBlock {
+            id: open_id.unwrap_or_else(allocate_id),
+            markup,
+            closed: finished,
+        };
+
+        self.next_id = next_id;
+        self.committed_end += open_start;
+        changed.push(block.clone());
+        if finished {
+            self.committed_end = self.source.len();
+            self.closed.push(block);
+        } else {
+            self.open = Some(block);
+        }
+        changed
+    }
+}
+
+struct TopLevelBlock {
+    range: Range<usize>,
+    /// Headings, rules and fenced code can't absorb what follows them
+    self_contained: bool,
+}
+
+fn top_level_blocks(text: &str) -> Vec<TopLevelBlock> {
+    let mut blocks = Vec::new();
+    let mut depth = 0usize;
+
+    for (event, range) in Parser::new_ext(text, markdown_options()).into_offset_iter() {
+        match event {
+            Event::Start(tag) => {
+                if depth == 0 {
+                    let self_contained = matches!(
+                        tag,
+                        Tag::Heading { .. } | Tag::CodeBlock(CodeBlockKind::Fenced(_))
+                    );
+                    blocks.push(TopLevelBlock {
+                        range,
+                        self_contained,
+                    });
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth = depth.saturating_sub(1),
+            _ if depth == 0 => blocks.push(TopLevelBlock {
+                range,
+                self_contained: true,
+            }),
+            _ => {}
+        }
+    }
+    blocks
+}
+
+/// Whether `text` ends with an empty (or whitespace-only) line
+fn ends_with_blank_line(text: &str) -> bool {
+    text.chars()
+        .rev()
+        .take_while(|c| c.is_whitespace())
+        .filter(|&c| c == '\n')
+        .count()
+        >= 2
+}
+
+/// A table whose delimiter row hasn't arrived yet parses as a paragraph of
+/// pipes; give it a delimiter row so it renders as a table from the start
+fn complete_partial_table(source: &str) -> Cow<'_, str> {
+    let lines: Vec<&str> = source.trim_end().lines().collect();
+    let header = match lines.first() {
+        Some(line) if line.trim_start().starts_with('|') => line.trim(),
+        _ => return Cow::Borrowed(source),
+    };
+    let cells = |line: &str| line.trim().trim_matches('|').split('|').count();
+    let is_delimiter = |line: &str| {
+        let line = line.trim();
+        !line.is_empty() && line.chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+    };
+
+    let columns = cells(header);
+    let delimiter = format!("|{}", "---|".repeat(columns));
+    match lines.len() {
+        1 => Cow::Owned(format!("{}\n{}", header, delimiter)),
+        // Delimiter row still streaming in
+        2 if is_delimiter(lines[1]) && cells(lines[1]) != columns => {
+            Cow::Owned(format!("{}\n{}", header, delimiter))
+        }
+        _ => Cow::Borrowed(source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "# Streaming\n\nA paragraph with **bold** text\nover two lines.\n\n\
+        - one\n- two\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\n\n\
+        | a | b |\n|---|---|\n| 1 | 2 |\n\n> quoted\n\n---\n\nThe end with $x^2$.";
+
+    fn stream(chunks: &[&str]) -> (StreamingRenderer, Vec<RenderedBlock>) {
+        let mut renderer = StreamingRenderer::new(None);
+        let mut updates = Vec::new();
+        for chunk in chunks {
+            renderer.source.push_str(chunk);
+            updates.extend(renderer.update(false));
+        }
+        updates.extend(renderer.update(true));
+        (renderer, updates)
+    }
+
+    fn split(text: &str, size: usize) -> Vec<&str> {
+        let mut chunks = Vec::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            let mut at = size.min(rest.len());
+            while !rest.is_char_boundary(at) {
+                at += 1;
+            }
+            let (chunk, tail) = rest.split_at(at);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        chunks
+    }
+
+    #[test]
+    fn matches_rendering_the_whole_document() {
+        let whole = render(DOCUMENT, &RenderOptions::default());
+        for size in [1, 3, 7, 16, 64, DOCUMENT.len()] {
+            let (renderer, _) = stream(&split(DOCUMENT, size));
+            assert_eq!(renderer.markup(), whole, "chunk size {}", size);
+            assert!(renderer.blocks().iter().all(|b| b.closed));
+        }
+    }
+
+    #[test]
+    fn closed_blocks_are_not_sent_again() {
+        let (renderer, updates) = stream(&split(DOCUMENT, 5));
+        let mut closed = std::collections::HashSet::new();
+        for block in &updates {
+            assert!(
+                !closed.contains(&block.id),
+                "block {} changed after closing",
+                block.id
+            );
+            if block.closed {
+                closed.insert(block.id);
+            }
+        }
+        let ids: Vec<u64> = renderer.blocks().iter().map(|b| b.id).collect();
+        assert_eq!(ids, (0..ids.len() as u64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn open_block_keeps_its_id() {
+        let mut renderer = StreamingRenderer::new(None);
+        renderer.source.push_str("Hello");
+        let first = renderer.update(false);
+        renderer.source.push_str(" world\n\nNext");
+        let second = renderer.update(false);
+
+        assert_eq!(first.len(), 1);
+        assert!(!first[0].closed);
+        assert_eq!(second[0].id, first[0].id);
+        assert!(second[0].closed);
+        assert_eq!(second[0].markup, "Hello world");
+        assert_eq!(second[1].markup, "Next");
+        assert!(!second[1].closed);
+    }
+
+    #[test]
+    fn partial_tables_render_as_tables() {
+        let mut renderer = StreamingRenderer::new(None);
+        renderer.source.push_str("| a | b |");
+        let update = renderer.update(false);
+        assert!(update[0].markup.starts_with("<tt>"));
+        assert_eq!(
+            complete_partial_table("| a | b |\n|--"),
+            "| a | b |\n|---|---|"
+        );
+        assert_eq!(complete_partial_table("plain"), "plain");
+    }
+
+    #[test]
+    fn reset_starts_over_with_fresh_blocks() {
+        let (mut renderer, _) = stream(&["first\n\nsecond"]);
+        renderer.reset();
+        assert!(renderer.blocks().is_empty());
+        renderer.source.push_str("again");
+        let update = renderer.update(true);
+        assert_eq!(update.len(), 1);
+        assert_eq!(renderer.markup(), "again");
+    }
+}
@@ -0,0 +1,359 @@
+// Box-drawn table layout for monospace Pango output
+//
+// Cells are kept as styled runs rather than markup so they can be wrapped
+// at any character: every output line reopens the styles it needs, which
+// keeps the markup well-formed. Widths are terminal cell widths, so CJK
+// and emoji count double.
+
+use pulldown_cmark::Alignment;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::pango::escape;
+
+/// Columns are never squeezed below this unless the table can't fit at all
+const MIN_COLUMN_WIDTH: usize = 3;
+
+/// Text with the markup that styles it
+#[derive(Clone, Debug, Default)]
+pub struct Run {
+    pub text: String,
+    pub open: String,
+    pub close: String,
+}
+
+pub type Cell = Vec<Run>;
+
+pub struct Table {
+    pub rows: Vec<Vec<Cell>>,
+    pub header_rows: usize,
+    pub alignments: Vec<Alignment>,
+}
+
+/// Lay the table out within `max_width` cells, returning markup lines
+///
+/// Borders are plain box-drawing characters; header cells are bold.
+pub fn format(table: &Table, max_width: usize) -> Vec<String> {
+    let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return Vec::new();
+    }
+    let widths = column_widths(table, columns, max_width);
+
+    let border = |left: &str, mid: &str, right: &str| {
+        let parts: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+        format!("{}{}{}", left, parts.join(mid), right)
+    };
+
+    let mut lines = vec![border("┌", "┬", "┐")];
+    for (r, row) in table.rows.iter().enumerate() {
+        let header = r < table.header_rows;
+        let wrapped: Vec<Vec<Vec<(usize, String)>>> = (0..columns)
+            .map(|i| {
+                row.get(i)
+                    .map_or_else(Vec::new, |cell| wrap(cell, widths[i]))
+            })
+            .collect();
+        let height = wrapped.iter().map(Vec::len).max().unwrap_or(0).max(1);
+
+        for l in 0..height {
+            let mut line = String::from("│");
+            for (i, cell_lines) in wrapped.iter().enumerate() {
+                let (markup, used) = match (cell_lines.get(l), row.get(i)) {
+                    (Some(pieces), Some(cell)) => (to_markup(cell, pieces), pieces_width(pieces)),
+                    _ => (String::new(), 0),
+                };
+                let markup = if header && !markup.is_empty() {
+                    format!("<b>{}</b>", markup)
+                } else {
+                    markup
+                };
+                let align = table.alignments.get(i).copied().unwrap_or(Alignment::None);
+                let (left, right) = padding(widths[i].saturating_sub(used), align);
+                line.push_str(&format!(
+                    " {}{}{} │",
+                    " ".repeat(left),
+                    markup,
+                    " ".repeat(right)
+                ));
+            }
+            lines.push(line);
+        }
+        if r + 1 == table.header_rows && table.rows.len() > table.header_rows {
+            lines.push(border("├", "┼", "┤"));
+        }
+    }
+    lines.push(border("└", "┴", "┘"));
+    lines
+}
+
+/// Natural widths, shrinking the widest column until the table fits
+fn column_widths(table: &Table, columns: usize, max_width: usize) -> Vec<usize> {
+    let mut widths = vec![0; columns];
+    for row in &table.rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width: usize = cell.iter().map(|run| run.text.width()).sum();
+            widths[i] = widths[i].max(width);
+        }
+    }
+
+    // Each column adds " x │": one space either side and a border
+    let chrome = 3 * columns + 1;
+    let budget = max_width.saturating_sub(chrome);
+    while widths.iter().sum::<usize>() > budget {
+        let Some(widest) = widths
+            .iter_mut()
+            .filter(|w| **w > MIN_COLUMN_WIDTH)
+            .max_by_key(|w| **w)
+        else {
+            break;
+        };
+        *widest -= 1;
+    }
+    widths
+}
+
+/// Word-wrap a cell to `width`, as lines of (run index, text) pieces
+fn wrap(cell: &Cell, width: usize) -> Vec<Vec<(usize, String)>> {
+    let width = width.max(1);
+    // Words keep trailing whitespace so it can be dropped at line ends
+    let mut words: Vec<Vec<(usize, char)>> = Vec::new();
+    let mut in_space = true;
+    for (r, run) in cell.iter().enumerate() {
+        for c in run.text.chars() {
+            if !c.is_whitespace() && in_space {
+                words.push(Vec::new());
+            }
+            in_space = c.is_whitespace();
+            if let Some(word) = words.last_mut() {
+                word.push((r, if in_space { ' ' } else { c }));
+            }
+        }
+    }
+
+    let mut lines: Vec<Vec<(usize, char)>> = vec![Vec::new()];
+    let mut used = 0;
+    for word in words {
+        let trimmed = word.iter().rev().skip_while(|(_, c)| *c == ' ').count();
+        let word_width: usize = word[..trimmed].iter().map(|&(_, c)| char_width(c)).sum();
+
+        if used > 0 && used + word_width > width {
+            lines.push(Vec::new());
+            used = 0;
+        }
+        for (r, c) in word {
+            let w = char_width(c);
+            if used + w > width {
+                if c == ' ' {
+                    continue;
+                }
+                // Hard break inside a word longer than the column
+                lines.push(Vec::new());
+                used = 0;
+            }
+            if c == ' ' && used == 0 {
+                continue;
+            }
+            lines.last_mut().unwrap().push((r, c));
+            used += w;
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|line| {
+            let mut pieces: Vec<(usize, String)> = Vec::new();
+            for (r, c) in line {
+                match pieces.last_mut() {
+                    Some((last, text)) if *last == r => text.push(c),
+                    _ => pieces.push((r, c.to_string())),
+                }
+            }
+            // Trailing spaces would misalign the padding
+            if let Some((_, text)) = pieces.last_mut() {
+                let len = text.trim_end().len();
+                text.truncate(len);
+            }
+            pieces
+        })
+        .filter(|pieces| !pieces.is_empty())
+        .collect()
+}
+
+fn to_markup(cell: &Cell, pieces: &[(usize, String)]) -> String {
+    pieces
+        .iter()
+        .map(|(r, text)| {
+            let run = &cell[*r];
+            format!("{}{}{}", run.open, escape(text), run.close)
+        })
+        .collect()
+}
+
+fn pieces_width(pieces: &[(usize, String)]) -> usize {
+    pieces.iter().map(|(_, text)| text.width()).sum()
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+fn padding(space: usize, align: Alignment) -> (usize, usize) {
+    match align {
+        Alignment::Right => (space, 0),
+        Alignment::Center => (space / 2, space - space / 2),
+        Alignment::Left | Alignment::None => (0, space),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::render::render;
+    use crate::parser::RenderOptions;
+
+    fn cell(text: &str) -> Cell {
+        vec![Run {
+            text: text.to_string(),
+            ..Run::default()
+        }]
+    }
+
+    fn table(rows: &[&[&str]], header_rows: usize, alignments: &[Alignment]) -> Table {
+        Table {
+            rows: rows
+                .iter()
+                .map(|row| row.iter().map(|text| cell(text)).collect())
+                .collect(),
+            header_rows,
+            alignments: alignments.to_vec(),
+        }
+    }
+
+    /// Display width of a markup line, tags and entities removed
+    fn width(line: &str) -> usize {
+        let mut text = String::new();
+        let mut in_tag = false;
+        for c in line.chars() {
+            match c {
+                '<' => in_tag = true,
+                '>' => in_tag = false,
+                _ if !in_tag => text.push(c),
+                _ => {}
+            }
+        }
+        text.replace("&lt;", "<").replace("&amp;", "&").width()
+    }
+
+    #[test]
+    fn header_rule_and_alignment() {
+        let t = table(
+            &[&["Name", "Qty", "Note"], &["tea", "12", "ok"]],
+            1,
+            &[Alignment::Left, Alignment::Right, Alignment::Center],
+        );
+        assert_eq!(
+            format(&t, 72),
+            [
+                "┌──────┬─────┬──────┐",
+                "│ <b>Name</b> │ <b>Qty</b> │ <b>Note</b> │",
+                "├──────┼─────┼──────┤",
+                "│ tea  │  12 │  ok  │",
+                "└──────┴─────┴──────┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn padding_follows_alignment() {
+        assert_eq!(padding(5, Alignment::Left), (0, 5));
+        assert_eq!(padding(5, Alignment::None), (0, 5));
+        assert_eq!(padding(5, Alignment::Right), (5, 0));
+        assert_eq!(padding(5, Alignment::Center), (2, 3));
+        assert_eq!(padding(0, Alignment::Center), (0, 0));
+    }
+
+    #[test]
+    fn wide_characters_count_double() {
+        let t = table(&[&["漢字", "x"], &["ab", "🦀"]], 0, &[]);
+        let lines = format(&t, 72);
+        assert_eq!(lines[1], "│ 漢字 │ x  │");
+        assert_eq!(lines[2], "│ ab   │ 🦀 │");
+        assert!(lines.iter().all(|line| width(line) == width(&lines[0])));
+    }
+
+    #[test]
+    fn cells_wrap_to_fit_the_width() {
+        let t = table(&[&["the quick brown fox", "a"]], 0, &[]);
+        let lines = format(&t, 18);
+        assert_eq!(
+            lines,
+            [
+                "┌────────────┬───┐",
+                "│ the quick  │ a │",
+                "│ brown fox  │   │",
+                "└────────────┴───┘",
+            ]
+        );
+        assert!(lines.iter().all(|line| width(line) == 18));
+    }
+
+    #[test]
+    fn long_words_and_wide_text_break_inside() {
+        let lines = wrap(&cell("abcdefghij"), 4);
+        let text: Vec<&str> = lines.iter().map(|l| l[0].1.as_str()).collect();
+        assert_eq!(text, ["abcd", "efgh", "ij"]);
+
+        // A wide character never straddles the edge
+        let lines = wrap(&cell("漢字漢字漢"), 5);
+        let text: Vec<&str> = lines.iter().map(|l| l[0].1.as_str()).collect();
+        assert_eq!(text, ["漢字", "漢字", "漢"]);
+    }
+
+    #[test]
+    fn styles_reopen_on_every_wrapped_line() {
+        let t = Table {
+            rows: vec![vec![vec![
+                Run {
+                    text: "plain ".to_string(),
+                    ..Run::default()
+                },
+                Run {
+                    text: "bold words here".to_string(),
+                    open: "<b>".to_string(),
+                    close: "</b>".to_string(),
+                },
+            ]]],
+            header_rows: 0,
+            alignments: Vec::new(),
+        };
+        let lines = format(&t, 14);
+        assert_eq!(
+            &lines[1..lines.len() - 1],
+            ["│ plain <b>bold</b> │", "│ <b>words here</b> │",]
+        );
+    }
+
+    #[test]
+    fn columns_keep_a_minimum_width() {
+        let t = table(&[&["aaaaaaaa", "bbbbbbbb", "cccccccc"]], 0, &[]);
+        let widths = column_widths(&t, 3, 5);
+        assert_eq!(widths, [MIN_COLUMN_WIDTH; 3]);
+        assert_eq!(column_widths(&t, 3, 100), [8, 8, 8]);
+    }
+
+    #[test]
+    fn cell_text_is_escaped() {
+        let t = table(&[&["a<b & c"]], 0, &[]);
+        assert_eq!(format(&t, 72)[1], "│ a&lt;b &amp; c │");
+        assert!(format(&table(&[], 0, &[]), 72).is_empty());
+    }
+
+    #[test]
+    fn alignment_markers_come_from_the_delimiter_row() {
+        let markup = render(
+            "| left | mid | right |\n|:--|:-:|--:|\n| x | y | z |",
+            &RenderOptions::default(),
+        );
+        assert!(markup.contains("│ x    │  y  │     z │"), "{}", markup);
+    }
+}
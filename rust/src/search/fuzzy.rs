@@ -0,0 +1,356 @@
+// Typo-tolerant fuzzy matching for short strings such as conversation titles
+
+use pyo3::prelude::*;
+
+use crate::pango;
+
+// Relative weights of the different ways a query word can match
+const PREFIX_SCORE: f64 = 1.0;
+const SUBSTRING_SCORE: f64 = 0.8;
+const TYPO_SCORE: f64 = 0.6;
+const SUBSEQUENCE_SCORE: f64 = 0.3;
+
+/// A ranked fuzzy match
+#[pyclass]
+#[derive(Clone)]
+pub struct FuzzyMatch {
+    /// Position of the item in the list passed to fuzzy_filter
+    #[pyo3(get)]
+    pub index: usize,
+    #[pyo3(get)]
+    pub text: String,
+    #[pyo3(get)]
+    pub score: f64,
+    /// Matched (start, end) character ranges, for highlighting
+    #[pyo3(get)]
+    pub spans: Vec<(usize, usize)>,
+}
+
+#[pymethods]
+impl FuzzyMatch {
+    /// Pango markup of the text with the matched characters wrapped in tags
+    #[pyo3(signature = (open = "<b>", close = "</b>"))]
+    fn markup(&self, open: &str, close: &str) -> String {
+        pango::highlight(&self.text, &self.spans, open, close)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "FuzzyMatch(index={}, score={:.3}, spans={:?})",
+            self.index, self.score, self.spans
+        )
+    }
+}
+
+/// Rank `items` against `query`, best first, dropping items that don't match
+///
+/// Each query word may match a word of the item as a prefix, a substring, or
+/// within a small edit distance; failing that the whole query is tried as a
+/// subsequence. An empty query returns every item unranked.
+#[pyfunction]
+#[pyo3(signature = (query, items, limit = None))]
+pub fn fuzzy_filter(
+    py: Python<'_>,
+    query: &str,
+    items: Vec<String>,
+    limit: Option<usize>,
+) -> Vec<FuzzyMatch> {
+    py.allow_threads(|| {
+        let mut matches: Vec<FuzzyMatch> = items
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, text)| {
+                let (score, spans) = score_item(query, &text)?;
+                Some(FuzzyMatch {
+                    index,
+                    text,
+                    score,
+                    spans,
+                })
+            })
+            .collect();
+
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.index.cmp(&b.index)));
+        if let Some(limit) = limit {
+            matches.truncate(limit);
+        }
+        matches
+    })
+}
+
+/// A word of the item, in characters
+struct Word {
+    chars: Vec<char>,
+    start: usize,
+}
+
+fn score_item(query: &str, text: &str) -> Option<(f64, Vec<(usize, usize)>)> {
+    let query_words: Vec<Vec<char>> = query
+        .split_whitespace()
+        .map(|w| w.to_lowercase().chars().collect())
+        .collect();
+    if query_words.is_empty() {
+        return Some((0.0, Vec::new()));
+    }
+
+    // One char per char so spans line up with the original text
+    let lower: Vec<char> = text
+        .chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect();
+    let words = split_words(&lower);
+
+    let mut total = 0.0;
+    let mut spans = Vec::new();
+    let mut all_matched = true;
+    for qw in &query_words {
+        match best_word_match(qw, &words, &lower) {
+            Some((score, span)) => {
+                total += score;
+                spans.push(span);
+            }
+            None => {
+                all_matched = false;
+                break;
+            }
+        }
+    }
+
+    if !all_matched {
+        let needle: Vec<char> = query_words.concat();
+        let positions = subsequence(&needle, &lower)?;
+        total = SUBSEQUENCE_SCORE * needle.len() as f64 * compactness(&positions);
+        spans = positions.iter().map(|&p| (p, p + 1)).collect();
+    }
+
+    // Shorter titles win ties: the match covers more of them
+    total += 0.1 * (1.0 / (1.0 + lower.len() as f64 / 32.0));
+    Some((total, merge_spans(spans)))
+}
+
+/// Best way a single query word matches any word of the item
+fn best_word_match(qw: &[char], words: &[Word], text: &[char]) -> Option<(f64, (usize, usize))> {
+    let len = qw.len() as f64;
+    let mut best = None;
+
+    for word in words {
+        if word.chars.starts_with(qw) {
+            // Earlier words are a slightly better match
+            let position_bonus = 0.05 / (1.0 + word.start as f64);
+            keep_best(
+                &mut best,
+                PREFIX_SCORE * len + position_bonus,
+                (word.start, word.start + qw.len()),
+            );
+            continue;
+        }
+
+        let max_typos = allowed_typos(qw.len());
+        if max_typos == 0 {
+            continue;
+        }
+        // Compare against the whole word and against a prefix of similar length,
+        // so both "kuberentes" and a mistyped start like "kubre" match "kubernetes"
+        let prefix_len = qw.len().min(word.chars.len());
+        let whole = edit_distance(qw, &word.chars);
+        let prefix = edit_distance(qw, &word.chars[..prefix_len]);
+        let (distance, end) = if whole <= prefix {
+            (whole, word.start + word.chars.len())
+        } else {
+            (prefix, word.start + prefix_len)
+        };
+        if distance <= max_typos {
+            let score = TYPO_SCORE * len * (1.0 - distance as f64 / (len + 1.0));
+            keep_best(&mut best, score, (word.start, end));
+        }
+    }
+
+    // Substrings that sit inside words ("ress" in "ingress")
+    if best.is_none() {
+        if let Some(start) = find(text, qw) {
+            keep_best(&mut best, SUBSTRING_SCORE * len, (start, start + qw.len()));
+        }
+    }
+
+    best
+}
+
+fn keep_best(best: &mut Option<(f64, (usize, usize))>, score: f64, span: (usize, usize)) {
+    if best.is_none_or(|(s, _)| score > s) {
+        *best = Some((score, span));
+    }
+}
+
+fn allowed_typos(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+fn split_words(text: &[char]) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<Word> = None;
+
+    for (i, &c) in text.iter().enumerate() {
+        if c.is_alphanumeric() {
+            current
+                .get_or_insert_with(|| Word {
+                    chars: Vec::new(),
+                    start: i,
+                })
+                .chars
+                .push(c);
+        } else if let Some(word) = current.take() {
+            words.push(word);
+        }
+    }
+    words.extend(current);
+    words
+}
+
+fn find(haystack: &[char], needle: &[char]) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Positions of `needle`'s characters appearing in order in `haystack`
+fn subsequence(needle: &[char], haystack: &[char]) -> Option<Vec<usize>> {
+    let mut positions = Vec::with_capacity(needle.len());
+    let mut from = 0;
+    for c in needle {
+        let offset = haystack[from..].iter().position(|h| h == c)?;
+        positions.push(from + offset);
+        from += offset + 1;
+    }
+    Some(positions)
+}
+
+/// 1.0 when the positions are consecutive, falling towards 0 as they spread out
+fn compactness(positions: &[usize]) -> f64 {
+    match (positions.first(), positions.last()) {
+        (Some(first), Some(last)) => positions.len() as f64 / (last - first + 1) as f64,
+        _ => 0.0,
+    }
+}
+
+/// Optimal string alignment distance (Levenshtein plus adjacent transpositions)
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let (n, m) = (a.len(), b.len());
+    let mut d = vec![vec![0usize; m + 1]; n + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=n {
+        for j in 1..=m {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[n][m]
+}
+
+fn merge_spans(mut spans: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn distance(a: &str, b: &str) -> usize {
+        edit_distance(&chars(a), &chars(b))
+    }
+
+    #[test]
+    fn osa_distance() {
+        assert_eq!(distance("", ""), 0);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("flaw", "lawn"), 2);
+        // An adjacent swap is one edit
+        assert_eq!(distance("ab", "ba"), 1);
+        assert_eq!(distance("kuberentes", "kubernetes"), 1);
+        // OSA never edits a substring twice, unlike full Damerau-Levenshtein
+        assert_eq!(distance("ca", "abc"), 3);
+    }
+
+    #[test]
+    fn typo_tolerance_grows_with_length() {
+        assert_eq!(allowed_typos(3), 0);
+        assert_eq!(allowed_typos(5), 1);
+        assert_eq!(allowed_typos(7), 2);
+    }
+
+    #[test]
+    fn match_kinds() {
+        // Prefix
+        assert_eq!(
+            score_item("kube", "Kubernetes notes").unwrap().1,
+            vec![(0, 4)]
+        );
+        // Typo against the whole word
+        assert_eq!(
+            score_item("kuberentes", "kubernetes").unwrap().1,
+            vec![(0, 10)]
+        );
+        // Typo against a prefix of the word
+        assert_eq!(score_item("kubre", "kubernetes").unwrap().1, vec![(0, 5)]);
+        // Substring inside a word
+        assert_eq!(score_item("ress", "ingress").unwrap().1, vec![(3, 7)]);
+        // Subsequence of the whole query
+        assert_eq!(
+            score_item("kbnts", "kubernetes").unwrap().1,
+            vec![(0, 1), (2, 3), (5, 6), (7, 8), (9, 10)]
+        );
+        assert!(score_item("xyz", "kubernetes").is_none());
+    }
+
+    #[test]
+    fn better_matches_score_higher() {
+        let score = |q: &str, t: &str| score_item(q, t).unwrap().0;
+        assert!(score("rust", "rust async") > score("rust", "trust issues"));
+        assert!(score("rust", "rust async") > score("rsut", "rust async"));
+        assert!(score("rsut", "rust async") > score("rst", "rust async"));
+        // Equal matches: the shorter title wins
+        assert!(score("rust", "rust") > score("rust", "rust and a long tail of words"));
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(score_item("  ", "anything"), Some((0.0, Vec::new())));
+    }
+
+    #[test]
+    fn spans_are_character_offsets() {
+        assert_eq!(score_item("café", "Le café noir").unwrap().1, vec![(3, 7)]);
+        assert_eq!(
+            merge_spans(vec![(4, 6), (0, 2), (1, 3)]),
+            vec![(0, 3), (4, 6)]
+        );
+    }
+}
@@ -0,0 +1,881 @@
+// Inverted index over message bodies, persisted in its own SQLite file
+//
+// The file holds what ranking and filtering need and no message text: per
+// message its postings and feature counts as varint blobs, plus the term
+// dictionary they refer to. Snippets are cut from conversations.db when a
+// query runs. Saving writes only the messages changed since the last save.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use sha2::{Digest, Sha256};
+
+use super::query::{Filter, Query};
+use super::tokenizer::tokenize;
+use super::vector::{self, Counts, Vector};
+
+/// Kept in the file's user_version; files of another version are rebuilt
+const FORMAT_VERSION: i64 = 3;
+
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS meta;
+    DROP TABLE IF EXISTS terms;
+    DROP TABLE IF EXISTS documents;
+    DROP TABLE IF EXISTS conversations;
+    CREATE TABLE meta (key TEXT PRIMARY KEY, value);
+    CREATE TABLE terms (id INTEGER PRIMARY KEY, term TEXT NOT NULL);
+    CREATE TABLE documents (
+        id INTEGER PRIMARY KEY,
+        conversation_id INTEGER NOT NULL,
+        role TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        used_web_search BOOLEAN NOT NULL,
+        checksum INTEGER NOT NULL,
+        vector BLOB NOT NULL,
+        postings BLOB NOT NULL
+    );
+    CREATE TABLE conversations (id INTEGER PRIMARY KEY, model TEXT NOT NULL, project TEXT);";
+
+// BM25 tuning, the usual defaults
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// A message as stored in the index
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub conversation_id: i64,
+    pub role: String,
+    /// Number of terms, used for length normalisation
+    pub length: u32,
+    /// messages.created_at as stored by SQLAlchemy, empty if unknown
+    pub created_at: String,
+    pub used_web_search: bool,
+    /// checksum() of the content, to spot edits without keeping the text
+    pub checksum: i64,
+    /// Hashed feature counts for semantic search
+    pub vector: Counts,
+    /// Ids of the distinct terms, to find the message's postings again
+    terms: Vec<u32>,
+}
+
+impl Document {
+    pub fn new(conversation_id: i64, role: &str) -> Self {
+        Document {
+            conversation_id,
+            role: role.to_string(),
+            length: 0,
+            created_at: String::new(),
+            used_web_search: false,
+            checksum: 0,
+            vector: Counts::new(),
+            terms: Vec::new(),
+        }
+    }
+}
+
+/// Stable fingerprint of a message body
+pub fn checksum(content: &str) -> i64 {
+    let digest = Sha256::digest(content.as_bytes());
+    i64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+/// Conversation-level fields used by query filters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConversationMeta {
+    pub model: String,
+    pub project: Option<String>,
+}
+
+/// message id -> term positions
+type Postings = HashMap<i64, Vec<u32>>;
+
+#[derive(Debug, Default)]
+pub struct InvertedIndex {
+    documents: HashMap<i64, Document>,
+    /// term id -> postings
+    postings: HashMap<u32, Postings>,
+    term_ids: HashMap<String, u32>,
+    /// Terms by id; ids are never reused
+    terms: Vec<String>,
+    total_length: u64,
+    conversations: HashMap<i64, ConversationMeta>,
+    /// Number of documents containing each vector bucket
+    bucket_df: Vec<u32>,
+    /// Highest messages.id seen by the last database sync
+    pub last_message_id: i64,
+    /// Newest conversations.updated_at seen by the last database sync
+    pub last_synced_at: String,
+    /// What save() still has to write: everything when the file is new or
+    /// of another version, else the terms from `saved_terms` on and the
+    /// messages and conversations changed since the last save
+    rewrite: bool,
+    saved_terms: usize,
+    changed: HashSet<i64>,
+    removed: HashSet<i64>,
+    conversations_changed: bool,
+}
+
+impl InvertedIndex {
+    pub fn new() -> Self {
+        InvertedIndex {
+            rewrite: true,
+            ..Default::default()
+        }
+    }
+
+    /// Load an index from disk, or start empty if the file doesn't exist yet
+    /// or was written in another format
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        let conn = Connection::open(path).map_err(io::Error::other)?;
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(io::Error::other)?;
+        if version != FORMAT_VERSION {
+            // The caller will re-sync from the database
+            return Ok(Self::new());
+        }
+        Self::read(&conn).map_err(io::Error::other)
+    }
+
+    fn read(conn: &Connection) -> rusqlite::Result<Self> {
+        let mut index = InvertedIndex::default();
+        let meta = |key: &str| {
+            conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                row.get::<_, rusqlite::types::Value>(0)
+            })
+            .optional()
+        };
+        if let Some(rusqlite::types::Value::Integer(id)) = meta("last_message_id")? {
+            index.last_message_id = id;
+        }
+        if let Some(rusqlite::types::Value::Text(at)) = meta("last_synced_at")? {
+            index.last_synced_at = at;
+        }
+
+        let mut stmt = conn.prepare("SELECT term FROM terms ORDER BY id")?;
+        index.terms = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        index.term_ids = (0..)
+            .zip(&index.terms)
+            .map(|(id, term)| (term.clone(), id))
+            .collect();
+        index.saved_terms = index.terms.len();
+
+        let mut stmt = conn.prepare(
+            "SELECT id, conversation_id, role, created_at, used_web_search, checksum, vector, postings
+             FROM documents",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let mut doc = Document::new(row.get(1)?, &row.get::<_, String>(2)?);
+            doc.created_at = row.get(3)?;
+            doc.used_web_search = row.get(4)?;
+            doc.checksum = row.get(5)?;
+            doc.vector = decode_vector(&row.get::<_, Vec<u8>>(6)?).ok_or_else(corrupt)?;
+            let postings = decode_postings(&row.get::<_, Vec<u8>>(7)?).ok_or_else(corrupt)?;
+            for (term, positions) in postings {
+                if term as usize >= index.terms.len() {
+                    return Err(corrupt());
+                }
+                doc.length += positions.len() as u32;
+                doc.terms.push(term);
+                index
+                    .postings
+                    .entry(term)
+                    .or_default()
+                    .insert(id, positions);
+            }
+            index.insert(id, doc);
+        }
+
+        let mut stmt = conn.prepare("SELECT id, model, project FROM conversations")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let meta = ConversationMeta {
+                model: row.get(1)?,
+                project: row.get(2)?,
+            };
+            index.conversations.insert(row.get(0)?, meta);
+        }
+        Ok(index)
+    }
+
+    /// Write what changed since the last save, in one transaction
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(path).map_err(io::Error::other)?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+        self.write(&tx)
+            .and_then(|()| tx.commit())
+            .map_err(io::Error::other)?;
+
+        self.rewrite = false;
+        self.saved_terms = self.terms.len();
+        self.changed.clear();
+        self.removed.clear();
+        self.conversations_changed = false;
+        Ok(())
+    }
+
+    fn write(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        if self.rewrite {
+            tx.execute_batch(SCHEMA)?;
+            tx.pragma_update(None, "user_version", FORMAT_VERSION)?;
+        }
+        let first_term = if self.rewrite { 0 } else { self.saved_terms };
+        let mut stmt = tx.prepare("INSERT OR REPLACE INTO terms (id, term) VALUES (?1, ?2)")?;
+        for (id, term) in self.terms.iter().enumerate().skip(first_term) {
+            stmt.execute(params![id as i64, term])?;
+        }
+
+        let mut delete = tx.prepare("DELETE FROM documents WHERE id = ?1")?;
+        for id in &self.removed {
+            delete.execute([id])?;
+        }
+        let mut insert = tx.prepare(
+            "INSERT OR REPLACE INTO documents
+             (id, conversation_id, role, created_at, used_web_search, checksum, vector, postings)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let ids: Box<dyn Iterator<Item = &i64>> = if self.rewrite {
+            Box::new(self.documents.keys())
+        } else {
+            Box::new(self.changed.iter())
+        };
+        for id in ids {
+            let Some(doc) = self.documents.get(id) else {
+                continue;
+            };
+            insert.execute(params![
+                id,
+                doc.conversation_id,
+                doc.role,
+                doc.created_at,
+                doc.used_web_search,
+                doc.checksum,
+                encode_vector(&doc.vector),
+                self.encode_postings(*id, doc)
+            ])?;
+        }
+
+        if self.rewrite || self.conversations_changed {
+            tx.execute("DELETE FROM conversations", [])?;
+            let mut stmt =
+                tx.prepare("INSERT INTO conversations (id, model, project) VALUES (?1, ?2, ?3)")?;
+            for (id, meta) in &self.conversations {
+                stmt.execute(params![id, meta.model, meta.project])?;
+            }
+        }
+
+        let mut stmt = tx.prepare("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?;
+        stmt.execute(params!["last_message_id", self.last_message_id])?;
+        stmt.execute(params!["last_synced_at", self.last_synced_at])?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn document(&self, message_id: i64) -> Option<&Document> {
+        self.documents.get(&message_id)
+    }
+
+    pub fn message_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.documents.keys().copied()
+    }
+
+    pub fn conversation_ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.conversations.keys().copied()
+    }
+
+    /// Record a conversation's model and project, returns whether anything changed
+    pub fn set_conversation(&mut self, conversation_id: i64, meta: ConversationMeta) -> bool {
+        let changed = self.conversations.insert(conversation_id, meta.clone()) != Some(meta);
+        self.conversations_changed |= changed;
+        changed
+    }
+
+    /// Add a message with its text, replacing any previous version with the
+    /// same id; the text itself is not kept
+    pub fn add(&mut self, message_id: i64, mut doc: Document, content: &str) {
+        self.remove(message_id);
+
+        let tokens = tokenize(content);
+        doc.terms.clear();
+        for (pos, token) in tokens.iter().enumerate() {
+            let term = self.intern(&token.term);
+            let positions = self
+                .postings
+                .entry(term)
+                .or_default()
+                .entry(message_id)
+                .or_default();
+            if positions.is_empty() {
+                doc.terms.push(term);
+            }
+            positions.push(pos as u32);
+        }
+        doc.length = tokens.len() as u32;
+        doc.checksum = checksum(content);
+        doc.vector = vector::embed(content);
+
+        self.insert(message_id, doc);
+        self.removed.remove(&message_id);
+        self.changed.insert(message_id);
+    }
+
+    /// Remove a message, returning whether it was indexed
+    pub fn remove(&mut self, message_id: i64) -> bool {
+        let Some(doc) = self.documents.remove(&message_id) else {
+            return false;
+        };
+
+        for term in &doc.terms {
+            if let Some(docs) = self.postings.get_mut(term) {
+                docs.remove(&message_id);
+                if docs.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+        self.total_length -= doc.length as u64;
+        for &(bucket, _) in &doc.vector {
+            if let Some(df) = self.bucket_df.get_mut(bucket as usize) {
+                *df = df.saturating_sub(1);
+            }
+        }
+        self.changed.remove(&message_id);
+        self.removed.insert(message_id);
+        true
+    }
+
+    /// Remove every message of a conversation, returning how many were removed
+    pub fn remove_conversation(&mut self, conversation_id: i64) -> usize {
+        let ids: Vec<i64> = self
+            .documents
+            .iter()
+            .filter(|(_, doc)| doc.conversation_id == conversation_id)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &ids {
+            self.remove(*id);
+        }
+        self.conversations_changed |= self.conversations.remove(&conversation_id).is_some();
+        ids.len()
+    }
+
+    /// Run a parsed query, best matches first
+    ///
+    /// Queries made only of filters and exclusions match on metadata alone
+    /// and come back newest first.
+    pub fn search(&self, query: &Query) -> Vec<(i64, f64)> {
+        let terms = query.scoring_terms();
+        let mut results = if terms.is_empty() {
+            self.documents.keys().map(|&id| (id, 0.0)).collect()
+        } else {
+            self.rank(&terms)
+        };
+
+        results.retain(|&(id, _)| self.matches(id, query));
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+        results
+    }
+
+    /// Messages most similar to `text`, best first
+    pub fn semantic_search(&self, text: &str, k: usize) -> Vec<(i64, f32)> {
+        let query = self.weigh(&vector::embed(text));
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut results: Vec<(i64, f32)> = self
+            .documents
+            .iter()
+            .map(|(&id, doc)| (id, vector::cosine(&query, &self.weigh(&doc.vector))))
+            .filter(|&(_, score)| score > 0.0)
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+        results.truncate(k);
+        results
+    }
+
+    /// Conversations whose messages are most similar to the given one's
+    pub fn similar_conversations(&self, conversation_id: i64, k: usize) -> Vec<(i64, f32)> {
+        let mut centroids: HashMap<i64, Vector> = HashMap::new();
+        for doc in self.documents.values() {
+            let centroid = centroids.entry(doc.conversation_id).or_default();
+            vector::accumulate(centroid, &self.weigh(&doc.vector));
+        }
+        for centroid in centroids.values_mut() {
+            vector::normalise(centroid);
+        }
+
+        let Some(target) = centroids.get(&conversation_id) else {
+            return Vec::new();
+        };
+        let mut results: Vec<(i64, f32)> = centroids
+            .iter()
+            .filter(|(&id, _)| id != conversation_id)
+            .map(|(&id, centroid)| (id, vector::cosine(target, centroid)))
+            .filter(|&(_, score)| score > 0.0)
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+        results.truncate(k);
+        results
+    }
+
+    fn weigh(&self, counts: &Counts) -> Vector {
+        vector::weigh(counts, &self.bucket_df, self.documents.len())
+    }
+
+    fn matches(&self, message_id: i64, query: &Query) -> bool {
+        let doc = &self.documents[&message_id];
+
+        query
+            .required
+            .iter()
+            .filter(|phrase| phrase.len() > 1)
+            .all(|phrase| self.contains_phrase(message_id, phrase))
+            && !query
+                .excluded
+                .iter()
+                .any(|phrase| self.contains_phrase(message_id, phrase))
+            && query
+                .filters
+                .iter()
+                .all(|(filter, negated)| self.filter_matches(doc, filter) != *negated)
+    }
+
+    fn filter_matches(&self, doc: &Document, filter: &Filter) -> bool {
+        let conversation = self.conversations.get(&doc.conversation_id);
+        // created_at is "YYYY-MM-DD HH:MM:SS[.ffffff]", compare the date part
+        let day = doc.created_at.get(..10).unwrap_or("");
+
+        match filter {
+            Filter::Role(roles) => roles.iter().any(|r| r.eq_ignore_ascii_case(&doc.role)),
+            Filter::Project(name) => conversation
+                .and_then(|c| c.project.as_deref())
+                .is_some_and(|p| p.to_lowercase() == *name),
+            Filter::Model(model) => conversation.is_some_and(|c| c.model.to_lowercase() == *model),
+            Filter::Before(date) => !day.is_empty() && day < date.as_str(),
+            Filter::After(date) => !day.is_empty() && day > date.as_str(),
+            Filter::HasSources => doc.used_web_search,
+        }
+    }
+
+    /// Whether the terms appear consecutively in the message
+    fn contains_phrase(&self, message_id: i64, phrase: &[String]) -> bool {
+        let positions: Option<Vec<&Vec<u32>>> = phrase
+            .iter()
+            .map(|term| self.postings_of(term)?.get(&message_id))
+            .collect();
+        let Some(positions) = positions else {
+            return false;
+        };
+
+        positions[0].iter().any(|&start| {
+            positions[1..]
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.binary_search(&(start + i as u32 + 1)).is_ok())
+        })
+    }
+
+    /// BM25-rank messages containing every term
+    fn rank(&self, terms: &[String]) -> Vec<(i64, f64)> {
+        if self.documents.is_empty() {
+            return Vec::new();
+        }
+
+        // Rarest term first keeps the candidate set small
+        let mut lists = Vec::with_capacity(terms.len());
+        for term in terms {
+            match self.postings_of(term) {
+                Some(docs) => lists.push(docs),
+                None => return Vec::new(),
+            }
+        }
+        lists.sort_by_key(|docs| docs.len());
+
+        let avg_len = self.total_length as f64 / self.documents.len() as f64;
+        let mut results = Vec::new();
+
+        'candidates: for &message_id in lists[0].keys() {
+            let mut score = 0.0;
+            for docs in &lists {
+                let Some(positions) = docs.get(&message_id) else {
+                    continue 'candidates;
+                };
+                let doc_len = self.documents[&message_id].length as f64;
+                score += self.idf(docs.len()) * bm25_tf(positions.len() as f64, doc_len, avg_len);
+            }
+            results.push((message_id, score));
+        }
+        results
+    }
+
+    fn postings_of(&self, term: &str) -> Option<&Postings> {
+        self.postings.get(self.term_ids.get(term)?)
+    }
+
+    fn intern(&mut self, term: &str) -> u32 {
+        if let Some(&id) = self.term_ids.get(term) {
+            return id;
+        }
+        let id = self.terms.len() as u32;
+        self.terms.push(term.to_string());
+        self.term_ids.insert(term.to_string(), id);
+        id
+    }
+
+    /// Count a document whose postings are already in place
+    fn insert(&mut self, message_id: i64, doc: Document) {
+        self.total_length += doc.length as u64;
+        self.bucket_df.resize(vector::DIMENSIONS, 0);
+        for &(bucket, _) in &doc.vector {
+            self.bucket_df[bucket as usize] += 1;
+        }
+        self.documents.insert(message_id, doc);
+    }
+
+    /// A document's postings as varints: term id, count, then the
+    /// positions as gaps
+    fn encode_postings(&self, message_id: i64, doc: &Document) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &term in &doc.terms {
+            let positions = &self.postings[&term][&message_id];
+            put_varint(&mut out, term as u64);
+            put_varint(&mut out, positions.len() as u64);
+            let mut last = 0;
+            for &pos in positions {
+                put_varint(&mut out, (pos - last) as u64);
+                last = pos;
+            }
+        }
+        out
+    }
+
+    fn idf(&self, doc_freq: usize) -> f64 {
+        let n = self.documents.len() as f64;
+        let df = doc_freq as f64;
+        ((n - df + 0.5) / (df + 0.5) + 1.0).ln()
+    }
+}
+
+fn bm25_tf(tf: f64, doc_len: f64, avg_len: f64) -> f64 {
+    let norm = 1.0 - BM25_B + BM25_B * doc_len / avg_len.max(1.0);
+    tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * norm)
+}
+
+fn decode_postings(mut data: &[u8]) -> Option<Vec<(u32, Vec<u32>)>> {
+    let mut postings = Vec::new();
+    while !data.is_empty() {
+        let term = u32::try_from(take_varint(&mut data)?).ok()?;
+        let count = take_varint(&mut data)? as usize;
+        let mut positions = Vec::with_capacity(count.min(data.len()));
+        let mut last: u32 = 0;
+        for _ in 0..count {
+            last = last.checked_add(u32::try_from(take_varint(&mut data)?).ok()?)?;
+            positions.push(last);
+        }
+        postings.push((term, positions));
+    }
+    Some(postings)
+}
+
+/// Feature counts as varint (bucket, count) pairs
+fn encode_vector(counts: &Counts) -> Vec<u8> {
+    let mut out = Vec::with_capacity(counts.len() * 3);
+    for &(bucket, count) in counts {
+        put_varint(&mut out, bucket as u64);
+        put_varint(&mut out, count as u64);
+    }
+    out
+}
+
+fn decode_vector(mut data: &[u8]) -> Option<Counts> {
+    let mut counts = Counts::new();
+    while !data.is_empty() {
+        let bucket = u16::try_from(take_varint(&mut data)?).ok()?;
+        let count = u16::try_from(take_varint(&mut data)?).ok()?;
+        counts.push((bucket, count));
+    }
+    Some(counts)
+}
+
+/// LEB128: seven bits per byte, low bits first
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn take_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn corrupt() -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(
+        0,
+        rusqlite::types::Type::Blob,
+        "corrupt search index entry".into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::query;
+
+    fn index(messages: &[(i64, &str)]) -> InvertedIndex {
+        let mut index = InvertedIndex::new();
+        for &(id, content) in messages {
+            index.add(id, Document::new(1, "user"), content);
+        }
+        index
+    }
+
+    fn ids(index: &InvertedIndex, q: &str) -> Vec<i64> {
+        let query = query::parse(q).unwrap();
+        index.search(&query).into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn every_term_must_match() {
+        let index = index(&[
+            (1, "kubernetes ingress controller"),
+            (2, "kubernetes pod"),
+            (3, "nginx ingress"),
+        ]);
+        assert_eq!(ids(&index, "kubernetes ingress"), vec![1]);
+        assert_eq!(ids(&index, "INGRESS"), vec![3, 1]);
+        assert!(ids(&index, "kubernetes docker").is_empty());
+    }
+
+    #[test]
+    fn more_occurrences_rank_higher() {
+        let index = index(&[
+            (1, "rust is a language with a borrow checker"),
+            (2, "rust rust rust everywhere"),
+            (3, "python is a language"),
+        ]);
+        assert_eq!(ids(&index, "rust"), vec![2, 1]);
+    }
+
+    #[test]
+    fn shorter_messages_rank_higher_at_equal_frequency() {
+        let index = index(&[
+            (1, "tokio runtime with many other words around the keyword"),
+            (2, "tokio runtime"),
+        ]);
+        assert_eq!(ids(&index, "tokio"), vec![2, 1]);
+    }
+
+    #[test]
+    fn rarer_terms_weigh_more() {
+        let index = index(&[
+            (1, "common common rare"),
+            (2, "common rare rare"),
+            (3, "common"),
+            (4, "common"),
+        ]);
+        let query = query::parse("common rare").unwrap();
+        let scores: HashMap<i64, f64> = index.search(&query).into_iter().collect();
+        assert!(scores[&2] > scores[&1]);
+    }
+
+    #[test]
+    fn phrases_need_consecutive_terms() {
+        let index = index(&[(1, "the quick brown fox"), (2, "brown and quick")]);
+        assert_eq!(ids(&index, "\"quick brown\""), vec![1]);
+        assert_eq!(ids(&index, "quick -\"quick brown\""), vec![2]);
+    }
+
+    #[test]
+    fn replacing_and_removing_update_postings() {
+        let mut index = index(&[(1, "alpha beta"), (2, "beta gamma")]);
+        index.add(1, Document::new(1, "user"), "delta");
+        assert_eq!(ids(&index, "alpha"), Vec::<i64>::new());
+        assert_eq!(ids(&index, "delta"), vec![1]);
+
+        assert!(index.remove(2));
+        assert!(!index.remove(2));
+        assert!(ids(&index, "beta").is_empty());
+        assert_eq!(index.total_length, 1);
+        assert!(index.postings_of("gamma").is_none());
+    }
+
+    #[test]
+    fn round_trips_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let mut saved = index(&[(7, "persisted message"), (8, "another persisted one")]);
+        let meta = ConversationMeta {
+            model: "gpt-4".into(),
+            project: None,
+        };
+        saved.set_conversation(1, meta.clone());
+        saved.last_message_id = 8;
+        saved.last_synced_at = "2024-01-01 10:00:00".into();
+        saved.save(&path).unwrap();
+
+        let loaded = InvertedIndex::load(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.last_message_id, 8);
+        assert_eq!(loaded.last_synced_at, "2024-01-01 10:00:00");
+        assert_eq!(loaded.total_length, saved.total_length);
+        assert_eq!(loaded.bucket_df, saved.bucket_df);
+        assert_eq!(loaded.conversations[&1], meta);
+        assert_eq!(ids(&loaded, "persisted"), vec![7, 8]);
+        assert_eq!(ids(&loaded, "\"one persisted\""), Vec::<i64>::new());
+        assert_eq!(ids(&loaded, "\"persisted one\""), vec![8]);
+        assert_eq!(ids(&loaded, "model:gpt-4 another"), vec![8]);
+        let doc = loaded.document(7).unwrap();
+        assert_eq!(doc.checksum, checksum("persisted message"));
+        assert_eq!(doc.vector, saved.document(7).unwrap().vector);
+
+        let missing = InvertedIndex::load(&dir.path().join("missing.db")).unwrap();
+        assert_eq!(missing.len(), 0);
+    }
+
+    #[test]
+    fn saves_write_only_what_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        let mut index = index(&[(1, "alpha beta"), (2, "beta gamma"), (3, "gamma")]);
+        index.save(&path).unwrap();
+        assert!(index.changed.is_empty() && !index.rewrite);
+
+        index.add(2, Document::new(1, "user"), "delta epsilon");
+        index.remove(3);
+        index.add(4, Document::new(1, "user"), "zeta");
+        assert_eq!(index.changed, HashSet::from([2, 4]));
+        assert_eq!(index.removed, HashSet::from([3]));
+        index.save(&path).unwrap();
+
+        let loaded = InvertedIndex::load(&path).unwrap();
+        let mut ids_: Vec<i64> = loaded.message_ids().collect();
+        ids_.sort();
+        assert_eq!(ids_, [1, 2, 4]);
+        assert_eq!(ids(&loaded, "gamma"), Vec::<i64>::new());
+        assert_eq!(ids(&loaded, "epsilon"), vec![2]);
+        assert_eq!(ids(&loaded, "zeta"), vec![4]);
+        assert_eq!(ids(&loaded, "beta"), vec![1]);
+    }
+
+    #[test]
+    fn message_text_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        index(&[(1, "remember the blue door")]).save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let text = b"remember the blue door";
+        assert!(!bytes.windows(text.len()).any(|w| w == text));
+    }
+
+    #[test]
+    fn other_formats_start_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.db");
+        index(&[(1, "old")]).save(&path).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", FORMAT_VERSION - 1)
+            .unwrap();
+
+        let mut stale = InvertedIndex::load(&path).unwrap();
+        assert_eq!(stale.len(), 0);
+        stale.add(2, Document::new(1, "user"), "new");
+        stale.save(&path).unwrap();
+        let loaded = InvertedIndex::load(&path).unwrap();
+        assert_eq!(loaded.message_ids().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let postings = vec![(0, vec![0, 1, 200]), (300, vec![70_000])];
+        let mut out = Vec::new();
+        for (term, positions) in &postings {
+            put_varint(&mut out, *term as u64);
+            put_varint(&mut out, positions.len() as u64);
+            let mut last = 0;
+            for &p in positions {
+                put_varint(&mut out, (p - last) as u64);
+                last = p;
+            }
+        }
+        assert_eq!(decode_postings(&out), Some(postings));
+        assert_eq!(decode_postings(&out[..out.len() - 1]), None);
+
+        let counts: Counts = vec![(0, 1), (511, 65535)];
+        assert_eq!(decode_vector(&encode_vector(&counts)), Some(counts));
+    }
+
+    fn add(index: &mut InvertedIndex, id: i64, conversation_id: i64, content: &str) {
+        index.add(id, Document::new(conversation_id, "user"), content);
+    }
+
+    #[test]
+    fn semantic_search_ranks_by_similarity() {
+        let mut index = InvertedIndex::new();
+        add(&mut index, 1, 1, "deploying the service to kubernetes");
+        add(&mut index, 2, 1, "kubernetes deployment failed again");
+        add(&mut index, 3, 2, "baking sourdough bread at home");
+
+        let hits = index.semantic_search("kubernetes deployment", 10);
+        let ids: Vec<i64> = hits.iter().map(|&(id, _)| id).collect();
+        assert_eq!(ids, [2, 1]);
+        assert!(hits[0].1 > hits[1].1);
+        assert_eq!(index.semantic_search("kubernetes deployment", 1).len(), 1);
+        assert!(index.semantic_search("the and", 10).is_empty());
+    }
+
+    #[test]
+    fn related_conversations_exclude_the_conversation_itself() {
+        let mut index = InvertedIndex::new();
+        add(&mut index, 1, 1, "rust borrow checker lifetimes");
+        add(&mut index, 2, 1, "rust traits and generics");
+        add(&mut index, 3, 2, "rust lifetimes explained");
+        add(&mut index, 4, 3, "sourdough starter hydration");
+        add(&mut index, 5, 4, "rust generics and trait objects");
+
+        let related = index.similar_conversations(1, 5);
+        let ids: Vec<i64> = related.iter().map(|&(id, _)| id).collect();
+        assert!(!ids.contains(&1));
+        // Shared trigrams can relate anything a little; it just ranks last
+        assert_eq!(ids.last(), Some(&3), "{:?}", related);
+        assert_eq!(ids.len(), 3);
+        assert!(related.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert_eq!(index.similar_conversations(1, 1).len(), 1);
+        assert!(index.similar_conversations(99, 5).is_empty());
+
+        // A conversation alone in the index has nothing related
+        let mut single = InvertedIndex::new();
+        add(&mut single, 1, 1, "rust rust rust");
+        add(&mut single, 2, 1, "more rust");
+        assert!(single.similar_conversations(1, 5).is_empty());
+    }
+}
@@ -0,0 +1,293 @@
+// Full-text search over the messages table
+
+mod fuzzy;
+mod index;
+mod query;
+mod snippet;
+mod sync;
+mod tokenizer;
+mod vector;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+
+use crate::store::Codec;
+use index::{ConversationMeta, Document, InvertedIndex};
+
+pub use fuzzy::{fuzzy_filter, FuzzyMatch};
+pub use sync::{IndexSync, SyncStats};
+
+create_exception!(
+    nanochat_rust,
+    QuerySyntaxError,
+    PyValueError,
+    "Invalid search query; `position` is the character offset of the problem."
+);
+
+/// File name used for the index when it lives next to conversations.db
+const INDEX_FILE_NAME: &str = "search_index.db";
+/// Where older versions kept the whole index, message text included
+const LEGACY_INDEX_FILE_NAME: &str = "search_index.json";
+
+/// How long to wait on a database locked by the app's own writes
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A single search result
+#[pyclass]
+#[derive(Clone)]
+pub struct SearchHit {
+    #[pyo3(get)]
+    pub conversation_id: i64,
+    #[pyo3(get)]
+    pub message_id: i64,
+    #[pyo3(get)]
+    pub score: f64,
+    /// Pango markup of the text around the best match
+    #[pyo3(get)]
+    pub snippet: String,
+}
+
+#[pymethods]
+impl SearchHit {
+    fn __repr__(&self) -> String {
+        format!(
+            "SearchHit(conversation_id={}, message_id={}, score={:.3})",
+            self.conversation_id, self.message_id, self.score
+        )
+    }
+}
+
+/// Persistent inverted index over the messages of conversations.db
+///
+/// The index file doesn't keep message text; snippets are cut from the
+/// messages in `db_path` when a query runs, and hits on messages deleted
+/// since the last sync are left out. Changes are kept in memory until
+/// `commit()` is called.
+#[pyclass]
+pub struct SearchIndex {
+    path: PathBuf,
+    db_path: String,
+    codec: Codec,
+    index: InvertedIndex,
+    dirty: bool,
+}
+
+#[pymethods]
+impl SearchIndex {
+    #[new]
+    fn new(index_path: &str, db_path: &str) -> PyResult<Self> {
+        let path = PathBuf::from(index_path);
+        let index = InvertedIndex::load(&path)
+            .map_err(|e| PyIOError::new_err(format!("Failed to load search index: {}", e)))?;
+        Ok(SearchIndex {
+            path,
+            db_path: db_path.to_string(),
+            codec: Codec::default(),
+            index,
+            dirty: false,
+        })
+    }
+
+    /// Open the index stored alongside the given conversations.db
+    #[staticmethod]
+    fn for_database(db_path: &str) -> PyResult<Self> {
+        let dir = Path::new(db_path).parent().unwrap_or(Path::new("."));
+        // Rebuilt by the next sync; the old file held every message's text
+        let _ = fs::remove_file(dir.join(LEGACY_INDEX_FILE_NAME));
+        Self::new(&dir.join(INDEX_FILE_NAME).to_string_lossy(), db_path)
+    }
+
+    #[getter]
+    fn path(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    #[getter]
+    fn db_path(&self) -> String {
+        self.db_path.clone()
+    }
+
+    /// Index a message, replacing it if it was already indexed
+    #[pyo3(signature = (
+        conversation_id,
+        message_id,
+        content,
+        role = "user",
+        created_at = None,
+        used_web_search = false
+    ))]
+    fn add_message(
+        &mut self,
+        conversation_id: i64,
+        message_id: i64,
+        content: &str,
+        role: &str,
+        created_at: Option<String>,
+        used_web_search: bool,
+    ) {
+        let mut doc = Document::new(conversation_id, role);
+        doc.created_at = created_at.unwrap_or_default();
+        doc.used_web_search = used_web_search;
+        self.index.add(message_id, doc, content);
+        self.dirty = true;
+    }
+
+    /// Record the model and project used by `model:` and `project:` filters
+    #[pyo3(signature = (conversation_id, model = None, project = None))]
+    fn set_conversation(
+        &mut self,
+        conversation_id: i64,
+        model: Option<String>,
+        project: Option<String>,
+    ) {
+        let meta = ConversationMeta {
+            model: model.unwrap_or_default(),
+            project,
+        };
+        self.dirty |= self.index.set_conversation(conversation_id, meta);
+    }
+
+    /// Drop a message from the index, returns False if it wasn't indexed
+    fn remove_message(&mut self, message_id: i64) -> bool {
+        let removed = self.index.remove(message_id);
+        self.dirty |= removed;
+        removed
+    }
+
+    /// Drop all messages of a conversation, returns the number removed
+    fn remove_conversation(&mut self, conversation_id: i64) -> usize {
+        let removed = self.index.remove_conversation(conversation_id);
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    /// Search message contents, best matches first
+    ///
+    /// Supports quoted phrases, `-exclusions` and the `role:`, `project:`,
+    /// `model:`, `before:`, `after:` and `has:sources` filters. Raises
+    /// QuerySyntaxError if the query can't be parsed.
+    #[pyo3(signature = (query, limit = 20))]
+    fn search(&self, py: Python<'_>, query: &str, limit: usize) -> PyResult<Vec<SearchHit>> {
+        let query = query::parse(query).map_err(|e| {
+            let err =
+                QuerySyntaxError::new_err(format!("{} (at position {})", e.message, e.position));
+            // Expose the offset so the UI can point at it
+            let _ = err.value(py).setattr("position", e.position);
+            err
+        })?;
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let terms: HashSet<String> = query.scoring_terms().into_iter().collect();
+        let ranked = self.index.search(&query);
+        py.allow_threads(|| self.hits(ranked, limit, &terms))
+            .map_err(read_error)
+    }
+
+    /// Messages with similar wording to `text`, even without shared keywords
+    #[pyo3(signature = (text, k = 10))]
+    fn semantic_search(&self, py: Python<'_>, text: &str, k: usize) -> PyResult<Vec<SearchHit>> {
+        py.allow_threads(|| {
+            let terms: HashSet<String> = tokenizer::terms(text).into_iter().collect();
+            let ranked = self.index.semantic_search(text, k);
+            self.hits(
+                ranked.into_iter().map(|(id, score)| (id, score as f64)),
+                k,
+                &terms,
+            )
+        })
+        .map_err(read_error)
+    }
+
+    /// Related conversations as (conversation_id, similarity) pairs, best first
+    #[pyo3(signature = (conversation_id, k = 5))]
+    fn similar_conversations(
+        &self,
+        py: Python<'_>,
+        conversation_id: i64,
+        k: usize,
+    ) -> Vec<(i64, f64)> {
+        py.allow_threads(|| {
+            self.index
+                .similar_conversations(conversation_id, k)
+                .into_iter()
+                .map(|(id, score)| (id, score as f64))
+                .collect()
+        })
+    }
+
+    /// Write pending changes to disk
+    fn commit(&mut self) -> PyResult<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.index
+            .save(&self.path)
+            .map_err(|e| PyIOError::new_err(format!("Failed to save search index: {}", e)))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.len()
+    }
+}
+
+impl SearchIndex {
+    /// The first `limit` of `ranked` still in the database, with snippets
+    /// around `terms`
+    fn hits(
+        &self,
+        ranked: impl IntoIterator<Item = (i64, f64)>,
+        limit: usize,
+        terms: &HashSet<String>,
+    ) -> rusqlite::Result<Vec<SearchHit>> {
+        let mut hits = Vec::new();
+        if limit == 0 {
+            return Ok(hits);
+        }
+        let conn = open_database(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT content FROM messages WHERE id = ?1")?;
+        for (message_id, score) in ranked {
+            let Some(doc) = self.index.document(message_id) else {
+                continue;
+            };
+            let Some(body) = stmt.query_row([message_id], |row| row.get(0)).optional()? else {
+                continue;
+            };
+            let content = self.codec.decode(&conn, body)?;
+            hits.push(SearchHit {
+                conversation_id: doc.conversation_id,
+                message_id,
+                score,
+                snippet: snippet::snippet(&content, terms),
+            });
+            if hits.len() == limit {
+                break;
+            }
+        }
+        Ok(hits)
+    }
+}
+
+/// A read-only connection to conversations.db
+fn open_database(db_path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+fn read_error(e: rusqlite::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("Failed to read search results: {}", e))
+}
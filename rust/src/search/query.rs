@@ -0,0 +1,324 @@
+// Search query language
+//
+//   kubernetes ingress        all terms must match
+//   "exact phrase"            terms must appear consecutively
+//   -word  -"some phrase"     exclude messages containing these
+//   role:user|assistant       message author
+//   project:"Work Stuff"      conversation's project name
+//   model:gpt-4               conversation's model
+//   before:2024-05-01         messages created before that day
+//   after:2024-05-01          messages created after that day
+//   has:sources               messages that used web search
+//
+// Any filter can be negated with a leading `-`.
+
+use super::tokenizer::terms;
+
+const ROLES: &[&str] = &["user", "assistant", "system"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Role(Vec<String>),
+    Project(String),
+    Model(String),
+    Before(String),
+    After(String),
+    HasSources,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    /// Required phrases; a single-term phrase is a plain term
+    pub required: Vec<Vec<String>>,
+    pub excluded: Vec<Vec<String>>,
+    /// Filters with their negation flag
+    pub filters: Vec<(Filter, bool)>,
+}
+
+impl Query {
+    /// Every required term, flattened, for scoring
+    pub fn scoring_terms(&self) -> Vec<String> {
+        self.required.iter().flatten().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.required.is_empty() && self.excluded.is_empty() && self.filters.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// Character offset into the query where parsing failed
+    pub position: usize,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, position: usize) -> Self {
+        ParseError {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Query, ParseError> {
+    Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+    }
+    .parse()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse(mut self) -> Result<Query, ParseError> {
+        let mut query = Query::default();
+
+        loop {
+            self.skip_whitespace();
+            let Some(c) = self.peek() else { break };
+
+            let negated = c == '-' && self.peek_at(1).is_some_and(|n| !n.is_whitespace());
+            if negated {
+                self.pos += 1;
+            }
+
+            if self.peek() == Some('"') {
+                let phrase = terms(&self.quoted()?);
+                if !phrase.is_empty() {
+                    self.push_text(&mut query, phrase, negated);
+                }
+                continue;
+            }
+
+            let start = self.pos;
+            let word = self.word();
+            if let Some((field, _)) = word.split_once(':') {
+                if is_field(field) {
+                    // Rewind to just after the colon so quoted values can be read
+                    self.pos = start + field.chars().count() + 1;
+                    let filter = self.filter(field)?;
+                    query.filters.push((filter, negated));
+                    continue;
+                }
+            }
+
+            let words = terms(&word);
+            if !words.is_empty() {
+                // "gpt-4" or "foo.bar" become phrases so the parts stay together
+                self.push_text(&mut query, words, negated);
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn push_text(&self, query: &mut Query, phrase: Vec<String>, negated: bool) {
+        if negated {
+            query.excluded.push(phrase);
+        } else {
+            query.required.push(phrase);
+        }
+    }
+
+    fn filter(&mut self, field: &str) -> Result<Filter, ParseError> {
+        let value_pos = self.pos;
+        let value = if self.peek() == Some('"') {
+            self.quoted()?
+        } else {
+            self.word()
+        };
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            return Err(ParseError::new(
+                format!("Missing value for '{}:'", field),
+                value_pos,
+            ));
+        }
+
+        match field {
+            "role" => {
+                let roles: Vec<String> = value.split('|').map(|r| r.to_lowercase()).collect();
+                if let Some(bad) = roles.iter().find(|r| !ROLES.contains(&r.as_str())) {
+                    return Err(ParseError::new(
+                        format!("Unknown role '{}'", bad),
+                        value_pos,
+                    ));
+                }
+                Ok(Filter::Role(roles))
+            }
+            "project" => Ok(Filter::Project(value.to_lowercase())),
+            "model" => Ok(Filter::Model(value.to_lowercase())),
+            "before" | "after" => {
+                if !is_date(&value) {
+                    return Err(ParseError::new(
+                        format!("Expected a YYYY-MM-DD date, got '{}'", value),
+                        value_pos,
+                    ));
+                }
+                Ok(if field == "before" {
+                    Filter::Before(value)
+                } else {
+                    Filter::After(value)
+                })
+            }
+            "has" => match value.to_lowercase().as_str() {
+                "sources" => Ok(Filter::HasSources),
+                other => Err(ParseError::new(
+                    format!("Unknown has: value '{}'", other),
+                    value_pos,
+                )),
+            },
+            _ => unreachable!("is_field() guards the field names"),
+        }
+    }
+
+    /// Read a double-quoted string starting at the opening quote
+    fn quoted(&mut self) -> Result<String, ParseError> {
+        let open = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '"' {
+                return Ok(value);
+            }
+            value.push(c);
+        }
+        Err(ParseError::new("Unterminated quote", open))
+    }
+
+    /// Read up to the next whitespace
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            word.push(c);
+            self.pos += 1;
+        }
+        word
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+}
+
+fn is_field(name: &str) -> bool {
+    matches!(
+        name,
+        "role" | "project" | "model" | "before" | "after" | "has"
+    )
+}
+
+fn is_date(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    let valid = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    parts.len() == 3 && valid(parts[0], 4) && valid(parts[1], 2) && valid(parts[2], 2) && {
+        let month: u32 = parts[1].parse().unwrap_or(0);
+        let day: u32 = parts[2].parse().unwrap_or(0);
+        (1..=12).contains(&month) && (1..=31).contains(&day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn terms_phrases_and_exclusions() {
+        let query = parse(r#"Kubernetes "load balancer" -nginx -"old config""#).unwrap();
+        assert_eq!(
+            query.required,
+            vec![words(&["kubernetes"]), words(&["load", "balancer"])]
+        );
+        assert_eq!(
+            query.excluded,
+            vec![words(&["nginx"]), words(&["old", "config"])]
+        );
+        assert_eq!(
+            query.scoring_terms(),
+            words(&["kubernetes", "load", "balancer"])
+        );
+    }
+
+    #[test]
+    fn punctuated_words_stay_together() {
+        let query = parse("gpt-4 a - b").unwrap();
+        assert_eq!(
+            query.required,
+            vec![words(&["gpt", "4"]), words(&["a"]), words(&["b"])]
+        );
+        assert!(query.excluded.is_empty());
+    }
+
+    #[test]
+    fn filters() {
+        let query = parse(
+            r#"role:User|assistant project:"Work Stuff" -model:GPT-4 after:2024-05-01 has:sources"#,
+        )
+        .unwrap();
+        assert!(query.required.is_empty());
+        assert_eq!(
+            query.filters,
+            vec![
+                (Filter::Role(words(&["user", "assistant"])), false),
+                (Filter::Project("work stuff".into()), false),
+                (Filter::Model("gpt-4".into()), true),
+                (Filter::After("2024-05-01".into()), false),
+                (Filter::HasSources, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_plain_text() {
+        let query = parse("http://example.com").unwrap();
+        assert!(query.filters.is_empty());
+        assert_eq!(query.required, vec![words(&["http", "example", "com"])]);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = parse(r#"rust "unterminated"#).unwrap_err();
+        assert_eq!(error.message, "Unterminated quote");
+        assert_eq!(error.position, 5);
+
+        let error = parse("role:robot").unwrap_err();
+        assert_eq!(error.message, "Unknown role 'robot'");
+        assert_eq!(error.position, 5);
+
+        assert!(parse("before:2024-13-01").is_err());
+        assert!(parse("after:yesterday").is_err());
+        assert!(parse("has:").is_err());
+        assert!(parse("has:images").is_err());
+    }
+
+    #[test]
+    fn empty_queries() {
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("  \"\"  ").unwrap().is_empty());
+        assert!(!parse("has:sources").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+// Context snippets for search hits, as Pango markup
+
+use std::collections::HashSet;
+
+use super::tokenizer::tokenize;
+use crate::pango;
+use crate::parser::to_plain_text;
+
+/// Target snippet length in characters
+const SNIPPET_CHARS: usize = 160;
+/// How much context to keep before the first match
+const LEAD_CHARS: usize = 40;
+/// How far to look for a word boundary when trimming the window
+const BOUNDARY_SLACK: usize = 20;
+
+// Matches use the app's accent colour
+const HIGHLIGHT_OPEN: &str = "<span foreground=\"#4a9eff\" weight=\"bold\">";
+const HIGHLIGHT_CLOSE: &str = "</span>";
+
+/// Build a snippet of `content` around the best cluster of `terms`
+///
+/// Markdown is stripped first, the window is cut at word boundaries and the
+/// result is escaped, so it can go straight into `Gtk.Label.set_markup`.
+pub fn snippet(content: &str, terms: &HashSet<String>) -> String {
+    let plain = to_plain_text(content);
+    let chars: Vec<char> = plain.chars().collect();
+    if chars.is_empty() {
+        return String::new();
+    }
+
+    // Match positions in characters; tokens come in order so count as we go
+    let mut hits: Vec<(usize, &str)> = Vec::new();
+    let (mut byte, mut char_pos) = (0, 0);
+    for token in tokenize(&plain) {
+        char_pos += plain[byte..token.start].chars().count();
+        byte = token.start;
+        if let Some(term) = terms.get(&token.term) {
+            hits.push((char_pos, term));
+        }
+    }
+
+    let (start, end) = window(&chars, best_anchor(&hits));
+    let text: String = chars[start..end].iter().collect();
+
+    let ranges: Vec<(usize, usize)> = tokenize(&text)
+        .into_iter()
+        .filter(|t| terms.contains(&t.term))
+        .map(|t| {
+            let s = text[..t.start].chars().count();
+            (s, s + text[t.start..t.end].chars().count())
+        })
+        .collect();
+
+    let mut markup = pango::highlight(&text, &ranges, HIGHLIGHT_OPEN, HIGHLIGHT_CLOSE);
+    if start > 0 {
+        markup.insert(0, '…');
+    }
+    if end < chars.len() {
+        markup.push('…');
+    }
+    markup
+}
+
+/// Character offset of the match whose window covers the most distinct terms
+fn best_anchor(hits: &[(usize, &str)]) -> usize {
+    let reach = SNIPPET_CHARS - LEAD_CHARS;
+    let mut best = (0, 0, 0);
+
+    for (i, &(pos, _)) in hits.iter().enumerate() {
+        let in_window: Vec<&str> = hits[i..]
+            .iter()
+            .take_while(|(p, _)| *p < pos + reach)
+            .map(|(_, term)| *term)
+            .collect();
+        let distinct = in_window.iter().collect::<HashSet<_>>().len();
+        if (distinct, in_window.len()) > (best.0, best.1) {
+            best = (distinct, in_window.len(), pos);
+        }
+    }
+    best.2
+}
+
+/// Snippet bounds around `anchor`, nudged onto word boundaries
+fn window(chars: &[char], anchor: usize) -> (usize, usize) {
+    let len = chars.len();
+    if len <= SNIPPET_CHARS {
+        return (0, len);
+    }
+
+    let mut start = anchor.saturating_sub(LEAD_CHARS).min(len - SNIPPET_CHARS);
+    let mut end = start + SNIPPET_CHARS;
+
+    if start > 0 && !chars[start - 1].is_whitespace() {
+        // Skip forward past the partial word we landed in
+        if let Some(offset) = chars[start..anchor.max(start)]
+            .iter()
+            .take(BOUNDARY_SLACK)
+            .position(|c| c.is_whitespace())
+        {
+            start += offset + 1;
+        }
+    }
+    if end < len {
+        // Back up to the end of the last whole word
+        if let Some(offset) = chars[start..end]
+            .iter()
+            .rev()
+            .take(BOUNDARY_SLACK)
+            .position(|c| c.is_whitespace())
+        {
+            end -= offset + 1;
+        }
+    }
+
+    (start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn mark(word: &str) -> String {
+        format!("{}{}{}", HIGHLIGHT_OPEN, word, HIGHLIGHT_CLOSE)
+    }
+
+    /// The snippet's text without highlight tags
+    fn text(markup: &str) -> String {
+        markup
+            .replace(HIGHLIGHT_OPEN, "")
+            .replace(HIGHLIGHT_CLOSE, "")
+    }
+
+    #[test]
+    fn short_messages_are_kept_whole() {
+        let markup = snippet("Rust has a borrow checker", &terms(&["borrow"]));
+        assert_eq!(markup, format!("Rust has a {} checker", mark("borrow")));
+        assert_eq!(snippet("", &terms(&["x"])), "");
+        assert_eq!(snippet("no match here", &terms(&[])), "no match here");
+    }
+
+    #[test]
+    fn every_occurrence_is_highlighted_case_insensitively() {
+        let markup = snippet("Tokio and tokio-util: TOKIO", &terms(&["tokio"]));
+        assert_eq!(
+            markup,
+            format!(
+                "{} and {}-util: {}",
+                mark("Tokio"),
+                mark("tokio"),
+                mark("TOKIO")
+            )
+        );
+        // Terms only match whole tokens
+        assert_eq!(snippet("tokios", &terms(&["tokio"])), "tokios");
+    }
+
+    #[test]
+    fn long_messages_are_windowed_around_the_match() {
+        let before = "filler ".repeat(60);
+        let after = " padding".repeat(60);
+        let content = format!("{}needle{}", before, after);
+        let markup = snippet(&content, &terms(&["needle"]));
+
+        assert!(markup.starts_with('…') && markup.ends_with('…'));
+        assert!(markup.contains(&mark("needle")));
+        let text = text(&markup);
+        let inner = text.trim_matches('…');
+        assert!(inner.chars().count() <= SNIPPET_CHARS);
+        // Cut at word boundaries, with some lead-in before the match
+        assert!(inner
+            .split(' ')
+            .all(|w| ["filler", "padding", "needle"].contains(&w)));
+        assert!(inner.find("needle").unwrap() >= LEAD_CHARS - BOUNDARY_SLACK);
+    }
+
+    #[test]
+    fn windows_prefer_clusters_of_distinct_terms() {
+        let gap = " word".repeat(80);
+        let content = format!("alpha{gap} alpha beta{gap} end");
+        let markup = snippet(&content, &terms(&["alpha", "beta"]));
+        assert!(markup.starts_with('…'));
+        assert!(markup.contains(&format!("{} {}", mark("alpha"), mark("beta"))));
+    }
+
+    #[test]
+    fn matches_at_the_edges_drop_that_ellipsis() {
+        let tail = " more".repeat(60);
+        let start = snippet(&format!("needle{}", tail), &terms(&["needle"]));
+        assert!(start.starts_with(&mark("needle")));
+        assert!(start.ends_with('…'));
+
+        let head = "more ".repeat(60);
+        let end = snippet(&format!("{}needle", head), &terms(&["needle"]));
+        assert!(end.starts_with('…'));
+        assert!(end.ends_with(&mark("needle")));
+    }
+
+    #[test]
+    fn markdown_is_stripped_and_markup_escaped() {
+        let markup = snippet(
+            "## Setup\n\nRun `cargo <build>` & **check** the [docs](https://x.y)",
+            &terms(&["check"]),
+        );
+        assert_eq!(
+            markup,
+            format!(
+                "Setup Run cargo &lt;build&gt; &amp; {} the docs",
+                mark("check")
+            )
+        );
+    }
+
+    #[test]
+    fn multibyte_text_is_cut_on_char_boundaries() {
+        let filler = "日本語のテキスト ".repeat(30);
+        let content = format!("{}café naïve{}", filler, " ünïcödé".repeat(30));
+        let markup = snippet(&content, &terms(&["naïve"]));
+        assert!(markup.contains(&mark("naïve")));
+        assert!(text(&markup).trim_matches('…').chars().count() <= SNIPPET_CHARS);
+
+        let emoji = snippet("🦀 crab 🦀 crab", &terms(&["crab"]));
+        assert_eq!(emoji, format!("🦀 {} 🦀 {}", mark("crab"), mark("crab")));
+    }
+}
@@ -0,0 +1,368 @@
+// Incremental index sync driven by the messages table of conversations.db
+
+use std::collections::HashSet;
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use rusqlite::{params, Connection};
+
+use super::index::{checksum, ConversationMeta, Document};
+use super::{open_database, SearchIndex};
+use crate::store::Codec;
+
+/// Counts of what a sync changed
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct SyncStats {
+    #[pyo3(get)]
+    pub added: usize,
+    #[pyo3(get)]
+    pub updated: usize,
+    #[pyo3(get)]
+    pub removed: usize,
+}
+
+#[pymethods]
+impl SyncStats {
+    /// Whether the sync touched the index at all
+    #[getter]
+    fn changed(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "SyncStats(added={}, updated={}, removed={})",
+            self.added, self.updated, self.removed
+        )
+    }
+}
+
+/// Brings a SearchIndex up to date with conversations.db
+///
+/// New messages are found by `messages.id`, edits by `conversations.updated_at`
+/// and deletions by comparing the indexed ids with the table, which is only
+/// scanned when its row count no longer matches the index.
+#[pyclass]
+pub struct IndexSync {
+    db_path: String,
+}
+
+#[pymethods]
+impl IndexSync {
+    #[new]
+    fn new(db_path: &str) -> Self {
+        IndexSync {
+            db_path: db_path.to_string(),
+        }
+    }
+
+    #[getter]
+    fn db_path(&self) -> String {
+        self.db_path.clone()
+    }
+
+    /// Apply changes made since the last sync and commit the index
+    fn sync(&self, py: Python<'_>, mut index: PyRefMut<'_, SearchIndex>) -> PyResult<SyncStats> {
+        let index = &mut *index;
+        let stats = py
+            .allow_threads(|| self.apply_changes(index))
+            .map_err(|e| PyRuntimeError::new_err(format!("Index sync failed: {}", e)))?;
+        index.commit()?;
+        Ok(stats)
+    }
+}
+
+impl IndexSync {
+    fn apply_changes(&self, target: &mut SearchIndex) -> rusqlite::Result<SyncStats> {
+        let conn = open_database(&self.db_path)?;
+        let codec = Codec::default();
+        let index = &mut target.index;
+        let mut stats = SyncStats::default();
+
+        // Deletions: anything indexed that is no longer in the table. Every
+        // id up to the watermark was indexed, so while the table still holds
+        // as many of those rows as the index does, nothing was deleted.
+        let last_id = index.last_message_id;
+        let remaining: usize = conn.query_row(
+            "SELECT COUNT(*) FROM messages WHERE id <= ?1",
+            [last_id],
+            |row| row.get(0),
+        )?;
+        if remaining != index.len() {
+            let existing: HashSet<i64> = conn
+                .prepare("SELECT id FROM messages WHERE id <= ?1")?
+                .query_map([last_id], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            let stale: Vec<i64> = index
+                .message_ids()
+                .filter(|id| !existing.contains(id))
+                .collect();
+            for id in stale {
+                index.remove(id);
+                stats.removed += 1;
+            }
+        }
+
+        // Conversation metadata is small, refresh all of it so project
+        // renames (which don't touch conversations.updated_at) are picked up
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.model_used, p.name
+             FROM conversations c LEFT JOIN projects p ON p.id = c.project_id",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut conversations = HashSet::new();
+        let mut meta_changed = false;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let meta = ConversationMeta {
+                model: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                project: row.get(2)?,
+            };
+            meta_changed |= index.set_conversation(id, meta);
+            conversations.insert(id);
+        }
+        let gone: Vec<i64> = index
+            .conversation_ids()
+            .filter(|id| !conversations.contains(id))
+            .collect();
+        for id in gone {
+            stats.removed += index.remove_conversation(id);
+            meta_changed = true;
+        }
+
+        // Edits: re-check messages of conversations touched since the last sync.
+        // >= because SQLAlchemy timestamps can collide within a sync window.
+        let mut stmt = conn.prepare(
+            "SELECT m.id, m.conversation_id, m.role, m.content, m.created_at, m.used_web_search
+             FROM messages m JOIN conversations c ON c.id = m.conversation_id
+             WHERE c.updated_at >= ?1 AND m.id <= ?2",
+        )?;
+        let mut rows = stmt.query(params![index.last_synced_at, last_id])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let (doc, content) = document_from_row(row, &conn, &codec)?;
+
+            let unchanged = index.document(id).is_some_and(|old| {
+                old.checksum == checksum(&content)
+                    && old.conversation_id == doc.conversation_id
+                    && old.used_web_search == doc.used_web_search
+            });
+            if !unchanged {
+                index.add(id, doc, &content);
+                stats.updated += 1;
+            }
+        }
+
+        // Additions: everything past the highest id we have seen
+        let mut stmt = conn.prepare(
+            "SELECT id, conversation_id, role, content, created_at, used_web_search
+             FROM messages WHERE id > ?1 ORDER BY id",
+        )?;
+        let mut rows = stmt.query(params![last_id])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let (doc, content) = document_from_row(row, &conn, &codec)?;
+            index.add(id, doc, &content);
+            index.last_message_id = index.last_message_id.max(id);
+            stats.added += 1;
+        }
+
+        let newest: Option<String> =
+            conn.query_row("SELECT MAX(updated_at) FROM conversations", [], |row| {
+                row.get(0)
+            })?;
+        let watermark_moved = newest.as_ref().is_some_and(|n| *n != index.last_synced_at);
+        if let Some(newest) = newest {
+            index.last_synced_at = newest;
+        }
+
+        target.dirty |= stats.changed() || watermark_moved || meta_changed;
+        Ok(stats)
+    }
+}
+
+/// Build a document and its text from (id, conversation_id, role, content,
+/// created_at, used_web_search)
+fn document_from_row(
+    row: &rusqlite::Row<'_>,
+    conn: &Connection,
+    codec: &Codec,
+) -> rusqlite::Result<(Document, String)> {
+    let mut doc = Document::new(row.get(1)?, &row.get::<_, String>(2)?);
+    doc.created_at = row.get::<_, Option<String>>(4)?.unwrap_or_default();
+    doc.used_web_search = row.get::<_, Option<bool>>(5)?.unwrap_or(false);
+    Ok((doc, codec.decode(conn, row.get(3)?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::index::InvertedIndex;
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        conn: Connection,
+        sync: IndexSync,
+        index: SearchIndex,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let db_path = dir.path().join("conversations.db");
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE projects (id INTEGER PRIMARY KEY, name TEXT);
+                 CREATE TABLE conversations (
+                     id INTEGER PRIMARY KEY, project_id INTEGER, model_used TEXT,
+                     updated_at TEXT);
+                 CREATE TABLE messages (
+                     id INTEGER PRIMARY KEY, conversation_id INTEGER, role TEXT,
+                     content TEXT, created_at TEXT, used_web_search BOOLEAN);
+                 INSERT INTO conversations VALUES (1, NULL, 'gpt-4', '2024-01-01 10:00:00');",
+            )
+            .unwrap();
+            Fixture {
+                sync: IndexSync::new(db_path.to_str().unwrap()),
+                index: SearchIndex {
+                    path: dir.path().join("index.db"),
+                    db_path: db_path.to_str().unwrap().to_string(),
+                    codec: Codec::default(),
+                    index: InvertedIndex::new(),
+                    dirty: false,
+                },
+                conn,
+                _dir: dir,
+            }
+        }
+
+        fn add(&self, id: i64, content: &str, at: &str) {
+            self.conn
+                .execute(
+                    "INSERT INTO messages VALUES (?1, 1, 'user', ?2, ?3, 0)",
+                    params![id, content, at],
+                )
+                .unwrap();
+            self.touch(at);
+        }
+
+        fn touch(&self, at: &str) {
+            self.conn
+                .execute("UPDATE conversations SET updated_at = ?1", [at])
+                .unwrap();
+        }
+
+        fn sync(&mut self) -> SyncStats {
+            self.sync.apply_changes(&mut self.index).unwrap()
+        }
+    }
+
+    #[test]
+    fn picks_up_only_new_messages() {
+        let mut f = Fixture::new();
+        f.add(1, "first", "2024-01-01 10:00:01");
+        f.add(2, "second", "2024-01-01 10:00:02");
+
+        let stats = f.sync();
+        assert_eq!((stats.added, stats.updated, stats.removed), (2, 0, 0));
+        assert_eq!(f.index.index.last_message_id, 2);
+        assert_eq!(f.index.index.last_synced_at, "2024-01-01 10:00:02");
+        assert!(f.index.dirty);
+
+        f.add(3, "third", "2024-01-01 10:00:03");
+        let stats = f.sync();
+        assert_eq!((stats.added, stats.updated, stats.removed), (1, 0, 0));
+        assert_eq!(f.index.index.last_message_id, 3);
+
+        f.index.dirty = false;
+        assert!(!f.sync().changed());
+        assert!(!f.index.dirty);
+    }
+
+    #[test]
+    fn edits_are_found_through_updated_at() {
+        let mut f = Fixture::new();
+        f.add(1, "original text", "2024-01-01 10:00:01");
+        f.sync();
+
+        f.conn
+            .execute(
+                "UPDATE messages SET content = 'edited text' WHERE id = 1",
+                [],
+            )
+            .unwrap();
+        // Same timestamp as the watermark still counts
+        let stats = f.sync();
+        assert_eq!(stats.updated, 1);
+        assert_eq!(
+            f.index.index.document(1).unwrap().checksum,
+            checksum("edited text")
+        );
+
+        f.conn
+            .execute(
+                "UPDATE messages SET content = 'edited again' WHERE id = 1",
+                [],
+            )
+            .unwrap();
+        f.touch("2024-01-01 09:00:00");
+        // The conversation looks older than the watermark: nothing to re-check
+        assert_eq!(f.sync().updated, 0);
+    }
+
+    #[test]
+    fn deletions_are_removed() {
+        let mut f = Fixture::new();
+        f.add(1, "keep", "2024-01-01 10:00:01");
+        f.add(2, "drop", "2024-01-01 10:00:02");
+        f.sync();
+
+        f.conn
+            .execute("DELETE FROM messages WHERE id = 2", [])
+            .unwrap();
+        let stats = f.sync();
+        assert_eq!(stats.removed, 1);
+        assert_eq!(f.index.index.len(), 1);
+        assert!(f.index.index.document(2).is_none());
+
+        // The deleted id stays below the watermark, new rows still arrive
+        f.add(3, "new", "2024-01-01 10:00:03");
+        assert_eq!(f.sync().added, 1);
+        assert_eq!(f.index.index.len(), 2);
+    }
+
+    #[test]
+    fn deleted_conversations_drop_their_messages() {
+        let mut f = Fixture::new();
+        f.add(1, "gone soon", "2024-01-01 10:00:01");
+        f.sync();
+
+        f.conn
+            .execute_batch("DELETE FROM messages; DELETE FROM conversations;")
+            .unwrap();
+        assert_eq!(f.sync().removed, 1);
+        assert_eq!(f.index.index.len(), 0);
+        assert_eq!(f.index.index.conversation_ids().count(), 0);
+    }
+
+    #[test]
+    fn hits_take_their_snippets_from_the_database() {
+        let mut f = Fixture::new();
+        f.add(1, "the **deploy** script", "2024-01-01 10:00:01");
+        f.add(2, "deploy again", "2024-01-01 10:00:02");
+        f.sync();
+        f.conn
+            .execute("DELETE FROM messages WHERE id = 2", [])
+            .unwrap();
+
+        let terms = HashSet::from(["deploy".to_string()]);
+        let ranked = vec![(2, 2.0), (1, 1.0), (99, 0.5)];
+        let hits = f.index.hits(ranked.clone(), 10, &terms).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, 1);
+        assert_eq!(hits[0].conversation_id, 1);
+        assert!(hits[0].snippet.contains("deploy</span> script"));
+        assert!(f.index.hits(ranked, 0, &terms).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+// Tokenizer shared by indexing and querying so both sides agree on terms
+
+/// A single term with its byte span in the original text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub term: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Split text into lowercase alphanumeric terms, keeping byte offsets
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            if start.is_none() {
+                start = Some(i);
+            }
+        } else if let Some(s) = start.take() {
+            tokens.push(make_token(text, s, i));
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(make_token(text, s, text.len()));
+    }
+
+    tokens
+}
+
+/// Lowercased terms only, for callers that don't need spans
+pub fn terms(text: &str) -> Vec<String> {
+    tokenize(text).into_iter().map(|t| t.term).collect()
+}
+
+fn make_token(text: &str, start: usize, end: usize) -> Token {
+    Token {
+        term: text[start..end].to_lowercase(),
+        start,
+        end,
+    }
+}
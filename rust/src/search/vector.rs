@@ -0,0 +1,186 @@
+// Lightweight CPU-only embeddings: hashed word and character trigram features
+// weighted by TF-IDF. Good enough to relate chats that share vocabulary or
+// word fragments ("deploy"/"deployment") without a model or network access.
+
+use std::collections::HashMap;
+
+use super::tokenizer::terms;
+
+/// Number of hash buckets; vectors are stored sparsely so this is cheap
+pub const DIMENSIONS: usize = 512;
+
+/// Function words that would otherwise make every English chat look alike
+const STOPWORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "your", "with", "this", "that", "from",
+    "have", "has", "was", "were", "what", "when", "where", "which", "who", "how", "why", "can",
+    "could", "would", "should", "will", "into", "then", "than", "there", "their", "they", "them",
+    "its", "our", "out", "about", "just", "also", "some", "any", "all", "one", "use", "using",
+];
+
+/// Raw feature counts as stored in the index: (bucket, count)
+pub type Counts = Vec<(u16, u16)>;
+
+/// Weighted, L2-normalised sparse vector
+pub type Vector = HashMap<u16, f32>;
+
+/// Count hashed features of a text
+pub fn embed(text: &str) -> Counts {
+    let mut counts: HashMap<u16, u16> = HashMap::new();
+    let mut bump = |feature: &[u8]| {
+        let count = counts.entry(bucket(feature)).or_default();
+        *count = count.saturating_add(1);
+    };
+
+    for word in terms(text) {
+        if word.chars().count() < 3 || STOPWORDS.contains(&word.as_str()) {
+            continue;
+        }
+
+        let mut feature = b"w:".to_vec();
+        feature.extend_from_slice(word.as_bytes());
+        bump(&feature);
+
+        // Trigrams with word boundary markers, on chars so UTF-8 stays intact
+        let chars: Vec<char> = format!("^{}$", word).chars().collect();
+        for gram in chars.windows(3) {
+            let gram: String = gram.iter().collect();
+            bump(gram.as_bytes());
+        }
+    }
+
+    let mut counts: Counts = counts.into_iter().collect();
+    counts.sort_unstable();
+    counts
+}
+
+/// Apply sublinear TF and IDF weights, then normalise
+pub fn weigh(counts: &Counts, doc_freq: &[u32], total_docs: usize) -> Vector {
+    let n = total_docs.max(1) as f32;
+    let mut vector: Vector = counts
+        .iter()
+        .map(|&(bucket, count)| {
+            let df = doc_freq.get(bucket as usize).copied().unwrap_or(0) as f32;
+            let idf = ((n + 1.0) / (df + 1.0)).ln() + 1.0;
+            (bucket, (1.0 + (count as f32).ln()) * idf)
+        })
+        .collect();
+    normalise(&mut vector);
+    vector
+}
+
+/// Add `other` into `sum`, for building conversation centroids
+pub fn accumulate(sum: &mut Vector, other: &Vector) {
+    for (&bucket, &weight) in other {
+        *sum.entry(bucket).or_default() += weight;
+    }
+}
+
+pub fn normalise(vector: &mut Vector) {
+    let norm = vector.values().map(|w| w * w).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for w in vector.values_mut() {
+            *w /= norm;
+        }
+    }
+}
+
+/// Cosine similarity of two normalised vectors
+pub fn cosine(a: &Vector, b: &Vector) -> f32 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    small
+        .iter()
+        .filter_map(|(bucket, w)| large.get(bucket).map(|v| w * v))
+        .sum()
+}
+
+/// FNV-1a, stable across builds since buckets are persisted
+fn bucket(feature: &[u8]) -> u16 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for &b in feature {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    (hash as usize % DIMENSIONS) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(text: &str) -> Vector {
+        weigh(&embed(text), &[], 1)
+    }
+
+    #[test]
+    fn features_are_words_and_trigrams() {
+        let counts = embed("Rust");
+        // "w:rust" plus ^ru, rus, ust, st$
+        let total: u16 = counts.iter().map(|&(_, c)| c).sum();
+        assert_eq!(total, 5);
+        assert!(counts.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(counts.iter().all(|&(b, _)| (b as usize) < DIMENSIONS));
+        assert_eq!(embed("rust"), counts);
+        assert_eq!(embed("rust rust")[0].1, counts[0].1 * 2);
+    }
+
+    #[test]
+    fn short_words_and_stopwords_are_skipped() {
+        assert!(embed("the and to of a is it").is_empty());
+        assert!(embed("").is_empty());
+        assert!(!embed("deploy").is_empty());
+    }
+
+    #[test]
+    fn buckets_are_stable() {
+        // Persisted in the index, so they must never change between builds
+        assert_eq!(bucket(b""), (0x811c_9dc5u32 as usize % DIMENSIONS) as u16);
+        assert_eq!(bucket(b"w:rust"), bucket(b"w:rust"));
+        assert_ne!(bucket(b"w:rust"), bucket(b"w:python"));
+    }
+
+    #[test]
+    fn trigrams_relate_word_forms() {
+        let deploy = vector("deploy");
+        assert!(cosine(&deploy, &vector("deployment")) > cosine(&deploy, &vector("kitchen")));
+        assert!(cosine(&deploy, &vector("deployment")) > 0.3);
+    }
+
+    #[test]
+    fn vectors_are_normalised_and_cosine_ranks_overlap() {
+        let a = vector("kubernetes cluster networking");
+        let norm: f32 = a.values().map(|w| w * w).sum();
+        assert!((norm - 1.0).abs() < 1e-5);
+        assert!((cosine(&a, &a) - 1.0).abs() < 1e-5);
+
+        let close = vector("kubernetes cluster upgrade");
+        let far = vector("chocolate cake recipe");
+        assert!(cosine(&a, &close) > cosine(&a, &far));
+        assert_eq!(cosine(&a, &close), cosine(&close, &a));
+        assert_eq!(cosine(&a, &Vector::new()), 0.0);
+    }
+
+    #[test]
+    fn common_features_weigh_less() {
+        let counts: Counts = vec![(1, 1), (2, 1)];
+        // Bucket 1 is in every document, bucket 2 in one of ten
+        let mut df = vec![0; DIMENSIONS];
+        df[1] = 10;
+        df[2] = 1;
+        let weighted = weigh(&counts, &df, 10);
+        assert!(weighted[&2] > weighted[&1]);
+    }
+
+    #[test]
+    fn centroids_sum_then_normalise() {
+        let mut sum = Vector::new();
+        accumulate(&mut sum, &HashMap::from([(1, 1.0)]));
+        accumulate(&mut sum, &HashMap::from([(1, 1.0), (2, 2.0)]));
+        assert_eq!(sum, HashMap::from([(1, 2.0), (2, 2.0)]));
+        normalise(&mut sum);
+        assert!((sum[&1] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+
+        let mut empty = Vector::new();
+        normalise(&mut empty);
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+// zstd compression of message bodies
+//
+// Bodies at or above the store's threshold are written as zstd frames in
+// place of the text, with messages.compressed set. A frame records the id of
+// the dictionary it was compressed with (0 for none), so every row decodes
+// against the zstd_dictionaries entry that produced it. Whether to decode is
+// decided by the value's storage class rather than the flag: text written
+// by the SQLAlchemy layer always reads back as it is.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::{Mutex, MutexGuard};
+
+use pyo3::prelude::*;
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, Type, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+use zstd::zstd_safe;
+
+use super::schema;
+use crate::api::py_number;
+
+/// Bodies shorter than this many bytes are stored as text
+pub const DEFAULT_THRESHOLD: usize = 1024;
+
+/// zstd's own default level; higher levels buy little on chat text
+const LEVEL: i32 = 3;
+
+/// Size of a trained dictionary
+const DICTIONARY_SIZE: usize = 32 * 1024;
+
+/// At most this much of the newest text is sampled to train a dictionary
+const MAX_SAMPLE_BYTES: usize = 8 * 1024 * 1024;
+
+/// messages.content as stored
+pub enum Body {
+    Text(String),
+    Zstd(Vec<u8>),
+}
+
+impl Body {
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Body::Zstd(_))
+    }
+}
+
+impl FromSql for Body {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Blob(data) => Ok(Body::Zstd(data.to_vec())),
+            ValueRef::Null => Ok(Body::Text(String::new())),
+            other => String::column_result(other).map(Body::Text),
+        }
+    }
+}
+
+impl ToSql for Body {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Body::Text(text) => ToSqlOutput::Borrowed(ValueRef::Text(text.as_bytes())),
+            Body::Zstd(data) => ToSqlOutput::Borrowed(ValueRef::Blob(data)),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Dictionaries {
+    loaded: bool,
+    decoders: HashMap<u32, DecoderDictionary<'static>>,
+    /// The newest dictionary, used for new rows
+    encoder: Option<(u32, EncoderDictionary<'static>)>,
+}
+
+impl Dictionaries {
+    fn reload(&mut self, conn: &Connection) -> rusqlite::Result<()> {
+        self.decoders.clear();
+        self.encoder = None;
+        // Read-only connections may see a database that isn't migrated yet
+        let migrated = conn
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'zstd_dictionaries'",
+                [],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if migrated {
+            let mut stmt = conn.prepare(
+                "SELECT id, dictionary FROM zstd_dictionaries ORDER BY created_at, rowid",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let id: u32 = row.get(0)?;
+                let dictionary: Vec<u8> = row.get(1)?;
+                self.decoders
+                    .insert(id, DecoderDictionary::copy(&dictionary));
+                self.encoder = Some((id, EncoderDictionary::copy(&dictionary, LEVEL)));
+            }
+        }
+        self.loaded = true;
+        Ok(())
+    }
+}
+
+/// Compresses and decompresses bodies for one database, caching its
+/// dictionaries
+#[derive(Default)]
+pub struct Codec {
+    dictionaries: Mutex<Dictionaries>,
+}
+
+impl Codec {
+    fn lock(&self) -> MutexGuard<'_, Dictionaries> {
+        self.dictionaries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Text of a stored body
+    pub fn decode(&self, conn: &Connection, body: Body) -> rusqlite::Result<String> {
+        let data = match body {
+            Body::Text(text) => return Ok(text),
+            Body::Zstd(data) => data,
+        };
+        let id = zstd_safe::get_dict_id_from_frame(&data).map_or(0, |id| id.get());
+        let mut dictionaries = self.lock();
+        // Another connection may have trained a dictionary since we loaded
+        if id != 0 && !dictionaries.decoders.contains_key(&id) {
+            dictionaries.reload(conn)?;
+        }
+        let mut text = String::new();
+        let read = match id {
+            0 => zstd::Decoder::new(&data[..]).and_then(|mut d| d.read_to_string(&mut text)),
+            id => match dictionaries.decoders.get(&id) {
+                Some(dictionary) => zstd::Decoder::with_prepared_dictionary(&data[..], dictionary)
+                    .and_then(|mut d| d.read_to_string(&mut text)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("zstd dictionary {} is missing", id),
+                )),
+            },
+        };
+        read.map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Blob, Box::new(e)))?;
+        Ok(text)
+    }
+
+    /// The body to store for `text`: a zstd frame when it is at least
+    /// `threshold` bytes and compressing saves space, the text otherwise
+    pub fn encode(
+        &self,
+        conn: &Connection,
+        text: String,
+        threshold: Option<usize>,
+    ) -> rusqlite::Result<Body> {
+        match threshold {
+            Some(threshold) if text.len() >= threshold => {}
+            _ => return Ok(Body::Text(text)),
+        }
+        let mut dictionaries = self.lock();
+        if !dictionaries.loaded {
+            dictionaries.reload(conn)?;
+        }
+        let compressed = match &dictionaries.encoder {
+            Some((_, dictionary)) => zstd::bulk::Compressor::with_prepared_dictionary(dictionary)
+                .and_then(|mut c| c.compress(text.as_bytes())),
+            None => zstd::bulk::compress(text.as_bytes(), LEVEL),
+        }
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        if compressed.len() < text.len() {
+            Ok(Body::Zstd(compressed))
+        } else {
+            Ok(Body::Text(text))
+        }
+    }
+
+    /// Train a dictionary on `samples` and store it as the one new rows
+    /// use, returning its id
+    ///
+    /// None when there is too little text to train on. Older dictionaries
+    /// are kept: rows other connections are writing may still use them.
+    pub fn train(&self, conn: &Connection, samples: &[String]) -> rusqlite::Result<Option<u32>> {
+        let Ok(dictionary) = zstd::dict::from_samples(samples, DICTIONARY_SIZE) else {
+            return Ok(None);
+        };
+        let Some(id) = zstd_safe::get_dict_id_from_dict(&dictionary) else {
+            return Ok(None);
+        };
+        conn.execute(
+            "INSERT OR REPLACE INTO zstd_dictionaries (id, dictionary, created_at)
+             VALUES (?1, ?2, ?3)",
+            params![id.get(), dictionary, schema::now()],
+        )?;
+        let mut dictionaries = self.lock();
+        dictionaries
+            .decoders
+            .insert(id.get(), DecoderDictionary::copy(&dictionary));
+        dictionaries.encoder = Some((id.get(), EncoderDictionary::copy(&dictionary, LEVEL)));
+        dictionaries.loaded = true;
+        Ok(Some(id.get()))
+    }
+
+    /// Forget the cached dictionaries, e.g. after a rolled back train
+    pub fn invalidate(&self) {
+        *self.lock() = Dictionaries::default();
+    }
+}
+
+/// The newest message bodies, up to the amount a dictionary is trained on
+pub fn samples(conn: &Connection, codec: &Codec) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT content FROM messages ORDER BY id DESC")?;
+    let mut rows = stmt.query([])?;
+    let mut samples = Vec::new();
+    let mut total = 0;
+    while let Some(row) = rows.next()? {
+        let text = codec.decode(conn, row.get(0)?)?;
+        if text.is_empty() {
+            continue;
+        }
+        total += text.len();
+        samples.push(text);
+        if total >= MAX_SAMPLE_BYTES {
+            break;
+        }
+    }
+    Ok(samples)
+}
+
+/// What compact_database did
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct CompactionStats {
+    /// Messages examined
+    #[pyo3(get)]
+    pub messages: usize,
+    /// Messages stored compressed afterwards
+    #[pyo3(get)]
+    pub compressed: usize,
+    /// Messages whose stored body changed
+    #[pyo3(get)]
+    pub rewritten: usize,
+    /// Dictionary trained for this compaction, if there was enough text
+    #[pyo3(get)]
+    pub dictionary_id: Option<u32>,
+    /// Database size in bytes before and after
+    #[pyo3(get)]
+    pub size_before: u64,
+    #[pyo3(get)]
+    pub size_after: u64,
+}
+
+#[pymethods]
+impl CompactionStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "CompactionStats(messages={}, compressed={}, rewritten={}, dictionary_id={}, size_before={}, size_after={})",
+            self.messages,
+            self.compressed,
+            self.rewritten,
+            py_number(self.dictionary_id),
+            self.size_before,
+            self.size_after
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::migrate::migrate;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, ":memory:", false).unwrap();
+        conn
+    }
+
+    /// Chat-like text that differs from one message to the next
+    fn message(i: usize) -> String {
+        format!(
+            "Message {i}: here is how you would approach problem number {i}. \
+             First, read the input and check that value {} is in range. Then \
+             loop over the items, keeping a running total of {} so far.\n",
+            i * 7,
+            i % 13
+        )
+        .repeat(4)
+    }
+
+    #[test]
+    fn short_bodies_stay_text() {
+        let conn = database();
+        let codec = Codec::default();
+        let body = codec
+            .encode(&conn, "hello".to_string(), Some(DEFAULT_THRESHOLD))
+            .unwrap();
+        assert!(!body.is_compressed());
+        let body = codec.encode(&conn, message(1), None).unwrap();
+        assert!(!body.is_compressed());
+    }
+
+    #[test]
+    fn round_trips_without_a_dictionary() {
+        let conn = database();
+        let codec = Codec::default();
+        let text = message(1).repeat(4);
+        let body = codec.encode(&conn, text.clone(), Some(16)).unwrap();
+        let Body::Zstd(data) = &body else {
+            panic!("expected a zstd frame");
+        };
+        assert_eq!(zstd_safe::get_dict_id_from_frame(data), None);
+        assert_eq!(codec.decode(&conn, body).unwrap(), text);
+    }
+
+    #[test]
+    fn round_trips_with_a_trained_dictionary() {
+        let conn = database();
+        let codec = Codec::default();
+        let samples: Vec<String> = (0..500).map(message).collect();
+        let id = codec
+            .train(&conn, &samples)
+            .unwrap()
+            .expect("enough text to train on");
+
+        let text = message(1000);
+        let body = codec.encode(&conn, text.clone(), Some(16)).unwrap();
+        let Body::Zstd(data) = &body else {
+            panic!("expected a zstd frame");
+        };
+        assert_eq!(
+            zstd_safe::get_dict_id_from_frame(data).map(|i| i.get()),
+            Some(id)
+        );
+
+        // A fresh codec, as another connection would have, loads it from the table
+        assert_eq!(Codec::default().decode(&conn, body).unwrap(), text);
+    }
+
+    #[test]
+    fn missing_dictionary_is_an_error() {
+        let conn = database();
+        let codec = Codec::default();
+        let samples: Vec<String> = (0..500).map(message).collect();
+        codec.train(&conn, &samples).unwrap().unwrap();
+        let body = codec.encode(&conn, message(1000), Some(16)).unwrap();
+
+        conn.execute("DELETE FROM zstd_dictionaries", []).unwrap();
+        assert!(Codec::default().decode(&conn, body).is_err());
+    }
+}
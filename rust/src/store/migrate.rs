@@ -0,0 +1,563 @@
+// Schema migrations for conversations.db
+//
+// Migrations are plain SQL embedded in the library and identified by a
+// SHA-256 of their text, recorded in `schema_migrations` as they run. All
+// pending migrations go through one IMMEDIATE transaction, so a database is
+// either left at its old version or fully upgraded.
+//
+// Databases from before this runner (created by nanochat.data's
+// MigrationManager or by models.py's create_all) have no record of what ran.
+// Each migration lists the tables, columns and indexes it creates; when all
+// of them already exist the migration is adopted as applied, when none do it
+// runs, and anything in between means an earlier run stopped halfway.
+
+use std::fmt;
+use std::path::Path;
+
+use pyo3::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use sha2::{Digest, Sha256};
+
+use super::schema;
+use crate::api::py_optional;
+
+/// A schema object a migration creates
+#[derive(Clone, Copy)]
+enum Object {
+    Table(&'static str),
+    Column(&'static str, &'static str),
+    Index(&'static str),
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Table(table) => write!(f, "table {}", table),
+            Object::Column(table, column) => write!(f, "column {}.{}", table, column),
+            Object::Index(index) => write!(f, "index {}", index),
+        }
+    }
+}
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+    creates: &'static [Object],
+}
+
+impl Migration {
+    fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// Every migration, oldest first. Never edit one that has shipped; add a
+/// new one instead, or databases that ran it will refuse to open.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+        creates: &[Object::Table("conversations"), Object::Table("messages")],
+    },
+    Migration {
+        version: 2,
+        name: "projects",
+        sql: include_str!("migrations/0002_projects.sql"),
+        creates: &[
+            Object::Table("projects"),
+            Object::Column("conversations", "project_id"),
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "web_search_pref",
+        sql: include_str!("migrations/0003_web_search_pref.sql"),
+        creates: &[Object::Column("conversations", "web_search_enabled")],
+    },
+    Migration {
+        version: 4,
+        name: "suggested_prompts",
+        sql: include_str!("migrations/0004_suggested_prompts.sql"),
+        creates: &[Object::Table("suggested_prompts")],
+    },
+    Migration {
+        version: 5,
+        name: "model_used",
+        sql: include_str!("migrations/0005_model_used.sql"),
+        creates: &[Object::Column("conversations", "model_used")],
+    },
+    Migration {
+        version: 6,
+        name: "message_index",
+        sql: include_str!("migrations/0006_message_index.sql"),
+        creates: &[Object::Index("ix_messages_conversation_id")],
+    },
+    Migration {
+        version: 7,
+        name: "message_compression",
+        sql: include_str!("migrations/0007_message_compression.sql"),
+        creates: &[
+            Object::Column("messages", "compressed"),
+            Object::Table("zstd_dictionaries"),
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "message_tree",
+        sql: include_str!("migrations/0008_message_tree.sql"),
+        creates: &[
+            Object::Column("messages", "parent_id"),
+            Object::Column("conversations", "active_message_id"),
+            Object::Index("ix_messages_parent_id"),
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "message_usage",
+        sql: include_str!("migrations/0009_message_usage.sql"),
+        creates: &[
+            Object::Table("message_usage"),
+            Object::Index("ix_message_usage_model"),
+        ],
+    },
+];
+
+const MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER NOT NULL,
+        name VARCHAR(100) NOT NULL,
+        checksum VARCHAR(64) NOT NULL,
+        applied_at DATETIME NOT NULL,
+        PRIMARY KEY (version)
+    )";
+
+#[derive(Debug)]
+pub enum MigrationFailure {
+    Db(rusqlite::Error),
+    /// The database records a migration this build doesn't have
+    Unknown {
+        version: i64,
+        name: String,
+    },
+    /// A recorded migration's SQL no longer matches the embedded one
+    Modified {
+        version: i64,
+        name: String,
+    },
+    /// Some but not all objects of a migration exist
+    Partial {
+        version: i64,
+        name: &'static str,
+        missing: Vec<String>,
+    },
+    /// Migrations are pending on a connection that doesn't run them
+    Pending {
+        version: i64,
+        latest: i64,
+    },
+}
+
+impl From<rusqlite::Error> for MigrationFailure {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationFailure::Db(e)
+    }
+}
+
+impl fmt::Display for MigrationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationFailure::Db(e) => write!(f, "Database error: {}", e),
+            MigrationFailure::Unknown { version, name } => write!(
+                f,
+                "Database has migration {} ({}) which this version doesn't know; it was \
+                 opened by a newer NanoChat",
+                version, name
+            ),
+            MigrationFailure::Modified { version, name } => write!(
+                f,
+                "Migration {} ({}) differs from the one applied to this database",
+                version, name
+            ),
+            MigrationFailure::Partial {
+                version,
+                name,
+                missing,
+            } => write!(
+                f,
+                "Database is partially migrated: migration {} ({}) is missing {}; restore a \
+                 backup before upgrading",
+                version,
+                name,
+                missing.join(", ")
+            ),
+            MigrationFailure::Pending { version, latest } => write!(
+                f,
+                "Database is at schema version {} of {}; run migrate_database first",
+                version, latest
+            ),
+        }
+    }
+}
+
+/// What a migration run did
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct MigrationReport {
+    /// Highest version recorded before the run, 0 for new or legacy databases
+    #[pyo3(get)]
+    pub from_version: i64,
+    #[pyo3(get)]
+    pub to_version: i64,
+    /// Migrations whose SQL ran, as "0003_web_search_pref"
+    #[pyo3(get)]
+    pub applied: Vec<String>,
+    /// Migrations found already present in a legacy database and recorded
+    /// without running
+    #[pyo3(get)]
+    pub adopted: Vec<String>,
+    /// Copy of the database taken before upgrading, if one was needed
+    #[pyo3(get)]
+    pub backup_path: Option<String>,
+}
+
+#[pymethods]
+impl MigrationReport {
+    /// Whether the run changed the database at all
+    #[getter]
+    fn changed(&self) -> bool {
+        !self.applied.is_empty() || !self.adopted.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MigrationReport(from_version={}, to_version={}, applied={:?}, adopted={:?}, backup_path={})",
+            self.from_version,
+            self.to_version,
+            self.applied,
+            self.adopted,
+            py_optional(&self.backup_path)
+        )
+    }
+}
+
+enum Step {
+    Adopt,
+    Apply,
+}
+
+/// Bring the database at `db_path`, open on `conn`, to the latest schema
+///
+/// With `backup`, a database that already holds tables is copied next to
+/// itself before any migration SQL runs.
+pub fn migrate(
+    conn: &mut Connection,
+    db_path: &str,
+    backup: bool,
+) -> Result<MigrationReport, MigrationFailure> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    tx.execute_batch(MIGRATIONS_TABLE)?;
+
+    let mut report = MigrationReport {
+        to_version: MIGRATIONS.last().map_or(0, |m| m.version),
+        ..Default::default()
+    };
+    let recorded = recorded_migrations(&tx)?;
+    report.from_version = verify(&recorded)?;
+
+    // Plan everything before writing, so a partial state is reported on an
+    // untouched database
+    let mut plan = Vec::new();
+    for migration in MIGRATIONS {
+        if recorded.iter().any(|(v, _, _)| *v == migration.version) {
+            continue;
+        }
+        let mut missing = Vec::new();
+        for object in migration.creates {
+            if !exists(&tx, *object)? {
+                missing.push(object.to_string());
+            }
+        }
+        let step = if missing.is_empty() {
+            Step::Adopt
+        } else if missing.len() == migration.creates.len() {
+            Step::Apply
+        } else {
+            return Err(MigrationFailure::Partial {
+                version: migration.version,
+                name: migration.name,
+                missing,
+            });
+        };
+        plan.push((migration, step));
+    }
+
+    let upgrading = plan.iter().any(|(_, step)| matches!(step, Step::Apply));
+    if backup && upgrading && has_tables(&tx)? {
+        report.backup_path = backup_database(db_path)?;
+    }
+
+    for (migration, step) in plan {
+        let label = format!("{:04}_{}", migration.version, migration.name);
+        match step {
+            Step::Adopt => report.adopted.push(label),
+            Step::Apply => {
+                tx.execute_batch(migration.sql)?;
+                report.applied.push(label);
+            }
+        }
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, checksum, applied_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                migration.version,
+                migration.name,
+                migration.checksum(),
+                schema::now()
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+/// Fail unless every migration has been applied, for connections that
+/// leave migrating to migrate()
+pub fn check(conn: &Connection) -> Result<(), MigrationFailure> {
+    let recorded = if schema_entry(conn, "table", "schema_migrations")? {
+        recorded_migrations(conn)?
+    } else {
+        Vec::new()
+    };
+    let version = verify(&recorded)?;
+    if recorded.len() < MIGRATIONS.len() {
+        return Err(MigrationFailure::Pending {
+            version,
+            latest: MIGRATIONS.last().map_or(0, |m| m.version),
+        });
+    }
+    Ok(())
+}
+
+/// Check recorded migrations against the embedded ones, returning the
+/// highest recorded version
+fn verify(recorded: &[(i64, String, String)]) -> Result<i64, MigrationFailure> {
+    let mut highest = 0;
+    for (version, name, checksum) in recorded {
+        match MIGRATIONS.iter().find(|m| m.version == *version) {
+            None => {
+                return Err(MigrationFailure::Unknown {
+                    version: *version,
+                    name: name.clone(),
+                })
+            }
+            Some(m) if m.checksum() != *checksum => {
+                return Err(MigrationFailure::Modified {
+                    version: *version,
+                    name: name.clone(),
+                })
+            }
+            Some(_) => highest = highest.max(*version),
+        }
+    }
+    Ok(highest)
+}
+
+fn recorded_migrations(conn: &Connection) -> rusqlite::Result<Vec<(i64, String, String)>> {
+    let mut stmt =
+        conn.prepare("SELECT version, name, checksum FROM schema_migrations ORDER BY version")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+fn exists(conn: &Connection, object: Object) -> rusqlite::Result<bool> {
+    match object {
+        Object::Table(table) => schema_entry(conn, "table", table),
+        Object::Index(index) => schema_entry(conn, "index", index),
+        Object::Column(table, column) => {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let columns = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(columns.iter().any(|c| c == column))
+        }
+    }
+}
+
+fn schema_entry(conn: &Connection, kind: &str, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE type = ?1 AND name = ?2",
+        [kind, name],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|found| found.is_some())
+}
+
+/// Whether the database holds anything besides the migrations table
+fn has_tables(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master
+         WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != 'schema_migrations'",
+        [],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// Copy the database to "<stem>_backup_<YYYYmmdd_HHMMSS>.db" beside it,
+/// the name DatabaseManager.backup uses, returning the path
+///
+/// Runs on its own connection while the caller holds the write lock, so
+/// the copy is consistent whatever the journal mode.
+fn backup_database(db_path: &str) -> rusqlite::Result<Option<String>> {
+    let path = Path::new(db_path);
+    if db_path.is_empty() || db_path == ":memory:" || !path.is_file() {
+        return Ok(None);
+    }
+    let stamp: String = schema::now()
+        .chars()
+        .take(19)
+        .filter_map(|c| match c {
+            '0'..='9' => Some(c),
+            ' ' => Some('_'),
+            _ => None,
+        })
+        .collect();
+    let stem = path
+        .file_stem()
+        .map_or_else(|| "conversations".into(), |s| s.to_string_lossy());
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut target = dir.join(format!("{}_backup_{}.db", stem, stamp));
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(format!("{}_backup_{}_{}.db", stem, stamp, n));
+        n += 1;
+    }
+    let target = target.to_string_lossy().into_owned();
+    Connection::open(path)?.execute("VACUUM INTO ?1", [&target])?;
+    Ok(Some(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> Connection {
+        Connection::open_in_memory().unwrap()
+    }
+
+    fn labels(migrations: &[Migration]) -> Vec<String> {
+        migrations
+            .iter()
+            .map(|m| format!("{:04}_{}", m.version, m.name))
+            .collect()
+    }
+
+    #[test]
+    fn new_database_gets_every_migration() {
+        let mut conn = memory();
+        assert!(matches!(
+            check(&conn),
+            Err(MigrationFailure::Pending { version: 0, .. })
+        ));
+
+        let report = migrate(&mut conn, ":memory:", true).unwrap();
+        assert_eq!(report.from_version, 0);
+        assert_eq!(report.applied, labels(MIGRATIONS));
+        assert!(report.adopted.is_empty());
+        assert_eq!(report.backup_path, None);
+        check(&conn).unwrap();
+
+        let again = migrate(&mut conn, ":memory:", true).unwrap();
+        assert_eq!(again.from_version, report.to_version);
+        assert!(!again.changed());
+    }
+
+    #[test]
+    fn legacy_schema_is_adopted() {
+        let mut conn = memory();
+        // What older releases left behind: tables but no migration records
+        for migration in &MIGRATIONS[..3] {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+
+        let report = migrate(&mut conn, ":memory:", false).unwrap();
+        assert_eq!(report.adopted, labels(&MIGRATIONS[..3]));
+        assert_eq!(report.applied, labels(&MIGRATIONS[3..]));
+        check(&conn).unwrap();
+    }
+
+    #[test]
+    fn partial_migration_is_refused_untouched() {
+        let mut conn = memory();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+        conn.execute_batch("CREATE TABLE projects (id INTEGER PRIMARY KEY)")
+            .unwrap();
+
+        match migrate(&mut conn, ":memory:", false) {
+            Err(MigrationFailure::Partial {
+                version, missing, ..
+            }) => {
+                assert_eq!(version, 2);
+                assert_eq!(missing, vec!["column conversations.project_id"]);
+            }
+            other => panic!("expected a partial migration, got {:?}", other.err()),
+        }
+        assert!(!exists(&conn, Object::Table("schema_migrations")).unwrap());
+    }
+
+    #[test]
+    fn modified_and_unknown_migrations_are_refused() {
+        let mut conn = memory();
+        migrate(&mut conn, ":memory:", false).unwrap();
+
+        conn.execute(
+            "UPDATE schema_migrations SET checksum = 'edited' WHERE version = 1",
+            [],
+        )
+        .unwrap();
+        assert!(matches!(
+            migrate(&mut conn, ":memory:", false),
+            Err(MigrationFailure::Modified { version: 1, .. })
+        ));
+        assert!(matches!(
+            check(&conn),
+            Err(MigrationFailure::Modified { version: 1, .. })
+        ));
+
+        conn.execute(
+            "UPDATE schema_migrations SET checksum = ?1 WHERE version = 1",
+            [MIGRATIONS[0].checksum()],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO schema_migrations VALUES (999, 'future', '', '2024-01-01')",
+            [],
+        )
+        .unwrap();
+        assert!(matches!(
+            migrate(&mut conn, ":memory:", false),
+            Err(MigrationFailure::Unknown { version: 999, .. })
+        ));
+    }
+
+    #[test]
+    fn upgrades_back_up_existing_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("conversations.db");
+        let db_path = path.to_str().unwrap();
+        let mut conn = Connection::open(&path).unwrap();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+
+        let report = migrate(&mut conn, db_path, true).unwrap();
+        let backup = report.backup_path.expect("a backup");
+        let copy = Connection::open(&backup).unwrap();
+        assert!(exists(&copy, Object::Table("conversations")).unwrap());
+        assert!(!exists(&copy, Object::Table("projects")).unwrap());
+    }
+}
@@ -0,0 +1,19 @@
+-- Conversations and their messages, as the first release created them
+CREATE TABLE conversations (
+    id INTEGER NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (id)
+);
+CREATE TABLE messages (
+    id INTEGER NOT NULL,
+    conversation_id INTEGER NOT NULL,
+    role VARCHAR(20) NOT NULL,
+    content TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    used_web_search BOOLEAN,
+    web_sources TEXT,
+    PRIMARY KEY (id),
+    FOREIGN KEY(conversation_id) REFERENCES conversations (id)
+);
@@ -0,0 +1,13 @@
+-- Projects group conversations in the sidebar
+CREATE TABLE projects (
+    id INTEGER NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    color VARCHAR(7),
+    description TEXT,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL,
+    order_index INTEGER NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE (name)
+);
+ALTER TABLE conversations ADD COLUMN project_id INTEGER REFERENCES projects (id);
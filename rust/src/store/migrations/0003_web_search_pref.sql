@@ -0,0 +1,2 @@
+-- Per-conversation web search preference
+ALTER TABLE conversations ADD COLUMN web_search_enabled BOOLEAN NOT NULL DEFAULT 0;
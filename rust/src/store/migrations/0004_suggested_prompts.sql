@@ -0,0 +1,32 @@
+-- Prompts offered on the welcome screen, seeded with the defaults
+CREATE TABLE suggested_prompts (
+    id INTEGER NOT NULL,
+    text VARCHAR(500) NOT NULL,
+    category VARCHAR(50) NOT NULL,
+    display_order INTEGER NOT NULL DEFAULT 0,
+    is_active BOOLEAN NOT NULL DEFAULT 1,
+    usage_count INTEGER NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id)
+);
+INSERT INTO suggested_prompts (text, category, display_order, created_at) VALUES
+    ('How does AI work?', 'general', 1, datetime('now')),
+    ('Are black holes real?', 'general', 2, datetime('now')),
+    ('What is the meaning of life?', 'general', 3, datetime('now')),
+    ('Explain quantum computing', 'general', 4, datetime('now')),
+    ('Write a poem about spring', 'create', 1, datetime('now')),
+    ('Create a marketing plan for a coffee shop', 'create', 2, datetime('now')),
+    ('Draft an email to request a meeting', 'create', 3, datetime('now')),
+    ('Write a short story about time travel', 'create', 4, datetime('now')),
+    ('What''s the latest news in technology?', 'explore', 1, datetime('now')),
+    ('Compare renewable energy sources', 'explore', 2, datetime('now')),
+    ('What are the current trends in AI?', 'explore', 3, datetime('now')),
+    ('Explain the history of the internet', 'explore', 4, datetime('now')),
+    ('Write a Python function to sort a list', 'code', 1, datetime('now')),
+    ('Create a React component for a button', 'code', 2, datetime('now')),
+    ('Debug this SQL query', 'code', 3, datetime('now')),
+    ('Explain Big O notation', 'code', 4, datetime('now')),
+    ('Teach me about machine learning', 'learn', 1, datetime('now')),
+    ('How does blockchain technology work?', 'learn', 2, datetime('now')),
+    ('Explain the basics of investing', 'learn', 3, datetime('now')),
+    ('What is cognitive behavioral therapy?', 'learn', 4, datetime('now'));
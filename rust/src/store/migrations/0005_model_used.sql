@@ -0,0 +1,2 @@
+-- Model a conversation was last sent to
+ALTER TABLE conversations ADD COLUMN model_used VARCHAR(50);
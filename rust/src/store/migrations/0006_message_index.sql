@@ -0,0 +1,2 @@
+-- Keeps message counts and history loads off a full table scan
+CREATE INDEX ix_messages_conversation_id ON messages (conversation_id);
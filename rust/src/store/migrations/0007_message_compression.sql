@@ -0,0 +1,9 @@
+-- Message bodies above the store's size threshold are kept as zstd frames,
+-- compressed against the shared dictionaries trained by compact_database
+ALTER TABLE messages ADD COLUMN compressed BOOLEAN NOT NULL DEFAULT 0;
+CREATE TABLE zstd_dictionaries (
+    id INTEGER NOT NULL,
+    dictionary BLOB NOT NULL,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (id)
+);
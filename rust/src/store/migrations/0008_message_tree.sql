@@ -0,0 +1,13 @@
+-- Messages form a tree: a message's parent is the one it replies to, and
+-- each conversation points at the tip of the branch it shows
+ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages (id);
+ALTER TABLE conversations ADD COLUMN active_message_id INTEGER REFERENCES messages (id);
+CREATE INDEX ix_messages_parent_id ON messages (parent_id);
+-- Existing conversations are a single branch in id order
+UPDATE messages SET parent_id = (
+    SELECT MAX(p.id) FROM messages p
+    WHERE p.conversation_id = messages.conversation_id AND p.id < messages.id
+);
+UPDATE conversations SET active_message_id = (
+    SELECT MAX(m.id) FROM messages m WHERE m.conversation_id = conversations.id
+);
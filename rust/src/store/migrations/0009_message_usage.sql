@@ -0,0 +1,12 @@
+-- Token counts and cost of assistant messages, recorded by UsageLedger
+CREATE TABLE message_usage (
+    message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    model VARCHAR(100) NOT NULL,
+    prompt_tokens INTEGER NOT NULL DEFAULT 0,
+    completion_tokens INTEGER NOT NULL DEFAULT 0,
+    reasoning_tokens INTEGER NOT NULL DEFAULT 0,
+    cost REAL,
+    recorded_at DATETIME NOT NULL,
+    PRIMARY KEY (message_id)
+);
+CREATE INDEX ix_message_usage_model ON message_usage (model);
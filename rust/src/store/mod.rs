@@ -0,0 +1,1380 @@
+// Conversations, messages and projects in conversations.db
+//
+// Replaces the SQLAlchemy repositories for callers that only need plain
+// data: every call is one short SQL transaction on a connection the store
+// keeps open, run without the GIL, and rows come back as the same dicts
+// ApplicationState builds from ORM objects today.
+
+mod compress;
+pub(crate) mod migrate;
+mod schema;
+mod tree;
+mod window;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyString};
+use rusqlite::{params, Connection, ErrorCode, OpenFlags, OptionalExtension, TransactionBehavior};
+use serde_json::Value;
+
+use crate::api::{json_to_py, py_to_json};
+use compress::Body;
+use migrate::MigrationFailure;
+use schema::{isoformat, DEFAULT_COLOR, DEFAULT_MODEL, DEFAULT_TITLE};
+
+pub(crate) use compress::Codec;
+pub use compress::CompactionStats;
+pub use migrate::MigrationReport;
+pub use window::MessageWindow;
+
+create_exception!(
+    nanochat_rust,
+    MigrationError,
+    PyRuntimeError,
+    "conversations.db can't be brought to the current schema; it is left unchanged."
+);
+
+/// How long to wait on a database locked by another connection
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages per page when the caller doesn't say
+const DEFAULT_PAGE_SIZE: i64 = 50;
+
+const CONVERSATION_COLUMNS: &str = "
+    c.id, c.title, c.created_at, c.updated_at, c.model_used, c.web_search_enabled, c.project_id,
+    (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id)";
+
+const MESSAGE_COLUMNS: &str =
+    "id, conversation_id, role, content, created_at, used_web_search, web_sources, parent_id";
+
+/// MESSAGE_COLUMNS of an unaliased `messages`, and how many alternatives
+/// the message has including itself
+const MESSAGE_SELECT: &str = "
+    id, conversation_id, role, content, created_at, used_web_search, web_sources, parent_id,
+    (SELECT COUNT(*) FROM messages s
+     WHERE s.conversation_id = messages.conversation_id AND s.parent_id IS messages.parent_id)";
+
+const PROJECT_COLUMNS: &str = "
+    p.id, p.name, p.color, p.description, p.created_at, p.updated_at, p.order_index,
+    (SELECT COUNT(*) FROM conversations c WHERE c.project_id = p.id)";
+
+struct ConversationRow {
+    id: i64,
+    title: String,
+    created_at: String,
+    updated_at: String,
+    model_used: Option<String>,
+    web_search_enabled: bool,
+    project_id: Option<i64>,
+    message_count: i64,
+}
+
+impl ConversationRow {
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(ConversationRow {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            created_at: row.get(2)?,
+            updated_at: row.get(3)?,
+            model_used: row.get(4)?,
+            web_search_enabled: row.get::<_, Option<bool>>(5)?.unwrap_or(false),
+            project_id: row.get(6)?,
+            message_count: row.get(7)?,
+        })
+    }
+
+    fn into_dict(self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("id", self.id)?;
+        dict.set_item("title", self.title)?;
+        dict.set_item("created_at", isoformat(&self.created_at))?;
+        dict.set_item("updated_at", isoformat(&self.updated_at))?;
+        dict.set_item("model_used", self.model_used)?;
+        dict.set_item("message_count", self.message_count)?;
+        dict.set_item("web_search_enabled", self.web_search_enabled)?;
+        dict.set_item("project_id", self.project_id)?;
+        Ok(dict.into())
+    }
+}
+
+struct MessageRow {
+    id: i64,
+    conversation_id: i64,
+    role: String,
+    content: String,
+    created_at: String,
+    used_web_search: bool,
+    web_sources: Option<String>,
+    parent_id: Option<i64>,
+    sibling_count: i64,
+}
+
+impl MessageRow {
+    fn read(row: &rusqlite::Row<'_>, conn: &Connection, codec: &Codec) -> rusqlite::Result<Self> {
+        Ok(MessageRow {
+            id: row.get(0)?,
+            conversation_id: row.get(1)?,
+            role: row.get(2)?,
+            content: codec.decode(conn, row.get(3)?)?,
+            created_at: row.get(4)?,
+            used_web_search: row.get::<_, Option<bool>>(5)?.unwrap_or(false),
+            web_sources: row.get(6)?,
+            parent_id: row.get(7)?,
+            sibling_count: row.get(8)?,
+        })
+    }
+
+    /// `timestamp` and a decoded `web_sources` as get_conversation_messages
+    /// returns them
+    fn into_dict(self, py: Python) -> PyResult<PyObject> {
+        let sources = self
+            .web_sources
+            .filter(|s| !s.is_empty())
+            .and_then(|s| serde_json::from_str::<Value>(&s).ok());
+        let dict = PyDict::new(py);
+        dict.set_item("id", self.id)?;
+        dict.set_item("conversation_id", self.conversation_id)?;
+        dict.set_item("role", self.role)?;
+        dict.set_item("content", self.content)?;
+        dict.set_item("timestamp", isoformat(&self.created_at))?;
+        dict.set_item("used_web_search", self.used_web_search)?;
+        match sources {
+            Some(sources) => dict.set_item("web_sources", json_to_py(py, &sources)?)?,
+            None => dict.set_item("web_sources", py.None())?,
+        }
+        dict.set_item("parent_id", self.parent_id)?;
+        dict.set_item("sibling_count", self.sibling_count)?;
+        Ok(dict.into())
+    }
+}
+
+struct ProjectRow {
+    id: i64,
+    name: String,
+    color: Option<String>,
+    description: Option<String>,
+    created_at: String,
+    updated_at: String,
+    order_index: i64,
+    conversation_count: i64,
+}
+
+impl ProjectRow {
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(ProjectRow {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            color: row.get(2)?,
+            description: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            order_index: row.get(6)?,
+            conversation_count: row.get(7)?,
+        })
+    }
+
+    fn into_dict(self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("id", self.id)?;
+        dict.set_item("name", self.name)?;
+        dict.set_item("color", self.color)?;
+        dict.set_item("description", self.description)?;
+        dict.set_item("created_at", isoformat(&self.created_at))?;
+        dict.set_item("updated_at", isoformat(&self.updated_at))?;
+        dict.set_item("order_index", self.order_index)?;
+        dict.set_item("conversation_count", self.conversation_count)?;
+        Ok(dict.into())
+    }
+}
+
+/// A message to insert
+struct NewMessage<'a> {
+    conversation_id: i64,
+    parent_id: Option<i64>,
+    role: &'a str,
+    content: &'a str,
+    used_web_search: bool,
+    web_sources: Option<&'a str>,
+}
+
+fn into_dicts<T>(
+    py: Python,
+    rows: Vec<T>,
+    into_dict: impl Fn(T, Python) -> PyResult<PyObject>,
+) -> PyResult<Vec<PyObject>> {
+    rows.into_iter().map(|row| into_dict(row, py)).collect()
+}
+
+/// Repository API over conversations.db
+///
+/// Methods mirror ApplicationState's and return plain dicts; ids of rows
+/// that don't exist give None or False rather than raising.
+#[pyclass]
+pub struct Store {
+    db_path: String,
+    conn: Mutex<Connection>,
+    codec: Codec,
+    /// Bodies of at least this many bytes are stored compressed; None
+    /// stores everything as text
+    #[pyo3(get)]
+    compress_threshold: Option<usize>,
+}
+
+#[pymethods]
+impl Store {
+    /// Open `db_path`, which migrate_database must have brought to the
+    /// current schema
+    ///
+    /// Message bodies of `compress_threshold` bytes or more are stored
+    /// zstd-compressed; pass None to store them as text. Raises
+    /// MigrationError if the database isn't fully migrated.
+    #[new]
+    #[pyo3(signature = (db_path, compress_threshold=Some(compress::DEFAULT_THRESHOLD)))]
+    fn new(py: Python, db_path: &str, compress_threshold: Option<usize>) -> PyResult<Self> {
+        let conn = py
+            .allow_threads(|| {
+                let conn = open(db_path)?;
+                migrate::check(&conn)?;
+                Ok(conn)
+            })
+            .map_err(migration_error)?;
+        Ok(Store {
+            db_path: db_path.to_string(),
+            conn: Mutex::new(conn),
+            codec: Codec::default(),
+            compress_threshold,
+        })
+    }
+
+    #[getter]
+    fn db_path(&self) -> String {
+        self.db_path.clone()
+    }
+
+    // ---- Conversations ----
+
+    /// Create a conversation and return its id
+    #[pyo3(signature = (title=DEFAULT_TITLE, model=DEFAULT_MODEL, web_search_enabled=false))]
+    fn create_conversation(
+        &self,
+        py: Python,
+        title: &str,
+        model: &str,
+        web_search_enabled: bool,
+    ) -> PyResult<i64> {
+        self.run(py, |conn| {
+            let now = schema::now();
+            conn.execute(
+                "INSERT INTO conversations (title, created_at, updated_at, model_used, web_search_enabled)
+                 VALUES (?1, ?2, ?2, ?3, ?4)",
+                params![title, now, model, web_search_enabled],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    fn get_conversation(&self, py: Python, conversation_id: i64) -> PyResult<Option<PyObject>> {
+        let row = self.run(py, |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM conversations c WHERE c.id = ?1",
+                    CONVERSATION_COLUMNS
+                ),
+                [conversation_id],
+                ConversationRow::read,
+            )
+            .optional()
+        })?;
+        row.map(|row| row.into_dict(py)).transpose()
+    }
+
+    /// Conversations, most recently updated first
+    #[pyo3(signature = (limit=None))]
+    fn get_all_conversations(&self, py: Python, limit: Option<i64>) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let sql = format!(
+                "SELECT {} FROM conversations c ORDER BY c.updated_at DESC LIMIT ?1",
+                CONVERSATION_COLUMNS
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map([limit.unwrap_or(-1)], ConversationRow::read)?;
+            rows.collect()
+        })?;
+        into_dicts(py, rows, ConversationRow::into_dict)
+    }
+
+    /// Conversations of one project, or outside any project for None
+    #[pyo3(signature = (project_id=None, limit=None))]
+    fn get_conversations_for_project(
+        &self,
+        py: Python,
+        project_id: Option<i64>,
+        limit: Option<i64>,
+    ) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let sql = format!(
+                "SELECT {} FROM conversations c WHERE c.project_id IS ?1
+                 ORDER BY c.updated_at DESC LIMIT ?2",
+                CONVERSATION_COLUMNS
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(
+                params![project_id, limit.unwrap_or(-1)],
+                ConversationRow::read,
+            )?;
+            rows.collect()
+        })?;
+        into_dicts(py, rows, ConversationRow::into_dict)
+    }
+
+    fn rename_conversation(&self, py: Python, conversation_id: i64, title: &str) -> PyResult<bool> {
+        self.touch_conversation(py, conversation_id, "title", title)
+    }
+
+    fn set_web_search_enabled(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        enabled: bool,
+    ) -> PyResult<bool> {
+        self.touch_conversation(py, conversation_id, "web_search_enabled", enabled)
+    }
+
+    /// The conversation's web search preference, False if it doesn't exist
+    fn get_web_search_enabled(&self, py: Python, conversation_id: i64) -> PyResult<bool> {
+        let enabled = self.run(py, |conn| {
+            conn.query_row(
+                "SELECT web_search_enabled FROM conversations WHERE id = ?1",
+                [conversation_id],
+                |row| row.get::<_, Option<bool>>(0),
+            )
+            .optional()
+        })?;
+        Ok(enabled.flatten().unwrap_or(false))
+    }
+
+    /// Move a conversation into a project, or out of any for None
+    #[pyo3(signature = (conversation_id, project_id=None))]
+    fn move_conversation_to_project(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        project_id: Option<i64>,
+    ) -> PyResult<bool> {
+        self.touch_conversation(py, conversation_id, "project_id", project_id)
+    }
+
+    /// Delete a conversation with all its messages
+    fn delete_conversation(&self, py: Python, conversation_id: i64) -> PyResult<bool> {
+        self.transaction(py, |tx| {
+            tree::set_tip(tx, conversation_id, None)?;
+            tx.execute(
+                "DELETE FROM message_usage WHERE message_id IN
+                 (SELECT id FROM messages WHERE conversation_id = ?1)",
+                [conversation_id],
+            )?;
+            tx.execute(
+                "DELETE FROM messages WHERE conversation_id = ?1",
+                [conversation_id],
+            )?;
+            Ok(tx.execute("DELETE FROM conversations WHERE id = ?1", [conversation_id])? > 0)
+        })
+    }
+
+    // ---- Messages ----
+
+    /// Add a message and return its id; the conversation counts as updated
+    ///
+    /// The message replies to the tip of the active branch, or to
+    /// `parent_id` to fork from any earlier message, and becomes the new
+    /// tip. `web_sources` may be the JSON string the app stores or the
+    /// sources themselves. Raises ValueError if `parent_id` is not a
+    /// message of the conversation.
+    #[pyo3(signature = (conversation_id, role, content, used_web_search=false, web_sources=None, parent_id=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn add_message(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        role: &str,
+        content: &str,
+        used_web_search: bool,
+        web_sources: Option<&PyAny>,
+        parent_id: Option<i64>,
+    ) -> PyResult<i64> {
+        let web_sources = match web_sources {
+            None => None,
+            Some(s) if s.is_none() => None,
+            Some(s) => match s.downcast::<PyString>() {
+                Ok(s) => Some(s.to_str()?.to_string()),
+                Err(_) => Some(py_to_json(s)?.to_string()),
+            },
+        };
+        let id = self.transaction(py, |tx| {
+            let parent_id = match parent_id {
+                None => tree::tip(tx, conversation_id)?,
+                Some(parent) => match tree::position(tx, parent)? {
+                    Some((conversation, _, _)) if conversation == conversation_id => Some(parent),
+                    _ => return Ok(None),
+                },
+            };
+            let message = NewMessage {
+                conversation_id,
+                parent_id,
+                role,
+                content,
+                used_web_search,
+                web_sources: web_sources.as_deref(),
+            };
+            self.insert_message(tx, message).map(Some)
+        })?;
+        id.ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Message {} is not in conversation {}",
+                parent_id.unwrap_or_default(),
+                conversation_id
+            ))
+        })
+    }
+
+    /// Add `content` as an alternative to a message, replying to the same
+    /// parent with the same role, and make it the tip
+    ///
+    /// Returns the new message's id, or None if `message_id` doesn't exist.
+    fn edit_message(&self, py: Python, message_id: i64, content: &str) -> PyResult<Option<i64>> {
+        self.transaction(py, |tx| {
+            let Some((conversation_id, parent_id, role)) = tree::position(tx, message_id)? else {
+                return Ok(None);
+            };
+            let message = NewMessage {
+                conversation_id,
+                parent_id,
+                role: &role,
+                content,
+                used_web_search: false,
+                web_sources: None,
+            };
+            self.insert_message(tx, message).map(Some)
+        })
+    }
+
+    /// A message and its alternatives, oldest first; empty if it doesn't
+    /// exist
+    fn get_siblings(&self, py: Python, message_id: i64) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let sql = format!(
+                "SELECT {} FROM messages
+                 WHERE conversation_id = (SELECT conversation_id FROM messages WHERE id = ?1)
+                   AND parent_id IS (SELECT parent_id FROM messages WHERE id = ?1)
+                 ORDER BY id",
+                MESSAGE_SELECT
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows =
+                stmt.query_map([message_id], |row| MessageRow::read(row, conn, &self.codec))?;
+            rows.collect()
+        })?;
+        into_dicts(py, rows, MessageRow::into_dict)
+    }
+
+    /// Show the branch through `message_id`, continuing below it along the
+    /// newest replies
+    fn switch_branch(&self, py: Python, message_id: i64) -> PyResult<bool> {
+        self.transaction(py, |tx| {
+            let Some((conversation_id, _, _)) = tree::position(tx, message_id)? else {
+                return Ok(false);
+            };
+            let leaf = tree::newest_leaf(tx, message_id)?;
+            tree::set_tip(tx, conversation_id, Some(leaf))?;
+            Ok(true)
+        })
+    }
+
+    fn get_message(&self, py: Python, message_id: i64) -> PyResult<Option<PyObject>> {
+        let row = self.run(py, |conn| {
+            conn.query_row(
+                &format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_SELECT),
+                [message_id],
+                |row| MessageRow::read(row, conn, &self.codec),
+            )
+            .optional()
+        })?;
+        row.map(|row| row.into_dict(py)).transpose()
+    }
+
+    /// Messages on the active branch of a conversation, oldest first
+    #[pyo3(signature = (conversation_id, limit=None))]
+    fn get_conversation_messages(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        limit: Option<i64>,
+    ) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let sql = format!(
+                "{} SELECT {} FROM messages WHERE id IN (SELECT id FROM path)
+                 ORDER BY id LIMIT ?2",
+                tree::ACTIVE_PATH,
+                MESSAGE_SELECT
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params![conversation_id, limit.unwrap_or(-1)], |row| {
+                MessageRow::read(row, conn, &self.codec)
+            })?;
+            rows.collect()
+        })?;
+        into_dicts(py, rows, MessageRow::into_dict)
+    }
+
+    /// Up to `n` messages of the active branch older than `before_id`,
+    /// oldest first
+    ///
+    /// Without `before_id` the page holds the newest messages. Pass the id
+    /// of the first message of a page to get the one before it; a page
+    /// shorter than `n` is the start of the conversation.
+    #[pyo3(signature = (conversation_id, before_id=None, n=DEFAULT_PAGE_SIZE))]
+    fn messages_page(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        before_id: Option<i64>,
+        n: i64,
+    ) -> PyResult<Vec<PyObject>> {
+        let rows = self.page(py, conversation_id, before_id, n)?;
+        into_dicts(py, rows, MessageRow::into_dict)
+    }
+
+    /// Pages of a conversation from the newest back, for loading older
+    /// messages as the user scrolls up
+    #[pyo3(signature = (conversation_id, page_size=DEFAULT_PAGE_SIZE))]
+    fn message_window(
+        slf: PyRef<'_, Self>,
+        conversation_id: i64,
+        page_size: i64,
+    ) -> PyResult<MessageWindow> {
+        MessageWindow::new(slf.into(), conversation_id, page_size)
+    }
+
+    /// The active branch as API messages, trimmed to `max_tokens`
+    ///
+    /// Same result as fit_context over the role and content of every
+    /// message on the branch, so long histories keep their oldest turns
+    /// when they fit.
+    #[pyo3(signature = (conversation_id, model, max_tokens, system_prompt=None))]
+    fn context_messages(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        model: &str,
+        max_tokens: usize,
+        system_prompt: Option<&str>,
+    ) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "{} SELECT role, content FROM messages WHERE id IN (SELECT id FROM path) ORDER BY id",
+                tree::ACTIVE_PATH
+            ))?;
+            let rows = stmt.query_map([conversation_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    self.codec.decode(conn, row.get(1)?)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        let messages = PyList::empty(py);
+        for (role, content) in rows {
+            let message = PyDict::new(py);
+            message.set_item("role", role)?;
+            message.set_item("content", content)?;
+            messages.append(message)?;
+        }
+        crate::tokens::fit_context(py, messages, model, max_tokens, system_prompt)
+    }
+
+    fn message_count(&self, py: Python, conversation_id: i64) -> PyResult<i64> {
+        self.run(py, |conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM messages WHERE conversation_id = ?1",
+                [conversation_id],
+                |row| row.get(0),
+            )
+        })
+    }
+
+    /// Delete a message together with every reply below it
+    fn delete_message(&self, py: Python, message_id: i64) -> PyResult<bool> {
+        self.transaction(py, |tx| Ok(tree::delete_branch(tx, message_id)? > 0))
+    }
+
+    /// Delete every message of a conversation, returning how many went
+    fn delete_messages(&self, py: Python, conversation_id: i64) -> PyResult<usize> {
+        self.transaction(py, |tx| {
+            tree::set_tip(tx, conversation_id, None)?;
+            tx.execute(
+                "DELETE FROM message_usage WHERE message_id IN
+                 (SELECT id FROM messages WHERE conversation_id = ?1)",
+                [conversation_id],
+            )?;
+            tx.execute(
+                "DELETE FROM messages WHERE conversation_id = ?1",
+                [conversation_id],
+            )
+        })
+    }
+
+    // ---- Projects ----
+
+    /// Create a project at the end of the list and return it
+    ///
+    /// Raises ValueError if the name is taken.
+    #[pyo3(signature = (name, color=DEFAULT_COLOR, description=None))]
+    fn create_project(
+        &self,
+        py: Python,
+        name: &str,
+        color: &str,
+        description: Option<&str>,
+    ) -> PyResult<PyObject> {
+        let row = self.transaction(py, |tx| {
+            let now = schema::now();
+            tx.execute(
+                "INSERT INTO projects (name, color, description, created_at, updated_at, order_index)
+                 VALUES (?1, ?2, ?3, ?4, ?4, (SELECT COUNT(*) FROM projects))",
+                params![name, color, description, now],
+            )?;
+            select_project(tx, tx.last_insert_rowid())
+        });
+        match row {
+            Ok(Some(row)) => row.into_dict(py),
+            Ok(None) => Err(PyRuntimeError::new_err("Database error: project vanished")),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_project(&self, py: Python, project_id: i64) -> PyResult<Option<PyObject>> {
+        let row = self.run(py, |conn| select_project(conn, project_id))?;
+        row.map(|row| row.into_dict(py)).transpose()
+    }
+
+    fn get_project_by_name(&self, py: Python, name: &str) -> PyResult<Option<PyObject>> {
+        let row = self.run(py, |conn| {
+            conn.query_row(
+                &format!(
+                    "SELECT {} FROM projects p WHERE p.name = ?1",
+                    PROJECT_COLUMNS
+                ),
+                [name],
+                ProjectRow::read,
+            )
+            .optional()
+        })?;
+        row.map(|row| row.into_dict(py)).transpose()
+    }
+
+    /// Projects in their sidebar order
+    fn get_all_projects(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let rows = self.run(py, |conn| {
+            let sql = format!(
+                "SELECT {} FROM projects p ORDER BY p.order_index, p.id",
+                PROJECT_COLUMNS
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map([], ProjectRow::read)?;
+            rows.collect()
+        })?;
+        into_dicts(py, rows, ProjectRow::into_dict)
+    }
+
+    /// Change the given fields of a project; None leaves a field as it is
+    #[pyo3(signature = (project_id, name=None, color=None, description=None))]
+    fn update_project(
+        &self,
+        py: Python,
+        project_id: i64,
+        name: Option<&str>,
+        color: Option<&str>,
+        description: Option<&str>,
+    ) -> PyResult<bool> {
+        self.run(py, |conn| {
+            let updated = conn.execute(
+                "UPDATE projects SET name = COALESCE(?2, name), color = COALESCE(?3, color),
+                     description = COALESCE(?4, description), updated_at = ?5
+                 WHERE id = ?1",
+                params![project_id, name, color, description, schema::now()],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+
+    /// Delete a project; its conversations move out of any project
+    fn delete_project(&self, py: Python, project_id: i64) -> PyResult<bool> {
+        self.transaction(py, |tx| {
+            tx.execute(
+                "UPDATE conversations SET project_id = NULL WHERE project_id = ?1",
+                [project_id],
+            )?;
+            Ok(tx.execute("DELETE FROM projects WHERE id = ?1", [project_id])? > 0)
+        })
+    }
+
+    /// Move a project to `new_index` in the sidebar order, shifting the
+    /// projects in between
+    fn reorder_project(&self, py: Python, project_id: i64, new_index: i64) -> PyResult<bool> {
+        self.transaction(py, |tx| {
+            let current: Option<i64> = tx
+                .query_row(
+                    "SELECT order_index FROM projects WHERE id = ?1",
+                    [project_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(current) = current else {
+                return Ok(false);
+            };
+            if current < new_index {
+                tx.execute(
+                    "UPDATE projects SET order_index = order_index - 1
+                     WHERE order_index > ?1 AND order_index <= ?2",
+                    [current, new_index],
+                )?;
+            } else if new_index < current {
+                tx.execute(
+                    "UPDATE projects SET order_index = order_index + 1
+                     WHERE order_index >= ?2 AND order_index < ?1",
+                    [current, new_index],
+                )?;
+            }
+            tx.execute(
+                "UPDATE projects SET order_index = ?2 WHERE id = ?1",
+                [project_id, new_index],
+            )?;
+            Ok(true)
+        })
+    }
+
+    // ---- Maintenance ----
+
+    /// Recompress every message against a dictionary trained on the newest
+    /// ones, store bodies under the threshold as text, then VACUUM
+    ///
+    /// Holds the write lock for the whole rewrite; run it when idle.
+    fn compact_database(&self, py: Python) -> PyResult<CompactionStats> {
+        py.allow_threads(|| {
+            let mut conn = self.connection();
+            let size_before = database_size(&conn)?;
+            let mut stats = self.recompress(&mut conn).inspect_err(|_| {
+                // A rolled back dictionary must not be used for new rows
+                self.codec.invalidate();
+            })?;
+            conn.execute_batch("VACUUM")?;
+            stats.size_before = size_before;
+            stats.size_after = database_size(&conn)?;
+            Ok(stats)
+        })
+        .map_err(db_error)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Store(db_path={:?})", self.db_path)
+    }
+}
+
+impl Store {
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `work` on the connection without holding the GIL
+    fn run<T: Send>(
+        &self,
+        py: Python,
+        work: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send,
+    ) -> PyResult<T> {
+        py.allow_threads(|| work(&self.connection()))
+            .map_err(db_error)
+    }
+
+    /// Like run, inside a transaction committed only if `work` succeeds
+    fn transaction<T: Send>(
+        &self,
+        py: Python,
+        work: impl FnOnce(&rusqlite::Transaction) -> rusqlite::Result<T> + Send,
+    ) -> PyResult<T> {
+        py.allow_threads(|| {
+            let mut conn = self.connection();
+            let tx = conn.transaction()?;
+            let result = work(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .map_err(db_error)
+    }
+
+    /// Insert a message as the conversation's new tip and bump updated_at
+    fn insert_message(&self, conn: &Connection, message: NewMessage) -> rusqlite::Result<i64> {
+        let now = schema::now();
+        let body = self
+            .codec
+            .encode(conn, message.content.to_string(), self.compress_threshold)?;
+        conn.execute(
+            &format!(
+                "INSERT INTO messages ({}, compressed) VALUES (NULL, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                MESSAGE_COLUMNS
+            ),
+            params![
+                message.conversation_id,
+                message.role,
+                body,
+                now,
+                message.used_web_search,
+                message.web_sources,
+                message.parent_id,
+                body.is_compressed()
+            ],
+        )?;
+        let id = conn.last_insert_rowid();
+        conn.execute(
+            "UPDATE conversations SET updated_at = ?2, active_message_id = ?3 WHERE id = ?1",
+            params![message.conversation_id, now, id],
+        )?;
+        Ok(id)
+    }
+
+    fn recompress(&self, conn: &mut Connection) -> rusqlite::Result<CompactionStats> {
+        /// Rows rewritten per query, bounding memory on large databases
+        const BATCH: i64 = 500;
+
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut stats = CompactionStats::default();
+        if self.compress_threshold.is_some() {
+            let samples = compress::samples(&tx, &self.codec)?;
+            stats.dictionary_id = self.codec.train(&tx, &samples)?;
+        }
+        let mut last_id = 0;
+        loop {
+            let batch = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, content FROM messages WHERE id > ?1 ORDER BY id LIMIT ?2",
+                )?;
+                let rows = stmt.query_map([last_id, BATCH], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, Body>(1)?))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            let Some(&(last, _)) = batch.last() else {
+                break;
+            };
+            last_id = last;
+            for (id, body) in batch {
+                stats.messages += 1;
+                let was_compressed = body.is_compressed();
+                let text = self.codec.decode(&tx, body)?;
+                let body = self.codec.encode(&tx, text, self.compress_threshold)?;
+                if body.is_compressed() {
+                    stats.compressed += 1;
+                }
+                // Compressed rows always move to the new dictionary
+                if was_compressed || body.is_compressed() {
+                    tx.execute(
+                        "UPDATE messages SET content = ?2, compressed = ?3 WHERE id = ?1",
+                        params![id, body, body.is_compressed()],
+                    )?;
+                    stats.rewritten += 1;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    fn page(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        before_id: Option<i64>,
+        n: i64,
+    ) -> PyResult<Vec<MessageRow>> {
+        if n < 1 {
+            return Err(PyValueError::new_err("n must be at least 1"));
+        }
+        let mut rows = self.run(py, |conn| {
+            let sql = format!(
+                "{} SELECT {} FROM messages
+                 WHERE id IN (SELECT id FROM path) AND (?2 IS NULL OR id < ?2)
+                 ORDER BY id DESC LIMIT ?3",
+                tree::ACTIVE_PATH,
+                MESSAGE_SELECT
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params![conversation_id, before_id, n], |row| {
+                MessageRow::read(row, conn, &self.codec)
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        rows.reverse();
+        Ok(rows)
+    }
+
+    /// Set one conversation column and bump updated_at
+    fn touch_conversation(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        column: &'static str,
+        value: impl rusqlite::ToSql + Send + Sync,
+    ) -> PyResult<bool> {
+        self.run(py, |conn| {
+            let updated = conn.execute(
+                &format!(
+                    "UPDATE conversations SET {} = ?2, updated_at = ?3 WHERE id = ?1",
+                    column
+                ),
+                params![conversation_id, value, schema::now()],
+            )?;
+            Ok(updated > 0)
+        })
+    }
+}
+
+fn select_project(conn: &Connection, project_id: i64) -> rusqlite::Result<Option<ProjectRow>> {
+    conn.query_row(
+        &format!("SELECT {} FROM projects p WHERE p.id = ?1", PROJECT_COLUMNS),
+        [project_id],
+        ProjectRow::read,
+    )
+    .optional()
+}
+
+/// Migrate the database at `db_path` to the current schema
+///
+/// Pending migrations run in one transaction, after copying a database that
+/// already has tables to "conversations_backup_<timestamp>.db" unless
+/// `backup` is False. Raises MigrationError for a database that is
+/// partially migrated, was migrated by a newer version, or whose recorded
+/// migrations don't match this build's.
+#[pyfunction]
+#[pyo3(signature = (db_path, backup=true))]
+pub fn migrate_database(py: Python, db_path: &str, backup: bool) -> PyResult<MigrationReport> {
+    py.allow_threads(|| {
+        let mut conn = open(db_path)?;
+        migrate::migrate(&mut conn, db_path, backup)
+    })
+    .map_err(migration_error)
+}
+
+/// Text of a message body read as bytes straight from conversations.db,
+/// for code reading the table without a Store
+#[pyfunction]
+pub fn decompress_content(py: Python, db_path: &str, data: &[u8]) -> PyResult<String> {
+    static READERS: OnceLock<Mutex<HashMap<String, Arc<Reader>>>> = OnceLock::new();
+
+    let reader = READERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(db_path.to_string())
+        .or_default()
+        .clone();
+    let body = Body::Zstd(data.to_vec());
+    py.allow_threads(|| {
+        let mut conn = reader.conn.lock().unwrap_or_else(|e| e.into_inner());
+        if conn.is_none() {
+            let opened = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            opened.busy_timeout(BUSY_TIMEOUT)?;
+            *conn = Some(opened);
+        }
+        let conn = conn.as_ref().expect("connection opened above");
+        reader.codec.decode(conn, body)
+    })
+    .map_err(db_error)
+}
+
+/// What decompress_content keeps per database: its dictionaries, and the
+/// read-only connection they are loaded through
+#[derive(Default)]
+struct Reader {
+    codec: Codec,
+    conn: Mutex<Option<Connection>>,
+}
+
+fn database_size(conn: &Connection) -> rusqlite::Result<u64> {
+    conn.query_row(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn open(db_path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+fn migration_error(e: MigrationFailure) -> PyErr {
+    match e {
+        MigrationFailure::Db(e) => db_error(e),
+        e => MigrationError::new_err(e.to_string()),
+    }
+}
+
+fn db_error(e: rusqlite::Error) -> PyErr {
+    if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) {
+        return PyValueError::new_err(format!("Constraint violated: {}", e));
+    }
+    PyRuntimeError::new_err(format!("Database error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store on a migrated in-memory database
+    pub(super) fn store(compress_threshold: Option<usize>) -> Store {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate::migrate(&mut conn, ":memory:", false).unwrap();
+        Store {
+            db_path: ":memory:".to_string(),
+            conn: Mutex::new(conn),
+            codec: Codec::default(),
+            compress_threshold,
+        }
+    }
+
+    pub(super) fn with_python(f: impl FnOnce(Python)) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(f)
+    }
+
+    pub(super) fn field<T: for<'a> FromPyObject<'a>>(py: Python, dict: &PyObject, key: &str) -> T {
+        dict.as_ref(py).get_item(key).unwrap().extract().unwrap()
+    }
+
+    pub(super) fn ids(py: Python, messages: &[PyObject]) -> Vec<i64> {
+        messages.iter().map(|m| field(py, m, "id")).collect()
+    }
+
+    /// A conversation of alternating user and assistant messages
+    pub(super) fn conversation(py: Python, store: &Store, turns: usize) -> (i64, Vec<i64>) {
+        let conversation = store.create_conversation(py, "Test", "m", false).unwrap();
+        let ids = (0..turns)
+            .map(|i| {
+                let role = if i % 2 == 0 { "user" } else { "assistant" };
+                store
+                    .add_message(
+                        py,
+                        conversation,
+                        role,
+                        &format!("message {}", i),
+                        false,
+                        None,
+                        None,
+                    )
+                    .unwrap()
+            })
+            .collect();
+        (conversation, ids)
+    }
+
+    #[test]
+    fn added_messages_form_the_active_branch() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, added) = conversation(py, &store, 3);
+            let sources = py
+                .eval("'[{\"url\": \"https://example.com\"}]'", None, None)
+                .unwrap();
+            let with_sources = store
+                .add_message(
+                    py,
+                    conversation,
+                    "assistant",
+                    "cited",
+                    true,
+                    Some(sources),
+                    None,
+                )
+                .unwrap();
+
+            let messages = store
+                .get_conversation_messages(py, conversation, None)
+                .unwrap();
+            assert_eq!(
+                ids(py, &messages),
+                [added.clone(), vec![with_sources]].concat()
+            );
+            let parents: Vec<Option<i64>> =
+                messages.iter().map(|m| field(py, m, "parent_id")).collect();
+            assert_eq!(
+                parents,
+                [None, Some(added[0]), Some(added[1]), Some(added[2])]
+            );
+            assert_eq!(field::<String>(py, &messages[1], "role"), "assistant");
+            assert_eq!(field::<String>(py, &messages[1], "content"), "message 1");
+
+            let last = &messages[3];
+            assert!(field::<bool>(py, last, "used_web_search"));
+            let url: String = last
+                .as_ref(py)
+                .get_item("web_sources")
+                .unwrap()
+                .get_item(0)
+                .unwrap()
+                .get_item("url")
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert_eq!(url, "https://example.com");
+
+            let first_two = store
+                .get_conversation_messages(py, conversation, Some(2))
+                .unwrap();
+            assert_eq!(ids(py, &first_two), &added[..2]);
+            assert!(store
+                .get_conversation_messages(py, 999, None)
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn replies_must_stay_in_their_conversation() {
+        with_python(|py| {
+            let store = store(None);
+            let (_, first) = conversation(py, &store, 2);
+            let (other, _) = conversation(py, &store, 2);
+            let err = store
+                .add_message(py, other, "user", "x", false, None, Some(first[0]))
+                .unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+
+            // Replying to an earlier message forks the branch there
+            let (conversation, ids_) = conversation(py, &store, 4);
+            let fork = store
+                .add_message(py, conversation, "user", "fork", false, None, Some(ids_[1]))
+                .unwrap();
+            let messages = store
+                .get_conversation_messages(py, conversation, None)
+                .unwrap();
+            assert_eq!(ids(py, &messages), [ids_[0], ids_[1], fork]);
+        });
+    }
+
+    #[test]
+    fn edits_and_branch_switches() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 4);
+
+            let edited = store.edit_message(py, m[2], "reworded").unwrap().unwrap();
+            let messages = store
+                .get_conversation_messages(py, conversation, None)
+                .unwrap();
+            assert_eq!(ids(py, &messages), [m[0], m[1], edited]);
+            assert_eq!(field::<String>(py, &messages[2], "role"), "user");
+            assert_eq!(field::<i64>(py, &messages[2], "sibling_count"), 2);
+
+            let siblings = store.get_siblings(py, edited).unwrap();
+            assert_eq!(ids(py, &siblings), [m[2], edited]);
+            assert_eq!(ids(py, &store.get_siblings(py, m[0]).unwrap()), [m[0]]);
+            assert!(store.get_siblings(py, 999).unwrap().is_empty());
+
+            // Back to the original, down to its newest reply
+            assert!(store.switch_branch(py, m[2]).unwrap());
+            let messages = store
+                .get_conversation_messages(py, conversation, None)
+                .unwrap();
+            assert_eq!(ids(py, &messages), m);
+
+            assert_eq!(store.edit_message(py, 999, "x").unwrap(), None);
+            assert!(!store.switch_branch(py, 999).unwrap());
+        });
+    }
+
+    #[test]
+    fn pages_walk_back_from_the_newest_message() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 5);
+            let page_ids =
+                |rows: Vec<MessageRow>| -> Vec<i64> { rows.iter().map(|r| r.id).collect() };
+
+            assert_eq!(
+                page_ids(store.page(py, conversation, None, 2).unwrap()),
+                &m[3..]
+            );
+            assert_eq!(
+                page_ids(store.page(py, conversation, Some(m[3]), 2).unwrap()),
+                &m[1..3]
+            );
+            assert_eq!(
+                page_ids(store.page(py, conversation, Some(m[1]), 2).unwrap()),
+                &m[..1]
+            );
+            assert!(store
+                .page(py, conversation, Some(m[0]), 2)
+                .unwrap()
+                .is_empty());
+            assert!(store.page(py, conversation, None, 0).is_err());
+        });
+    }
+
+    #[test]
+    fn pages_follow_only_the_active_branch() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 4);
+            let edited = store.edit_message(py, m[1], "reworded").unwrap().unwrap();
+            let page_ids =
+                |rows: Vec<MessageRow>| -> Vec<i64> { rows.iter().map(|r| r.id).collect() };
+
+            assert_eq!(
+                page_ids(store.page(py, conversation, None, 10).unwrap()),
+                [m[0], edited]
+            );
+            assert_eq!(
+                page_ids(store.page(py, conversation, Some(edited), 10).unwrap()),
+                [m[0]]
+            );
+            assert!(store.switch_branch(py, m[1]).unwrap());
+            assert_eq!(page_ids(store.page(py, conversation, None, 10).unwrap()), m);
+        });
+    }
+
+    #[test]
+    fn context_messages_fit_the_budget() {
+        with_python(|py| {
+            let store = store(None);
+            let conversation = store.create_conversation(py, "Test", "m", false).unwrap();
+            let long = "lorem ipsum ".repeat(200);
+            for (role, content) in [
+                ("user", long.as_str()),
+                ("assistant", long.as_str()),
+                ("user", "short question"),
+            ] {
+                store
+                    .add_message(py, conversation, role, content, false, None, None)
+                    .unwrap();
+            }
+            let contents = |messages: Vec<PyObject>| -> Vec<(String, String)> {
+                messages
+                    .iter()
+                    .map(|m| (field(py, m, "role"), field(py, m, "content")))
+                    .collect()
+            };
+
+            let all = contents(
+                store
+                    .context_messages(py, conversation, "gpt-4o", 100_000, Some("Be brief"))
+                    .unwrap(),
+            );
+            assert_eq!(all.len(), 4);
+            assert_eq!(all[0], ("system".to_string(), "Be brief".to_string()));
+            assert_eq!(all[1].1, long);
+
+            let trimmed = contents(
+                store
+                    .context_messages(py, conversation, "gpt-4o", 50, Some("Be brief"))
+                    .unwrap(),
+            );
+            assert_eq!(trimmed[0].1, "Be brief");
+            assert_eq!(trimmed.last().unwrap().1, "short question");
+            assert!(trimmed.iter().all(|(_, content)| *content != long));
+        });
+    }
+
+    #[test]
+    fn compressed_bodies_read_back_as_text() {
+        with_python(|py| {
+            let store = store(Some(64));
+            let conversation = store.create_conversation(py, "Test", "m", false).unwrap();
+            let long = "a long reply that is well over the threshold ".repeat(10);
+            let id = store
+                .add_message(py, conversation, "assistant", &long, false, None, None)
+                .unwrap();
+            let short = store
+                .add_message(py, conversation, "user", "short", false, None, None)
+                .unwrap();
+
+            let stored = |id: i64| -> (bool, usize) {
+                store
+                    .connection()
+                    .query_row(
+                        "SELECT compressed, length(content) FROM messages WHERE id = ?1",
+                        [id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .unwrap()
+            };
+            let (compressed, size) = stored(id);
+            assert!(compressed);
+            assert!(size < long.len());
+            assert_eq!(stored(short), (false, 5));
+
+            let message = store.get_message(py, id).unwrap().unwrap();
+            assert_eq!(field::<String>(py, &message, "content"), long);
+            let messages = store
+                .get_conversation_messages(py, conversation, None)
+                .unwrap();
+            assert_eq!(field::<String>(py, &messages[0], "content"), long);
+            assert_eq!(field::<String>(py, &messages[1], "content"), "short");
+        });
+    }
+
+    #[test]
+    fn conversation_updates_bump_updated_at() {
+        with_python(|py| {
+            let store = store(None);
+            let conversation = store.create_conversation(py, "Test", "m", false).unwrap();
+            let age = |store: &Store| -> String {
+                store
+                    .connection()
+                    .query_row(
+                        "SELECT updated_at FROM conversations WHERE id = ?1",
+                        [conversation],
+                        |row| row.get(0),
+                    )
+                    .unwrap()
+            };
+            let make_old = || {
+                store
+                    .connection()
+                    .execute(
+                        "UPDATE conversations SET updated_at = '2000-01-01 00:00:00'",
+                        [],
+                    )
+                    .unwrap();
+            };
+
+            make_old();
+            assert!(store
+                .rename_conversation(py, conversation, "Renamed")
+                .unwrap());
+            assert!(age(&store).as_str() > "2000-01-02");
+            let dict = store.get_conversation(py, conversation).unwrap().unwrap();
+            assert_eq!(field::<String>(py, &dict, "title"), "Renamed");
+
+            make_old();
+            assert!(store
+                .set_web_search_enabled(py, conversation, true)
+                .unwrap());
+            assert!(age(&store).as_str() > "2000-01-02");
+            assert!(store.get_web_search_enabled(py, conversation).unwrap());
+
+            make_old();
+            store
+                .add_message(py, conversation, "user", "hi", false, None, None)
+                .unwrap();
+            assert!(age(&store).as_str() > "2000-01-02");
+
+            assert!(!store.rename_conversation(py, 999, "Missing").unwrap());
+            assert!(!store.get_web_search_enabled(py, 999).unwrap());
+        });
+    }
+}
@@ -0,0 +1,58 @@
+// Column values of conversations.db, as nanochat.data.models fills them
+//
+// SQLAlchemy applies column defaults in Python rather than in the DDL, so
+// the store supplies every value on insert, exactly as the ORM would.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Column defaults from models.py
+pub const DEFAULT_TITLE: &str = "New Chat";
+pub const DEFAULT_MODEL: &str = "gpt-4";
+pub const DEFAULT_COLOR: &str = "#4a9eff";
+
+/// The current UTC time as SQLAlchemy stores a DateTime:
+/// "YYYY-MM-DD HH:MM:SS.ffffff"
+pub fn now() -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let time = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+/// Gregorian date of a day count since 1970-01-01 (Howard Hinnant's
+/// civil_from_days)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A stored DateTime in datetime.isoformat() form, which the UI code
+/// already parses: "T" between date and time, and no fraction when it is
+/// zero
+pub fn isoformat(stored: &str) -> String {
+    let iso = stored.replacen(' ', "T", 1);
+    match iso.split_once('.') {
+        Some((whole, fraction)) if fraction.bytes().all(|b| b == b'0') => whole.to_string(),
+        _ => iso,
+    }
+}
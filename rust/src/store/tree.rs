@@ -0,0 +1,248 @@
+// Messages as a tree
+//
+// A message's parent_id is the message it replies to, so regenerating or
+// editing adds a sibling instead of replacing anything.
+// conversations.active_message_id is the tip of the branch on screen; the
+// active path runs from there up to a root. A reply is always written after
+// its parent, so ids rise along any path from root to tip.
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Ids on the active path of conversation ?1, tip first. Without a pointer
+/// (databases written by older code) the newest message is the tip.
+pub const ACTIVE_PATH: &str = "
+    WITH RECURSIVE path(id) AS (
+        SELECT COALESCE(
+            (SELECT active_message_id FROM conversations WHERE id = ?1),
+            (SELECT MAX(id) FROM messages WHERE conversation_id = ?1)
+        )
+        UNION ALL
+        SELECT m.parent_id FROM messages m JOIN path ON m.id = path.id
+        WHERE m.parent_id IS NOT NULL
+    )";
+
+/// The message new replies in a conversation go under
+pub fn tip(conn: &Connection, conversation_id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT COALESCE(
+             (SELECT active_message_id FROM conversations WHERE id = ?1),
+             (SELECT MAX(id) FROM messages WHERE conversation_id = ?1)
+         )",
+        [conversation_id],
+        |row| row.get(0),
+    )
+}
+
+pub fn set_tip(
+    conn: &Connection,
+    conversation_id: i64,
+    message_id: Option<i64>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE conversations SET active_message_id = ?2 WHERE id = ?1",
+        params![conversation_id, message_id],
+    )?;
+    Ok(())
+}
+
+/// The leaf reached from `message_id` by following the newest reply at
+/// each step, i.e. the branch last worked on below it
+pub fn newest_leaf(conn: &Connection, message_id: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "WITH RECURSIVE walk(id) AS (
+             SELECT ?1
+             UNION ALL
+             SELECT (SELECT MAX(c.id) FROM messages c WHERE c.parent_id = walk.id)
+             FROM walk WHERE walk.id IS NOT NULL
+         )
+         SELECT MAX(id) FROM walk",
+        [message_id],
+        |row| row.get(0),
+    )
+}
+
+/// (conversation_id, parent_id, role) of a message
+pub fn position(
+    conn: &Connection,
+    message_id: i64,
+) -> rusqlite::Result<Option<(i64, Option<i64>, String)>> {
+    conn.query_row(
+        "SELECT conversation_id, parent_id, role FROM messages WHERE id = ?1",
+        [message_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()
+}
+
+/// Delete a message with every reply below it and their usage records,
+/// returning how many messages went.
+/// A tip inside the deleted branch moves to the newest branch left under
+/// its parent, or under the newest root for a deleted root.
+pub fn delete_branch(conn: &Connection, message_id: i64) -> rusqlite::Result<usize> {
+    const BRANCH: &str = "
+        WITH RECURSIVE branch(id) AS (
+            SELECT ?1
+            UNION ALL
+            SELECT m.id FROM messages m JOIN branch ON m.parent_id = branch.id
+        )";
+
+    let Some((conversation_id, parent_id, _)) = position(conn, message_id)? else {
+        return Ok(0);
+    };
+    // The pointer is a foreign key, so it must let go before the rows do
+    let tip_deleted: bool = conn.query_row(
+        &format!(
+            "{} SELECT EXISTS (SELECT 1 FROM conversations c JOIN branch b
+                               ON b.id = c.active_message_id WHERE c.id = ?2)",
+            BRANCH
+        ),
+        [message_id, conversation_id],
+        |row| row.get(0),
+    )?;
+    if tip_deleted {
+        set_tip(conn, conversation_id, None)?;
+    }
+    conn.execute(
+        &format!(
+            "{} DELETE FROM message_usage WHERE message_id IN (SELECT id FROM branch)",
+            BRANCH
+        ),
+        [message_id],
+    )?;
+    let deleted = conn.execute(
+        &format!(
+            "{} DELETE FROM messages WHERE id IN (SELECT id FROM branch)",
+            BRANCH
+        ),
+        [message_id],
+    )?;
+    if tip_deleted {
+        let start = match parent_id {
+            Some(parent) => Some(parent),
+            None => conn.query_row(
+                "SELECT MAX(id) FROM messages WHERE conversation_id = ?1 AND parent_id IS NULL",
+                [conversation_id],
+                |row| row.get(0),
+            )?,
+        };
+        let tip = start.map(|id| newest_leaf(conn, id)).transpose()?;
+        set_tip(conn, conversation_id, tip)?;
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::migrate::migrate;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, ":memory:", false).unwrap();
+        conn.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at)
+             VALUES (1, 'Test', '2024-01-01', '2024-01-01')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Add a message under `parent` and make it the tip, as Store does
+    fn reply(conn: &Connection, parent: Option<i64>, role: &str) -> i64 {
+        conn.execute(
+            "INSERT INTO messages (conversation_id, role, content, created_at, parent_id)
+             VALUES (1, ?1, '', '2024-01-01', ?2)",
+            params![role, parent],
+        )
+        .unwrap();
+        let id = conn.last_insert_rowid();
+        set_tip(conn, 1, Some(id)).unwrap();
+        id
+    }
+
+    fn active_path(conn: &Connection) -> Vec<i64> {
+        let mut stmt = conn
+            .prepare(&format!("{} SELECT id FROM path ORDER BY id", ACTIVE_PATH))
+            .unwrap();
+        let ids = stmt.query_map([1], |row| row.get(0)).unwrap();
+        ids.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn regenerating_forks_a_sibling() {
+        let conn = database();
+        let question = reply(&conn, None, "user");
+        let first = reply(&conn, Some(question), "assistant");
+        let second = reply(&conn, Some(question), "assistant");
+
+        assert_eq!(active_path(&conn), vec![question, second]);
+        assert_eq!(position(&conn, first).unwrap().unwrap().1, Some(question));
+        assert_eq!(newest_leaf(&conn, question).unwrap(), second);
+    }
+
+    #[test]
+    fn switching_shows_the_newest_branch_below() {
+        let conn = database();
+        let question = reply(&conn, None, "user");
+        let first = reply(&conn, Some(question), "assistant");
+        let follow_up = reply(&conn, Some(first), "user");
+        let second = reply(&conn, Some(question), "assistant");
+        assert_eq!(active_path(&conn), vec![question, second]);
+
+        set_tip(&conn, 1, Some(newest_leaf(&conn, first).unwrap())).unwrap();
+        assert_eq!(active_path(&conn), vec![question, first, follow_up]);
+        assert_eq!(tip(&conn, 1).unwrap(), Some(follow_up));
+    }
+
+    #[test]
+    fn deleting_the_shown_branch_moves_to_a_sibling() {
+        let conn = database();
+        let question = reply(&conn, None, "user");
+        let first = reply(&conn, Some(question), "assistant");
+        let second = reply(&conn, Some(question), "assistant");
+        reply(&conn, Some(second), "user");
+        for id in [first, second] {
+            conn.execute(
+                "INSERT INTO message_usage (message_id, model, recorded_at)
+                 VALUES (?1, 'gpt-4', '2024-01-01')",
+                [id],
+            )
+            .unwrap();
+        }
+
+        assert_eq!(delete_branch(&conn, second).unwrap(), 2);
+        assert_eq!(active_path(&conn), vec![question, first]);
+        assert_eq!(position(&conn, second).unwrap(), None);
+        let usage: Vec<i64> = conn
+            .prepare("SELECT message_id FROM message_usage")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(usage, vec![first]);
+    }
+
+    #[test]
+    fn deleting_a_hidden_branch_keeps_the_tip() {
+        let conn = database();
+        let question = reply(&conn, None, "user");
+        let first = reply(&conn, Some(question), "assistant");
+        let second = reply(&conn, Some(question), "assistant");
+
+        assert_eq!(delete_branch(&conn, first).unwrap(), 1);
+        assert_eq!(tip(&conn, 1).unwrap(), Some(second));
+    }
+
+    #[test]
+    fn deleting_the_only_root_clears_the_tip() {
+        let conn = database();
+        let question = reply(&conn, None, "user");
+        reply(&conn, Some(question), "assistant");
+
+        assert_eq!(delete_branch(&conn, question).unwrap(), 2);
+        assert_eq!(tip(&conn, 1).unwrap(), None);
+        assert_eq!(delete_branch(&conn, question).unwrap(), 0);
+    }
+}
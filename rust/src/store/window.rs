@@ -0,0 +1,180 @@
+// Lazy, newest-first paging through a conversation's messages
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use super::{into_dicts, MessageRow, Store};
+use crate::api::{py_bool, py_number};
+
+/// Walks a conversation back from its newest message one page at a time
+///
+/// Iterating yields pages of the active branch, each oldest first, until
+/// the first message has been returned. Messages added after the window was
+/// made aren't included; open a new window to see them, or after switching
+/// branches.
+#[pyclass]
+pub struct MessageWindow {
+    store: Py<Store>,
+    #[pyo3(get)]
+    conversation_id: i64,
+    #[pyo3(get)]
+    page_size: i64,
+    /// Id of the oldest message returned so far
+    #[pyo3(get)]
+    oldest_id: Option<i64>,
+    exhausted: bool,
+}
+
+impl MessageWindow {
+    pub fn new(store: Py<Store>, conversation_id: i64, page_size: i64) -> PyResult<Self> {
+        if page_size < 1 {
+            return Err(PyValueError::new_err("page_size must be at least 1"));
+        }
+        Ok(MessageWindow {
+            store,
+            conversation_id,
+            page_size,
+            oldest_id: None,
+            exhausted: false,
+        })
+    }
+}
+
+#[pymethods]
+impl MessageWindow {
+    /// Whether older messages may remain
+    #[getter]
+    fn has_more(&self) -> bool {
+        !self.exhausted
+    }
+
+    /// The next page back, or an empty list once the start is reached
+    fn load_older(&mut self, py: Python) -> PyResult<Vec<PyObject>> {
+        if self.exhausted {
+            return Ok(Vec::new());
+        }
+        let rows =
+            self.store
+                .borrow(py)
+                .page(py, self.conversation_id, self.oldest_id, self.page_size)?;
+        if (rows.len() as i64) < self.page_size {
+            self.exhausted = true;
+        }
+        if let Some(first) = rows.first() {
+            self.oldest_id = Some(first.id);
+        }
+        into_dicts(py, rows, MessageRow::into_dict)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<Vec<PyObject>>> {
+        let page = self.load_older(py)?;
+        Ok((!page.is_empty()).then_some(page))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MessageWindow(conversation_id={}, page_size={}, oldest_id={}, has_more={})",
+            self.conversation_id,
+            self.page_size,
+            py_number(self.oldest_id),
+            py_bool(!self.exhausted)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{conversation, ids, store, with_python};
+    use super::*;
+
+    fn window(py: Python, store: Store, conversation_id: i64, page_size: i64) -> MessageWindow {
+        MessageWindow::new(Py::new(py, store).unwrap(), conversation_id, page_size).unwrap()
+    }
+
+    #[test]
+    fn pages_walk_back_oldest_first() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 5);
+            let mut window = window(py, store, conversation, 2);
+
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), &m[3..]);
+            assert_eq!(window.oldest_id, Some(m[3]));
+            assert!(window.has_more());
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), &m[1..3]);
+            assert_eq!(window.oldest_id, Some(m[1]));
+            // The short last page marks the start
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), &m[..1]);
+            assert_eq!(window.oldest_id, Some(m[0]));
+            assert!(!window.has_more());
+            assert!(window.load_older(py).unwrap().is_empty());
+            assert_eq!(window.oldest_id, Some(m[0]));
+        });
+    }
+
+    #[test]
+    fn exact_multiples_end_on_an_empty_page() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 4);
+            let mut window = window(py, store, conversation, 2);
+
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), &m[2..]);
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), &m[..2]);
+            // A full page can't tell whether anything is left
+            assert!(window.has_more());
+            assert!(window.__next__(py).unwrap().is_none());
+            assert!(!window.has_more());
+        });
+    }
+
+    #[test]
+    fn empty_conversations_have_no_pages() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, _) = conversation(py, &store, 0);
+            let mut window = window(py, store, conversation, 3);
+            assert!(window.has_more());
+            assert!(window.load_older(py).unwrap().is_empty());
+            assert!(!window.has_more());
+            assert_eq!(window.oldest_id, None);
+        });
+    }
+
+    #[test]
+    fn only_the_active_branch_is_paged() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, m) = conversation(py, &store, 4);
+            let edited = store.edit_message(py, m[2], "reworded").unwrap().unwrap();
+            let mut window = window(py, store, conversation, 2);
+
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), [m[1], edited]);
+            assert_eq!(ids(py, &window.load_older(py).unwrap()), [m[0]]);
+            assert!(!window.has_more());
+        });
+    }
+
+    #[test]
+    fn page_size_must_be_positive() {
+        with_python(|py| {
+            let store = store(None);
+            let (conversation, _) = conversation(py, &store, 0);
+            let store = Py::new(py, store).unwrap();
+            assert!(MessageWindow::new(store.clone_ref(py), conversation, 0).is_err());
+            assert!(MessageWindow::new(store.clone_ref(py), conversation, -1).is_err());
+            let window = MessageWindow::new(store, conversation, 1).unwrap();
+            assert_eq!(
+                window.__repr__(),
+                format!(
+                    "MessageWindow(conversation_id={}, page_size=1, oldest_id=None, has_more=True)",
+                    conversation
+                )
+            );
+        });
+    }
+}
@@ -0,0 +1,391 @@
+// Separating model reasoning from the answer in streamed text
+//
+// Reasoning models wrap their chain of thought in tags such as <think>,
+// <thought>, <reasoning> or Kimi's ◁think▷. Tags may arrive split across
+// chunks, so any text that could be the start of a tag is held back until
+// the next chunk decides it.
+
+use std::collections::BTreeMap;
+
+use pyo3::prelude::*;
+
+use crate::api::py_bool;
+
+const OPENERS: &[&str] = &[
+    "<think>",
+    "<thinking>",
+    "<thought>",
+    "<reasoning>",
+    "◁think▷",
+];
+const CLOSERS: &[&str] = &[
+    "</think>",
+    "</thinking>",
+    "</thought>",
+    "</reasoning>",
+    "◁/think▷",
+];
+
+/// A run of reasoning or answer text
+#[pyclass]
+#[derive(Clone)]
+pub struct Segment {
+    /// "reasoning" or "answer"
+    #[pyo3(get)]
+    pub kind: &'static str,
+    /// Position among all segments of the message
+    #[pyo3(get)]
+    pub index: usize,
+    #[pyo3(get)]
+    pub text: String,
+    /// Text added by the call that returned this segment
+    #[pyo3(get)]
+    pub delta: String,
+    /// False while more text may still be appended
+    #[pyo3(get)]
+    pub closed: bool,
+}
+
+#[pymethods]
+impl Segment {
+    fn __repr__(&self) -> String {
+        format!(
+            "Segment(kind={:?}, index={}, closed={}, text={:?})",
+            self.kind,
+            self.index,
+            py_bool(self.closed),
+            self.text
+        )
+    }
+}
+
+/// Incremental splitter of a response into reasoning and answer segments
+#[pyclass]
+#[derive(Default)]
+pub struct ThinkingParser {
+    segments: Vec<Segment>,
+    /// Text not yet assigned because it may be the start of a tag
+    pending: String,
+    in_reasoning: bool,
+    /// Segments touched by the current call, with the text they gained
+    changed: BTreeMap<usize, String>,
+}
+
+#[pymethods]
+impl ThinkingParser {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a chunk and return the segments it changed
+    fn feed(&mut self, chunk: &str) -> Vec<Segment> {
+        self.pending.push_str(chunk);
+        self.process();
+        self.take_changes()
+    }
+
+    /// End of stream: flush held-back text and close every segment
+    fn finish(&mut self) -> Vec<Segment> {
+        let rest = std::mem::take(&mut self.pending);
+        self.emit(&rest);
+        for i in 0..self.segments.len() {
+            if !self.segments[i].closed {
+                self.close_segment(i);
+            }
+        }
+        self.in_reasoning = false;
+        self.take_changes()
+    }
+
+    fn segments(&self) -> Vec<Segment> {
+        self.segments.clone()
+    }
+
+    fn reasoning_segments(&self) -> Vec<Segment> {
+        self.of_kind("reasoning")
+    }
+
+    fn answer_segments(&self) -> Vec<Segment> {
+        self.of_kind("answer")
+    }
+
+    /// All reasoning so far, blocks separated by blank lines
+    #[getter]
+    fn reasoning(&self) -> String {
+        self.joined("reasoning")
+    }
+
+    /// The answer with all reasoning removed
+    #[getter]
+    fn answer(&self) -> String {
+        self.joined("answer")
+    }
+
+    /// True while inside an unclosed reasoning block
+    #[getter]
+    fn is_reasoning(&self) -> bool {
+        self.in_reasoning
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl ThinkingParser {
+    fn process(&mut self) {
+        loop {
+            let lower = self.pending.to_ascii_lowercase();
+            let tags = if self.in_reasoning { CLOSERS } else { OPENERS };
+            let found = find_first(&lower, tags);
+            // A closer without an opener: some models omit the opening tag
+            let stray = if self.in_reasoning {
+                None
+            } else {
+                find_first(&lower, CLOSERS).filter(|&(at, _)| found.is_none_or(|(f, _)| at < f))
+            };
+
+            if let Some((at, len)) = stray {
+                let text = self.pending[..at].to_string();
+                self.pending.drain(..at + len);
+                self.emit(&text);
+                self.close_leading_reasoning();
+                continue;
+            }
+            let Some((at, len)) = found else {
+                // Keep a possible partial tag for the next chunk
+                let keep = partial_tag_len(&lower);
+                let text: String = self.pending.drain(..self.pending.len() - keep).collect();
+                self.emit(&text);
+                return;
+            };
+
+            let text = self.pending[..at].to_string();
+            self.pending.drain(..at + len);
+            self.emit(&text);
+            if let Some(last) = self.segments.len().checked_sub(1) {
+                if !self.segments[last].closed {
+                    self.close_segment(last);
+                }
+            }
+            self.in_reasoning = !self.in_reasoning;
+            if self.in_reasoning {
+                // Announce the block right away so the UI can show it
+                self.push_segment("reasoning");
+            }
+        }
+    }
+
+    /// Append text to the open segment of the current kind
+    fn emit(&mut self, text: &str) {
+        let kind = if self.in_reasoning {
+            "reasoning"
+        } else {
+            "answer"
+        };
+        let index = match self.segments.last() {
+            Some(last) if !last.closed && last.kind == kind => self.segments.len() - 1,
+            _ => {
+                // Whitespace between blocks doesn't make a segment
+                if text.trim().is_empty() {
+                    return;
+                }
+                self.push_segment(kind)
+            }
+        };
+
+        let segment = &mut self.segments[index];
+        let text = if segment.text.is_empty() {
+            text.trim_start()
+        } else {
+            text
+        };
+        if text.is_empty() {
+            return;
+        }
+        segment.text.push_str(text);
+        self.changed.entry(index).or_default().push_str(text);
+    }
+
+    fn push_segment(&mut self, kind: &'static str) -> usize {
+        let index = self.segments.len();
+        self.segments.push(Segment {
+            kind,
+            index,
+            text: String::new(),
+            delta: String::new(),
+            closed: false,
+        });
+        self.changed.entry(index).or_default();
+        index
+    }
+
+    fn close_segment(&mut self, index: usize) {
+        self.segments[index].closed = true;
+        self.changed.entry(index).or_default();
+    }
+
+    /// Treat everything before a stray closing tag as reasoning, if it is
+    /// the only thing seen so far; otherwise the tag is just dropped
+    fn close_leading_reasoning(&mut self) {
+        if let [first] = self.segments.as_mut_slice() {
+            if first.kind == "answer" {
+                first.kind = "reasoning";
+                first.closed = true;
+                self.changed.entry(0).or_default();
+            }
+        }
+    }
+
+    fn take_changes(&mut self) -> Vec<Segment> {
+        std::mem::take(&mut self.changed)
+            .into_iter()
+            .map(|(index, delta)| Segment {
+                delta,
+                ..self.segments[index].clone()
+            })
+            .collect()
+    }
+
+    fn of_kind(&self, kind: &str) -> Vec<Segment> {
+        self.segments
+            .iter()
+            .filter(|s| s.kind == kind)
+            .cloned()
+            .collect()
+    }
+
+    fn joined(&self, kind: &str) -> String {
+        self.segments
+            .iter()
+            .filter(|s| s.kind == kind && !s.text.trim().is_empty())
+            .map(|s| s.text.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// Earliest occurrence of any tag as (byte offset, tag length)
+fn find_first(text: &str, tags: &[&str]) -> Option<(usize, usize)> {
+    tags.iter()
+        .filter_map(|tag| text.find(tag).map(|at| (at, tag.len())))
+        .min_by_key(|&(at, _)| at)
+}
+
+/// Length of the longest suffix of `text` that could begin any tag
+fn partial_tag_len(text: &str) -> usize {
+    OPENERS
+        .iter()
+        .chain(CLOSERS)
+        .flat_map(|tag| {
+            tag.char_indices()
+                .skip(1)
+                .map(|(i, _)| &tag[..i])
+                .filter(|prefix| text.ends_with(prefix))
+                .map(str::len)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(chunks: &[&str]) -> ThinkingParser {
+        let mut parser = ThinkingParser::new();
+        for chunk in chunks {
+            parser.feed(chunk);
+        }
+        parser.finish();
+        parser
+    }
+
+    fn kinds(parser: &ThinkingParser) -> Vec<(&'static str, String)> {
+        parser
+            .segments
+            .iter()
+            .map(|s| (s.kind, s.text.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_reasoning_from_answer() {
+        let parser = parse(&["<think>Let me see.</think>\n\nThe answer is 4."]);
+        assert_eq!(parser.reasoning(), "Let me see.");
+        assert_eq!(parser.answer(), "The answer is 4.");
+        assert!(parser.segments.iter().all(|s| s.closed));
+    }
+
+    #[test]
+    fn tags_split_across_chunks() {
+        let text = "Intro <thinking>deep thought</thinking> outro";
+        for size in 1..text.len() {
+            let chunks: Vec<&str> = text
+                .as_bytes()
+                .chunks(size)
+                .map(|c| std::str::from_utf8(c).unwrap())
+                .collect();
+            let parser = parse(&chunks);
+            assert_eq!(
+                kinds(&parser),
+                [
+                    ("answer", "Intro ".to_string()),
+                    ("reasoning", "deep thought".to_string()),
+                    ("answer", "outro".to_string()),
+                ],
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn other_tag_styles_and_case() {
+        assert_eq!(parse(&["◁think▷hmm◁/think▷ok"]).reasoning(), "hmm");
+        assert_eq!(parse(&["<REASONING>r</Reasoning>a"]).answer(), "a");
+        assert_eq!(parse(&["<thought>t</thought>a"]).reasoning(), "t");
+    }
+
+    #[test]
+    fn stray_closer_makes_leading_text_reasoning() {
+        let parser = parse(&["thinking without an opener", "</think>answer"]);
+        assert_eq!(parser.reasoning(), "thinking without an opener");
+        assert_eq!(parser.answer(), "answer");
+    }
+
+    #[test]
+    fn unclosed_reasoning_is_closed_by_finish() {
+        let mut parser = ThinkingParser::new();
+        let changed = parser.feed("<think>still going");
+        assert!(parser.is_reasoning());
+        assert_eq!(changed.len(), 1);
+        assert!(!changed[0].closed);
+        assert_eq!(changed[0].delta, "still going");
+
+        let changed = parser.finish();
+        assert!(!parser.is_reasoning());
+        assert!(changed[0].closed);
+        assert_eq!(changed[0].delta, "");
+    }
+
+    #[test]
+    fn possible_tag_start_is_held_back() {
+        let mut parser = ThinkingParser::new();
+        let changed = parser.feed("a <thi");
+        assert_eq!(changed[0].text, "a ");
+        let changed = parser.feed("s is not a tag");
+        assert_eq!(changed[0].delta, "<this is not a tag");
+        assert_eq!(parser.answer(), "a <this is not a tag");
+    }
+
+    #[test]
+    fn repr_uses_python_values() {
+        let parser = parse(&["<think>x</think>"]);
+        assert_eq!(
+            parser.segments[0].__repr__(),
+            "Segment(kind=\"reasoning\", index=0, closed=True, text=\"x\")"
+        );
+    }
+}
@@ -0,0 +1,369 @@
+// Token counting and context-window trimming
+//
+// Counts use the BPE vocabularies bundled with tiktoken: o200k_base for the
+// GPT-4o family and newer OpenAI models, cl100k_base for everything else.
+// Other vendors' tokenizers differ, but cl100k is close enough to keep a
+// request inside the window with the margin callers already leave.
+
+use std::sync::OnceLock;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyString};
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::CoreBPE;
+
+/// Framing around each message: <|start|>{role}\n{content}<|end|>\n
+const TOKENS_PER_MESSAGE: usize = 3;
+const TOKENS_PER_NAME: usize = 1;
+/// Every reply is primed with <|start|>assistant<|message|>
+const REPLY_PRIMING: usize = 3;
+
+/// OpenAI models newer than the tiktoken tables, all on o200k_base
+const O200K_PREFIXES: &[&str] = &[
+    "gpt-4o",
+    "chatgpt-4o",
+    "gpt-4.1",
+    "gpt-4.5",
+    "gpt-5",
+    "o1",
+    "o3",
+    "o4",
+];
+
+fn encoder(model: &str) -> &'static CoreBPE {
+    static CL100K: OnceLock<CoreBPE> = OnceLock::new();
+    static O200K: OnceLock<CoreBPE> = OnceLock::new();
+
+    // NanoGPT ids may carry a provider prefix, e.g. "openai/gpt-4o"
+    let name = model
+        .rsplit('/')
+        .next()
+        .unwrap_or(model)
+        .to_ascii_lowercase();
+    let o200k = O200K_PREFIXES.iter().any(|p| name.starts_with(p))
+        || get_tokenizer(&name) == Some(Tokenizer::O200kBase);
+    if o200k {
+        O200K.get_or_init(|| tiktoken_rs::o200k_base().expect("bundled o200k_base vocabulary"))
+    } else {
+        CL100K.get_or_init(|| tiktoken_rs::cl100k_base().expect("bundled cl100k_base vocabulary"))
+    }
+}
+
+/// A chat message reduced to what costs tokens
+struct Message {
+    role: String,
+    content: String,
+    name: Option<String>,
+}
+
+impl Message {
+    fn from_py(item: &PyAny) -> PyResult<Self> {
+        let dict: &PyDict = item
+            .downcast()
+            .map_err(|_| PyValueError::new_err("messages must be dicts with role and content"))?;
+        let text = |key: &str| -> PyResult<Option<String>> {
+            match dict.get_item(key)? {
+                None => Ok(None),
+                Some(v) if v.is_none() => Ok(None),
+                Some(v) => match v.downcast::<PyString>() {
+                    Ok(s) => Ok(Some(s.to_str()?.to_string())),
+                    // Structured content (e.g. a list of parts) counts as its text form
+                    Err(_) => Ok(Some(v.str()?.to_str()?.to_string())),
+                },
+            }
+        };
+        Ok(Self {
+            role: text("role")?.unwrap_or_default(),
+            content: text("content")?.unwrap_or_default(),
+            name: text("name")?,
+        })
+    }
+
+    fn tokens(&self, bpe: &CoreBPE) -> usize {
+        let mut count = TOKENS_PER_MESSAGE
+            + bpe.encode_ordinary(&self.role).len()
+            + bpe.encode_ordinary(&self.content).len();
+        if let Some(name) = &self.name {
+            count += TOKENS_PER_NAME + bpe.encode_ordinary(name).len();
+        }
+        count
+    }
+}
+
+fn extract_messages(messages: &PyList) -> PyResult<Vec<Message>> {
+    messages.iter().map(Message::from_py).collect()
+}
+
+/// Tokens a request would use: a list of message dicts, or plain text
+#[pyfunction]
+#[pyo3(signature = (messages, model="gpt-4"))]
+pub fn count_tokens(py: Python, messages: &PyAny, model: &str) -> PyResult<usize> {
+    let bpe = encoder(model);
+    if let Ok(text) = messages.downcast::<PyString>() {
+        let text = text.to_str()?;
+        return Ok(py.allow_threads(|| bpe.encode_ordinary(text).len()));
+    }
+    let list: &PyList = messages
+        .downcast()
+        .map_err(|_| PyValueError::new_err("messages must be a list of dicts or a string"))?;
+    let messages = extract_messages(list)?;
+    Ok(py.allow_threads(|| REPLY_PRIMING + messages.iter().map(|m| m.tokens(bpe)).sum::<usize>()))
+}
+
+/// Trim `messages` to fit in `max_tokens`, dropping the oldest turns first
+///
+/// System messages and the latest user message are always kept, and
+/// `system_prompt` (the mode's prompt from MODE_CONFIGS) is put first
+/// unless already present. Dropped turns are replaced by one system
+/// message saying how many were omitted. The dicts that remain are the
+/// caller's own objects, in their original order.
+#[pyfunction]
+#[pyo3(signature = (messages, model, max_tokens, system_prompt=None))]
+pub fn fit_context(
+    py: Python,
+    messages: &PyList,
+    model: &str,
+    max_tokens: usize,
+    system_prompt: Option<&str>,
+) -> PyResult<Vec<PyObject>> {
+    let bpe = encoder(model);
+    let parsed = extract_messages(messages)?;
+
+    let prompt = system_prompt
+        .filter(|p| !p.trim().is_empty())
+        .filter(|p| !parsed.iter().any(|m| m.role == "system" && m.content == *p))
+        .map(|p| Message {
+            role: "system".to_string(),
+            content: p.to_string(),
+            name: None,
+        });
+
+    let plan = py.allow_threads(|| plan(&parsed, prompt.as_ref(), bpe, max_tokens));
+
+    let mut out = Vec::with_capacity(messages.len() + 2);
+    if let Some(prompt) = &prompt {
+        out.push(system_message(py, &prompt.content)?);
+    }
+    for (i, item) in messages.iter().enumerate() {
+        if plan.marker_at == Some(i) {
+            out.push(system_message(py, &omitted_notice(plan.dropped))?);
+        }
+        if plan.keep[i] {
+            out.push(item.into());
+        }
+    }
+    if plan.dropped > 0 && plan.marker_at.is_none() {
+        out.push(system_message(py, &omitted_notice(plan.dropped))?);
+    }
+    Ok(out)
+}
+
+struct Plan {
+    keep: Vec<bool>,
+    dropped: usize,
+    /// Index of the message the omission notice goes before; at the end
+    /// when no later message was kept
+    marker_at: Option<usize>,
+}
+
+/// Decide which messages to keep. Only messages that could still fit are
+/// tokenised, so the cost follows the budget rather than the history.
+fn plan(messages: &[Message], prompt: Option<&Message>, bpe: &CoreBPE, max_tokens: usize) -> Plan {
+    let latest_user = messages.iter().rposition(|m| m.role == "user");
+    let mut keep: Vec<bool> = messages
+        .iter()
+        .enumerate()
+        .map(|(i, m)| m.role == "system" || Some(i) == latest_user)
+        .collect();
+    let mut used = REPLY_PRIMING
+        + prompt.map_or(0, |p| p.tokens(bpe))
+        + messages
+            .iter()
+            .zip(&keep)
+            .filter(|(_, &k)| k)
+            .map(|(m, _)| m.tokens(bpe))
+            .sum::<usize>();
+
+    // Newest first, stopping at the first turn that doesn't fit so the
+    // kept history has no holes
+    let mut window = Vec::new();
+    let mut overflow = false;
+    for i in (0..messages.len()).rev() {
+        if keep[i] {
+            continue;
+        }
+        let cost = messages[i].tokens(bpe);
+        if used + cost > max_tokens {
+            overflow = true;
+            break;
+        }
+        keep[i] = true;
+        used += cost;
+        window.push((i, cost));
+    }
+    if !overflow {
+        return Plan {
+            keep,
+            dropped: 0,
+            marker_at: None,
+        };
+    }
+
+    // Make room for the notice; it can't say more than "all of them"
+    let notice = Message {
+        role: "system".to_string(),
+        content: omitted_notice(messages.len()),
+        name: None,
+    }
+    .tokens(bpe);
+    while used + notice > max_tokens {
+        let Some((i, cost)) = window.pop() else {
+            break;
+        };
+        keep[i] = false;
+        used -= cost;
+    }
+
+    // Replies whose question was dropped make no sense on their own
+    let Some(first_dropped) = keep.iter().position(|&k| !k) else {
+        return Plan {
+            keep,
+            dropped: 0,
+            marker_at: None,
+        };
+    };
+    let kept_after_gap = |keep: &[bool]| {
+        (first_dropped..messages.len()).find(|&i| keep[i] && messages[i].role != "system")
+    };
+    if let Some(first) = kept_after_gap(&keep) {
+        for (i, message) in messages.iter().enumerate().skip(first) {
+            match message.role.as_str() {
+                "system" => {}
+                "user" => break,
+                _ => keep[i] = false,
+            }
+        }
+    }
+
+    let dropped = keep.iter().filter(|&&k| !k).count();
+    let marker_at = kept_after_gap(&keep);
+    Plan {
+        keep,
+        dropped,
+        marker_at,
+    }
+}
+
+fn omitted_notice(count: usize) -> String {
+    let noun = if count == 1 { "message" } else { "messages" };
+    format!(
+        "[{} earlier {} omitted to fit the context window]",
+        count, noun
+    )
+}
+
+fn system_message(py: Python, content: &str) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("role", "system")?;
+    dict.set_item("content", content)?;
+    Ok(dict.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: &str, content: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            name: None,
+        }
+    }
+
+    /// system, then three turns; the middle turn's text is long
+    fn conversation() -> Vec<Message> {
+        let long = "a fairly long message about nothing in particular ".repeat(8);
+        vec![
+            message("system", "Be brief."),
+            message("user", &long),
+            message("assistant", &long),
+            message("user", &long),
+            message("assistant", "Short answer."),
+            message("user", "And now?"),
+        ]
+    }
+
+    fn cost(messages: &[Message], indices: &[usize], bpe: &CoreBPE) -> usize {
+        REPLY_PRIMING
+            + indices
+                .iter()
+                .map(|&i| messages[i].tokens(bpe))
+                .sum::<usize>()
+    }
+
+    fn notice(messages: &[Message], bpe: &CoreBPE) -> usize {
+        message("system", &omitted_notice(messages.len())).tokens(bpe)
+    }
+
+    fn kept(plan: &Plan) -> Vec<usize> {
+        (0..plan.keep.len()).filter(|&i| plan.keep[i]).collect()
+    }
+
+    #[test]
+    fn keeps_everything_that_fits() {
+        let bpe = encoder("gpt-4");
+        let messages = conversation();
+        let all: Vec<usize> = (0..messages.len()).collect();
+        let plan = plan(&messages, None, bpe, cost(&messages, &all, bpe));
+        assert_eq!(kept(&plan), all);
+        assert_eq!(plan.dropped, 0);
+        assert_eq!(plan.marker_at, None);
+    }
+
+    #[test]
+    fn drops_oldest_turns_first() {
+        let bpe = encoder("gpt-4");
+        let messages = conversation();
+        let budget = cost(&messages, &[0, 3, 4, 5], bpe) + notice(&messages, bpe);
+        let plan = plan(&messages, None, bpe, budget);
+        assert_eq!(kept(&plan), vec![0, 3, 4, 5]);
+        assert_eq!(plan.dropped, 2);
+        assert_eq!(plan.marker_at, Some(3));
+    }
+
+    #[test]
+    fn drops_replies_whose_question_was_dropped() {
+        let bpe = encoder("gpt-4");
+        let messages = conversation();
+        // Room for the short reply, but not for the question before it
+        let budget = cost(&messages, &[0, 4, 5], bpe) + notice(&messages, bpe);
+        let plan = plan(&messages, None, bpe, budget);
+        assert_eq!(kept(&plan), vec![0, 5]);
+        assert_eq!(plan.dropped, 4);
+        assert_eq!(plan.marker_at, Some(5));
+    }
+
+    #[test]
+    fn system_prompt_counts_against_the_budget() {
+        let bpe = encoder("gpt-4");
+        let messages = conversation();
+        let all: Vec<usize> = (0..messages.len()).collect();
+        let prompt = message("system", "You are a coding assistant.");
+        let plan = plan(&messages, Some(&prompt), bpe, cost(&messages, &all, bpe));
+        assert!(plan.dropped > 0);
+        assert!(plan.keep[0] && plan.keep[5]);
+    }
+
+    #[test]
+    fn provider_prefixes_pick_the_right_vocabulary() {
+        let o200k = tiktoken_rs::o200k_base().unwrap();
+        let text = "Tokenizers differ on this sentence, 12345.";
+        assert_eq!(
+            encoder("openai/gpt-4o").encode_ordinary(text),
+            o200k.encode_ordinary(text)
+        );
+        assert!(std::ptr::eq(encoder("anthropic/claude"), encoder("gpt-4")));
+    }
+}
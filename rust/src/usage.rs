@@ -0,0 +1,621 @@
+// Token usage and cost per assistant message
+//
+// Counts live in their own table of conversations.db (migration 0009),
+// keyed by message id, so the SQLAlchemy models don't need to know about
+// them. Store deletes them along with their messages; rows left behind by
+// deletions through the ORM are skipped by joining on messages.
+
+use std::time::Duration;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::api::{py_to_json, Usage};
+use crate::models::ModelRegistry;
+
+/// How long to wait on a database locked by the app's own writes
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Summed usage of a group of messages
+#[pyclass]
+pub struct UsageTotals {
+    /// What the group is: a conversation or project id, a model id or a
+    /// YYYY-MM-DD day; None for conversations without a project
+    #[pyo3(get)]
+    pub key: PyObject,
+    /// Conversation title, project name, model id or day
+    #[pyo3(get)]
+    pub label: String,
+    /// Assistant messages with recorded usage
+    #[pyo3(get)]
+    pub messages: u64,
+    #[pyo3(get)]
+    pub prompt_tokens: u64,
+    /// Includes reasoning tokens
+    #[pyo3(get)]
+    pub completion_tokens: u64,
+    #[pyo3(get)]
+    pub reasoning_tokens: u64,
+    /// USD, summed over the messages whose model price was known
+    #[pyo3(get)]
+    pub cost: f64,
+    /// Messages left out of `cost` because their price was unknown
+    #[pyo3(get)]
+    pub unpriced: u64,
+}
+
+#[pymethods]
+impl UsageTotals {
+    #[getter]
+    fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "UsageTotals(label={:?}, messages={}, total_tokens={}, cost={:.4})",
+            self.label,
+            self.messages,
+            self.total_tokens(),
+            self.cost
+        )
+    }
+}
+
+/// One aggregate row before it is handed to Python
+struct Row {
+    key: SqlValue,
+    label: String,
+    messages: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    reasoning_tokens: u64,
+    cost: f64,
+    unpriced: u64,
+}
+
+impl Row {
+    fn into_totals(self, py: Python) -> UsageTotals {
+        let key = match self.key {
+            SqlValue::Integer(i) => i.into_py(py),
+            SqlValue::Real(f) => f.into_py(py),
+            SqlValue::Text(s) => s.into_py(py),
+            SqlValue::Null | SqlValue::Blob(_) => py.None(),
+        };
+        UsageTotals {
+            key,
+            label: self.label,
+            messages: self.messages,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            reasoning_tokens: self.reasoning_tokens,
+            cost: self.cost,
+            unpriced: self.unpriced,
+        }
+    }
+}
+
+/// How a report groups messages: key and label expressions, and order
+struct Grouping {
+    key: &'static str,
+    label: &'static str,
+    order: &'static str,
+}
+
+/// Biggest spenders first
+const BY_COST: &str = "TOTAL(u.cost) DESC, SUM(u.prompt_tokens + u.completion_tokens) DESC";
+
+const BY_CONVERSATION: Grouping = Grouping {
+    key: "c.id",
+    label: "c.title",
+    order: BY_COST,
+};
+const BY_PROJECT: Grouping = Grouping {
+    key: "p.id",
+    label: "COALESCE(p.name, 'No project')",
+    order: BY_COST,
+};
+const BY_MODEL: Grouping = Grouping {
+    key: "u.model",
+    label: "u.model",
+    order: BY_COST,
+};
+const BY_DAY: Grouping = Grouping {
+    key: "substr(m.created_at, 1, 10)",
+    label: "substr(m.created_at, 1, 10)",
+    order: "1",
+};
+
+/// Records what each response cost and sums it up for reports
+///
+/// `since` and `until` limit reports to messages created on those days or
+/// between them, as YYYY-MM-DD in UTC like the stored timestamps.
+#[pyclass]
+pub struct UsageLedger {
+    db_path: String,
+}
+
+#[pymethods]
+impl UsageLedger {
+    /// Open the ledger of `db_path`, a database already brought up to
+    /// date by migrate_database
+    #[new]
+    fn new(db_path: &str) -> PyResult<Self> {
+        let ledger = UsageLedger {
+            db_path: db_path.to_string(),
+        };
+        ledger.open().map_err(db_error)?;
+        Ok(ledger)
+    }
+
+    #[getter]
+    fn db_path(&self) -> String {
+        self.db_path.clone()
+    }
+
+    /// Store the usage of assistant message `message_id`, replacing any
+    /// earlier record, and return its cost in USD
+    ///
+    /// `usage` is a Usage from ChatStream.usage or the API's usage dict.
+    /// The cost is `cost` when given, else worked out from the model's
+    /// prices in `registry`; None when neither is available.
+    #[pyo3(signature = (message_id, model, usage, registry=None, cost=None))]
+    fn record(
+        &self,
+        py: Python,
+        message_id: i64,
+        model: &str,
+        usage: &PyAny,
+        registry: Option<PyRef<'_, ModelRegistry>>,
+        cost: Option<f64>,
+    ) -> PyResult<Option<f64>> {
+        let usage = extract_usage(usage)?;
+        let cost = cost.or_else(|| {
+            let (input, output) = registry?.prices(model)?;
+            Some(
+                (usage.prompt_tokens as f64 * input + usage.completion_tokens as f64 * output)
+                    / 1_000_000.0,
+            )
+        });
+        py.allow_threads(|| {
+            let conn = self.open()?;
+            conn.execute(
+                "INSERT OR REPLACE INTO message_usage
+                 (message_id, model, prompt_tokens, completion_tokens, reasoning_tokens, cost, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, strftime('%Y-%m-%d %H:%M:%f', 'now'))",
+                params![
+                    message_id,
+                    model,
+                    usage.prompt_tokens as i64,
+                    usage.completion_tokens as i64,
+                    usage.reasoning_tokens as i64,
+                    cost
+                ],
+            )
+        })
+        .map_err(db_error)?;
+        Ok(cost)
+    }
+
+    /// Usage recorded for one message, None if there is none
+    fn for_message(&self, py: Python, message_id: i64) -> PyResult<Option<UsageTotals>> {
+        let rows = self.report(
+            py,
+            &BY_MODEL,
+            "u.message_id = ?3",
+            Some(message_id),
+            None,
+            None,
+        )?;
+        Ok(rows.into_iter().next().map(|row| row.into_totals(py)))
+    }
+
+    /// Totals of one conversation, zero if nothing was recorded
+    #[pyo3(signature = (conversation_id, since=None, until=None))]
+    fn for_conversation(
+        &self,
+        py: Python,
+        conversation_id: i64,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<UsageTotals> {
+        let rows = self.report(
+            py,
+            &BY_CONVERSATION,
+            "c.id = ?3",
+            Some(conversation_id),
+            since,
+            until,
+        )?;
+        Ok(match rows.into_iter().next() {
+            Some(row) => row.into_totals(py),
+            None => UsageTotals {
+                key: conversation_id.into_py(py),
+                label: String::new(),
+                messages: 0,
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                reasoning_tokens: 0,
+                cost: 0.0,
+                unpriced: 0,
+            },
+        })
+    }
+
+    /// Totals per conversation, most expensive first
+    #[pyo3(signature = (since=None, until=None))]
+    fn by_conversation(
+        &self,
+        py: Python,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<UsageTotals>> {
+        self.grouped(py, &BY_CONVERSATION, since, until)
+    }
+
+    /// Totals per project, most expensive first; conversations outside any
+    /// project are grouped under a None key
+    #[pyo3(signature = (since=None, until=None))]
+    fn by_project(
+        &self,
+        py: Python,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<UsageTotals>> {
+        self.grouped(py, &BY_PROJECT, since, until)
+    }
+
+    /// Totals per model, most expensive first
+    #[pyo3(signature = (since=None, until=None))]
+    fn by_model(
+        &self,
+        py: Python,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<UsageTotals>> {
+        self.grouped(py, &BY_MODEL, since, until)
+    }
+
+    /// Totals per day, oldest first; days without usage are left out
+    #[pyo3(signature = (since=None, until=None))]
+    fn by_day(
+        &self,
+        py: Python,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<UsageTotals>> {
+        self.grouped(py, &BY_DAY, since, until)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("UsageLedger(db_path={:?})", self.db_path)
+    }
+}
+
+impl UsageLedger {
+    fn open(&self) -> rusqlite::Result<Connection> {
+        let conn = Connection::open_with_flags(
+            &self.db_path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
+
+    fn grouped(
+        &self,
+        py: Python,
+        grouping: &Grouping,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<UsageTotals>> {
+        let rows = self.report(py, grouping, "?3 IS NULL", None, since, until)?;
+        Ok(rows.into_iter().map(|row| row.into_totals(py)).collect())
+    }
+
+    /// Run an aggregate query; `filter` may use ?3, bound to `value`
+    fn report(
+        &self,
+        py: Python,
+        grouping: &Grouping,
+        filter: &str,
+        value: Option<i64>,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> PyResult<Vec<Row>> {
+        let sql = format!(
+            "SELECT {key}, {label}, COUNT(*), SUM(u.prompt_tokens), SUM(u.completion_tokens),
+                    SUM(u.reasoning_tokens), TOTAL(u.cost), SUM(u.cost IS NULL)
+             FROM message_usage u
+             JOIN messages m ON m.id = u.message_id
+             JOIN conversations c ON c.id = m.conversation_id
+             LEFT JOIN projects p ON p.id = c.project_id
+             WHERE {filter}
+               AND (?1 IS NULL OR m.created_at >= ?1)
+               AND (?2 IS NULL OR m.created_at < date(?2, '+1 day'))
+             GROUP BY {key}
+             ORDER BY {order}",
+            key = grouping.key,
+            label = grouping.label,
+            order = grouping.order,
+        );
+        py.allow_threads(|| {
+            let conn = self.open()?;
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params![since, until, value], |row| {
+                Ok(Row {
+                    key: row.get(0)?,
+                    label: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                    messages: row.get::<_, i64>(2)? as u64,
+                    prompt_tokens: row.get::<_, i64>(3)? as u64,
+                    completion_tokens: row.get::<_, i64>(4)? as u64,
+                    reasoning_tokens: row.get::<_, i64>(5)? as u64,
+                    cost: row.get(6)?,
+                    unpriced: row.get::<_, i64>(7)? as u64,
+                })
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .map_err(db_error)
+    }
+}
+
+/// A Usage, or a usage dict as the API sends it
+fn extract_usage(usage: &PyAny) -> PyResult<Usage> {
+    if let Ok(usage) = usage.extract::<Usage>() {
+        return Ok(usage);
+    }
+    if usage.downcast::<PyDict>().is_ok() {
+        if let Some(usage) = Usage::from_json(&py_to_json(usage)?) {
+            return Ok(usage);
+        }
+    }
+    Err(PyValueError::new_err(
+        "usage must be a Usage or a usage dict",
+    ))
+}
+
+fn db_error(e: rusqlite::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("Usage ledger error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::migrate::migrate;
+
+    /// A migrated database holding two conversations, one of them in a
+    /// project, with assistant messages 2, 4 and 6
+    fn ledger(dir: &tempfile::TempDir) -> UsageLedger {
+        let path = dir.path().join("conversations.db");
+        let mut conn = Connection::open(&path).unwrap();
+        migrate(&mut conn, path.to_str().unwrap(), false).unwrap();
+        conn.execute_batch(
+            "INSERT INTO projects (id, name, created_at, updated_at, order_index)
+                 VALUES (1, 'Work', '2024-05-01', '2024-05-01', 0);
+             INSERT INTO conversations (id, title, created_at, updated_at, project_id)
+                 VALUES (1, 'Budget', '2024-05-01', '2024-05-01', 1),
+                        (2, 'Recipes', '2024-05-02', '2024-05-02', NULL);
+             INSERT INTO messages (id, conversation_id, role, content, created_at) VALUES
+                 (1, 1, 'user', 'q', '2024-05-01 09:00:00'),
+                 (2, 1, 'assistant', 'a', '2024-05-01 09:00:05'),
+                 (3, 1, 'user', 'q', '2024-05-02 10:00:00'),
+                 (4, 1, 'assistant', 'a', '2024-05-02 10:00:05'),
+                 (5, 2, 'user', 'q', '2024-05-02 11:00:00'),
+                 (6, 2, 'assistant', 'a', '2024-05-02 11:00:05');",
+        )
+        .unwrap();
+        UsageLedger::new(path.to_str().unwrap()).unwrap()
+    }
+
+    fn usage(py: Python<'_>, prompt: u64, completion: u64, reasoning: u64) -> &PyAny {
+        let usage = Usage {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+            reasoning_tokens: reasoning,
+        };
+        PyCell::new(py, usage).unwrap()
+    }
+
+    /// A registry that knows the prices of "priced-model" only
+    fn registry<'py>(py: Python<'py>, dir: &tempfile::TempDir) -> &'py PyCell<ModelRegistry> {
+        let mut registry = ModelRegistry::new(dir.path().to_str()).unwrap();
+        let payload = py
+            .eval(
+                "[{'id': 'priced-model', 'pricing': {'prompt': 2.0, 'completion': 10.0}}]",
+                None,
+                None,
+            )
+            .unwrap();
+        registry.update(payload).unwrap();
+        PyCell::new(py, registry).unwrap()
+    }
+
+    fn with_python(f: impl FnOnce(Python)) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(f)
+    }
+
+    #[test]
+    fn record_prices_usage_from_the_registry() {
+        with_python(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let ledger = ledger(&dir);
+            let registry = registry(py, &dir);
+
+            let cost = ledger
+                .record(
+                    py,
+                    2,
+                    "priced-model",
+                    usage(py, 1000, 500, 100),
+                    Some(registry.borrow()),
+                    None,
+                )
+                .unwrap();
+            assert_eq!(cost, Some((1000.0 * 2.0 + 500.0 * 10.0) / 1_000_000.0));
+
+            // An explicit cost wins, and unknown prices leave it unset
+            let given = ledger
+                .record(py, 4, "priced-model", usage(py, 10, 10, 0), None, Some(0.5))
+                .unwrap();
+            assert_eq!(given, Some(0.5));
+            let unknown = ledger
+                .record(
+                    py,
+                    6,
+                    "mystery-model",
+                    usage(py, 10, 10, 0),
+                    Some(registry.borrow()),
+                    None,
+                )
+                .unwrap();
+            assert_eq!(unknown, None);
+        });
+    }
+
+    #[test]
+    fn record_accepts_usage_dicts_and_replaces_earlier_records() {
+        with_python(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let ledger = ledger(&dir);
+            ledger
+                .record(py, 2, "m", usage(py, 1, 1, 0), None, None)
+                .unwrap();
+            let dict = py
+                .eval(
+                    "{'prompt_tokens': 30, 'completion_tokens': 20, \
+                      'completion_tokens_details': {'reasoning_tokens': 5}}",
+                    None,
+                    None,
+                )
+                .unwrap();
+            ledger.record(py, 2, "m", dict, None, None).unwrap();
+
+            let totals = ledger.for_message(py, 2).unwrap().unwrap();
+            assert_eq!(totals.messages, 1);
+            assert_eq!(
+                (
+                    totals.prompt_tokens,
+                    totals.completion_tokens,
+                    totals.reasoning_tokens
+                ),
+                (30, 20, 5)
+            );
+            assert_eq!(totals.total_tokens(), 50);
+            assert_eq!(totals.unpriced, 1);
+            assert!(ledger.for_message(py, 4).unwrap().is_none());
+
+            let bad = py.eval("[1, 2]", None, None).unwrap();
+            assert!(ledger.record(py, 2, "m", bad, None, None).is_err());
+        });
+    }
+
+    #[test]
+    fn conversation_totals_count_unpriced_messages() {
+        with_python(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let ledger = ledger(&dir);
+            ledger
+                .record(py, 2, "a", usage(py, 100, 50, 0), None, Some(0.25))
+                .unwrap();
+            ledger
+                .record(py, 4, "b", usage(py, 200, 80, 30), None, None)
+                .unwrap();
+
+            let totals = ledger.for_conversation(py, 1, None, None).unwrap();
+            assert_eq!(totals.label, "Budget");
+            assert_eq!(totals.messages, 2);
+            assert_eq!((totals.prompt_tokens, totals.completion_tokens), (300, 130));
+            assert_eq!(totals.reasoning_tokens, 30);
+            assert_eq!(totals.cost, 0.25);
+            assert_eq!(totals.unpriced, 1);
+
+            // Limited to a day, and zero where nothing was recorded
+            let day = ledger
+                .for_conversation(py, 1, Some("2024-05-02"), Some("2024-05-02"))
+                .unwrap();
+            assert_eq!((day.messages, day.cost), (1, 0.0));
+            let empty = ledger.for_conversation(py, 2, None, None).unwrap();
+            assert_eq!(empty.key.extract::<i64>(py).unwrap(), 2);
+            assert_eq!((empty.messages, empty.cost, empty.unpriced), (0, 0.0, 0));
+        });
+    }
+
+    #[test]
+    fn reports_group_and_order_usage() {
+        with_python(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let ledger = ledger(&dir);
+            ledger
+                .record(py, 2, "cheap", usage(py, 10, 10, 0), None, Some(0.5))
+                .unwrap();
+            ledger
+                .record(py, 4, "dear", usage(py, 10, 10, 0), None, Some(1.0))
+                .unwrap();
+            ledger
+                .record(py, 6, "unknown", usage(py, 500, 500, 0), None, None)
+                .unwrap();
+
+            let labels = |rows: Vec<UsageTotals>| -> Vec<(String, u64, f64)> {
+                rows.into_iter()
+                    .map(|r| (r.label, r.messages, r.cost))
+                    .collect()
+            };
+            assert_eq!(
+                labels(ledger.by_conversation(py, None, None).unwrap()),
+                [("Budget".into(), 2, 1.5), ("Recipes".into(), 1, 0.0)]
+            );
+            let projects = ledger.by_project(py, None, None).unwrap();
+            assert_eq!(projects[0].key.extract::<i64>(py).unwrap(), 1);
+            assert!(projects[1].key.is_none(py));
+            assert_eq!(
+                labels(projects),
+                [("Work".into(), 2, 1.5), ("No project".into(), 1, 0.0)]
+            );
+            // Unpriced usage sorts by tokens after everything with a cost
+            assert_eq!(
+                labels(ledger.by_model(py, None, None).unwrap()),
+                [
+                    ("dear".into(), 1, 1.0),
+                    ("cheap".into(), 1, 0.5),
+                    ("unknown".into(), 1, 0.0)
+                ]
+            );
+            assert_eq!(
+                labels(ledger.by_day(py, None, None).unwrap()),
+                [("2024-05-01".into(), 1, 0.5), ("2024-05-02".into(), 2, 1.0)]
+            );
+            assert_eq!(
+                labels(ledger.by_day(py, Some("2024-05-02"), None).unwrap()),
+                [("2024-05-02".into(), 2, 1.0)]
+            );
+            assert!(ledger
+                .by_model(py, None, Some("2024-04-30"))
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn usage_of_deleted_messages_is_left_out() {
+        with_python(|py| {
+            let dir = tempfile::tempdir().unwrap();
+            let ledger = ledger(&dir);
+            ledger
+                .record(py, 6, "m", usage(py, 10, 10, 0), None, Some(0.1))
+                .unwrap();
+            ledger
+                .open()
+                .unwrap()
+                .execute("DELETE FROM messages WHERE id = 6", [])
+                .unwrap();
+            assert!(ledger.for_message(py, 6).unwrap().is_none());
+            assert!(ledger.by_model(py, None, None).unwrap().is_empty());
+        });
+    }
+}